@@ -0,0 +1,70 @@
+use rq::query::{Executable, Query};
+use serde_json::{json, Value};
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+/// Runs a query against an input repeatedly for about a second, printing
+/// the mean and fastest time of a run.
+fn bench(name: &str, query: &str, input: &Value) {
+    let q: Query = query.parse().unwrap();
+    q.execute(input).unwrap();
+
+    let mut runs = 0u32;
+    let mut fastest = Duration::MAX;
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(1) {
+        let run = Instant::now();
+        black_box(q.execute(black_box(input)).unwrap());
+        fastest = fastest.min(run.elapsed());
+        runs += 1;
+    }
+    println!(
+        "{:<24} {:>12.3?} mean {:>12.3?} fastest ({} runs)",
+        name,
+        start.elapsed() / runs,
+        fastest,
+        runs
+    );
+}
+
+/// An object nested `depth` levels deep under the key `a`, with a few
+/// other keys at each level.
+fn nested(depth: usize) -> Value {
+    (0..depth).fold(
+        json!(0),
+        |inner, i| json!({"a": inner, "b": i, "c": [i, i + 1], "d": "text"}),
+    )
+}
+
+/// An array of records, every tenth of which isn't an object.
+fn records(len: usize) -> Value {
+    (0..len)
+        .map(|i| match i % 10 {
+            0 => json!(i),
+            _ => json!({"id": i, "name": format!("item {}", i), "tags": ["x", "y"]}),
+        })
+        .collect()
+}
+
+fn main() {
+    let deep = nested(64);
+    let path = vec![".a"; 64].concat();
+    bench("deep index", &path, &deep);
+    bench("deep optional index", &format!("{}?", path), &deep);
+
+    let wide = json!({ "items": records(1_000), "deep": nested(32) });
+    bench("recurse", "[..]", &wide);
+    bench("recurse and index", "[.. | .id?]", &wide);
+
+    let array = records(10_000);
+    bench("map", "[.[] | .id? * 2]", &array);
+    bench("filter", "[.[] | .name?]", &array);
+    bench(
+        "construct object",
+        "[.[] | {id: .id?, tag: .tags?[0]}]",
+        &array,
+    );
+    bench("construct array", "[.[] | [.id?, .name?]]", &array);
+}
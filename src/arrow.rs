@@ -0,0 +1,1161 @@
+use serde_json::{Map, Number, Value};
+use std::{collections::HashMap, convert::TryFrom, vec};
+use thiserror::Error;
+
+use crate::{bson::civil, bytes, cbor::half};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ArrowError {
+    #[error("Unexpected end of Arrow input")]
+    Eof,
+    #[error("Invalid Arrow metadata")]
+    Metadata,
+    #[error("Arrow text is not UTF-8")]
+    Utf8,
+    #[error("Cannot read Arrow {0}")]
+    Unsupported(String),
+}
+
+const MAGIC: &[u8] = b"ARROW1";
+
+/// Reads every row of the record batches of an Arrow IPC file or stream,
+/// each as an object of its columns.
+///
+/// Dates and times are written as ISO 8601 text, decimals as text so that
+/// none of their digits are lost, and binary data as arrays of its bytes.
+pub fn from_slice(bytes: &[u8]) -> Result<Vec<Value>, ArrowError> {
+    // A file is a stream between its magic numbers, followed by a footer
+    // which is only needed to seek to a batch
+    let mut stream = match bytes.strip_prefix(MAGIC) {
+        Some(_) => file_stream(bytes).ok_or(ArrowError::Eof)?,
+        None => bytes,
+    };
+    let mut fields: Option<Vec<Field>> = None;
+    let mut dictionaries = HashMap::new();
+    let mut rows = Vec::new();
+    while let Some((message, body)) = message(&mut stream)? {
+        let header = message.table(2)?.ok_or(ArrowError::Metadata)?;
+        match message.u8(1, 0)? {
+            1 => fields = Some(Field::read_all(header.tables(1)?)?),
+            2 => {
+                let fields = fields.as_ref().ok_or(ArrowError::Metadata)?;
+                let id = header.i64(0, 0)?;
+                let field = fields
+                    .iter()
+                    .find_map(|f| f.encoded(id))
+                    .ok_or(ArrowError::Metadata)?;
+                let data = header.table(1)?.ok_or(ArrowError::Metadata)?;
+                let length = usize::try_from(data.i64(0, 0)?).map_err(|_| ArrowError::Metadata)?;
+                let mut batch = Batch::read(data, body, &dictionaries)?;
+                let values = batch.column(&field, length)?;
+                match header.u8(2, 0)? {
+                    0 => {
+                        dictionaries.insert(id, values);
+                    }
+                    _ => dictionaries.entry(id).or_default().extend(values),
+                }
+            }
+            3 => {
+                let fields = fields.as_ref().ok_or(ArrowError::Metadata)?;
+                let length =
+                    usize::try_from(header.i64(0, 0)?).map_err(|_| ArrowError::Metadata)?;
+                let mut batch = Batch::read(header, body, &dictionaries)?;
+                let mut columns = Vec::with_capacity(fields.len());
+                for field in fields {
+                    columns.push(batch.column(field, length)?.into_iter());
+                }
+                for _ in 0..length {
+                    let row = fields
+                        .iter()
+                        .zip(&mut columns)
+                        .map(|(f, c)| (f.name.clone(), c.next().unwrap_or(Value::Null)));
+                    rows.push(Value::Object(row.collect::<Map<_, _>>()));
+                }
+            }
+            kind => return Err(ArrowError::Unsupported(format!("message type {}", kind))),
+        }
+    }
+    Ok(rows)
+}
+
+/// The stream within a file, up to its footer.
+fn file_stream(bytes: &[u8]) -> Option<&[u8]> {
+    let trailer = bytes.len().checked_sub(MAGIC.len() + 4)?;
+    if trailer < 8 || !bytes.ends_with(MAGIC) {
+        return None;
+    }
+    let footer = u32::from_le_bytes(<[u8; 4]>::try_from(&bytes[trailer..trailer + 4]).ok()?);
+    bytes.get(8..trailer.checked_sub(usize::try_from(footer).ok()?)?)
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], ArrowError> {
+    if bytes.len() < n {
+        return Err(ArrowError::Eof);
+    }
+    let (taken, rest) = bytes.split_at(n);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn length(bytes: &mut &[u8]) -> Result<usize, ArrowError> {
+    let n = u32::from_le_bytes(<[u8; 4]>::try_from(take(bytes, 4)?).unwrap_or_default());
+    usize::try_from(n).map_err(|_| ArrowError::Eof)
+}
+
+/// The metadata and body of the next message, or `None` at the end of the
+/// stream.
+fn message<'a>(stream: &mut &'a [u8]) -> Result<Option<(Table<'a>, &'a [u8])>, ArrowError> {
+    if stream.is_empty() {
+        return Ok(None);
+    }
+    let mut n = length(stream)?;
+    // Since version 0.15 the length follows a continuation marker
+    if n == 0xffff_ffff {
+        n = length(stream)?;
+    }
+    if n == 0 {
+        return Ok(None);
+    }
+    let message = Table::root(take(stream, n)?)?;
+    let body = usize::try_from(message.i64(3, 0)?).map_err(|_| ArrowError::Metadata)?;
+    Ok(Some((message, take(stream, body)?)))
+}
+
+/// A table of flatbuffer metadata, read in place.
+#[derive(Clone, Copy)]
+struct Table<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+fn read<const N: usize>(buf: &[u8], pos: usize) -> Result<[u8; N], ArrowError> {
+    buf.get(pos..pos.checked_add(N).ok_or(ArrowError::Metadata)?)
+        .and_then(|b| <[u8; N]>::try_from(b).ok())
+        .ok_or(ArrowError::Metadata)
+}
+
+/// The position an offset stored at `pos` refers to.
+fn offset(buf: &[u8], pos: usize) -> Result<usize, ArrowError> {
+    let n =
+        usize::try_from(u32::from_le_bytes(read(buf, pos)?)).map_err(|_| ArrowError::Metadata)?;
+    pos.checked_add(n).ok_or(ArrowError::Metadata)
+}
+
+impl<'a> Table<'a> {
+    fn root(buf: &'a [u8]) -> Result<Self, ArrowError> {
+        Ok(Table {
+            buf,
+            pos: offset(buf, 0)?,
+        })
+    }
+
+    /// Where the field in `slot` is, or `None` if it takes its default.
+    fn field(&self, slot: usize) -> Result<Option<usize>, ArrowError> {
+        let vtable = i64::try_from(self.pos).map_err(|_| ArrowError::Metadata)?
+            - i64::from(i32::from_le_bytes(read(self.buf, self.pos)?));
+        let vtable = usize::try_from(vtable).map_err(|_| ArrowError::Metadata)?;
+        let size = usize::from(u16::from_le_bytes(read(self.buf, vtable)?));
+        let entry = 4 + 2 * slot;
+        if entry + 2 > size {
+            return Ok(None);
+        }
+        match u16::from_le_bytes(read(self.buf, vtable + entry)?) {
+            0 => Ok(None),
+            n => Ok(Some(self.pos + usize::from(n))),
+        }
+    }
+
+    fn scalar<const N: usize>(&self, slot: usize) -> Result<Option<[u8; N]>, ArrowError> {
+        self.field(slot)?.map(|pos| read(self.buf, pos)).transpose()
+    }
+
+    fn u8(&self, slot: usize, default: u8) -> Result<u8, ArrowError> {
+        Ok(self.scalar(slot)?.map_or(default, u8::from_le_bytes))
+    }
+
+    fn i16(&self, slot: usize, default: i16) -> Result<i16, ArrowError> {
+        Ok(self.scalar(slot)?.map_or(default, i16::from_le_bytes))
+    }
+
+    fn i32(&self, slot: usize, default: i32) -> Result<i32, ArrowError> {
+        Ok(self.scalar(slot)?.map_or(default, i32::from_le_bytes))
+    }
+
+    fn i64(&self, slot: usize, default: i64) -> Result<i64, ArrowError> {
+        Ok(self.scalar(slot)?.map_or(default, i64::from_le_bytes))
+    }
+
+    fn table(&self, slot: usize) -> Result<Option<Table<'a>>, ArrowError> {
+        match self.field(slot)? {
+            Some(pos) => Ok(Some(Table {
+                buf: self.buf,
+                pos: offset(self.buf, pos)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// The position of the first item of a vector and how many there are.
+    fn vector(&self, slot: usize) -> Result<(usize, usize), ArrowError> {
+        match self.field(slot)? {
+            Some(pos) => {
+                let start = offset(self.buf, pos)?;
+                let n = u32::from_le_bytes(read(self.buf, start)?);
+                Ok((
+                    start + 4,
+                    usize::try_from(n).map_err(|_| ArrowError::Metadata)?,
+                ))
+            }
+            None => Ok((0, 0)),
+        }
+    }
+
+    fn string(&self, slot: usize) -> Result<&'a str, ArrowError> {
+        let (start, n) = self.vector(slot)?;
+        let bytes = self.buf.get(start..start + n).ok_or(ArrowError::Metadata)?;
+        std::str::from_utf8(bytes).map_err(|_| ArrowError::Utf8)
+    }
+
+    fn tables(&self, slot: usize) -> Result<Vec<Table<'a>>, ArrowError> {
+        let (start, n) = self.vector(slot)?;
+        (0..n)
+            .map(|i| {
+                Ok(Table {
+                    buf: self.buf,
+                    pos: offset(self.buf, start + 4 * i)?,
+                })
+            })
+            .collect()
+    }
+
+    fn structs(&self, slot: usize, size: usize) -> Result<Vec<&'a [u8]>, ArrowError> {
+        let (start, n) = self.vector(slot)?;
+        let end = n
+            .checked_mul(size)
+            .and_then(|n| n.checked_add(start))
+            .ok_or(ArrowError::Metadata)?;
+        let items = self.buf.get(start..end).ok_or(ArrowError::Metadata)?;
+        Ok(items.chunks(size).collect())
+    }
+}
+
+/// A column of the schema, or a child of one.
+#[derive(Clone)]
+struct Field {
+    name: String,
+    kind: Kind,
+    /// The dictionary the values are indices into, and the type of those
+    /// indices.
+    dictionary: Option<(i64, Kind)>,
+    children: Vec<Field>,
+}
+
+/// How the values of a column are laid out, with the metadata reading them
+/// needs.
+#[derive(Clone)]
+enum Kind {
+    Null,
+    Int {
+        bytes: usize,
+        signed: bool,
+    },
+    Float(i16),
+    Bool,
+    Binary {
+        wide: bool,
+        text: bool,
+    },
+    FixedBinary(usize),
+    Decimal {
+        bytes: usize,
+        scale: i32,
+    },
+    /// Days, or milliseconds with 8 bytes.
+    Date {
+        bytes: usize,
+    },
+    Time {
+        unit: i16,
+        bytes: usize,
+    },
+    Timestamp {
+        unit: i16,
+        zoned: bool,
+    },
+    Duration,
+    List {
+        wide: bool,
+    },
+    FixedList(usize),
+    Struct,
+    Map,
+}
+
+impl Field {
+    fn read_all(tables: Vec<Table>) -> Result<Vec<Field>, ArrowError> {
+        tables.into_iter().map(Field::read).collect()
+    }
+
+    fn read(table: Table) -> Result<Field, ArrowError> {
+        let dictionary = match table.table(4)? {
+            Some(d) => {
+                let index = match d.table(1)? {
+                    Some(int) => Kind::read(2, Some(int))?,
+                    None => Kind::Int {
+                        bytes: 4,
+                        signed: true,
+                    },
+                };
+                Some((d.i64(0, 0)?, index))
+            }
+            None => None,
+        };
+        Ok(Field {
+            name: table.string(0)?.to_string(),
+            kind: Kind::read(table.u8(2, 0)?, table.table(3)?)?,
+            dictionary,
+            children: Field::read_all(table.tables(5)?)?,
+        })
+    }
+
+    /// The field which holds the values of dictionary `id`, as they are
+    /// written in its dictionary batches.
+    fn encoded(&self, id: i64) -> Option<Field> {
+        match self.dictionary {
+            Some((d, _)) if d == id => Some(Field {
+                dictionary: None,
+                ..self.clone()
+            }),
+            _ => self.children.iter().find_map(|c| c.encoded(id)),
+        }
+    }
+}
+
+impl Kind {
+    fn read(id: u8, table: Option<Table>) -> Result<Kind, ArrowError> {
+        let table = || table.ok_or(ArrowError::Metadata);
+        let bytes = |bits: i32| match bits {
+            8 | 16 | 32 | 64 | 128 => Ok(bits as usize / 8),
+            bits => Err(ArrowError::Unsupported(format!("{} bit numbers", bits))),
+        };
+        Ok(match id {
+            1 => Kind::Null,
+            2 => Kind::Int {
+                bytes: bytes(table()?.i32(0, 0)?)?,
+                signed: table()?.u8(1, 0)? != 0,
+            },
+            3 => Kind::Float(table()?.i16(0, 0)?),
+            4 => Kind::Binary {
+                wide: false,
+                text: false,
+            },
+            5 => Kind::Binary {
+                wide: false,
+                text: true,
+            },
+            6 => Kind::Bool,
+            7 => Kind::Decimal {
+                bytes: bytes(table()?.i32(2, 128)?)?,
+                // No more digits than the widest decimal has
+                scale: Some(table()?.i32(1, 0)?)
+                    .filter(|s| s.unsigned_abs() <= 76)
+                    .ok_or(ArrowError::Metadata)?,
+            },
+            8 => Kind::Date {
+                bytes: if table()?.i16(0, 1)? == 0 { 4 } else { 8 },
+            },
+            9 => Kind::Time {
+                unit: table()?.i16(0, 1)?,
+                bytes: bytes(table()?.i32(1, 32)?)?,
+            },
+            10 => Kind::Timestamp {
+                unit: table()?.i16(0, 0)?,
+                zoned: !table()?.string(1)?.is_empty(),
+            },
+            12 => Kind::List { wide: false },
+            13 => Kind::Struct,
+            15 => Kind::FixedBinary(
+                usize::try_from(table()?.i32(0, 0)?).map_err(|_| ArrowError::Metadata)?,
+            ),
+            16 => Kind::FixedList(
+                usize::try_from(table()?.i32(0, 0)?).map_err(|_| ArrowError::Metadata)?,
+            ),
+            17 => Kind::Map,
+            18 => Kind::Duration,
+            19 => Kind::Binary {
+                wide: true,
+                text: false,
+            },
+            20 => Kind::Binary {
+                wide: true,
+                text: true,
+            },
+            21 => Kind::List { wide: true },
+            11 => return Err(ArrowError::Unsupported("intervals".to_string())),
+            14 => return Err(ArrowError::Unsupported("unions".to_string())),
+            id => return Err(ArrowError::Unsupported(format!("type {}", id))),
+        })
+    }
+}
+
+/// The nodes and buffers of a record batch, taken in order as each column
+/// and its children are read.
+struct Batch<'a> {
+    nodes: vec::IntoIter<&'a [u8]>,
+    buffers: vec::IntoIter<&'a [u8]>,
+    body: &'a [u8],
+    dictionaries: &'a HashMap<i64, Vec<Value>>,
+}
+
+impl<'a> Batch<'a> {
+    fn read(
+        batch: Table<'a>,
+        body: &'a [u8],
+        dictionaries: &'a HashMap<i64, Vec<Value>>,
+    ) -> Result<Self, ArrowError> {
+        if batch.table(3)?.is_some() {
+            return Err(ArrowError::Unsupported("compressed batches".to_string()));
+        }
+        Ok(Batch {
+            nodes: batch.structs(1, 16)?.into_iter(),
+            buffers: batch.structs(2, 16)?.into_iter(),
+            body,
+            dictionaries,
+        })
+    }
+
+    /// The length and null count of the next column.
+    fn node(&mut self) -> Result<(usize, usize), ArrowError> {
+        let node = self.nodes.next().ok_or(ArrowError::Metadata)?;
+        let number = |pos| {
+            usize::try_from(i64::from_le_bytes(read(node, pos)?)).map_err(|_| ArrowError::Metadata)
+        };
+        Ok((number(0)?, number(8)?))
+    }
+
+    fn buffer(&mut self) -> Result<&'a [u8], ArrowError> {
+        let buffer = self.buffers.next().ok_or(ArrowError::Metadata)?;
+        let number = |pos| {
+            usize::try_from(i64::from_le_bytes(read(buffer, pos)?))
+                .map_err(|_| ArrowError::Metadata)
+        };
+        let (start, n) = (number(0)?, number(8)?);
+        self.body
+            .get(start..start.checked_add(n).ok_or(ArrowError::Eof)?)
+            .ok_or(ArrowError::Eof)
+    }
+
+    /// The first `length` values of the next column, which is as many as
+    /// its batch or parent refers to.
+    fn column(&mut self, field: &Field, length: usize) -> Result<Vec<Value>, ArrowError> {
+        let (nodes, nulls) = self.node()?;
+        if nodes < length {
+            return Err(ArrowError::Metadata);
+        }
+        if let (Kind::Null, None) = (&field.kind, &field.dictionary) {
+            return Ok(vec![Value::Null; length]);
+        }
+        let validity = self.buffer()?;
+        let mut values = match &field.dictionary {
+            Some((id, Kind::Int { bytes, signed })) => {
+                let dictionary = self.dictionaries.get(id).ok_or(ArrowError::Metadata)?;
+                let indices = self.buffer()?;
+                (0..length)
+                    .map(|i| {
+                        let index = integer(word(indices, i, *bytes)?, *signed);
+                        // Only valid entries need refer to the dictionary
+                        Ok(usize::try_from(index)
+                            .ok()
+                            .and_then(|i| dictionary.get(i))
+                            .cloned()
+                            .unwrap_or(Value::Null))
+                    })
+                    .collect::<Result<_, ArrowError>>()?
+            }
+            Some(_) => return Err(ArrowError::Metadata),
+            None => self.values(field, length)?,
+        };
+        if nulls > 0 {
+            for (i, v) in values.iter_mut().enumerate() {
+                if !bit(validity, i) {
+                    *v = Value::Null;
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// Every value of a column, whether or not it is null.
+    fn values(&mut self, field: &Field, length: usize) -> Result<Vec<Value>, ArrowError> {
+        let child = |i: usize| field.children.get(i).ok_or(ArrowError::Metadata);
+        let fixed = |data: &'a [u8], bytes: usize| (0..length).map(move |i| word(data, i, bytes));
+        Ok(match field.kind {
+            Kind::Null => vec![Value::Null; length],
+            Kind::Int { bytes, signed } => fixed(self.buffer()?, bytes)
+                .map(|w| {
+                    let n = integer(w?, signed);
+                    Ok(match (i64::try_from(n), u64::try_from(n)) {
+                        (Ok(n), _) => Value::from(n),
+                        (_, Ok(n)) => Value::from(n),
+                        _ => Value::Null,
+                    })
+                })
+                .collect::<Result<_, ArrowError>>()?,
+            Kind::Float(precision) => {
+                let bytes = match precision {
+                    0 => 2,
+                    1 => 4,
+                    _ => 8,
+                };
+                fixed(self.buffer()?, bytes)
+                    .map(|w| {
+                        let f = match w? {
+                            &[a, b] => half(u16::from_le_bytes([a, b])),
+                            &[a, b, c, d] => f64::from(f32::from_le_bytes([a, b, c, d])),
+                            w => f64::from_le_bytes(<[u8; 8]>::try_from(w).unwrap_or_default()),
+                        };
+                        Ok(Number::from_f64(f).map_or(Value::Null, Value::Number))
+                    })
+                    .collect::<Result<_, ArrowError>>()?
+            }
+            Kind::Bool => {
+                let data = self.buffer()?;
+                if data.len() * 8 < length {
+                    return Err(ArrowError::Eof);
+                }
+                (0..length).map(|i| Value::Bool(bit(data, i))).collect()
+            }
+            Kind::Binary { wide, text } => {
+                let offsets = offsets(self.buffer()?, length, wide)?;
+                let data = self.buffer()?;
+                offsets
+                    .windows(2)
+                    .map(|w| {
+                        let slice = data.get(w[0]..w[1]).ok_or(ArrowError::Eof)?;
+                        match text {
+                            true => std::str::from_utf8(slice)
+                                .map(Value::from)
+                                .map_err(|_| ArrowError::Utf8),
+                            false => Ok(bytes::to_value(slice)),
+                        }
+                    })
+                    .collect::<Result<_, ArrowError>>()?
+            }
+            Kind::FixedBinary(width) => fixed(self.buffer()?, width)
+                .map(|w| w.map(bytes::to_value))
+                .collect::<Result<_, ArrowError>>()?,
+            Kind::Decimal { bytes, scale } => fixed(self.buffer()?, bytes)
+                .map(|w| Ok(Value::from(decimal(integer(w?, true), scale))))
+                .collect::<Result<_, ArrowError>>()?,
+            Kind::Date { bytes } => fixed(self.buffer()?, bytes)
+                .map(|w| {
+                    let n = integer(w?, true) as i64;
+                    let days = if bytes == 4 {
+                        n
+                    } else {
+                        n.div_euclid(86_400_000)
+                    };
+                    Ok(Value::from(date(days)))
+                })
+                .collect::<Result<_, ArrowError>>()?,
+            Kind::Time { unit, bytes } => fixed(self.buffer()?, bytes)
+                .map(|w| {
+                    let (per_second, digits) = unit_scale(unit);
+                    let n = integer(w?, true) as i64;
+                    Ok(Value::from(clock(
+                        n.div_euclid(per_second),
+                        n.rem_euclid(per_second),
+                        digits,
+                    )))
+                })
+                .collect::<Result<_, ArrowError>>()?,
+            Kind::Timestamp { unit, zoned } => fixed(self.buffer()?, 8)
+                .map(|w| {
+                    let (per_second, digits) = unit_scale(unit);
+                    let n = integer(w?, true) as i64;
+                    let seconds = n.div_euclid(per_second);
+                    let time = clock(seconds.rem_euclid(86_400), n.rem_euclid(per_second), digits);
+                    let zone = if zoned { "Z" } else { "" };
+                    Ok(Value::from(format!(
+                        "{}T{}{}",
+                        date(seconds.div_euclid(86_400)),
+                        time,
+                        zone
+                    )))
+                })
+                .collect::<Result<_, ArrowError>>()?,
+            Kind::Duration => fixed(self.buffer()?, 8)
+                .map(|w| Ok(Value::from(integer(w?, true) as i64)))
+                .collect::<Result<_, ArrowError>>()?,
+            Kind::List { wide } => {
+                let offsets = offsets(self.buffer()?, length, wide)?;
+                let items = self.column(child(0)?, offsets[length])?;
+                offsets
+                    .windows(2)
+                    .map(|w| {
+                        let items = items.get(w[0]..w[1]).ok_or(ArrowError::Eof)?;
+                        Ok(Value::from(items.to_vec()))
+                    })
+                    .collect::<Result<_, ArrowError>>()?
+            }
+            Kind::FixedList(size) => {
+                let items = size.checked_mul(length).ok_or(ArrowError::Metadata)?;
+                let items = self.column(child(0)?, items)?;
+                if size == 0 {
+                    return Ok(vec![Value::Array(Vec::new()); length]);
+                }
+                items
+                    .chunks(size)
+                    .take(length)
+                    .map(|c| Value::from(c.to_vec()))
+                    .collect()
+            }
+            Kind::Struct => {
+                let mut columns = Vec::with_capacity(field.children.len());
+                for child in &field.children {
+                    columns.push(self.column(child, length)?.into_iter());
+                }
+                (0..length)
+                    .map(|_| {
+                        let row = field
+                            .children
+                            .iter()
+                            .zip(&mut columns)
+                            .map(|(f, c)| (f.name.clone(), c.next().unwrap_or(Value::Null)));
+                        Value::Object(row.collect::<Map<_, _>>())
+                    })
+                    .collect()
+            }
+            Kind::Map => {
+                let offsets = offsets(self.buffer()?, length, false)?;
+                let entries = child(0)?;
+                let (key, value) = match entries.children.as_slice() {
+                    [key, value] => (key.name.as_str(), value.name.as_str()),
+                    _ => return Err(ArrowError::Metadata),
+                };
+                let mut entries = self.column(entries, offsets[length])?;
+                offsets
+                    .windows(2)
+                    .map(|w| {
+                        let entries = entries.get_mut(w[0]..w[1]).ok_or(ArrowError::Eof)?;
+                        let object = entries.iter_mut().map(|entry| {
+                            let k = match entry[key].take() {
+                                Value::String(k) => k,
+                                k => k.to_string(),
+                            };
+                            (k, entry[value].take())
+                        });
+                        Ok(Value::Object(object.collect::<Map<_, _>>()))
+                    })
+                    .collect::<Result<_, ArrowError>>()?
+            }
+        })
+    }
+}
+
+fn bit(bitmap: &[u8], i: usize) -> bool {
+    bitmap.get(i / 8).is_some_and(|b| b >> (i % 8) & 1 == 1)
+}
+
+fn word(data: &[u8], i: usize, bytes: usize) -> Result<&[u8], ArrowError> {
+    data.get(i * bytes..(i + 1) * bytes).ok_or(ArrowError::Eof)
+}
+
+/// A little endian integer of up to 16 bytes.
+fn integer(bytes: &[u8], signed: bool) -> i128 {
+    let n = bytes
+        .iter()
+        .rev()
+        .fold(0i128, |n, &b| n << 8 | i128::from(b));
+    let bits = 8 * bytes.len() as u32;
+    match signed && bits < 128 && n >> (bits - 1) & 1 == 1 {
+        true => n - (1 << bits),
+        false => n,
+    }
+}
+
+/// The start of each of `length` values and the end of the last.
+fn offsets(buffer: &[u8], length: usize, wide: bool) -> Result<Vec<usize>, ArrowError> {
+    if length == 0 {
+        return Ok(vec![0]);
+    }
+    let bytes = if wide { 8 } else { 4 };
+    (0..=length)
+        .map(|i| {
+            usize::try_from(integer(word(buffer, i, bytes)?, true)).map_err(|_| ArrowError::Eof)
+        })
+        .collect()
+}
+
+/// How many of a time unit make a second, and the digits of a fraction of
+/// a second in it.
+fn unit_scale(unit: i16) -> (i64, usize) {
+    match unit {
+        0 => (1, 0),
+        1 => (1_000, 3),
+        2 => (1_000_000, 6),
+        _ => (1_000_000_000, 9),
+    }
+}
+
+fn date(days: i64) -> String {
+    let (year, month, day) = civil(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn clock(seconds: i64, fraction: i64, digits: usize) -> String {
+    let mut time = format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    if fraction != 0 {
+        time.push_str(&format!(".{:0width$}", fraction, width = digits));
+    }
+    time
+}
+
+fn decimal(n: i128, scale: i32) -> String {
+    let sign = if n < 0 { "-" } else { "" };
+    let digits = n.unsigned_abs().to_string();
+    match usize::try_from(scale) {
+        Ok(0) => format!("{}{}", sign, digits),
+        Ok(scale) => {
+            let digits = format!("{:0>width$}", digits, width = scale + 1);
+            let (whole, fraction) = digits.split_at(digits.len() - scale);
+            format!("{}{}.{}", sign, whole, fraction)
+        }
+        Err(_) if n == 0 => digits,
+        Err(_) => format!(
+            "{}{}{}",
+            sign,
+            digits,
+            "0".repeat(scale.unsigned_abs() as usize)
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // Written by tests/data/arrow/generate.py
+    const TYPES_STREAM: &[u8] = include_bytes!("../tests/data/arrow/types.arrows");
+    const TYPES_FILE: &[u8] = include_bytes!("../tests/data/arrow/types.arrow");
+    const DICTIONARIES: &[u8] = include_bytes!("../tests/data/arrow/dictionaries.arrows");
+
+    /// A flatbuffer item, written with offsets always pointing forwards.
+    enum F {
+        U8(u8),
+        I16(i16),
+        I32(i32),
+        I64(i64),
+        Str(&'static str),
+        Table(Vec<(usize, F)>),
+        Tables(Vec<F>),
+        Structs(Vec<u8>, usize),
+    }
+
+    fn flatbuffer(root: &F) -> Vec<u8> {
+        let mut buf = vec![0; 4];
+        let pos = write(&mut buf, root);
+        buf[..4].copy_from_slice(&(pos as u32).to_le_bytes());
+        buf
+    }
+
+    fn patch(buf: &mut [u8], at: usize, target: usize) {
+        buf[at..at + 4].copy_from_slice(&((target - at) as u32).to_le_bytes());
+    }
+
+    /// Writes a table, string or vector, returning where offsets to it point.
+    fn write(buf: &mut Vec<u8>, item: &F) -> usize {
+        let pos = buf.len();
+        match item {
+            F::Table(fields) => {
+                let slots = fields.iter().map(|(s, _)| s + 1).max().unwrap_or(0);
+                let mut offsets = vec![0u16; slots];
+                let mut data = vec![0u8; 4];
+                let mut references = Vec::new();
+                for (slot, f) in fields {
+                    offsets[*slot] = data.len() as u16;
+                    match f {
+                        F::U8(n) => data.push(*n),
+                        F::I16(n) => data.extend(n.to_le_bytes()),
+                        F::I32(n) => data.extend(n.to_le_bytes()),
+                        F::I64(n) => data.extend(n.to_le_bytes()),
+                        f => {
+                            references.push((data.len(), f));
+                            data.extend([0; 4]);
+                        }
+                    }
+                }
+                buf.extend((4 + 2 * slots as u16).to_le_bytes());
+                buf.extend((data.len() as u16).to_le_bytes());
+                offsets.iter().for_each(|o| buf.extend(o.to_le_bytes()));
+                let table = buf.len();
+                data[..4].copy_from_slice(&((table - pos) as i32).to_le_bytes());
+                buf.extend(data);
+                for (at, f) in references {
+                    let target = write(buf, f);
+                    patch(buf, table + at, target);
+                }
+                return table;
+            }
+            F::Str(s) => {
+                buf.extend((s.len() as u32).to_le_bytes());
+                buf.extend(s.as_bytes());
+                buf.push(0);
+            }
+            F::Tables(items) => {
+                buf.extend((items.len() as u32).to_le_bytes());
+                buf.extend(vec![0; 4 * items.len()]);
+                for (i, item) in items.iter().enumerate() {
+                    let target = write(buf, item);
+                    patch(buf, pos + 4 + 4 * i, target);
+                }
+            }
+            F::Structs(bytes, n) => {
+                buf.extend((*n as u32).to_le_bytes());
+                buf.extend(bytes);
+            }
+            _ => unreachable!(),
+        }
+        pos
+    }
+
+    fn message(kind: u8, header: F, body: &[u8]) -> Vec<u8> {
+        let metadata = flatbuffer(&F::Table(vec![
+            (0, F::I16(4)),
+            (1, F::U8(kind)),
+            (2, header),
+            (3, F::I64(body.len() as i64)),
+        ]));
+        let padded = metadata.len().div_ceil(8) * 8;
+        let mut message = vec![0xff; 4];
+        message.extend((padded as u32).to_le_bytes());
+        message.extend(&metadata);
+        message.resize(8 + padded, 0);
+        message.extend(body);
+        message
+    }
+
+    fn field(name: &'static str, kind: u8, options: Vec<(usize, F)>, children: Vec<F>) -> F {
+        F::Table(vec![
+            (0, F::Str(name)),
+            (1, F::U8(1)),
+            (2, F::U8(kind)),
+            (3, F::Table(options)),
+            (5, F::Tables(children)),
+        ])
+    }
+
+    /// A record batch, with the nodes and buffers of its columns.
+    #[derive(Default)]
+    struct Batch {
+        body: Vec<u8>,
+        nodes: Vec<u8>,
+        buffers: Vec<u8>,
+    }
+
+    impl Batch {
+        fn node(&mut self, length: i64, nulls: i64) -> &mut Self {
+            self.nodes.extend(length.to_le_bytes());
+            self.nodes.extend(nulls.to_le_bytes());
+            self
+        }
+
+        fn buffer(&mut self, data: &[u8]) -> &mut Self {
+            self.buffers.extend((self.body.len() as i64).to_le_bytes());
+            self.buffers.extend((data.len() as i64).to_le_bytes());
+            self.body.extend(data);
+            self.body.resize(self.body.len().div_ceil(8) * 8, 0);
+            self
+        }
+
+        fn message(self, length: i64) -> (F, Vec<u8>) {
+            let (nodes, buffers) = (self.nodes.len() / 16, self.buffers.len() / 16);
+            let header = F::Table(vec![
+                (0, F::I64(length)),
+                (1, F::Structs(self.nodes, nodes)),
+                (2, F::Structs(self.buffers, buffers)),
+            ]);
+            (header, self.body)
+        }
+    }
+
+    fn words<T: Copy, const N: usize>(values: &[T], f: fn(T) -> [u8; N]) -> Vec<u8> {
+        values.iter().flat_map(|&v| f(v)).collect()
+    }
+
+    fn stream() -> Vec<u8> {
+        let int = |bits, signed| vec![(0, F::I32(bits)), (1, F::U8(signed))];
+        let mut color = field("color", 5, vec![], vec![]);
+        if let F::Table(fields) = &mut color {
+            let indices = (1, F::Table(int(8, 1)));
+            fields.push((4, F::Table(vec![(0, F::I64(7)), indices])));
+        }
+        let fields = vec![
+            field("id", 2, int(64, 1), vec![]),
+            field("name", 5, vec![], vec![]),
+            field("ok", 6, vec![], vec![]),
+            field("score", 3, vec![(0, F::I16(2))], vec![]),
+            field("tags", 12, vec![], vec![field("item", 5, vec![], vec![])]),
+            field("point", 13, vec![], vec![field("x", 2, int(32, 1), vec![])]),
+            field("at", 10, vec![(0, F::I16(1)), (1, F::Str("UTC"))], vec![]),
+            field("day", 8, vec![(0, F::I16(0))], vec![]),
+            field("price", 7, vec![(0, F::I32(5)), (1, F::I32(2))], vec![]),
+            color,
+        ];
+        let mut bytes = message(1, F::Table(vec![(1, F::Tables(fields))]), &[]);
+
+        let mut dictionary = Batch::default();
+        dictionary
+            .node(2, 0)
+            .buffer(&[])
+            .buffer(&words(&[0i32, 4, 7], i32::to_le_bytes))
+            .buffer(b"bluered");
+        let (data, body) = dictionary.message(2);
+        bytes.extend(message(2, F::Table(vec![(0, F::I64(7)), (1, data)]), &body));
+
+        let mut batch = Batch::default();
+        batch
+            .node(3, 1)
+            .buffer(&[0b101])
+            .buffer(&words(&[1i64, 0, 3], i64::to_le_bytes))
+            .node(3, 0)
+            .buffer(&[])
+            .buffer(&words(&[0i32, 1, 3, 3], i32::to_le_bytes))
+            .buffer(b"abc")
+            .node(3, 0)
+            .buffer(&[])
+            .buffer(&[0b101])
+            .node(3, 0)
+            .buffer(&[])
+            .buffer(&words(&[1.5f64, 2.0, -0.25], f64::to_le_bytes))
+            .node(3, 0)
+            .buffer(&[])
+            .buffer(&words(&[0i32, 1, 1, 3], i32::to_le_bytes))
+            .node(3, 0)
+            .buffer(&[])
+            .buffer(&words(&[0i32, 1, 2, 3], i32::to_le_bytes))
+            .buffer(b"xyz")
+            .node(3, 1)
+            .buffer(&[0b011])
+            .node(3, 0)
+            .buffer(&[])
+            .buffer(&words(&[1i32, 2, 3], i32::to_le_bytes))
+            .node(3, 0)
+            .buffer(&[])
+            .buffer(&words(&[1_577_836_800_500i64, 0, -1], i64::to_le_bytes))
+            .node(3, 0)
+            .buffer(&[])
+            .buffer(&words(&[0i32, 18262, -1], i32::to_le_bytes))
+            .node(3, 0)
+            .buffer(&[])
+            .buffer(&words(&[12345i128, -5, 0], i128::to_le_bytes))
+            .node(3, 0)
+            .buffer(&[])
+            .buffer(&[1, 0, 1]);
+        let (header, body) = batch.message(3);
+        bytes.extend(message(3, header, &body));
+        bytes
+    }
+
+    #[test]
+    fn read_arrow() {
+        let rows = vec![
+            json!({
+                "id": 1, "name": "a", "ok": true, "score": 1.5, "tags": ["x"],
+                "point": {"x": 1}, "at": "2020-01-01T00:00:00.500Z", "day": "1970-01-01",
+                "price": "123.45", "color": "red"
+            }),
+            json!({
+                "id": null, "name": "bc", "ok": false, "score": 2.0, "tags": [],
+                "point": {"x": 2}, "at": "1970-01-01T00:00:00Z", "day": "2020-01-01",
+                "price": "-0.05", "color": "blue"
+            }),
+            json!({
+                "id": 3, "name": "", "ok": true, "score": -0.25, "tags": ["y", "z"],
+                "point": null, "at": "1969-12-31T23:59:59.999Z", "day": "1969-12-31",
+                "price": "0.00", "color": "red"
+            }),
+        ];
+        let mut stream = stream();
+        let file_length = stream.len();
+        stream.extend([0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+        assert_eq!(Ok(rows.clone()), from_slice(&stream));
+
+        // A file holds the stream between its magic numbers and before its footer
+        let mut file = b"ARROW1\0\0".to_vec();
+        file.extend(&stream[..file_length]);
+        file.extend([0; 8]);
+        file.extend(8u32.to_le_bytes());
+        file.extend(MAGIC);
+        assert_eq!(Ok(rows), from_slice(&file));
+
+        assert_eq!(Err(ArrowError::Eof), from_slice(&stream[..file_length - 1]));
+        assert_eq!(Err(ArrowError::Eof), from_slice(&file[..20]));
+        let compressed = message(
+            3,
+            F::Table(vec![(0, F::I64(0)), (3, F::Table(vec![(0, F::U8(0))]))]),
+            &[],
+        );
+        let mut bytes = stream[..file_length].to_vec();
+        bytes.extend(compressed);
+        assert_eq!(
+            Err(ArrowError::Unsupported("compressed batches".to_string())),
+            from_slice(&bytes)
+        );
+    }
+
+    fn types() -> Vec<Value> {
+        vec![
+            json!({
+                "null": null, "i8": -128, "u16": 65535, "i32": null,
+                "i64": -9223372036854775808i64, "u64": 18446744073709551615u64,
+                "half": 1.5, "single": 0.25, "double": 1e300, "flag": true,
+                "blob": [0, 1], "text": "héllo", "large_blob": [97, 98], "large_text": "x",
+                "fixed": [97, 98], "decimal": "123.45", "hundreds": "1500",
+                "day": "1970-01-01", "day_ms": "2020-01-01",
+                "time_s": "12:34:56", "time_ms": "12:34:56.001",
+                "time_us": "12:34:56.000001", "time_ns": "00:00:00.000000001",
+                "at_s": "2020-01-01T00:00:00", "at_ms": "2020-01-01T00:00:00.500Z",
+                "at_us": null, "at_ns": "1970-01-01T00:00:00.000000001", "duration": 1000,
+                "list": [1, null], "large_list": ["a"], "pair": [1, 2],
+                "point": {"a": 1, "b": "x"}, "map": {"k": 1},
+                "numbered": {"1": true, "2": false}
+            }),
+            json!({
+                "null": null, "i8": 127, "u16": 0, "i32": -1, "i64": 1, "u64": null,
+                "half": -2.0, "single": null, "double": -0.5, "flag": false,
+                "blob": [], "text": "", "large_blob": null, "large_text": "yz",
+                "fixed": [0, 0], "decimal": "-0.05", "hundreds": "0",
+                "day": "2020-01-01", "day_ms": "1969-12-31",
+                "time_s": null, "time_ms": "00:00:00", "time_us": null, "time_ns": "12:34:56",
+                "at_s": "1969-12-31T23:59:59", "at_ms": "1970-01-01T00:00:00Z",
+                "at_us": "1970-01-01T00:00:01.500000Z", "at_ns": null, "duration": -1,
+                "list": [], "large_list": null, "pair": null, "point": null, "map": {},
+                "numbered": null
+            }),
+            json!({
+                "null": null, "i8": null, "u16": 1, "i32": 2147483647, "i64": null, "u64": 0,
+                "half": null, "single": -1.0, "double": null, "flag": null,
+                "blob": null, "text": null, "large_blob": [], "large_text": null,
+                "fixed": null, "decimal": null, "hundreds": "-300",
+                "day": "1969-12-31", "day_ms": null,
+                "time_s": "00:00:00", "time_ms": null, "time_us": "00:00:00", "time_ns": null,
+                "at_s": null, "at_ms": "1969-12-31T23:59:59.999Z",
+                "at_us": "1970-01-01T00:00:00Z", "at_ns": "1969-12-31T23:59:59.999999999",
+                "duration": null, "list": null, "large_list": [], "pair": [3, null],
+                "point": {"a": null, "b": "y"}, "map": null, "numbered": {}
+            }),
+            json!({
+                "null": null, "i8": 0, "u16": null, "i32": 5, "i64": 0, "u64": 42,
+                "half": 65504.0, "single": 3.5, "double": 2.0, "flag": true,
+                "blob": [255], "text": "🦀", "large_blob": [99], "large_text": "",
+                "fixed": [122, 122], "decimal": "0.00", "hundreds": null,
+                "day": null, "day_ms": "1970-01-01",
+                "time_s": "23:59:59", "time_ms": "23:59:59.999",
+                "time_us": "00:00:00.000001", "time_ns": "00:00:00",
+                "at_s": "1970-01-01T00:00:00", "at_ms": null,
+                "at_us": "1969-12-31T23:59:59.999999Z", "at_ns": "2020-01-01T00:00:00.000000001",
+                "duration": 0, "list": [3], "large_list": ["b", "c"], "pair": [0, 0],
+                "point": {"a": 4, "b": null}, "map": {"a": 2, "b": null}, "numbered": {}
+            }),
+        ]
+    }
+
+    #[test]
+    fn read_fixtures() {
+        assert_eq!(Ok(types()), from_slice(TYPES_STREAM));
+        assert_eq!(Ok(types()), from_slice(TYPES_FILE));
+        assert_eq!(
+            Ok(vec![
+                json!({"color": "blue", "tags": ["x", "y"]}),
+                json!({"color": "red", "tags": []}),
+                json!({"color": null, "tags": null}),
+                json!({"color": "green", "tags": ["y"]}),
+                json!({"color": "cyan", "tags": ["y", "y"]}),
+                json!({"color": null, "tags": ["x"]}),
+            ]),
+            from_slice(DICTIONARIES)
+        );
+    }
+
+    #[test]
+    fn truncated_fixtures() {
+        for fixture in [TYPES_STREAM, TYPES_FILE, DICTIONARIES] {
+            let rows = from_slice(fixture).unwrap();
+            for end in 0..fixture.len() {
+                // Only the rows of whole batches, if it ends between messages
+                match from_slice(&fixture[..end]) {
+                    Ok(read) => assert!(rows.starts_with(&read), "at {}", end),
+                    Err(ArrowError::Eof | ArrowError::Metadata) => {}
+                    Err(e) => panic!("{} at {}", e, end),
+                }
+            }
+        }
+        // A file without its footer is nothing
+        assert_eq!(
+            Err(ArrowError::Eof),
+            from_slice(&TYPES_FILE[..TYPES_FILE.len() - 1])
+        );
+    }
+
+    #[test]
+    fn corrupt_fixtures() {
+        // Any byte may be wrong, but none may be trusted to be in bounds
+        for fixture in [TYPES_STREAM, DICTIONARIES] {
+            for i in 0..fixture.len() {
+                for flip in [0x10, 0xff] {
+                    let mut corrupt = fixture.to_vec();
+                    corrupt[i] ^= flip;
+                    let _ = from_slice(&corrupt);
+                }
+            }
+        }
+
+        let schema = message(
+            1,
+            F::Table(vec![(1, F::Tables(vec![field("s", 5, vec![], vec![])]))]),
+            &[],
+        );
+        let text = |length, offsets: &[i32]| {
+            let mut batch = Batch::default();
+            batch
+                .node(length, 0)
+                .buffer(&[])
+                .buffer(&words(offsets, i32::to_le_bytes))
+                .buffer(b"abc");
+            let (header, body) = batch.message(2);
+            let mut bytes = schema.clone();
+            bytes.extend(message(3, header, &body));
+            bytes
+        };
+        assert_eq!(
+            Ok(vec![json!({"s": "ab"}), json!({"s": "c"})]),
+            from_slice(&text(2, &[0, 2, 3]))
+        );
+        // Offsets which go backwards, or past the data
+        assert_eq!(Err(ArrowError::Eof), from_slice(&text(2, &[2, 0, 3])));
+        assert_eq!(Err(ArrowError::Eof), from_slice(&text(2, &[0, 2, 9])));
+        assert_eq!(Err(ArrowError::Eof), from_slice(&text(2, &[0, 2])));
+        // A column shorter than its batch
+        assert_eq!(Err(ArrowError::Metadata), from_slice(&text(1, &[0, 2, 3])));
+    }
+
+    #[test]
+    fn arrow_values() {
+        assert_eq!(-1, integer(&[0xff], true));
+        assert_eq!(255, integer(&[0xff], false));
+        assert_eq!(-2, integer(&(-2i128).to_le_bytes(), true));
+        assert_eq!("1.05", decimal(105, 2));
+        assert_eq!("-105", decimal(-105, 0));
+        assert_eq!("1500", decimal(15, -2));
+        assert_eq!("0", decimal(0, -2));
+        assert_eq!("12:34:56.000001", clock(45296, 1, 6));
+    }
+}
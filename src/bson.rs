@@ -0,0 +1,320 @@
+use serde_json::{json, Map, Number, Value};
+use std::convert::TryFrom;
+use thiserror::Error;
+
+use crate::format::base64_encode;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum BsonError {
+    #[error("Unexpected end of BSON input")]
+    Eof,
+    #[error("BSON document is {0} bytes long but says it is {1}")]
+    Length(usize, usize),
+    #[error("BSON text is not UTF-8")]
+    Utf8,
+    #[error("Cannot read BSON element type 0x{0:02x}")]
+    Type(u8),
+}
+
+/// Reads each of the BSON documents one after another in `bytes`, as
+/// `mongodump` writes them.
+///
+/// Values which JSON has no type for are written as MongoDB's relaxed
+/// extended JSON writes them, such as `{"$oid": "..."}` for an object id.
+pub fn from_slice(mut bytes: &[u8]) -> Result<Vec<Value>, BsonError> {
+    let mut documents = Vec::new();
+    while !bytes.is_empty() {
+        documents.push(Value::Object(document(&mut bytes)?));
+    }
+    Ok(documents)
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], BsonError> {
+    if bytes.len() < n {
+        return Err(BsonError::Eof);
+    }
+    let (taken, rest) = bytes.split_at(n);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn array<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], BsonError> {
+    Ok(<[u8; N]>::try_from(take(bytes, N)?).unwrap_or([0; N]))
+}
+
+fn int32(bytes: &mut &[u8]) -> Result<i32, BsonError> {
+    Ok(i32::from_le_bytes(array(bytes)?))
+}
+
+fn int64(bytes: &mut &[u8]) -> Result<i64, BsonError> {
+    Ok(i64::from_le_bytes(array(bytes)?))
+}
+
+fn length(bytes: &mut &[u8]) -> Result<usize, BsonError> {
+    usize::try_from(int32(bytes)?).map_err(|_| BsonError::Eof)
+}
+
+/// A string ending with a nul byte.
+fn cstring(bytes: &mut &[u8]) -> Result<String, BsonError> {
+    let end = bytes.iter().position(|&b| b == 0).ok_or(BsonError::Eof)?;
+    let s = take(bytes, end + 1)?;
+    String::from_utf8(s[..end].to_vec()).map_err(|_| BsonError::Utf8)
+}
+
+/// A string preceded by its length and ending with a nul byte.
+fn string(bytes: &mut &[u8]) -> Result<String, BsonError> {
+    let n = length(bytes)?;
+    match take(bytes, n)? {
+        [s @ .., 0] => String::from_utf8(s.to_vec()).map_err(|_| BsonError::Utf8),
+        _ => Err(BsonError::Eof),
+    }
+}
+
+fn document(bytes: &mut &[u8]) -> Result<Map<String, Value>, BsonError> {
+    let n = length(bytes)?;
+    if n < 5 || n > bytes.len() + 4 {
+        return Err(BsonError::Length(bytes.len() + 4, n));
+    }
+    let mut elements = take(bytes, n - 4)?;
+    let mut document = Map::new();
+    loop {
+        match take(&mut elements, 1)?[0] {
+            0 if elements.is_empty() => return Ok(document),
+            0 => return Err(BsonError::Length(n - elements.len(), n)),
+            kind => {
+                let name = cstring(&mut elements)?;
+                document.insert(name, element(&mut elements, kind)?);
+            }
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn element(bytes: &mut &[u8], kind: u8) -> Result<Value, BsonError> {
+    Ok(match kind {
+        0x01 => {
+            let f = f64::from_le_bytes(array(bytes)?);
+            match Number::from_f64(f) {
+                Some(n) => Value::Number(n),
+                None => json!({"$numberDouble": f.to_string()}),
+            }
+        }
+        0x02 | 0x0e => Value::from(string(bytes)?),
+        0x03 => Value::Object(document(bytes)?),
+        0x04 => Value::Array(document(bytes)?.into_iter().map(|(_, v)| v).collect()),
+        0x05 => {
+            let n = length(bytes)?;
+            let subtype = take(bytes, 1)?[0];
+            let data = take(bytes, n)?;
+            json!({"$binary": {"base64": base64_encode(data), "subType": hex(&[subtype])}})
+        }
+        0x06 => Value::Null,
+        0x07 => json!({"$oid": hex(take(bytes, 12)?)}),
+        0x08 => Value::Bool(take(bytes, 1)?[0] != 0),
+        0x09 => {
+            let millis = int64(bytes)?;
+            match iso_date(millis) {
+                Some(date) => json!({"$date": date}),
+                None => json!({"$date": {"$numberLong": millis.to_string()}}),
+            }
+        }
+        0x0a => Value::Null,
+        0x0b => {
+            let pattern = cstring(bytes)?;
+            let options = cstring(bytes)?;
+            json!({"$regularExpression": {"pattern": pattern, "options": options}})
+        }
+        0x0d => json!({"$code": string(bytes)?}),
+        0x10 => Value::from(int32(bytes)?),
+        0x11 => {
+            let increment = u32::from_le_bytes(array(bytes)?);
+            let time = u32::from_le_bytes(array(bytes)?);
+            json!({"$timestamp": {"t": time, "i": increment}})
+        }
+        0x12 => Value::from(int64(bytes)?),
+        0x13 => json!({"$numberDecimal": decimal(u128::from_le_bytes(array(bytes)?))}),
+        0x7f => json!({"$maxKey": 1}),
+        0xff => json!({"$minKey": 1}),
+        kind => return Err(BsonError::Type(kind)),
+    })
+}
+
+/// The largest significand of a decimal128, which has 34 digits.
+const MAX_SIGNIFICAND: u128 = 10u128.pow(34) - 1;
+
+/// A decimal128 as the text extended JSON writes it as, in scientific
+/// notation only where it is very large or small.
+fn decimal(bits: u128) -> String {
+    let sign = if bits >> 127 == 1 { "-" } else { "" };
+    let (exponent, significand) = match bits >> 122 & 0x1f {
+        0x1e => return format!("{}Infinity", sign),
+        0x1f => return "NaN".to_string(),
+        // Beyond the largest significand, which is read as zero
+        c if c >> 3 == 0b11 => (bits >> 111 & 0x3fff, 0),
+        _ => (bits >> 113 & 0x3fff, bits & ((1 << 113) - 1)),
+    };
+    let digits = match significand {
+        s if s > MAX_SIGNIFICAND => "0".to_string(),
+        s => s.to_string(),
+    };
+    let exponent = exponent as i64 - 6176;
+    let adjusted = exponent + digits.len() as i64 - 1;
+    let text = if exponent > 0 || adjusted < -6 {
+        let (first, rest) = digits.split_at(1);
+        let point = if rest.is_empty() { "" } else { "." };
+        format!("{}{}{}E{:+}", first, point, rest, adjusted)
+    } else {
+        match digits.len() as i64 + exponent {
+            _ if exponent == 0 => digits,
+            point if point > 0 => {
+                let (whole, fraction) = digits.split_at(point as usize);
+                format!("{}.{}", whole, fraction)
+            }
+            point => format!("0.{}{}", "0".repeat(-point as usize), digits),
+        }
+    };
+    format!("{}{}", sign, text)
+}
+
+/// The time as ISO 8601 text, for years from 1970 to 9999 as relaxed
+/// extended JSON writes them.
+fn iso_date(millis: i64) -> Option<String> {
+    if !(0..253_402_300_800_000).contains(&millis) {
+        return None;
+    }
+    let (days, time) = (millis / 86_400_000, millis % 86_400_000);
+    let (year, month, day) = civil(days);
+    let (seconds, ms) = (time / 1000, time % 1000);
+    let mut date = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    if ms != 0 {
+        date.push_str(&format!(".{:03}", ms));
+    }
+    date.push('Z');
+    Some(date)
+}
+
+/// The year, month and day a number of days since 1970 falls on.
+pub(crate) fn civil(days: i64) -> (i64, i64, i64) {
+    // Counting in eras of 400 years from March 0000
+    let z = days + 719_468;
+    let (era, day_of_era) = (z.div_euclid(146_097), z.rem_euclid(146_097));
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let m = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * m + 2) / 5 + 1;
+    let month = if m < 10 { m + 3 } else { m - 9 };
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A document of the given elements, each its type, name and value.
+    fn bson(elements: &[(u8, &str, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (kind, name, value) in elements {
+            body.push(*kind);
+            body.extend(name.as_bytes());
+            body.push(0);
+            body.extend(*value);
+        }
+        body.push(0);
+        let mut document = ((body.len() + 4) as i32).to_le_bytes().to_vec();
+        document.extend(body);
+        document
+    }
+
+    #[test]
+    fn read_bson() {
+        let array = bson(&[(0x10, "0", &1i32.to_le_bytes()), (0x0a, "1", &[])]);
+        let nested = bson(&[(0x08, "ok", &[1])]);
+        let mut bytes = bson(&[
+            (0x07, "_id", &[0xab; 12]),
+            (0x02, "name", b"\x03\0\0\0rq\0"),
+            (0x01, "score", &1.5f64.to_le_bytes()),
+            (0x12, "big", &(1i64 << 40).to_le_bytes()),
+            (0x04, "list", &array),
+            (0x03, "nested", &nested),
+            (0x09, "at", &1_577_836_800_500i64.to_le_bytes()),
+            (0x05, "data", b"\x02\0\0\0\0hi"),
+        ]);
+        bytes.extend(bson(&[(0x09, "at", &(-1i64).to_le_bytes())]));
+        assert_eq!(
+            Ok(vec![
+                json!({
+                    "_id": {"$oid": "abababababababababababab"},
+                    "name": "rq",
+                    "score": 1.5,
+                    "big": 1099511627776i64,
+                    "list": [1, null],
+                    "nested": {"ok": true},
+                    "at": {"$date": "2020-01-01T00:00:00.500Z"},
+                    "data": {"$binary": {"base64": "aGk=", "subType": "00"}}
+                }),
+                json!({"at": {"$date": {"$numberLong": "-1"}}})
+            ]),
+            from_slice(&bytes)
+        );
+        assert_eq!(Some("1970-01-01T00:00:00Z".to_string()), iso_date(0));
+        assert_eq!(
+            Some("2000-02-29T23:59:59Z".to_string()),
+            iso_date(951_868_799_000)
+        );
+
+        assert_eq!(Err(BsonError::Eof), from_slice(&bytes[..3]));
+        assert!(matches!(
+            from_slice(&bytes[..10]),
+            Err(BsonError::Length(..))
+        ));
+        assert_eq!(
+            Err(BsonError::Type(0x0c)),
+            from_slice(&bson(&[(0x0c, "p", &[0; 16])]))
+        );
+    }
+
+    #[test]
+    fn decimals() {
+        let d = |high: u64, low: u64| decimal(u128::from(high) << 64 | u128::from(low));
+        assert_eq!("0", d(0x3040_0000_0000_0000, 0));
+        assert_eq!("-0", d(0xb040_0000_0000_0000, 0));
+        assert_eq!("-1", d(0xb040_0000_0000_0000, 1));
+        assert_eq!("0.1", d(0x303e_0000_0000_0000, 1));
+        assert_eq!("12.34", d(0x303c_0000_0000_0000, 1234));
+        assert_eq!("0.001234", d(0x3034_0000_0000_0000, 1234));
+        assert_eq!("1.234E-7", d(0x302c_0000_0000_0000, 1234));
+        assert_eq!("1E+3", d(0x3046_0000_0000_0000, 1));
+        assert_eq!("0E+3", d(0x3046_0000_0000_0000, 0));
+        assert_eq!(
+            "9.999999999999999999999999999999999E+6144",
+            d(0x5fff_ed09_bead_87c0, 0x378d_8e63_ffff_ffff)
+        );
+        assert_eq!("Infinity", d(0x7800_0000_0000_0000, 0));
+        assert_eq!("-Infinity", d(0xf800_0000_0000_0000, 0));
+        assert_eq!("NaN", d(0x7c00_0000_0000_0000, 0));
+        // A significand too large for 34 digits
+        assert_eq!("0", d(0x3041_ed09_bead_87c0, 0x378d_8e64_0000_0000));
+        assert_eq!("0E+2", d(0x6c11_0000_0000_0000, 0));
+
+        let value = 1u128 << 64 | 1234;
+        let bytes = bson(&[(0x13, "d", &(value | 0x303c << 112).to_le_bytes())]);
+        assert_eq!(
+            Ok(vec![
+                json!({"d": {"$numberDecimal": "184467440737095528.50"}})
+            ]),
+            from_slice(&bytes)
+        );
+    }
+}
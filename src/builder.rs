@@ -0,0 +1,123 @@
+use serde_json::Value;
+
+use crate::{
+    combinator::{Chain, Optional, Split},
+    function::Call,
+    index::Index,
+    query::Query,
+    range::Range,
+    raw::Raw,
+};
+
+/// Constructors for building a query in code, where names and values are
+/// used as given rather than being parsed as query text.
+impl Query {
+    /// `.`
+    pub fn identity() -> Self {
+        Query::Identity
+    }
+
+    /// `.["name"]`
+    pub fn field(name: &str) -> Self {
+        Query::Index(Index::String(name.to_string()))
+    }
+
+    /// `.[]`
+    pub fn iter() -> Self {
+        Query::Iterator
+    }
+
+    /// A literal value.
+    pub fn literal<V: Into<Value>>(value: V) -> Self {
+        Query::Raw(Raw(value.into()))
+    }
+
+    /// `name(a; b)`
+    pub fn call(name: &str, args: Vec<Query>) -> Self {
+        Query::Call(Call::new(name, args))
+    }
+
+    /// `self | next`
+    pub fn pipe(self, next: Query) -> Self {
+        Query::Chain(Box::new(Chain(self, next)))
+    }
+
+    /// `self.["name"]`
+    pub fn key(self, name: &str) -> Self {
+        self.pipe(Query::field(name))
+    }
+
+    /// `self.[i]`
+    pub fn index(self, i: i32) -> Self {
+        self.pipe(Query::Index(Index::Integer(i)))
+    }
+
+    /// `self.[from:to]`
+    pub fn slice(self, range: Range) -> Self {
+        self.pipe(Query::Index(Index::Slice(range)))
+    }
+
+    /// `self.[]`
+    pub fn each(self) -> Self {
+        self.pipe(Query::Iterator)
+    }
+
+    /// `self, other`
+    pub fn comma(self, other: Query) -> Self {
+        Query::Split(Box::new(Split(self, other)))
+    }
+
+    /// `self?`
+    pub fn optional(self) -> Self {
+        Query::Optional(Box::new(Optional(self)))
+    }
+
+    /// `self | ..`
+    pub fn recurse(self) -> Self {
+        self.pipe(Query::Recurse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::Executable;
+
+    use super::*;
+
+    #[test]
+    fn build() {
+        let v: Value = serde_json::from_str(r#"{"foo": [[1, 2], 3], "a.b | .c": 4}"#).unwrap();
+
+        let q = Query::field("foo").index(0).pipe(Query::iter());
+        let p: Query = ".foo[0] | .[]".parse().unwrap();
+        assert_eq!(p.execute(&v).unwrap(), q.execute(&v).unwrap());
+
+        let q = Query::identity().key("foo").slice(Range::upper(1)).each();
+        assert_eq!(r#"[1,2]"#, q.execute(&v).unwrap()[0].to_string());
+
+        // Names are never interpreted as query text
+        let q = Query::field("a.b | .c");
+        assert_eq!(r#"4"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q = Query::literal("x").pipe(Query::call("f", vec![]));
+        assert!(q.execute(&v).is_err());
+    }
+
+    #[test]
+    fn compose() {
+        let v: Value = serde_json::from_str(r#"{"a": [1, {"b": 2}]}"#).unwrap();
+        let a: Query = ".a[]".parse().unwrap();
+        let b: Query = ".b".parse().unwrap();
+
+        let q = a.clone().pipe(b.clone()).optional();
+        let p: Query = "(.a[] | .b)?".parse().unwrap();
+        assert_eq!(p, q);
+        assert!(q.execute(&v).unwrap().is_empty());
+
+        let q = a.comma(b).recurse();
+        assert_eq!(
+            r#"[1,{"b":2},2,null]"#,
+            Value::Array(q.execute(&v).unwrap()).to_string()
+        );
+    }
+}
@@ -0,0 +1,535 @@
+use serde_json::Value;
+use std::{borrow::Cow, iter, ops::Range};
+
+use crate::{
+    bytes,
+    csv::{self, CsvOptions},
+    describe,
+    env::Env,
+    format::{base64_decode, base64_encode, Format},
+    function::Call,
+    index::substring,
+    order::compare,
+    patch, pointer,
+    query::Eval,
+    single,
+    stream::{self, Rebuild},
+    update, QueryError, QueryIter, QueryResult, SharedResult,
+};
+
+/// A function provided by the engine itself, which may use the arguments and
+/// execution of the call directly.
+pub(crate) type Core = for<'a> fn(&'a Call, &Env<'a>, &Value) -> QueryResult;
+
+/// Finds the core function for `name/arity`, which any definition or
+/// registered builtin of the same name shadows.
+pub(crate) fn lookup(name: &str, arity: usize) -> Option<Core> {
+    match (name, arity) {
+        ("input", 0) => Some(input),
+        ("inputs", 0) => Some(inputs),
+        ("range", 1) | ("range", 2) => Some(range),
+        ("first", 1) => Some(first),
+        ("limit", 2) => Some(limit),
+        ("sort", 0) | ("sort_by", 1) => Some(sort),
+        ("min", 0) | ("min_by", 1) => Some(min),
+        ("max", 0) | ("max_by", 1) => Some(max),
+        ("unique", 0) | ("unique_by", 1) => Some(unique),
+        ("length", 0) => Some(length),
+        ("getpath", 1) => Some(getpath),
+        ("setpath", 2) => Some(setpath),
+        ("mergepatch", 1) => Some(mergepatch),
+        ("halt", 0) => Some(halt),
+        ("halt_error", 0) | ("halt_error", 1) => Some(halt_error),
+        ("tostream", 0) => Some(tostream),
+        ("fromstream", 1) => Some(fromstream),
+        ("fromcsv", 0) => Some(fromcsv),
+        ("fromtsv", 0) => Some(fromtsv),
+        ("tocsv", 0) => Some(tocsv),
+        ("totsv", 0) => Some(totsv),
+        ("tobytes", 0) => Some(tobytes),
+        ("frombytes", 0) => Some(frombytes),
+        ("tobase64", 0) => Some(tobase64),
+        ("frombase64", 0) => Some(frombase64),
+        ("tohex", 0) => Some(tohex),
+        ("fromhex", 0) => Some(fromhex),
+        ("ltrimstr", 1) | ("rtrimstr", 1) | ("ascii_downcase", 0) | ("ascii_upcase", 0) => {
+            Some(borrowing)
+        }
+        _ => None,
+    }
+}
+
+/// The core functions which jq doesn't have, as `name/arity`.
+pub(crate) const EXTENSIONS: &[(&str, usize)] = &[
+    ("mergepatch", 1),
+    ("fromcsv", 0),
+    ("fromtsv", 0),
+    ("tocsv", 0),
+    ("totsv", 0),
+    ("tobytes", 0),
+    ("frombytes", 0),
+    ("tobase64", 0),
+    ("frombase64", 0),
+    ("tohex", 0),
+    ("fromhex", 0),
+];
+
+/// A core function which produces its outputs only as they are consumed,
+/// used in place of the eager one of the same name when results are streamed.
+pub(crate) type Generator = for<'a> fn(&'a Call, &Env<'a>, Cow<'a, Value>) -> QueryIter<'a>;
+
+pub(crate) fn generator(name: &str, arity: usize) -> Option<Generator> {
+    match (name, arity) {
+        ("inputs", 0) => Some(inputs_iter),
+        ("range", 1) | ("range", 2) => Some(range_iter),
+        ("first", 1) => Some(first_iter),
+        ("limit", 2) => Some(limit_iter),
+        _ => None,
+    }
+}
+
+/// A core function which may output its input as it is, or changed in place,
+/// so takes it borrowed or owned to avoid copying it either way.
+pub(crate) type Shared = for<'a, 'v> fn(&'a Call, &Env<'a>, Cow<'v, Value>) -> SharedResult<'v>;
+
+pub(crate) fn shared(name: &str, arity: usize) -> Option<Shared> {
+    match (name, arity) {
+        ("ltrimstr", 1) => Some(ltrimstr),
+        ("rtrimstr", 1) => Some(rtrimstr),
+        ("ascii_downcase", 0) => Some(ascii_downcase),
+        ("ascii_upcase", 0) => Some(ascii_upcase),
+        _ => None,
+    }
+}
+
+/// Evaluates a shared core function against a borrowed input.
+fn borrowing<'a>(call: &'a Call, env: &Env<'a>, value: &Value) -> QueryResult {
+    match shared(&call.name, call.args.len()) {
+        Some(f) => Ok(f(call, env, Cow::Borrowed(value))?
+            .into_iter()
+            .map(Cow::into_owned)
+            .collect()),
+        None => Err(QueryError::Undefined(call.name.clone(), call.args.len())),
+    }
+}
+
+fn input<'a>(_: &'a Call, env: &Env<'a>, _: &Value) -> QueryResult {
+    env.input().map_or(Err(QueryError::NoMoreInputs), single)
+}
+
+fn inputs<'a>(_: &'a Call, env: &Env<'a>, _: &Value) -> QueryResult {
+    Ok(iter::from_fn(|| env.input()).collect())
+}
+
+/// Takes each input document only once the one before it was consumed.
+fn inputs_iter<'a>(_: &'a Call, env: &Env<'a>, _: Cow<'a, Value>) -> QueryIter<'a> {
+    let env = env.clone();
+    Box::new(iter::from_fn(move || env.input()).map(Ok))
+}
+
+fn range<'a>(call: &'a Call, env: &Env<'a>, value: &Value) -> QueryResult {
+    call.apply(env, value, |args, _| Ok(numbers(args)?.collect()))
+}
+
+fn range_iter<'a>(call: &'a Call, env: &Env<'a>, value: Cow<'a, Value>) -> QueryIter<'a> {
+    match call.combinations(env, &value) {
+        Ok(combinations) => {
+            Box::new(
+                combinations
+                    .into_iter()
+                    .flat_map(|args| match numbers(&args) {
+                        Ok(numbers) => Box::new(numbers.map(Ok)),
+                        Err(e) => Box::new(iter::once(Err(e))) as QueryIter,
+                    }),
+            )
+        }
+        Err(e) => Box::new(iter::once(Err(e))),
+    }
+}
+
+/// The numbers from the first bound, or zero, up to but excluding the last.
+fn numbers(args: &[Value]) -> Result<impl Iterator<Item = Value>, QueryError> {
+    let bounds: Option<Vec<f64>> = args.iter().map(Value::as_f64).collect();
+    let (from, upto) = match bounds.as_deref() {
+        Some([upto]) => (0.0, *upto),
+        Some([from, upto]) => (*from, *upto),
+        _ => return Err(QueryError::Numerical),
+    };
+    Ok(iter::successors(Some(from), |n| Some(n + 1.0))
+        .take_while(move |n| *n < upto)
+        .map(number))
+}
+
+/// The first output of the argument, which stops it producing any more.
+fn first<'a>(call: &'a Call, env: &Env<'a>, value: &Value) -> QueryResult {
+    first_iter(call, env, Cow::Owned(value.clone())).collect()
+}
+
+fn first_iter<'a>(call: &'a Call, env: &Env<'a>, value: Cow<'a, Value>) -> QueryIter<'a> {
+    Box::new(call.args[0].eval_iter(env, value).take(1))
+}
+
+/// At most `n` outputs of the second argument for each output `n` of the
+/// first, or all of them if `n` is negative.
+fn limit<'a>(call: &'a Call, env: &Env<'a>, value: &Value) -> QueryResult {
+    limit_iter(call, env, Cow::Owned(value.clone())).collect()
+}
+
+fn limit_iter<'a>(call: &'a Call, env: &Env<'a>, value: Cow<'a, Value>) -> QueryIter<'a> {
+    let counts = match call.args[0].eval(env, &value) {
+        Ok(counts) => counts,
+        Err(e) => return Box::new(iter::once(Err(e))),
+    };
+    let env = env.clone();
+    Box::new(counts.into_iter().flat_map(move |n| {
+        let outputs = call.args[1].eval_iter(&env, value.clone());
+        match n.as_f64() {
+            Some(n) if n < 0.0 => outputs,
+            Some(n) => Box::new(outputs.take(n.ceil() as usize)),
+            None => Box::new(iter::once(Err(QueryError::Numerical))),
+        }
+    }))
+}
+
+/// The elements of an array along with what each is ordered by: itself, or
+/// all the outputs of the argument for it, as jq orders by `[f]`.
+fn keyed<'a, 'v>(
+    call: &'a Call,
+    env: &Env<'a>,
+    value: &'v Value,
+    name: &'static str,
+) -> Result<Vec<(Cow<'v, Value>, &'v Value)>, QueryError> {
+    let elements = match value {
+        Value::Array(elements) => elements,
+        v => return Err(QueryError::Apply(name, describe(v))),
+    };
+    elements
+        .iter()
+        .map(|v| match call.args.first() {
+            Some(f) => Ok((Cow::Owned(Value::Array(f.eval(env, v)?)), v)),
+            None => Ok((Cow::Borrowed(v), v)),
+        })
+        .collect()
+}
+
+/// The elements of an array in order, keeping the order of equal ones.
+fn sort<'a>(call: &'a Call, env: &Env<'a>, value: &Value) -> QueryResult {
+    let name = if call.args.is_empty() {
+        "sort"
+    } else {
+        "sort_by"
+    };
+    let mut keyed = keyed(call, env, value, name)?;
+    keyed.sort_by(|a, b| compare(&a.0, &b.0));
+    single(keyed.into_iter().map(|(_, v)| v.clone()).collect())
+}
+
+/// The first of the least elements of an array, or null if it is empty.
+fn min<'a>(call: &'a Call, env: &Env<'a>, value: &Value) -> QueryResult {
+    let name = if call.args.is_empty() {
+        "min"
+    } else {
+        "min_by"
+    };
+    let least = keyed(call, env, value, name)?.into_iter().reduce(|m, x| {
+        if compare(&x.0, &m.0).is_lt() {
+            x
+        } else {
+            m
+        }
+    });
+    single(least.map_or(Value::Null, |(_, v)| v.clone()))
+}
+
+/// The last of the greatest elements of an array, or null if it is empty.
+fn max<'a>(call: &'a Call, env: &Env<'a>, value: &Value) -> QueryResult {
+    let name = if call.args.is_empty() {
+        "max"
+    } else {
+        "max_by"
+    };
+    let greatest = keyed(call, env, value, name)?.into_iter().reduce(|m, x| {
+        if compare(&x.0, &m.0).is_ge() {
+            x
+        } else {
+            m
+        }
+    });
+    single(greatest.map_or(Value::Null, |(_, v)| v.clone()))
+}
+
+/// The first of each set of equal elements of an array, in order.
+fn unique<'a>(call: &'a Call, env: &Env<'a>, value: &Value) -> QueryResult {
+    let name = if call.args.is_empty() {
+        "unique"
+    } else {
+        "unique_by"
+    };
+    let mut keyed = keyed(call, env, value, name)?;
+    keyed.sort_by(|a, b| compare(&a.0, &b.0));
+    keyed.dedup_by(|a, b| compare(&a.0, &b.0).is_eq());
+    single(keyed.into_iter().map(|(_, v)| v.clone()).collect())
+}
+
+/// The number of codepoints of a string, elements of an array or keys of an
+/// object, the absolute value of a number, or zero for null.
+fn length<'a>(_: &'a Call, _: &Env<'a>, value: &Value) -> QueryResult {
+    single(match value {
+        Value::Null => Value::from(0),
+        Value::Number(n) => match (n.as_u64(), n.as_i64(), n.as_f64()) {
+            (Some(_), _, _) => value.clone(),
+            (_, Some(i), _) => Value::from(i.unsigned_abs()),
+            (_, _, Some(f)) => number(f.abs()),
+            _ => value.clone(),
+        },
+        Value::String(s) => Value::from(s.chars().count()),
+        Value::Array(arr) => Value::from(arr.len()),
+        Value::Object(map) => Value::from(map.len()),
+        Value::Bool(_) => return Err(QueryError::Apply("length", describe(value))),
+    })
+}
+
+/// The value at a path, or a JSON Pointer string, or null if there is none.
+fn getpath<'a>(call: &'a Call, env: &Env<'a>, value: &Value) -> QueryResult {
+    call.apply(env, value, |args, value| {
+        let path = path(&args[0], value)?;
+        single(update::get(value, &path)?.into_owned())
+    })
+}
+
+/// The input with the value at a path, or a JSON Pointer string, replaced.
+fn setpath<'a>(call: &'a Call, env: &Env<'a>, value: &Value) -> QueryResult {
+    call.apply(env, value, |args, value| {
+        let path = path(&args[0], value)?;
+        let mut output = value.clone();
+        update::set(env, &mut output, &path, args[1].clone())?;
+        single(output)
+    })
+}
+
+/// A path given as an array, or as a JSON Pointer into `value`.
+fn path<'p>(p: &'p Value, value: &Value) -> Result<Cow<'p, [Value]>, QueryError> {
+    match p {
+        Value::Array(path) => Ok(Cow::Borrowed(path)),
+        Value::String(pointer) => Ok(Cow::Owned(pointer::resolve(pointer, value)?)),
+        p => Err(QueryError::Path(describe(p))),
+    }
+}
+
+/// The input with a JSON Merge Patch applied, where a null removes its key.
+fn mergepatch<'a>(call: &'a Call, env: &Env<'a>, value: &Value) -> QueryResult {
+    call.apply(env, value, |args, value| {
+        let mut output = value.clone();
+        patch::merge(&mut output, &args[0]);
+        single(output)
+    })
+}
+
+fn halt<'a>(_: &'a Call, _: &Env<'a>, _: &Value) -> QueryResult {
+    Err(QueryError::Halt(0, None))
+}
+
+/// Halts with the input as the message, and an exit code of 5 unless one is
+/// given.
+fn halt_error<'a>(call: &'a Call, env: &Env<'a>, value: &Value) -> QueryResult {
+    if call.args.is_empty() {
+        return Err(QueryError::Halt(5, Some(value.clone())));
+    }
+    call.apply(env, value, |args, value| match args[0].as_i64() {
+        Some(code) => Err(QueryError::Halt(code as i32, Some(value.clone()))),
+        None => Err(QueryError::Numerical),
+    })
+}
+
+/// The `[path, leaf]` events describing the input, as `--stream` reads it.
+fn tostream<'a>(_: &'a Call, _: &Env<'a>, value: &Value) -> QueryResult {
+    let mut output = Vec::new();
+    stream::events(value, &mut Vec::new(), &mut output);
+    Ok(output)
+}
+
+/// The values which the events of the argument describe.
+fn fromstream<'a>(call: &'a Call, env: &Env<'a>, value: &Value) -> QueryResult {
+    let mut rebuild = Rebuild::default();
+    let mut output = Vec::new();
+    for event in call.args[0].eval(env, value)? {
+        output.extend(rebuild.push(env, &event)?);
+    }
+    Ok(output)
+}
+
+/// The rows of a CSV string, each an array of its fields.
+fn fromcsv<'a>(_: &'a Call, _: &Env<'a>, value: &Value) -> QueryResult {
+    let options = CsvOptions {
+        header: false,
+        ..CsvOptions::default()
+    };
+    from_delimited("csv", value, &options)
+}
+
+/// The rows of a TSV string as `@tsv` writes them, each an array of its fields.
+fn fromtsv<'a>(_: &'a Call, _: &Env<'a>, value: &Value) -> QueryResult {
+    let options = CsvOptions {
+        header: false,
+        ..CsvOptions::tsv()
+    };
+    from_delimited("tsv", value, &options)
+}
+
+fn from_delimited(name: &'static str, value: &Value, options: &CsvOptions) -> QueryResult {
+    match value {
+        Value::String(s) => single(csv::from_str(s, options)?),
+        v => Err(QueryError::Parse(name, describe(v))),
+    }
+}
+
+/// An array of rows as lines of `@csv`.
+fn tocsv<'a>(_: &'a Call, _: &Env<'a>, value: &Value) -> QueryResult {
+    to_delimited("csv", Format::Csv, value)
+}
+
+/// An array of rows as lines of `@tsv`.
+fn totsv<'a>(_: &'a Call, _: &Env<'a>, value: &Value) -> QueryResult {
+    to_delimited("tsv", Format::Tsv, value)
+}
+
+fn to_delimited(name: &'static str, format: Format, value: &Value) -> QueryResult {
+    let rows = match value {
+        Value::Array(rows) => rows,
+        v => return Err(QueryError::Format(name, describe(v))),
+    };
+    let mut text = String::new();
+    for row in rows {
+        text.push_str(&format.apply(row)?);
+        text.push('\n');
+    }
+    single(Value::from(text))
+}
+
+/// The bytes of a string's UTF-8, or of an array of bytes as it is.
+fn bytes_of(value: &Value) -> Result<Cow<'_, [u8]>, QueryError> {
+    match value {
+        Value::String(s) => Ok(Cow::Borrowed(s.as_bytes())),
+        v => bytes::from_value(v)
+            .map(Cow::Owned)
+            .ok_or_else(|| QueryError::Format("bytes", describe(v))),
+    }
+}
+
+fn tobytes<'a>(_: &'a Call, _: &Env<'a>, value: &Value) -> QueryResult {
+    single(bytes::to_value(&bytes_of(value)?))
+}
+
+/// An array of bytes as the string they encode in UTF-8.
+fn frombytes<'a>(_: &'a Call, _: &Env<'a>, value: &Value) -> QueryResult {
+    let bytes = bytes_of(value)?;
+    match std::str::from_utf8(&bytes) {
+        Ok(s) => single(Value::from(s)),
+        Err(_) => Err(QueryError::Parse("UTF-8", describe(value))),
+    }
+}
+
+fn tobase64<'a>(_: &'a Call, _: &Env<'a>, value: &Value) -> QueryResult {
+    single(Value::from(base64_encode(&bytes_of(value)?)))
+}
+
+/// The bytes of a base64 string, which unlike `@base64d` needn't be UTF-8.
+fn frombase64<'a>(_: &'a Call, _: &Env<'a>, value: &Value) -> QueryResult {
+    match value.as_str().and_then(base64_decode) {
+        Some(bytes) => single(bytes::to_value(&bytes)),
+        None => Err(QueryError::Parse("base64", describe(value))),
+    }
+}
+
+fn tohex<'a>(_: &'a Call, _: &Env<'a>, value: &Value) -> QueryResult {
+    single(Value::from(bytes::hex_encode(&bytes_of(value)?)))
+}
+
+fn fromhex<'a>(_: &'a Call, _: &Env<'a>, value: &Value) -> QueryResult {
+    match value.as_str().and_then(bytes::hex_decode) {
+        Some(bytes) => single(bytes::to_value(&bytes)),
+        None => Err(QueryError::Parse("hex", describe(value))),
+    }
+}
+
+/// The input without the prefix, if it is a string which starts with it.
+fn ltrimstr<'a, 'v>(call: &'a Call, env: &Env<'a>, value: Cow<'v, Value>) -> SharedResult<'v> {
+    trim(call, env, value, |s, prefix| {
+        s.strip_prefix(prefix).map(|_| prefix.len()..s.len())
+    })
+}
+
+/// The input without the suffix, if it is a string which ends with it.
+fn rtrimstr<'a, 'v>(call: &'a Call, env: &Env<'a>, value: Cow<'v, Value>) -> SharedResult<'v> {
+    trim(call, env, value, |s, suffix| {
+        s.strip_suffix(suffix).map(|rest| 0..rest.len())
+    })
+}
+
+/// The input cut down to the part of it `kept` finds for each output of the
+/// argument, or left as it is when either isn't a string.
+fn trim<'a, 'v>(
+    call: &'a Call,
+    env: &Env<'a>,
+    value: Cow<'v, Value>,
+    kept: fn(&str, &str) -> Option<Range<usize>>,
+) -> SharedResult<'v> {
+    let args = call.args[0].eval(env, &value)?;
+    let outputs = iter::repeat_n(value, args.len()).zip(&args);
+    Ok(outputs
+        .map(|(v, arg)| match (v.as_ref(), arg) {
+            (Value::String(s), Value::String(t)) => match kept(s, t) {
+                Some(range) => substring(v, range),
+                None => v,
+            },
+            _ => v,
+        })
+        .collect())
+}
+
+fn ascii_downcase<'a, 'v>(_: &'a Call, _: &Env<'a>, value: Cow<'v, Value>) -> SharedResult<'v> {
+    recase(
+        "ascii_downcase",
+        value,
+        u8::is_ascii_uppercase,
+        str::make_ascii_lowercase,
+    )
+}
+
+fn ascii_upcase<'a, 'v>(_: &'a Call, _: &Env<'a>, value: Cow<'v, Value>) -> SharedResult<'v> {
+    recase(
+        "ascii_upcase",
+        value,
+        u8::is_ascii_lowercase,
+        str::make_ascii_uppercase,
+    )
+}
+
+/// A string with `change` applied, which is only copied if it is borrowed
+/// and has a byte which `changes`.
+fn recase<'v>(
+    name: &'static str,
+    value: Cow<'v, Value>,
+    changes: fn(&u8) -> bool,
+    change: fn(&mut str),
+) -> SharedResult<'v> {
+    match value {
+        Cow::Borrowed(Value::String(s)) if !s.bytes().any(|b| changes(&b)) => Ok(vec![value]),
+        v => match v.into_owned() {
+            Value::String(mut s) => {
+                change(&mut s);
+                Ok(vec![Cow::Owned(Value::String(s))])
+            }
+            v => Err(QueryError::Apply(name, describe(&v))),
+        },
+    }
+}
+
+/// A number as an integer where possible, as jq would print it.
+fn number(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < i64::MAX as f64 {
+        Value::from(n as i64)
+    } else {
+        Value::from(n)
+    }
+}
@@ -0,0 +1,89 @@
+use serde_json::Value;
+use std::convert::TryFrom;
+
+/// The bytes an array of integers from 0 to 255 stands for, as binary data
+/// such as a CBOR byte string is read, or `None` if it isn't one.
+pub fn from_value(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::Array(a) => a
+            .iter()
+            .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+            .collect(),
+        _ => None,
+    }
+}
+
+pub fn to_value(bytes: &[u8]) -> Value {
+    Value::Array(bytes.iter().map(|&b| Value::from(b)).collect())
+}
+
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Bytes written as pairs of hexadecimal digits in either case.
+pub fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        query::{Executable, Query},
+        QueryError,
+    };
+
+    #[test]
+    fn convert_bytes() {
+        assert_eq!(Some(vec![0, 255]), from_value(&json!([0, 255])));
+        assert_eq!(None, from_value(&json!([256])));
+        assert_eq!(None, from_value(&json!([1.5])));
+        assert_eq!(None, from_value(&json!("a")));
+        assert_eq!("00ff10", hex_encode(&[0, 255, 16]));
+        assert_eq!(Some(vec![0, 255, 16]), hex_decode("00Ff10"));
+        assert_eq!(None, hex_decode("abc"));
+        assert_eq!(None, hex_decode("zz"));
+        assert_eq!(None, hex_decode("é0"));
+    }
+
+    #[test]
+    fn byte_builtins() {
+        let v = json!({"text": "hé", "encoded": "/wA=", "hex": "ff00"});
+        let run = |s: &str| s.parse::<Query>().unwrap().execute(&v);
+        let output = |s: &str| run(s).unwrap();
+        assert_eq!(vec![json!([104, 195, 169])], output(".text | tobytes"));
+        assert_eq!(vec![json!("hé")], output(".text | tobytes | frombytes"));
+        assert_eq!(vec![json!([255, 0])], output(".encoded | frombase64"));
+        assert_eq!(vec![json!([255, 0])], output(".hex | fromhex"));
+        assert_eq!(vec![json!("/wA=")], output(".hex | fromhex | tobase64"));
+        assert_eq!(vec![json!("ff00")], output(".encoded | frombase64 | tohex"));
+        assert_eq!(vec![json!("6869")], output("\"hi\" | tohex"));
+        assert_eq!(vec![json!([1])], output("[1] | tobytes"));
+
+        assert!(matches!(
+            run("[255] | frombytes"),
+            Err(QueryError::Parse("UTF-8", _))
+        ));
+        assert!(matches!(
+            run("\"!\" | frombase64"),
+            Err(QueryError::Parse("base64", _))
+        ));
+        assert!(matches!(
+            run("\"f\" | fromhex"),
+            Err(QueryError::Parse("hex", _))
+        ));
+        assert!(matches!(
+            run("[300] | tohex"),
+            Err(QueryError::Format("bytes", _))
+        ));
+    }
+}
@@ -0,0 +1,451 @@
+use serde_json::{Map, Number, Value};
+use std::convert::TryFrom;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CborError {
+    #[error("Unexpected end of CBOR input")]
+    Eof,
+    #[error("Invalid CBOR item starting with 0x{0:02x}")]
+    Invalid(u8),
+    #[error("CBOR text is not UTF-8")]
+    Utf8,
+    #[error("Cannot read CBOR tag {0}")]
+    Tag(u64),
+    #[error("CBOR nested more than {} deep", MAX_DEPTH)]
+    Depth,
+}
+
+/// How many arrays, maps and tags deep an item can be, as deep as
+/// serde_json reads JSON.
+const MAX_DEPTH: usize = 128;
+
+/// Reads each of the CBOR items one after another in `bytes`.
+///
+/// Byte strings are read as arrays of their bytes, map keys which aren't
+/// text as their JSON text, and tagged items as the items themselves, but
+/// for big numbers, which are read as numbers where they fit one and as the
+/// text of their digits where they don't.
+pub fn from_slice(mut bytes: &[u8]) -> Result<Vec<Value>, CborError> {
+    let mut values = Vec::new();
+    while !bytes.is_empty() {
+        match read(&mut bytes, 0)? {
+            Item::Value(v) => values.push(v),
+            Item::Break => return Err(CborError::Invalid(BREAK)),
+        }
+    }
+    Ok(values)
+}
+
+/// The byte ending an item of indefinite length.
+const BREAK: u8 = 0xff;
+
+enum Item {
+    Value(Value),
+    Break,
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], CborError> {
+    if bytes.len() < n {
+        return Err(CborError::Eof);
+    }
+    let (taken, rest) = bytes.split_at(n);
+    *bytes = rest;
+    Ok(taken)
+}
+
+/// The argument following the initial byte, or `None` for an indefinite
+/// length.
+fn argument(bytes: &mut &[u8], initial: u8) -> Result<Option<u64>, CborError> {
+    let n = match initial & 0x1f {
+        info @ 0..=23 => return Ok(Some(u64::from(info))),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        31 => return Ok(None),
+        _ => return Err(CborError::Invalid(initial)),
+    };
+    Ok(Some(
+        take(bytes, n)?
+            .iter()
+            .fold(0, |acc, &b| (acc << 8) | u64::from(b)),
+    ))
+}
+
+fn float(f: f64) -> Value {
+    Number::from_f64(f).map_or(Value::Null, Value::Number)
+}
+
+/// A half precision float, as CBOR allows.
+pub(crate) fn half(bits: u16) -> f64 {
+    let exponent = i32::from((bits >> 10) & 0x1f);
+    let fraction = f64::from(bits & 0x3ff);
+    let magnitude = match exponent {
+        0 => fraction * 2f64.powi(-24),
+        31 if fraction == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        e => (1.0 + fraction / 1024.0) * 2f64.powi(e - 15),
+    };
+    match bits & 0x8000 {
+        0 => magnitude,
+        _ => -magnitude,
+    }
+}
+
+fn value(bytes: &mut &[u8], depth: usize) -> Result<Value, CborError> {
+    let initial = bytes.first().copied();
+    match read(bytes, depth)? {
+        Item::Value(v) => Ok(v),
+        Item::Break => Err(CborError::Invalid(initial.unwrap_or(BREAK))),
+    }
+}
+
+/// Reads an item within `depth` others.
+fn read(bytes: &mut &[u8], depth: usize) -> Result<Item, CborError> {
+    let initial = take(bytes, 1)?[0];
+    if (4..=6).contains(&(initial >> 5)) && depth >= MAX_DEPTH {
+        return Err(CborError::Depth);
+    }
+    let argument = argument(bytes, initial)?;
+    let length = |n: u64| usize::try_from(n).map_err(|_| CborError::Eof);
+    let v = match (initial >> 5, argument) {
+        (0, Some(n)) => Value::from(n),
+        (1, Some(n)) => negative(n),
+        (2, _) => Value::from(chunks(bytes, initial, argument)?),
+        (3, _) => {
+            let text = String::from_utf8(chunks(bytes, initial, argument)?);
+            Value::from(text.map_err(|_| CborError::Utf8)?)
+        }
+        (4, Some(n)) => {
+            // The count can't be trusted to reserve space, but each item takes a byte
+            let mut a = Vec::with_capacity(length(n)?.min(bytes.len()));
+            for _ in 0..n {
+                a.push(value(bytes, depth + 1)?);
+            }
+            Value::Array(a)
+        }
+        (4, None) => {
+            let mut a = Vec::new();
+            while let Item::Value(v) = read(bytes, depth + 1)? {
+                a.push(v);
+            }
+            Value::Array(a)
+        }
+        (5, n) => {
+            let mut o = Map::new();
+            let mut remaining = n;
+            while remaining != Some(0) {
+                let key = match read(bytes, depth + 1)? {
+                    Item::Break if remaining.is_none() => break,
+                    Item::Break => return Err(CborError::Invalid(BREAK)),
+                    Item::Value(Value::String(k)) => k,
+                    Item::Value(k) => k.to_string(),
+                };
+                o.insert(key, value(bytes, depth + 1)?);
+                remaining = remaining.map(|n| n - 1);
+            }
+            Value::Object(o)
+        }
+        (6, Some(tag @ (2 | 3))) => match bytes.first() {
+            Some(&c) if c >> 5 == 2 => {
+                take(bytes, 1)?;
+                let length = self::argument(bytes, c)?;
+                let magnitude = chunks(bytes, c, length)?;
+                bignum(tag == 3, &magnitude).ok_or(CborError::Tag(tag))?
+            }
+            _ => return Err(CborError::Tag(tag)),
+        },
+        (6, Some(_)) => value(bytes, depth + 1)?,
+        (7, _) => match initial & 0x1f {
+            20 => Value::Bool(false),
+            21 => Value::Bool(true),
+            22 | 23 => Value::Null,
+            25 => float(half(argument.unwrap_or_default() as u16)),
+            26 => float(f64::from(f32::from_bits(
+                argument.unwrap_or_default() as u32
+            ))),
+            27 => float(f64::from_bits(argument.unwrap_or_default())),
+            31 => return Ok(Item::Break),
+            _ => return Err(CborError::Invalid(initial)),
+        },
+        _ => return Err(CborError::Invalid(initial)),
+    };
+    Ok(Item::Value(v))
+}
+
+/// `-1 - n`, as a negative integer holds it.
+fn negative(n: u64) -> Value {
+    match i64::try_from(n) {
+        Ok(n) => Value::from(-1 - n),
+        Err(_) => float(-1.0 - n as f64),
+    }
+}
+
+/// How many bytes long a big number can be, beyond which finding its digits
+/// would take too long.
+const MAX_BIGNUM: usize = 1024;
+
+/// The big number of the big-endian bytes of its magnitude, or of `-1` less
+/// that if it is negative, as a number if JSON has one large enough.
+fn bignum(negative: bool, magnitude: &[u8]) -> Option<Value> {
+    let start = magnitude.iter().position(|&b| b != 0);
+    let magnitude = &magnitude[start.unwrap_or(magnitude.len())..];
+    if magnitude.len() > MAX_BIGNUM {
+        return None;
+    }
+    if magnitude.len() <= 8 {
+        let n = magnitude.iter().fold(0, |n, &b| n << 8 | u64::from(b));
+        return Some(if negative {
+            self::negative(n)
+        } else {
+            Value::from(n)
+        });
+    }
+
+    // In limbs of 32 bits, most significant first, divided by 10^9 over and
+    // over for the last nine digits each time
+    let mut limbs: Vec<u32> = magnitude
+        .rchunks(4)
+        .rev()
+        .map(|chunk| chunk.iter().fold(0, |n, &b| n << 8 | u32::from(b)))
+        .collect();
+    if negative {
+        let carried = limbs.iter_mut().rev().all(|limb| {
+            *limb = limb.wrapping_add(1);
+            *limb == 0
+        });
+        if carried {
+            limbs.insert(0, 1);
+        }
+    }
+    let mut groups = Vec::new();
+    while limbs.iter().any(|&l| l != 0) {
+        let mut remainder = 0;
+        for limb in &mut limbs {
+            let n = u64::from(remainder) << 32 | u64::from(*limb);
+            *limb = (n / 1_000_000_000) as u32;
+            remainder = (n % 1_000_000_000) as u32;
+        }
+        groups.push(remainder);
+    }
+    let mut digits = String::from(if negative { "-" } else { "" });
+    for (i, group) in groups.iter().rev().enumerate() {
+        match i {
+            0 => digits.push_str(&group.to_string()),
+            _ => digits.push_str(&format!("{:09}", group)),
+        }
+    }
+    Some(match digits.parse() {
+        Ok(n) => Value::Number(n),
+        Err(_) => Value::String(digits),
+    })
+}
+
+/// The contents of a byte or text string, which may be given in chunks of
+/// the same type when its length is indefinite.
+fn chunks(bytes: &mut &[u8], initial: u8, length: Option<u64>) -> Result<Vec<u8>, CborError> {
+    match length {
+        Some(n) => Ok(take(bytes, usize::try_from(n).map_err(|_| CborError::Eof)?)?.to_vec()),
+        None => {
+            let mut contents = Vec::new();
+            loop {
+                let chunk = take(bytes, 1)?[0];
+                match chunk {
+                    BREAK => return Ok(contents),
+                    c if c >> 5 == initial >> 5 => match argument(bytes, c)? {
+                        Some(n) => contents.extend(chunks(bytes, c, Some(n))?),
+                        None => return Err(CborError::Invalid(c)),
+                    },
+                    c => return Err(CborError::Invalid(c)),
+                }
+            }
+        }
+    }
+}
+
+/// Writes a value as CBOR, each number and length in as few bytes as it
+/// fits.
+pub fn to_vec(value: &Value) -> Vec<u8> {
+    let mut output = Vec::new();
+    write(&mut output, value);
+    output
+}
+
+/// Writes the initial byte of an item of the given major type, along with
+/// its argument.
+fn write_head(output: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    match n {
+        n if n < 24 => output.push(major | n as u8),
+        n if n <= 0xff => output.extend([major | 24, n as u8]),
+        n if n <= 0xffff => {
+            output.push(major | 25);
+            output.extend((n as u16).to_be_bytes());
+        }
+        n if n <= 0xffff_ffff => {
+            output.push(major | 26);
+            output.extend((n as u32).to_be_bytes());
+        }
+        n => {
+            output.push(major | 27);
+            output.extend(n.to_be_bytes());
+        }
+    }
+}
+
+fn write(output: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => output.push(0xf6),
+        Value::Bool(b) => output.push(if *b { 0xf5 } else { 0xf4 }),
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(u), _) => write_head(output, 0, u),
+            (_, Some(i)) => write_head(output, 1, (-1 - i) as u64),
+            _ => {
+                output.push(0xfb);
+                output.extend(n.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        },
+        Value::String(s) => {
+            write_head(output, 3, s.len() as u64);
+            output.extend(s.as_bytes());
+        }
+        Value::Array(a) => {
+            write_head(output, 4, a.len() as u64);
+            for v in a {
+                write(output, v);
+            }
+        }
+        Value::Object(o) => {
+            write_head(output, 5, o.len() as u64);
+            for (k, v) in o {
+                write_head(output, 3, k.len() as u64);
+                output.extend(k.as_bytes());
+                write(output, v);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn read_cbor() {
+        // {"a": [1, -1, true, null], "bc": 1.5} then 500
+        let bytes = b"\xa2\x61a\x84\x01\x20\xf5\xf6\x62bc\xf9\x3e\x00\x19\x01\xf4";
+        assert_eq!(
+            Ok(vec![
+                json!({"a": [1, -1, true, null], "bc": 1.5}),
+                json!(500)
+            ]),
+            from_slice(bytes)
+        );
+        assert_eq!(Ok(vec![json!([1, 2])]), from_slice(b"\x42\x01\x02"));
+        assert_eq!(Ok(vec![json!({"1": 2})]), from_slice(b"\xa1\x01\x02"));
+        assert_eq!(Ok(vec![json!(-0.5)]), from_slice(b"\xfa\xbf\0\0\0"));
+        // Indefinite lengths, and a date tagged as one
+        assert_eq!(
+            Ok(vec![json!({"ab": [1, "2020"]})]),
+            from_slice(b"\xbf\x7f\x61a\x61b\xff\x9f\x01\xc0\x642020\xff\xff")
+        );
+
+        assert_eq!(Err(CborError::Eof), from_slice(b"\x82\x01"));
+        assert_eq!(Err(CborError::Invalid(0x1c)), from_slice(b"\x1c"));
+        assert_eq!(Err(CborError::Invalid(BREAK)), from_slice(b"\x82\x01\xff"));
+        assert_eq!(Err(CborError::Utf8), from_slice(b"\x61\xff"));
+        assert_eq!(Err(CborError::Tag(2)), from_slice(b"\xc2\x01"));
+    }
+
+    #[test]
+    fn bignums() {
+        let bignum = |tag: u8, magnitude: &[u8]| {
+            let mut bytes = vec![tag, 0x58, magnitude.len() as u8];
+            bytes.extend(magnitude);
+            from_slice(&bytes).map(|mut v| v.remove(0))
+        };
+        assert_eq!(
+            Ok(json!(1)),
+            from_slice(b"\xc2\x41\x01").map(|mut v| v.remove(0))
+        );
+        assert_eq!(Ok(json!(0)), bignum(0xc2, &[]));
+        assert_eq!(Ok(json!(-1)), bignum(0xc3, &[]));
+        assert_eq!(
+            Ok(json!(u64::MAX)),
+            bignum(
+                0xc2,
+                &[0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+            )
+        );
+        assert_eq!(Ok(json!(-256)), bignum(0xc3, &[0xff]));
+        // Indefinite lengths in chunks
+        assert_eq!(
+            Ok(vec![json!(258)]),
+            from_slice(b"\xc2\x5f\x41\x01\x41\x02\xff")
+        );
+
+        // 2^64 and -1 - 2^64, in digits where JSON numbers are exact
+        let two_64 = [1, 0, 0, 0, 0, 0, 0, 0, 0];
+        let (positive, negative) = (bignum(0xc2, &two_64), bignum(0xc3, &two_64));
+        #[cfg(feature = "arbitrary_precision")]
+        {
+            assert_eq!("18446744073709551616", positive.unwrap().to_string());
+            assert_eq!("-18446744073709551617", negative.unwrap().to_string());
+        }
+        #[cfg(not(feature = "arbitrary_precision"))]
+        {
+            assert_eq!(Ok(json!(18446744073709551616.0)), positive);
+            assert_eq!(Ok(json!(-18446744073709551617.0)), negative);
+        }
+
+        // Beyond the largest float, or too long to read
+        let huge = bignum(0xc2, &[0xff; 200]).unwrap();
+        #[cfg(not(feature = "arbitrary_precision"))]
+        assert!(huge.is_string());
+        let digits = huge.as_str().map_or_else(|| huge.to_string(), String::from);
+        assert_eq!(482, digits.len());
+        assert!(digits.starts_with("4446241647") && digits.ends_with("6364645375"));
+        let carry = bignum(0xc3, &[0xff; 12]).unwrap();
+        #[cfg(feature = "arbitrary_precision")]
+        assert_eq!("-79228162514264337593543950336", carry.to_string());
+        #[cfg(not(feature = "arbitrary_precision"))]
+        assert_eq!(Some(-79228162514264337593543950336.0), carry.as_f64());
+        let mut long = vec![0xc3, 0x59, 0x04, 0x01];
+        long.extend([1; 1025]);
+        assert_eq!(Err(CborError::Tag(3)), from_slice(&long));
+    }
+
+    #[test]
+    fn depth() {
+        let nested = |n: usize, initial: u8| [vec![initial; n], vec![0]].concat();
+        let deepest = (0..MAX_DEPTH).fold(json!(0), |v, _| json!([v]));
+        assert_eq!(Ok(vec![deepest]), from_slice(&nested(MAX_DEPTH, 0x81)));
+        assert_eq!(
+            Err(CborError::Depth),
+            from_slice(&nested(MAX_DEPTH + 1, 0x81))
+        );
+        // Too deep to recurse into at all
+        assert_eq!(Err(CborError::Depth), from_slice(&nested(200_000, 0x81)));
+        assert_eq!(Err(CborError::Depth), from_slice(&nested(200_000, 0xc0)));
+        assert_eq!(
+            Err(CborError::Depth),
+            from_slice(&[0xa1, 0].repeat(100_000))
+        );
+    }
+
+    #[test]
+    fn write_cbor() {
+        let v = json!({
+            "n": [0, 23, 24, 255, 65536, 4294967296u64, -1, -25, -2147483649i64, 0.25],
+            "s": "x".repeat(30),
+            "a": (0..30).collect::<Vec<_>>(),
+            "o": {}
+        });
+        let bytes = to_vec(&v);
+        assert_eq!(Ok(vec![v]), from_slice(&bytes));
+        assert_eq!(b"\xa1\x61a\x38\x18".to_vec(), to_vec(&json!({"a": -25})));
+    }
+}
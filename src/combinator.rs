@@ -1,40 +1,232 @@
 use nom::{character::complete::char, combinator::opt, IResult};
 use serde_json::Value;
+use std::{borrow::Cow, iter};
 
 use crate::{
-    empty,
+    env::Env,
+    optimize::pure,
     parse::{parse_chain, ParseError},
-    query::{iterate_results, iterate_values, Executable, Query},
-    QueryResult,
+    query::{iterate_results, paths, results, Descendants, Eval, Query},
+    PathResult, QueryError, QueryIter, QueryResult, SharedResult,
 };
 
+/// Produces the outputs of the left query followed by those of the right.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Split(pub Query, pub Query);
 
-impl Executable for Split {
-    fn execute(&self, value: &Value) -> QueryResult {
-        iterate_results(vec![self.0.execute(value), self.1.execute(value)])
+impl Eval for Split {
+    fn eval<'a>(&'a self, env: &Env<'a>, value: &Value) -> QueryResult {
+        let mut output = self.0.eval(env, value)?;
+        output.extend(self.1.eval(env, value)?);
+        Ok(output)
+    }
+
+    fn eval_owned<'a>(&'a self, env: &Env<'a>, value: Value) -> QueryResult {
+        let mut output = self.0.eval(env, &value)?;
+        output.extend(self.1.eval_owned(env, value)?);
+        Ok(output)
+    }
+
+    fn eval_into<'a>(
+        &'a self,
+        env: &Env<'a>,
+        value: &Value,
+        output: &mut Vec<Value>,
+    ) -> Result<(), QueryError> {
+        self.0.eval_into(env, value, output)?;
+        self.1.eval_into(env, value, output)
+    }
+
+    fn eval_iter<'a>(&'a self, env: &Env<'a>, value: Cow<'a, Value>) -> QueryIter<'a> {
+        let left = self.0.eval_iter(env, value.clone());
+        Box::new(left.chain(self.1.eval_iter(env, value)))
+    }
+
+    fn eval_ref<'a, 'v>(&'a self, env: &Env<'a>, value: &'v Value) -> SharedResult<'v> {
+        let mut output = self.0.eval_ref(env, value)?;
+        output.extend(self.1.eval_ref(env, value)?);
+        Ok(output)
+    }
+
+    fn eval_paths<'a>(&'a self, env: &Env<'a>, value: &Value) -> PathResult {
+        let mut output = self.0.eval_paths(env, value)?;
+        output.extend(self.1.eval_paths(env, value)?);
+        Ok(output)
     }
 }
 
+/// Feeds every output of the left query into the right query.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Chain(pub Query, pub Query);
 
-impl Executable for Chain {
-    fn execute(&self, value: &Value) -> QueryResult {
-        iterate_values(self.0.execute(value)?.iter(), &self.1)
+impl Eval for Chain {
+    /// Parts of the input the left query leads to are only copied if the
+    /// right query outputs them.
+    fn eval<'a>(&'a self, env: &Env<'a>, value: &Value) -> QueryResult {
+        #[cfg(feature = "parallel")]
+        if let Some(result) = self.eval_par(env, value) {
+            return result;
+        }
+        let lefts = self.0.eval_ref(env, value)?.into_iter().enumerate();
+        iterate_results(lefts.map(|(k, v)| {
+            match v {
+                Cow::Borrowed(v) => self.1.eval(env, v),
+                Cow::Owned(v) => self.1.eval_owned(env, v),
+            }
+            .map_err(|e| self.locate(value, k, e))
+        }))
+    }
+
+    fn eval_owned<'a>(&'a self, env: &Env<'a>, value: Value) -> QueryResult {
+        if descends(&self.0) {
+            return self.eval(env, &value);
+        }
+        #[cfg(feature = "parallel")]
+        if let Some(result) = self.eval_par(env, &value) {
+            return result;
+        }
+        iterate_results(
+            self.0
+                .eval_owned(env, value)?
+                .into_iter()
+                .map(|v| self.1.eval_owned(env, v)),
+        )
+    }
+
+    fn eval_into<'a>(
+        &'a self,
+        env: &Env<'a>,
+        value: &Value,
+        output: &mut Vec<Value>,
+    ) -> Result<(), QueryError> {
+        for (k, v) in self.0.eval_ref(env, value)?.into_iter().enumerate() {
+            match v {
+                Cow::Borrowed(v) => self.1.eval_into(env, v, output),
+                Cow::Owned(v) => self.1.eval_owned(env, v).map(|vs| output.extend(vs)),
+            }
+            .map_err(|e| self.locate(value, k, e))?;
+        }
+        Ok(())
+    }
+
+    /// Everything within the input is only copied if the right query
+    /// outputs it when the left query is `..`, as it is with `eval`. That
+    /// means evaluating all the outputs for an owned input at once, since
+    /// they would borrow from it.
+    fn eval_iter<'a>(&'a self, env: &Env<'a>, value: Cow<'a, Value>) -> QueryIter<'a> {
+        #[cfg(feature = "parallel")]
+        if let Some(result) = self.eval_par(env, &value) {
+            return results(result);
+        }
+        let env = env.clone();
+        match value {
+            Cow::Borrowed(v) if descends(&self.0) => {
+                return Box::new(
+                    Descendants::new(v).flat_map(move |v| self.1.eval_iter(&env, Cow::Borrowed(v))),
+                )
+            }
+            Cow::Owned(v) if descends(&self.0) => return results(self.eval(&env, &v)),
+            _ => {}
+        }
+        Box::new(self.0.eval_iter(&env, value).flat_map(move |r| match r {
+            Ok(v) => self.1.eval_iter(&env, Cow::Owned(v)),
+            Err(e) => Box::new(iter::once(Err(e))),
+        }))
+    }
+
+    fn eval_ref<'a, 'v>(&'a self, env: &Env<'a>, value: &'v Value) -> SharedResult<'v> {
+        let mut output = Vec::new();
+        for (k, v) in self.0.eval_ref(env, value)?.into_iter().enumerate() {
+            match v {
+                Cow::Borrowed(v) => self.1.eval_ref(env, v).map(|vs| output.extend(vs)),
+                Cow::Owned(v) => self
+                    .1
+                    .eval_owned(env, v)
+                    .map(|vs| output.extend(vs.into_iter().map(Cow::Owned))),
+            }
+            .map_err(|e| self.locate(value, k, e))?;
+        }
+        Ok(output)
+    }
+
+    fn eval_paths<'a>(&'a self, env: &Env<'a>, value: &Value) -> PathResult {
+        let mut output = Vec::new();
+        for (prefix, v) in self.0.eval_paths(env, value)? {
+            let paths = self
+                .1
+                .eval_paths(env, &v)
+                .map_err(|e| e.at(prefix.clone()))?;
+            output.extend(
+                paths
+                    .into_iter()
+                    .map(|(path, vv)| (prefix.iter().cloned().chain(path).collect(), vv)),
+            );
+        }
+        Ok(output)
     }
 }
 
+impl Chain {
+    /// Whether the chain is a path into a variable such as `$config.a`,
+    /// whose outputs are the same for any input.
+    pub(crate) fn invariant(&self) -> bool {
+        fn variable(query: &Query) -> bool {
+            match query {
+                Query::Variable(_) => true,
+                Query::Spanned(s) => variable(&s.query),
+                _ => false,
+            }
+        }
+        variable(&self.0) && pure(&self.1)
+    }
+
+    /// Adds where the `k`th output of the left query is within the input to
+    /// an error the right query produced from it.
+    pub(crate) fn locate(&self, value: &Value, k: usize, e: QueryError) -> QueryError {
+        match paths(&self.0, value).and_then(|mut p| (k < p.len()).then(|| p.swap_remove(k))) {
+            Some(prefix) => e.at(prefix),
+            None => e,
+        }
+    }
+}
+
+/// Whether a query is `..`, which leads to every value within its input.
+fn descends(query: &Query) -> bool {
+    match query {
+        Query::Recurse => true,
+        Query::Spanned(s) => descends(&s.query),
+        _ => false,
+    }
+}
+
+/// Suppresses any error from the inner query other than halting, producing
+/// no output instead.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Optional(pub Query);
 
-impl Executable for Optional {
-    fn execute(&self, value: &Value) -> QueryResult {
-        match self.0.execute(value) {
-            Ok(v) => Ok(v),
-            Err(_) => empty(),
-        }
+/// The output of a query, or none if it failed without halting.
+fn suppress<T: Default>(result: Result<T, QueryError>) -> Result<T, QueryError> {
+    match result {
+        Err(e @ QueryError::Halt(..)) => Err(e),
+        result => Ok(result.unwrap_or_default()),
+    }
+}
+
+impl Eval for Optional {
+    fn eval<'a>(&'a self, env: &Env<'a>, value: &Value) -> QueryResult {
+        suppress(self.0.eval(env, value))
+    }
+
+    fn eval_owned<'a>(&'a self, env: &Env<'a>, value: Value) -> QueryResult {
+        suppress(self.0.eval_owned(env, value))
+    }
+
+    fn eval_ref<'a, 'v>(&'a self, env: &Env<'a>, value: &'v Value) -> SharedResult<'v> {
+        suppress(self.0.eval_ref(env, value))
+    }
+
+    fn eval_paths<'a>(&'a self, env: &Env<'a>, value: &Value) -> PathResult {
+        suppress(self.0.eval_paths(env, value))
     }
 }
 
@@ -155,6 +347,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_optional_chain() {
+        let optional = |s: &str| {
+            Query::Optional(Box::new(Optional(Query::Index(Index::String(
+                s.to_string(),
+            )))))
+        };
+        assert_eq!(
+            Query::Chain(Box::new(Chain(
+                optional("a"),
+                Query::Chain(Box::new(Chain(optional("b"), optional("c"))))
+            ))),
+            Query::parse(".a?.b?.c?").unwrap()
+        );
+        assert_eq!(
+            Query::Chain(Box::new(Chain(
+                optional("a"),
+                Query::Chain(Box::new(Chain(
+                    Query::Optional(Box::new(Optional(Query::Index(Index::Integer(0))))),
+                    Query::Optional(Box::new(Optional(Query::Iterator)))
+                )))
+            ))),
+            Query::parse(".a?[0]?[]?").unwrap()
+        );
+    }
+
     #[test]
     fn parse_index_chain() {
         assert!(Query::parse(".foo.[0]").is_err());
@@ -198,4 +416,39 @@ mod tests {
             ".[][][]".parse().unwrap()
         );
     }
+
+    #[test]
+    fn recurse_chain() {
+        use crate::{context::Context, query::Executable};
+        let v = serde_json::json!({"a": {"a": [{"a": 1}]}, "b": [{"a": null}]});
+        let expected = r#"[{"a":[{"a":1}]},[{"a":1}],1,null,null]"#;
+        for s in &[".. | .a?", "def f: .a?; .. | f"] {
+            let q = Query::parse(s).unwrap();
+            assert_eq!(expected, Value::from(q.execute(&v).unwrap()).to_string());
+            let r: Result<Vec<_>, _> = q.execute_iter(&v).collect();
+            assert_eq!(expected, Value::from(r.unwrap()).to_string());
+            let r = q.execute_owned(v.clone()).unwrap();
+            assert_eq!(expected, Value::from(r).to_string());
+            let r: Result<Vec<_>, _> = q.execute_stream(vec![v.clone()], &Context::new()).collect();
+            assert_eq!(expected, Value::from(r.unwrap()).to_string());
+        }
+    }
+
+    #[test]
+    fn halt() {
+        use crate::query::Executable;
+        let v = serde_json::json!({"a": "b"});
+        let halted = |q: &str| match Query::parse(q).unwrap().execute(&v) {
+            Err(QueryError::Halt(code, message)) => Some((code, message)),
+            _ => None,
+        };
+        assert_eq!(Some((0, None)), halted("halt"));
+        assert_eq!(
+            Some((5, Some(Value::from("b")))),
+            halted("(.a | halt_error)?")
+        );
+        assert_eq!(Some((1, Some(v.clone()))), halted(".a, halt_error(1)"));
+        assert_eq!(None, halted("halt_error(.a)"));
+        assert_eq!(None, halted(".x?"));
+    }
 }
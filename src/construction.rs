@@ -1,14 +1,16 @@
 use crate::{
+    describe,
+    env::Env,
     index::Index,
-    parse::{parse_init, parse_pipe, ParseError, Parseable},
-    query::{Executable, Query},
-    space, type_str, QueryError, QueryResult,
+    parse::{parse_identifier, parse_init, parse_pipe, ParseError, Parseable},
+    query::{Eval, Query},
+    raw::parse_string,
+    space, QueryError, QueryResult,
 };
 use itertools::Itertools;
 use nom::{
     branch::alt,
-    bytes::complete::take_while1,
-    character::complete::{alphanumeric1, char},
+    character::complete::char,
     combinator::map,
     multi::separated_list0,
     sequence::{delimited, separated_pair},
@@ -16,28 +18,34 @@ use nom::{
 };
 use serde_json::Value;
 
+/// Array and object construction.
 #[derive(Debug, PartialEq, Clone)]
 pub enum Construct {
+    /// `[...]` collects every output of the inner query into an array.
     Array(Box<Query>),
+    /// `{...}` produces one object for every combination of key and value outputs.
     Object(Vec<(Key, Query)>),
 }
 
+/// The key of an object construction entry.
 #[derive(Debug, PartialEq, Clone)]
 pub enum Key {
+    /// `foo` or `"foo"`
     Simple(String),
+    /// `(...)`, which must produce strings
     Query(Query),
 }
 
 impl Key {
-    fn execute(&self, value: &Value) -> Result<Vec<String>, QueryError> {
+    fn eval<'a>(&'a self, env: &Env<'a>, value: &Value) -> Result<Vec<String>, QueryError> {
         let keys = match self {
             Key::Simple(s) => vec![s.clone()],
             Key::Query(inner) => {
                 let mut keys = Vec::new();
-                for k in inner.execute(value)? {
+                for k in inner.eval(env, value)? {
                     match k {
                         Value::String(s) => keys.push(s),
-                        vv => return Err(QueryError::ObjectKey(type_str(&vv))),
+                        vv => return Err(QueryError::ObjectKey(describe(&vv))),
                     }
                 }
                 keys
@@ -48,6 +56,7 @@ impl Key {
 }
 
 impl Construct {
+    /// The `{foo}` entry, equivalent to `{foo: .foo}`.
     pub fn shorthand(s: String) -> (Key, Query) {
         let k = Key::Simple(s.clone());
         let q = Query::Index(Index::String(s));
@@ -55,26 +64,38 @@ impl Construct {
     }
 }
 
-impl Executable for Construct {
-    fn execute(&self, value: &Value) -> QueryResult {
+impl Eval for Construct {
+    fn eval<'a>(&'a self, env: &Env<'a>, value: &Value) -> QueryResult {
         match self {
-            Construct::Array(inner) => construct_array(value, inner),
-            Construct::Object(kvs) => construct_object(value, kvs),
+            Construct::Array(inner) => construct_array(env, value, inner),
+            Construct::Object(kvs) => construct_object(env, value, kvs),
         }
     }
 }
 
-fn construct_array(v: &Value, inner: &Query) -> QueryResult {
-    Ok(vec![Value::Array(inner.execute(v)?)])
+fn construct_array<'a>(env: &Env<'a>, v: &Value, inner: &'a Query) -> QueryResult {
+    Ok(vec![Value::Array(inner.eval(env, v)?)])
 }
 
-fn construct_object(value: &Value, kvs: &[(Key, Query)]) -> QueryResult {
-    Ok(kvs
+fn construct_object<'a>(env: &Env<'a>, value: &Value, kvs: &'a [(Key, Query)]) -> QueryResult {
+    let mut entries = Vec::with_capacity(kvs.len());
+    for (k, v) in kvs {
+        entries.push((k.eval(env, value)?, v.eval(env, value)?));
+    }
+    // Usually every key and value has one output, which can be moved into
+    // the only object rather than copied into each combination
+    if entries
         .iter()
-        .map(|(k, v)| (k.execute(value), v.execute(value)))
-        .map(|(kr, vr)| kr.and_then(|ks| vr.map(|vs| (ks, vs))))
-        .collect::<Result<Vec<(Vec<String>, Vec<Value>)>, _>>()? // Unwrap pairs of results into just pairs of vectors
-        .into_iter() // At this point, each of key and value might have been evaluated to to many values
+        .all(|(ks, vs)| ks.len() == 1 && vs.len() == 1)
+    {
+        let object = entries
+            .into_iter()
+            .flat_map(|(ks, vs)| ks.into_iter().zip(vs))
+            .collect();
+        return Ok(vec![Value::Object(object)]);
+    }
+    Ok(entries
+        .into_iter() // Each key and value might have been evaluated to many values
         .map(|(ks, vs)| ks.into_iter().cartesian_product(vs)) // Get all combinations for each pair
         .multi_cartesian_product() // Get all combinations of different pairs
         .map(|pairs| pairs.into_iter().collect())
@@ -103,17 +124,16 @@ fn parse_object(input: &str) -> IResult<&str, Construct, ParseError> {
                     alt((
                         map(delimited(char('('), parse_init, char(')')), Key::Query),
                         map(
-                            alt((
-                                alphanumeric1,
-                                delimited(char('"'), take_while1(|c| c != '"'), char('"')),
-                            )),
-                            |s: &str| Key::Simple(s.to_string()),
+                            alt((map(parse_identifier, str::to_string), parse_string)),
+                            Key::Simple,
                         ),
                     )),
                     space::around(char(':')),
                     parse_init,
                 ),
-                map(alphanumeric1, |s: &str| Construct::shorthand(s.to_string())),
+                map(parse_identifier, |s: &str| {
+                    Construct::shorthand(s.to_string())
+                }),
             ))),
         )),
         char('}'),
@@ -174,4 +194,18 @@ mod tests {
             Construct::parse("{foo,bar:.bar,(.baz):.[]}").unwrap()
         );
     }
+
+    #[test]
+    fn construct_object() {
+        use crate::query::Executable;
+        let v: Value = serde_json::from_str(r#"{"a": [1, 2], "b": "c"}"#).unwrap();
+        let run = |s: &str| Value::from(Query::parse(s).unwrap().execute(&v).unwrap()).to_string();
+        assert_eq!(r#"[{"a":[1,2],"c":"c"}]"#, run("{a, (.b): .b}"));
+        assert_eq!(r#"[{"x":1},{"x":2}]"#, run("{x: .a[]}"));
+        assert_eq!(r#"[{"b":"c","x":1},{"b":"c","x":2}]"#, run("{x: .a[], b}"));
+        assert_eq!(r#"[]"#, run("{x: .a[], b: .a[1:1][]}"));
+        assert_eq!(r#"[{"b":2}]"#, run(r#"{b: 1, "b": 2}"#));
+        assert_eq!(r#"[{}]"#, run("{}"));
+        assert!(Query::parse("{(.a): 1}").unwrap().execute(&v).is_err());
+    }
 }
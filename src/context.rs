@@ -0,0 +1,569 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt, iter, mem,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+
+use crate::{query::Query, QueryError, QueryResult};
+
+/// A function implemented in Rust, called with one value for each argument
+/// and the input of the call.
+pub type Builtin = Arc<dyn Fn(&[Value], &Value) -> QueryResult + Send + Sync>;
+
+/// A custom `@name` format implemented in Rust, called with each value to
+/// format.
+pub type Formatter = Arc<dyn Fn(&Value) -> Result<String, QueryError> + Send + Sync>;
+
+/// Everything a query can use from outside its own text, shared by every
+/// execution it is passed to.
+#[derive(Clone, Default)]
+pub struct Context {
+    builtins: HashMap<(String, usize), Builtin>,
+    formats: HashMap<String, Formatter>,
+    variables: HashMap<String, Value>,
+    limits: Limits,
+    cancelled: Option<Arc<AtomicBool>>,
+    slurp: bool,
+    null_input: bool,
+    #[cfg(feature = "parallel")]
+    parallel: Option<usize>,
+    tracer: Option<Arc<dyn Tracer + Send + Sync>>,
+}
+
+/// A hook for observing execution, such as for debugging or coverage.
+pub trait Tracer {
+    /// Called as every stage begins, with its input.
+    fn begin(&self, _query: &Query, _input: &Value) {}
+
+    /// Called as every stage finishes, with its input and the outputs or error it produced.
+    fn trace(&self, query: &Query, input: &Value, result: &QueryResult);
+}
+
+/// How deeply function calls nest unless the limits say otherwise, which
+/// leaves room on the stack of a main thread for calls which don't recurse
+/// in place.
+pub const DEPTH: usize = 1000;
+
+/// Bounds on the resources of a single execution, where `None` is unbounded.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// How deeply function calls may nest, by default [`DEPTH`].
+    pub depth: Option<usize>,
+    /// How many values the query may produce.
+    pub outputs: Option<usize>,
+    /// Approximately how many bytes of new values the query may build.
+    pub memory: Option<usize>,
+    /// How long the query may run for.
+    pub time: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            depth: Some(DEPTH),
+            outputs: None,
+            memory: None,
+            time: None,
+        }
+    }
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `name/arity` callable from queries, unless shadowed by a `def`.
+    ///
+    /// Like jq's own builtins, `f` is called for every combination of the
+    /// outputs of the arguments, with later arguments varying slowest.
+    pub fn register<F>(&mut self, name: &str, arity: usize, f: F) -> &mut Self
+    where
+        F: Fn(&[Value], &Value) -> QueryResult + Send + Sync + 'static,
+    {
+        self.builtins.insert((name.to_string(), arity), Arc::new(f));
+        self
+    }
+
+    /// Makes `@name` format its input, or each interpolation of a string
+    /// after it, with `f`.
+    ///
+    /// rq's own formats such as `@csv` can't be replaced.
+    pub fn register_format<F>(&mut self, name: &str, f: F) -> &mut Self
+    where
+        F: Fn(&Value) -> Result<String, QueryError> + Send + Sync + 'static,
+    {
+        self.formats.insert(name.to_string(), Arc::new(f));
+        self
+    }
+
+    /// Binds `$name` for every query, like jq's `--arg` and `--argjson`.
+    pub fn var<V: Into<Value>>(&mut self, name: &str, value: V) -> &mut Self {
+        self.variables.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// Bounds every execution, failing with [`QueryError::LimitExceeded`] beyond them.
+    pub fn limits(&mut self, limits: Limits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Aborts executions with [`QueryError::Cancelled`] once `flag` is set,
+    /// which is checked between evaluation steps.
+    pub fn cancel_with(&mut self, flag: Arc<AtomicBool>) -> &mut Self {
+        self.cancelled = Some(flag);
+        self
+    }
+
+    /// Gathers every input of a stream into one array to execute against, like jq's `-s`.
+    pub fn slurp(&mut self, slurp: bool) -> &mut Self {
+        self.slurp = slurp;
+        self
+    }
+
+    /// Executes a stream once against `null`, leaving every input to `input`
+    /// and `inputs`, like jq's `-n`.
+    pub fn null_input(&mut self, null_input: bool) -> &mut Self {
+        self.null_input = null_input;
+        self
+    }
+
+    /// Evaluates `f` in `.[] | f` for the elements of arrays with at least
+    /// `threshold` of them on as many threads as the machine has cores, when
+    /// `f` depends on nothing but its input, such as no function calls or
+    /// variables.
+    ///
+    /// The threads share the limits of the execution, and tracing turns
+    /// this off.
+    #[cfg(feature = "parallel")]
+    pub fn parallel(&mut self, threshold: Option<usize>) -> &mut Self {
+        self.parallel = threshold;
+        self
+    }
+
+    /// Observes every stage of every execution, which also disables
+    /// evaluation which moves values instead of copying them.
+    pub fn trace_with<T: Tracer + Send + Sync + 'static>(&mut self, tracer: T) -> &mut Self {
+        self.tracer = Some(Arc::new(tracer));
+        self
+    }
+
+    pub(crate) fn variable(&self, name: &str) -> Option<&Value> {
+        self.variables.get(name)
+    }
+
+    pub(crate) fn builtin(&self, name: &str, arity: usize) -> Option<&Builtin> {
+        self.builtins.get(&(name.to_string(), arity))
+    }
+
+    pub(crate) fn format(&self, name: &str) -> Option<&Formatter> {
+        self.formats.get(name)
+    }
+
+    #[cfg(feature = "parallel")]
+    pub(crate) fn parallel_threshold(&self) -> Option<usize> {
+        self.parallel
+    }
+}
+
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Context");
+        s.field("builtins", &self.builtins.keys().collect::<Vec<_>>())
+            .field("formats", &self.formats.keys().collect::<Vec<_>>())
+            .field("variables", &self.variables)
+            .field("limits", &self.limits)
+            .field("cancelled", &self.cancelled)
+            .field("slurp", &self.slurp)
+            .field("null_input", &self.null_input);
+        #[cfg(feature = "parallel")]
+        s.field("parallel", &self.parallel);
+        s.field("tracer", &self.tracer.is_some()).finish()
+    }
+}
+
+/// The state of one execution within a context.
+pub(crate) struct Execution<'a> {
+    pub context: &'a Context,
+    started: Instant,
+    /// Shared with any threads evaluating part of the execution.
+    memory: Arc<AtomicUsize>,
+    /// Documents not yet taken by `input`, `inputs` or the execution itself.
+    inputs: RefCell<Box<dyn Iterator<Item = Value> + 'a>>,
+    /// The outputs of queries which don't depend on their input, by where
+    /// the query is, as they were first evaluated.
+    memo: RefCell<HashMap<*const Query, Vec<Value>>>,
+    /// The last string indexed by codepoint, with where each codepoint starts.
+    boundaries: RefCell<Option<(String, Rc<[usize]>)>>,
+}
+
+impl<'a> Execution<'a> {
+    pub fn new(context: &'a Context) -> Self {
+        Self::with_inputs(context, Box::new(iter::empty()))
+    }
+
+    pub fn with_inputs(context: &'a Context, inputs: Box<dyn Iterator<Item = Value> + 'a>) -> Self {
+        let inputs = match context.slurp {
+            true => Box::new(iter::once_with(move || Value::Array(inputs.collect()))),
+            false => inputs,
+        };
+        Execution {
+            context,
+            started: Instant::now(),
+            memory: Arc::new(AtomicUsize::new(0)),
+            inputs: RefCell::new(inputs),
+            memo: RefCell::new(HashMap::new()),
+            boundaries: RefCell::new(None),
+        }
+    }
+
+    pub fn next_input(&self) -> Option<Value> {
+        self.inputs.borrow_mut().next()
+    }
+
+    /// The values a stream executes against, each taken only when needed.
+    pub fn documents(self: &Rc<Self>) -> Box<dyn Iterator<Item = Value> + 'a> {
+        match self.context.null_input {
+            true => Box::new(iter::once(Value::Null)),
+            false => {
+                let execution = self.clone();
+                Box::new(iter::from_fn(move || execution.next_input()))
+            }
+        }
+    }
+
+    /// Checks for cancellation and the limits which apply to every step of evaluation.
+    pub fn step(&self) -> Result<(), QueryError> {
+        if let Some(flag) = &self.context.cancelled {
+            if flag.load(Ordering::Relaxed) {
+                return Err(QueryError::Cancelled);
+            }
+        }
+        match self.context.limits.time {
+            Some(time) if self.started.elapsed() > time => Err(QueryError::LimitExceeded("time")),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_depth(&self, depth: usize) -> Result<(), QueryError> {
+        match self.context.limits.depth {
+            Some(max) if depth > max => Err(QueryError::LimitExceeded("depth")),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_outputs(&self, outputs: usize) -> Result<(), QueryError> {
+        match self.context.limits.outputs {
+            Some(max) if outputs > max => Err(QueryError::LimitExceeded("output")),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn tracing(&self) -> bool {
+        self.context.tracer.is_some()
+    }
+
+    pub fn begin(&self, query: &Query, input: &Value) {
+        if let Some(tracer) = &self.context.tracer {
+            tracer.begin(query, input);
+        }
+    }
+
+    pub fn trace(&self, query: &Query, input: &Value, result: &QueryResult) {
+        if let Some(tracer) = &self.context.tracer {
+            tracer.trace(query, input, result);
+        }
+    }
+
+    /// The outputs of `query` as `f` first evaluated them in this execution.
+    ///
+    /// Only queries whose outputs are the same for any input can be
+    /// remembered, since inputs have no identity which outlives them.
+    pub fn memoize<F>(&self, query: &Query, f: F) -> QueryResult
+    where
+        F: FnOnce() -> QueryResult,
+    {
+        let key = query as *const Query;
+        if let Some(values) = self.memo.borrow().get(&key) {
+            return Ok(values.clone());
+        }
+        let values = f()?;
+        self.memo.borrow_mut().insert(key, values.clone());
+        Ok(values)
+    }
+
+    /// The byte offset where each codepoint of `s` starts, followed by its
+    /// length.
+    ///
+    /// Those of the last string are kept, so that slicing the same string
+    /// again, as `.[0:100], .[100:200]` does, doesn't walk it again.
+    pub fn boundaries(&self, s: &str) -> Rc<[usize]> {
+        if let Some((last, boundaries)) = &*self.boundaries.borrow() {
+            if last == s {
+                return boundaries.clone();
+            }
+        }
+        let boundaries: Rc<[usize]> = s
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(iter::once(s.len()))
+            .collect();
+        *self.boundaries.borrow_mut() = Some((s.to_string(), boundaries.clone()));
+        boundaries
+    }
+
+    /// What a thread evaluating part of the execution, from `depth` calls
+    /// deep, needs to be bound by the same limits and cancellation.
+    #[cfg(feature = "parallel")]
+    pub fn worker(&self, depth: usize) -> Worker<'a> {
+        Worker {
+            context: self.context,
+            started: self.started,
+            memory: self.memory.clone(),
+            depth,
+        }
+    }
+
+    /// Checks that `bytes` more would fit in the memory limit, before a
+    /// step builds them.
+    pub fn check_memory(&self, bytes: usize) -> Result<(), QueryError> {
+        match self.context.limits.memory {
+            Some(max) if self.memory.load(Ordering::Relaxed).saturating_add(bytes) > max => {
+                Err(QueryError::LimitExceeded("memory"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Accounts for values newly built by a step.
+    pub fn allocate(&self, values: &[Value]) -> Result<(), QueryError> {
+        if let Some(max) = self.context.limits.memory {
+            let n = values.iter().map(size).sum::<usize>();
+            let used = self.memory.fetch_add(n, Ordering::Relaxed) + n;
+            if used > max {
+                return Err(QueryError::LimitExceeded("memory"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The part of an execution which other threads share.
+#[cfg(feature = "parallel")]
+pub(crate) struct Worker<'a> {
+    context: &'a Context,
+    started: Instant,
+    memory: Arc<AtomicUsize>,
+    pub depth: usize,
+}
+
+#[cfg(feature = "parallel")]
+impl<'a> Worker<'a> {
+    /// An execution on this thread, which has no inputs of its own but
+    /// started when the one it is part of did.
+    pub fn execution(&self) -> Execution<'a> {
+        Execution {
+            started: self.started,
+            memory: self.memory.clone(),
+            ..Execution::new(self.context)
+        }
+    }
+}
+
+fn size(v: &Value) -> usize {
+    mem::size_of::<Value>()
+        + match v {
+            Value::String(s) => s.len(),
+            Value::Array(arr) => arr.iter().map(size).sum(),
+            Value::Object(map) => map.iter().map(|(k, v)| k.len() + size(v)).sum(),
+            _ => 0,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn limited(limits: Limits) -> Context {
+        let mut context = Context::new();
+        context.limits(limits);
+        context
+    }
+
+    #[test]
+    fn allocate() {
+        let context = limited(Limits {
+            memory: Some(3 * size(&json!("abc"))),
+            ..Default::default()
+        });
+        let execution = Execution::new(&context);
+        assert!(execution.allocate(&[json!("abc")]).is_ok());
+        assert!(execution.allocate(&[json!("abc"), json!("abc")]).is_ok());
+        assert!(matches!(
+            execution.allocate(&[json!(null)]),
+            Err(QueryError::LimitExceeded("memory"))
+        ));
+
+        // Each execution starts from nothing
+        assert!(Execution::new(&context).allocate(&[json!("abc")]).is_ok());
+        let unlimited = Context::new();
+        assert!(Execution::new(&unlimited)
+            .allocate(&[json!([1, 2, 3])])
+            .is_ok());
+    }
+
+    #[test]
+    fn size_of_values() {
+        let unit = mem::size_of::<Value>();
+        assert_eq!(unit, size(&json!(1)));
+        assert_eq!(unit + 3, size(&json!("abc")));
+        assert_eq!(3 * unit, size(&json!([1, 2])));
+        assert_eq!(2 * unit + 1, size(&json!({"a": null})));
+    }
+
+    #[test]
+    fn depth_and_outputs() {
+        let context = limited(Limits {
+            depth: Some(2),
+            outputs: Some(0),
+            ..Default::default()
+        });
+        let execution = Execution::new(&context);
+        assert!(execution.check_depth(2).is_ok());
+        assert!(matches!(
+            execution.check_depth(3),
+            Err(QueryError::LimitExceeded("depth"))
+        ));
+        assert!(execution.check_outputs(0).is_ok());
+        assert!(matches!(
+            execution.check_outputs(1),
+            Err(QueryError::LimitExceeded("output"))
+        ));
+
+        let context = Context::new();
+        let execution = Execution::new(&context);
+        assert!(execution.check_depth(DEPTH).is_ok());
+        assert!(execution.check_depth(DEPTH + 1).is_err());
+        assert!(execution.check_outputs(usize::MAX).is_ok());
+
+        let unlimited = limited(Limits {
+            depth: None,
+            ..Default::default()
+        });
+        assert!(Execution::new(&unlimited).check_depth(usize::MAX).is_ok());
+    }
+
+    #[test]
+    fn step() {
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut context = Context::new();
+        context.cancel_with(flag.clone());
+        let execution = Execution::new(&context);
+        assert!(execution.step().is_ok());
+        flag.store(true, Ordering::Relaxed);
+        assert!(matches!(execution.step(), Err(QueryError::Cancelled)));
+
+        let context = limited(Limits {
+            time: Some(Duration::ZERO),
+            ..Default::default()
+        });
+        let execution = Execution::new(&context);
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(matches!(
+            execution.step(),
+            Err(QueryError::LimitExceeded("time"))
+        ));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn worker() {
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut context = limited(Limits {
+            memory: Some(2 * size(&json!(0))),
+            time: Some(Duration::from_millis(20)),
+            ..Default::default()
+        });
+        context.cancel_with(flag.clone());
+        let execution = Execution::new(&context);
+        let worker = execution.worker(3);
+        assert_eq!(3, worker.depth);
+
+        // Memory is counted against the execution, both ways
+        assert!(execution.allocate(&[json!(0)]).is_ok());
+        assert!(worker.execution().allocate(&[json!(0)]).is_ok());
+        assert!(execution.allocate(&[json!(0)]).is_err());
+
+        // As is time from when the execution, not the thread, began
+        assert!(worker.execution().step().is_ok());
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(matches!(
+            worker.execution().step(),
+            Err(QueryError::LimitExceeded("time"))
+        ));
+        assert!(Execution::new(&context).step().is_ok());
+
+        flag.store(true, Ordering::Relaxed);
+        assert!(matches!(
+            worker.execution().step(),
+            Err(QueryError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn memoize() {
+        let context = Context::new();
+        let execution = Execution::new(&context);
+        let (a, b) = (Query::Identity, Query::Identity);
+        let calls = Cell::new(0);
+        let f = || {
+            calls.set(calls.get() + 1);
+            Ok(vec![json!(calls.get())])
+        };
+        assert_eq!(vec![json!(1)], execution.memoize(&a, f).unwrap());
+        assert_eq!(vec![json!(1)], execution.memoize(&a, f).unwrap());
+        // Queries are told apart by where they are, not what they are
+        assert_eq!(vec![json!(2)], execution.memoize(&b, f).unwrap());
+        // Errors aren't remembered
+        assert!(execution
+            .memoize(&Query::Empty, || Err(QueryError::Cancelled))
+            .is_err());
+        assert_eq!(vec![json!(3)], execution.memoize(&Query::Empty, f).unwrap());
+        assert_eq!(3, calls.get());
+    }
+
+    #[test]
+    fn boundaries() {
+        let context = Context::new();
+        let execution = Execution::new(&context);
+        let boundaries = execution.boundaries("h🦀o");
+        assert_eq!(&[0, 1, 5, 6][..], &*boundaries);
+        // Kept for an equal string, wherever it is
+        let copy = String::from("h🦀o");
+        assert!(Rc::ptr_eq(&boundaries, &execution.boundaries(&copy)));
+        assert_eq!(&[0, 2][..], &*execution.boundaries("é"));
+        assert!(!Rc::ptr_eq(&boundaries, &execution.boundaries("h🦀o")));
+        assert_eq!(&[0][..], &*execution.boundaries(""));
+    }
+
+    #[test]
+    fn slurp() {
+        let mut context = Context::new();
+        context.slurp(true);
+        let execution = Execution::with_inputs(&context, Box::new((1..=3).map(Value::from)));
+        assert_eq!(Some(json!([1, 2, 3])), execution.next_input());
+        assert_eq!(None, execution.next_input());
+    }
+}
@@ -0,0 +1,291 @@
+use serde_json::{Map, Value};
+use std::{collections::HashSet, iter::Peekable};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CsvError {
+    #[error("Unterminated quoted field starting at line {0}")]
+    Unterminated(usize),
+    #[error("Expected {1} fields at line {0} but found {2}")]
+    Fields(usize, usize, usize),
+}
+
+/// How delimited text is read by [`from_str`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvOptions {
+    /// The character between fields.
+    pub delimiter: char,
+    /// The character a field containing delimiters or line breaks is quoted
+    /// with, written twice for the character itself.
+    pub quote: Option<char>,
+    /// Whether `\t`, `\n`, `\r` and `\\` stand for the characters `@tsv`
+    /// escapes with them.
+    pub escapes: bool,
+    /// Whether the first row names the fields, making every other row an
+    /// object rather than an array.
+    pub header: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            quote: Some('"'),
+            escapes: false,
+            header: true,
+        }
+    }
+}
+
+impl CsvOptions {
+    /// Tab separated fields as `@tsv` writes them, unquoted but escaped.
+    pub fn tsv() -> Self {
+        CsvOptions {
+            delimiter: '\t',
+            quote: None,
+            escapes: true,
+            header: true,
+        }
+    }
+}
+
+/// Reads delimited text as an array of its rows, each field a string.
+///
+/// Blank lines are skipped. With a header every row must have as many
+/// fields as it does, and a name the header repeats is numbered after the
+/// first, as `a`, `a_2`, `a_3`, so that no field is lost.
+pub fn from_str(text: &str, options: &CsvOptions) -> Result<Value, CsvError> {
+    let mut rows = Rows {
+        chars: text.chars().peekable(),
+        line: 1,
+        options,
+    };
+    let mut records = Vec::new();
+    let header = match options.header {
+        true => rows.next().transpose()?.map(|(_, names)| unique(names)),
+        false => None,
+    };
+    for row in rows {
+        let (line, fields) = row?;
+        records.push(match &header {
+            Some(names) if names.len() != fields.len() => {
+                return Err(CsvError::Fields(line, names.len(), fields.len()))
+            }
+            Some(names) => Value::Object(
+                names
+                    .iter()
+                    .cloned()
+                    .zip(fields.into_iter().map(Value::from))
+                    .collect::<Map<_, _>>(),
+            ),
+            None => Value::from(fields),
+        });
+    }
+    Ok(Value::Array(records))
+}
+
+/// Names which are each different from the others, numbering those already
+/// taken.
+fn unique(names: Vec<String>) -> Vec<String> {
+    let mut taken = HashSet::new();
+    names
+        .into_iter()
+        .map(|name| {
+            let mut unique = name.clone();
+            let mut n = 1;
+            while !taken.insert(unique.clone()) {
+                n += 1;
+                unique = format!("{}_{}", name, n);
+            }
+            unique
+        })
+        .collect()
+}
+
+/// The fields of each row along with the line it starts on.
+struct Rows<'a, I: Iterator<Item = char>> {
+    chars: Peekable<I>,
+    line: usize,
+    options: &'a CsvOptions,
+}
+
+impl<I: Iterator<Item = char>> Rows<'_, I> {
+    fn row(&mut self) -> Result<Vec<String>, CsvError> {
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        loop {
+            match self.chars.next() {
+                Some(c) if Some(c) == self.options.quote && field.is_empty() && !quoted => {
+                    quoted = true;
+                    self.quoted(&mut field, c)?;
+                }
+                Some(c) if c == self.options.delimiter => {
+                    fields.push(self.field(&mut field, quoted));
+                    quoted = false;
+                }
+                Some('\r') if self.chars.peek() == Some(&'\n') => {}
+                Some('\n') => {
+                    self.line += 1;
+                    break;
+                }
+                Some(c) => field.push(c),
+                None => break,
+            }
+        }
+        if quoted || !fields.is_empty() || !field.is_empty() {
+            fields.push(self.field(&mut field, quoted));
+        }
+        Ok(fields)
+    }
+
+    /// Reads the rest of a quoted field, up to the closing quote.
+    fn quoted(&mut self, field: &mut String, quote: char) -> Result<(), CsvError> {
+        let start = self.line;
+        loop {
+            match self.chars.next() {
+                Some(c) if c == quote => match self.chars.peek() {
+                    Some(&next) if next == quote => {
+                        self.chars.next();
+                        field.push(quote);
+                    }
+                    _ => return Ok(()),
+                },
+                Some(c) => {
+                    if c == '\n' {
+                        self.line += 1;
+                    }
+                    field.push(c);
+                }
+                None => return Err(CsvError::Unterminated(start)),
+            }
+        }
+    }
+
+    fn field(&self, field: &mut String, quoted: bool) -> String {
+        let field = std::mem::take(field);
+        match self.options.escapes && !quoted {
+            true => unescape(&field),
+            false => field,
+        }
+    }
+}
+
+impl<I: Iterator<Item = char>> Iterator for Rows<'_, I> {
+    type Item = Result<(usize, Vec<String>), CsvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.chars.peek().is_some() {
+            let line = self.line;
+            match self.row() {
+                Ok(fields) if fields.is_empty() => continue,
+                row => return Some(row.map(|fields| (line, fields))),
+            }
+        }
+        None
+    }
+}
+
+fn unescape(field: &str) -> String {
+    let mut text = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('t') => text.push('\t'),
+                Some('n') => text.push('\n'),
+                Some('r') => text.push('\r'),
+                Some(c) => text.push(c),
+                None => text.push('\\'),
+            },
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        query::{Executable, Query},
+        QueryError,
+    };
+
+    #[test]
+    fn read_csv() {
+        let options = CsvOptions::default();
+        let text = "name,note\r\nrq,\"a, \"\"b\"\"\nc\"\n\njq,\n";
+        assert_eq!(
+            Ok(json!([
+                {"name": "rq", "note": "a, \"b\"\nc"},
+                {"name": "jq", "note": ""}
+            ])),
+            from_str(text, &options)
+        );
+        assert_eq!(Ok(json!([])), from_str("", &options));
+        assert_eq!(
+            Err(CsvError::Fields(3, 2, 1)),
+            from_str("a,b\n1,2\n3\n", &options)
+        );
+        assert_eq!(
+            Err(CsvError::Unterminated(2)),
+            from_str("a\n\"1\n", &options)
+        );
+        assert_eq!(
+            Ok(json!([{"a": "1", "b": "2", "a_2": "3", "a_3": "4", "a_2_2": "5"}])),
+            from_str("a,b,a,a,a_2\n1,2,3,4,5\n", &options)
+        );
+
+        let options = CsvOptions {
+            delimiter: ';',
+            quote: Some('\''),
+            header: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            Ok(json!([["1", "a;b"], ["2"], [""]])),
+            from_str("1;'a;b'\n2\n''", &options)
+        );
+    }
+
+    #[test]
+    fn read_tsv() {
+        let text = "a\tb\nx\\ty\t\"z\"\\\\\n";
+        assert_eq!(
+            Ok(json!([{"a": "x\ty", "b": "\"z\"\\"}])),
+            from_str(text, &CsvOptions::tsv())
+        );
+    }
+
+    #[test]
+    fn delimited_builtins() {
+        let v = json!({"csv": "a,\"b,c\"\n1,\n", "tsv": "a\\tb\tc\n"});
+        let q: Query = ".csv | fromcsv".parse().unwrap();
+        assert_eq!(
+            vec![json!([["a", "b,c"], ["1", ""]])],
+            q.execute(&v).unwrap()
+        );
+        let q: Query = ".tsv | fromtsv".parse().unwrap();
+        assert_eq!(vec![json!([["a\tb", "c"]])], q.execute(&v).unwrap());
+
+        let q: Query = ".csv | fromcsv | tocsv".parse().unwrap();
+        assert_eq!(
+            vec![json!("\"a\",\"b,c\"\n\"1\",\"\"\n")],
+            q.execute(&v).unwrap()
+        );
+        let q: Query = ".tsv | fromtsv | totsv".parse().unwrap();
+        assert_eq!(vec![v["tsv"].clone()], q.execute(&v).unwrap());
+
+        let error = |s: &str| s.parse::<Query>().unwrap().execute(&v).unwrap_err();
+        assert!(matches!(error("fromcsv"), QueryError::Parse("csv", _)));
+        assert_eq!(
+            "Cannot parse CSV: Unterminated quoted field starting at line 1",
+            error(r#".csv |= "\"" | .csv | fromcsv"#).to_string()
+        );
+        assert!(matches!(error("tocsv"), QueryError::Format("csv", _)));
+        assert!(matches!(error("[1] | tocsv"), QueryError::Format("csv", _)));
+    }
+}
@@ -0,0 +1,359 @@
+use serde_core::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Value};
+use std::convert::TryFrom;
+
+use crate::{
+    combinator::{Chain, Optional, Split},
+    construction::{Construct, Key},
+    format::{Format, Part, Template},
+    function::{Call, Define, Function},
+    index::Index,
+    module::Import,
+    operators::{Op, Sign},
+    parse::ParseError,
+    query::Query,
+    range::Range,
+    raw::Raw,
+    reduce::Reduce,
+    span::Spanned,
+    update::{Assign, Update},
+};
+
+/// Conversion of a query to and from JSON, so a parsed query can be cached
+/// and restored without parsing its text again.
+///
+/// Every node is an array of its name followed by its fields, such as
+/// `["chain", ["index", "foo"], ["iterator"]]` for `.foo[]`.
+impl Query {
+    pub fn encode(&self) -> Value {
+        match self {
+            Query::Empty => json!(["empty"]),
+            Query::Identity => json!(["identity"]),
+            Query::Iterator => json!(["iterator"]),
+            Query::Recurse => json!(["recurse"]),
+            Query::Index(Index::String(s)) => json!(["index", s]),
+            Query::Index(Index::Integer(i)) => json!(["index", i]),
+            Query::Index(Index::Slice(Range(from, to))) => json!(["slice", from, to]),
+            Query::Split(split) => json!(["split", split.0.encode(), split.1.encode()]),
+            Query::Chain(chain) => json!(["chain", chain.0.encode(), chain.1.encode()]),
+            Query::Construct(Construct::Array(inner)) => json!(["array", inner.encode()]),
+            Query::Construct(Construct::Object(kvs)) => {
+                let kvs: Vec<_> = kvs
+                    .iter()
+                    .map(|(k, v)| match k {
+                        Key::Simple(s) => json!([s, v.encode()]),
+                        Key::Query(q) => json!([q.encode(), v.encode()]),
+                    })
+                    .collect();
+                json!(["object", kvs])
+            }
+            Query::Optional(opt) => json!(["optional", opt.0.encode()]),
+            Query::Raw(Raw(v)) => json!(["literal", v]),
+            Query::Op(op) => json!([sign_name(&op.sign), op.left.encode(), op.right.encode()]),
+            Query::Update(update) => {
+                let name = match update.assign {
+                    Assign::Set => "set",
+                    Assign::Modify => "modify",
+                };
+                json!([name, update.path.encode(), update.value.encode()])
+            }
+            Query::Call(call) => json!(["call", call.name, encode_all(&call.args)]),
+            Query::Define(define) => {
+                let f = &define.function;
+                json!([
+                    "def",
+                    f.name,
+                    f.params,
+                    f.body.encode(),
+                    define.rest.encode()
+                ])
+            }
+            Query::Import(import) => json!([
+                "import",
+                import.path,
+                import.alias,
+                import.module.encode(),
+                import.rest.encode()
+            ]),
+            Query::Format(f) => json!(["format", f.name()]),
+            Query::Template(t) => {
+                let parts: Vec<_> = t
+                    .parts
+                    .iter()
+                    .map(|p| match p {
+                        Part::Literal(s) => json!(s),
+                        Part::Query(q) => q.encode(),
+                    })
+                    .collect();
+                json!(["template", t.format.name(), parts])
+            }
+            Query::Variable(name) => json!(["var", name]),
+            Query::Reduce(reduce) => json!([
+                "reduce",
+                reduce.source.encode(),
+                reduce.name,
+                reduce.init.encode(),
+                reduce.update.encode()
+            ]),
+            Query::Spanned(s) => json!(["span", s.span.start, s.span.end, s.query.encode()]),
+        }
+    }
+
+    /// Restores a query from the output of [`Query::encode`].
+    pub fn decode(value: &Value) -> Result<Query, ParseError> {
+        let node = value.as_array().and_then(|node| node.split_first());
+        let (name, fields) = match node {
+            Some((Value::String(name), fields)) => (name.as_str(), fields),
+            _ => {
+                return Err(ParseError::Encoding(
+                    "a node must start with its name".into(),
+                ))
+            }
+        };
+        let invalid = || ParseError::Encoding(format!("invalid fields of {}", name));
+
+        let query = match (name, fields) {
+            ("empty", []) => Query::Empty,
+            ("identity", []) => Query::Identity,
+            ("iterator", []) => Query::Iterator,
+            ("recurse", []) => Query::Recurse,
+            ("index", [Value::String(s)]) => Query::Index(Index::String(s.clone())),
+            ("index", [i]) => Query::Index(Index::Integer(integer(i).ok_or_else(invalid)?)),
+            ("slice", [from, to]) => {
+                let bound = |b: &Value| match b {
+                    Value::Null => Ok(None),
+                    b => integer(b).map(Some).ok_or_else(invalid),
+                };
+                Query::Index(Index::Slice(Range(bound(from)?, bound(to)?)))
+            }
+            ("split", [a, b]) => {
+                Query::Split(Box::new(Split(Query::decode(a)?, Query::decode(b)?)))
+            }
+            ("chain", [a, b]) => {
+                Query::Chain(Box::new(Chain(Query::decode(a)?, Query::decode(b)?)))
+            }
+            ("array", [inner]) => {
+                Query::Construct(Construct::Array(Box::new(Query::decode(inner)?)))
+            }
+            ("object", [Value::Array(kvs)]) => {
+                let kvs = kvs
+                    .iter()
+                    .map(|kv| match kv.as_array().map(Vec::as_slice) {
+                        Some([Value::String(k), v]) => {
+                            Ok((Key::Simple(k.clone()), Query::decode(v)?))
+                        }
+                        Some([k, v]) => Ok((Key::Query(Query::decode(k)?), Query::decode(v)?)),
+                        _ => Err(invalid()),
+                    })
+                    .collect::<Result<_, _>>()?;
+                Query::Construct(Construct::Object(kvs))
+            }
+            ("optional", [inner]) => Query::Optional(Box::new(Optional(Query::decode(inner)?))),
+            ("literal", [v]) => Query::Raw(Raw(v.clone())),
+            ("call", [Value::String(name), Value::Array(args)]) => {
+                Query::Call(Call::new(name, decode_all(args)?))
+            }
+            ("def", [Value::String(name), Value::Array(params), body, rest]) => {
+                let params = params
+                    .iter()
+                    .map(|p| p.as_str().map(str::to_string))
+                    .collect::<Option<_>>()
+                    .ok_or_else(invalid)?;
+                Query::Define(Box::new(Define {
+                    function: Function {
+                        name: name.clone(),
+                        params,
+                        body: Query::decode(body)?,
+                    },
+                    rest: Query::decode(rest)?,
+                }))
+            }
+            ("import", [Value::String(path), alias, module, rest]) => {
+                let alias = match alias {
+                    Value::Null => None,
+                    Value::String(alias) => Some(alias.clone()),
+                    _ => return Err(invalid()),
+                };
+                Query::Import(Box::new(Import {
+                    path: path.clone(),
+                    alias,
+                    module: Query::decode(module)?,
+                    rest: Query::decode(rest)?,
+                }))
+            }
+            ("format", [f]) => Query::Format(format(f).ok_or_else(invalid)?),
+            ("template", [f, Value::Array(parts)]) => {
+                let parts = parts
+                    .iter()
+                    .map(|p| match p {
+                        Value::String(s) => Ok(Part::Literal(s.clone())),
+                        p => Query::decode(p).map(Part::Query),
+                    })
+                    .collect::<Result<_, _>>()?;
+                Query::Template(Template {
+                    format: format(f).ok_or_else(invalid)?,
+                    parts,
+                })
+            }
+            ("var", [Value::String(name)]) => Query::Variable(name.clone()),
+            ("reduce", [source, Value::String(name), init, update]) => {
+                Query::Reduce(Box::new(Reduce {
+                    source: Query::decode(source)?,
+                    name: name.clone(),
+                    init: Query::decode(init)?,
+                    update: Query::decode(update)?,
+                }))
+            }
+            ("span", [start, end, inner]) => {
+                let offset = |v: &Value| {
+                    v.as_u64()
+                        .and_then(|i| usize::try_from(i).ok())
+                        .ok_or_else(invalid)
+                };
+                Query::Spanned(Box::new(Spanned {
+                    span: offset(start)?..offset(end)?,
+                    query: Query::decode(inner)?,
+                }))
+            }
+            (name @ ("set" | "modify"), [path, value]) => Query::Update(Box::new(Update {
+                path: Query::decode(path)?,
+                assign: if name == "set" {
+                    Assign::Set
+                } else {
+                    Assign::Modify
+                },
+                value: Query::decode(value)?,
+            })),
+            (name, [left, right]) => match sign(name) {
+                Some(sign) => Query::Op(Box::new(Op {
+                    left: Query::decode(left)?,
+                    sign,
+                    right: Query::decode(right)?,
+                })),
+                None => return Err(invalid()),
+            },
+            _ => return Err(invalid()),
+        };
+        Ok(query)
+    }
+}
+
+impl Serialize for Query {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.encode().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Query {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        Query::decode(&value).map_err(de::Error::custom)
+    }
+}
+
+fn encode_all(queries: &[Query]) -> Vec<Value> {
+    queries.iter().map(Query::encode).collect()
+}
+
+fn decode_all(values: &[Value]) -> Result<Vec<Query>, ParseError> {
+    values.iter().map(Query::decode).collect()
+}
+
+fn integer(v: &Value) -> Option<i32> {
+    v.as_i64().and_then(|i| i32::try_from(i).ok())
+}
+
+/// A format by the name `@name` would parse, custom unless rq has its own.
+fn format(v: &Value) -> Option<Format> {
+    v.as_str()
+        .filter(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric()))
+        .map(Format::named)
+}
+
+fn sign_name(sign: &Sign) -> &'static str {
+    match sign {
+        Sign::Add => "add",
+        Sign::Sub => "sub",
+        Sign::Mul => "mul",
+        Sign::Div => "div",
+        Sign::Mod => "mod",
+        Sign::Lt => "lt",
+        Sign::Le => "le",
+        Sign::Gt => "gt",
+        Sign::Ge => "ge",
+        Sign::Eq => "eq",
+        Sign::Ne => "ne",
+    }
+}
+
+fn sign(name: &str) -> Option<Sign> {
+    match name {
+        "add" => Some(Sign::Add),
+        "sub" => Some(Sign::Sub),
+        "mul" => Some(Sign::Mul),
+        "div" => Some(Sign::Div),
+        "mod" => Some(Sign::Mod),
+        "lt" => Some(Sign::Lt),
+        "le" => Some(Sign::Le),
+        "gt" => Some(Sign::Gt),
+        "ge" => Some(Sign::Ge),
+        "eq" => Some(Sign::Eq),
+        "ne" => Some(Sign::Ne),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::ParseOptions;
+
+    #[test]
+    fn round_trip() {
+        for s in &[
+            "",
+            ".foo[1:][-1] | .[]?, ..",
+            r#"[.a, 1, "b", null] | {a, "b": .c, (.d): [.e]}"#,
+            ".a + .b * 2 - 3 / 4 % 5",
+            ".a < 1, .b <= 2, .c > 3, .d >= 4, .e == 5, .f != 6",
+            ".a[] = 1 | .b |= . + 1",
+            "def f(g; h): g | h; f(.; $x)",
+            "reduce .[] as $x (0; . + $x)",
+            r#"@base64, @csv "a \(.b) c", "\(1)""#,
+        ] {
+            let q: Query = s.parse().unwrap();
+            assert_eq!(q, Query::decode(&q.encode()).unwrap());
+
+            let text = serde_json::to_string(&q).unwrap();
+            assert_eq!(q, serde_json::from_str(&text).unwrap());
+        }
+
+        let options = ParseOptions {
+            spans: true,
+            ..Default::default()
+        };
+        let q = Query::parse_with(".a[0] + 1 | f", &options).unwrap();
+        assert_eq!(q, Query::decode(&q.encode()).unwrap());
+
+        assert_eq!(
+            json!(["chain", ["index", "foo"], ["iterator"]]),
+            ".foo[]".parse::<Query>().unwrap().encode()
+        );
+    }
+
+    #[test]
+    fn decode_errors() {
+        for v in &[
+            json!("identity"),
+            json!([]),
+            json!(["identity", 1]),
+            json!(["index", 1.5]),
+            json!(["slice", "a", null]),
+            json!(["template", "no pe", []]),
+            json!(["pow", ["identity"], ["identity"]]),
+        ] {
+            assert!(matches!(Query::decode(v), Err(ParseError::Encoding(_))));
+        }
+        assert!(serde_json::from_str::<Query>(r#"["call", "f"]"#).is_err());
+    }
+}
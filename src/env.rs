@@ -0,0 +1,344 @@
+use serde_json::Value;
+use std::{borrow::Cow, ops, rc::Rc};
+
+#[cfg(feature = "parallel")]
+use crate::context::Worker;
+use crate::{
+    context::{Builtin, Execution, Formatter, DEPTH},
+    function::Function,
+    query::Query,
+    range::Range,
+    QueryError, QueryResult,
+};
+
+/// How long a string has to be, in bytes, for the execution to keep where
+/// its codepoints are rather than walk it for each slice.
+const INDEXED: usize = 1024;
+
+/// The lexical scope a query is evaluated in, as a linked list of bindings
+/// borrowed from the query itself, along with the state of the execution.
+#[derive(Clone, Default)]
+pub(crate) struct Env<'a> {
+    scope: Option<Rc<Scope<'a>>>,
+    execution: Option<Rc<Execution<'a>>>,
+    /// How many function calls deep the scope is.
+    depth: usize,
+    /// Whether the scope binds any variables of its own.
+    variables: bool,
+}
+
+struct Scope<'a> {
+    binding: Binding<'a>,
+    parent: Env<'a>,
+}
+
+pub(crate) enum Binding<'a> {
+    /// A `def`, visible to its own body and everything after it.
+    Function(&'a Function),
+    /// A filter argument, evaluated in the scope of the caller.
+    Closure(&'a str, &'a Query, Env<'a>),
+    /// The definitions of a library, optionally qualified by an alias.
+    Module(Option<&'a str>, Env<'a>),
+    /// `$name`, bound by `reduce` for each output of its source.
+    Variable(&'a str, Value),
+}
+
+pub(crate) enum Callable<'a> {
+    Function(&'a Function, Env<'a>),
+    Closure(&'a Query, Env<'a>),
+    Builtin(&'a Builtin),
+}
+
+impl<'a> Env<'a> {
+    pub fn new(execution: Rc<Execution<'a>>) -> Self {
+        Env {
+            scope: None,
+            execution: Some(execution),
+            depth: 0,
+            variables: false,
+        }
+    }
+
+    /// An empty scope on another thread, within the execution `worker` is
+    /// part of.
+    #[cfg(feature = "parallel")]
+    pub fn within(worker: &Worker<'a>) -> Self {
+        Env {
+            depth: worker.depth,
+            ..Env::new(Rc::new(worker.execution()))
+        }
+    }
+
+    /// An empty scope within the same execution, as seen by a library.
+    pub fn root(&self) -> Env<'a> {
+        Env {
+            scope: None,
+            variables: false,
+            ..self.clone()
+        }
+    }
+
+    pub fn bind(&self, binding: Binding<'a>) -> Env<'a> {
+        let variables = self.variables || matches!(binding, Binding::Variable(..));
+        Env {
+            variables,
+            scope: Some(Rc::new(Scope {
+                binding,
+                parent: self.clone(),
+            })),
+            ..self.clone()
+        }
+    }
+
+    /// The scope of a function body called from `caller`.
+    pub fn enter(self, caller: &Env<'a>) -> Result<Env<'a>, QueryError> {
+        let depth = caller.depth + 1;
+        match &self.execution {
+            Some(execution) => execution.check_depth(depth)?,
+            None if depth > DEPTH => return Err(QueryError::LimitExceeded("depth")),
+            None => {}
+        }
+        Ok(Env { depth, ..self })
+    }
+
+    pub fn step(&self) -> Result<(), QueryError> {
+        self.execution.as_ref().map_or(Ok(()), |e| e.step())
+    }
+
+    pub fn tracing(&self) -> bool {
+        self.execution.as_ref().is_some_and(|e| e.tracing())
+    }
+
+    /// Tells the tracer of the context that a stage is beginning.
+    pub fn begin(&self, query: &Query, input: &Value) {
+        if let Some(execution) = &self.execution {
+            execution.begin(query, input);
+        }
+    }
+
+    /// Passes the result of a stage through the tracer of the context.
+    pub fn trace(&self, query: &Query, input: &Value, result: QueryResult) -> QueryResult {
+        if let Some(execution) = &self.execution {
+            execution.trace(query, input, &result);
+        }
+        result
+    }
+
+    /// The outputs of a query which don't depend on its input, evaluated by
+    /// `f` only the first time in an execution unless it is being traced.
+    ///
+    /// Variables bound within the query may differ each time, so nothing is
+    /// cached in their scope.
+    pub fn memoize<F>(&self, query: &Query, f: F) -> QueryResult
+    where
+        F: FnOnce() -> QueryResult,
+    {
+        match &self.execution {
+            Some(execution) if !execution.tracing() && !self.variables => {
+                execution.memoize(query, f)
+            }
+            _ => f(),
+        }
+    }
+
+    /// The shortest array whose elements are evaluated on several threads,
+    /// if they can be.
+    #[cfg(feature = "parallel")]
+    pub fn parallel(&self) -> Option<usize> {
+        self.execution
+            .as_ref()
+            .filter(|e| !e.tracing())
+            .and_then(|e| e.context.parallel_threshold())
+    }
+
+    /// What another thread needs to evaluate part of the execution.
+    #[cfg(feature = "parallel")]
+    pub fn worker(&self) -> Option<Worker<'a>> {
+        self.execution.as_ref().map(|e| e.worker(self.depth))
+    }
+
+    /// Takes the next input document, if there is one.
+    pub fn input(&self) -> Option<Value> {
+        self.execution.as_ref().and_then(|e| e.next_input())
+    }
+
+    /// The byte range of the codepoints of `s` within `range`.
+    pub fn codepoints(&self, range: &Range, s: &str) -> ops::Range<usize> {
+        match &self.execution {
+            Some(execution) if s.len() >= INDEXED && !s.is_ascii() => {
+                range.between(&execution.boundaries(s))
+            }
+            _ => range.codepoints(s),
+        }
+    }
+
+    /// Checks that `n` more values would fit in the memory limit, before
+    /// building them.
+    pub fn check_memory(&self, n: usize) -> Result<(), QueryError> {
+        match &self.execution {
+            Some(execution) => {
+                execution.check_memory(n.saturating_mul(std::mem::size_of::<Value>()))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Accounts for the values built by a step against the memory limit.
+    pub fn allocate(&self, result: QueryResult) -> QueryResult {
+        let values = result?;
+        if let Some(execution) = &self.execution {
+            execution.allocate(&values)?;
+        }
+        Ok(values)
+    }
+
+    /// Finds the innermost definition of `name/arity`, falling back to the
+    /// builtins of the context.
+    pub fn lookup(&self, name: &str, arity: usize) -> Option<Callable<'a>> {
+        self.lookup_scope(name, arity).or_else(|| {
+            self.execution
+                .as_ref()
+                .and_then(|e| e.context.builtin(name, arity))
+                .map(Callable::Builtin)
+        })
+    }
+
+    pub fn format(&self, name: &str) -> Option<&'a Formatter> {
+        self.execution.as_ref().and_then(|e| e.context.format(name))
+    }
+
+    /// The innermost binding of `$name`, falling back to the variables of
+    /// the context.
+    pub fn variable(&self, name: &str) -> Option<Cow<'a, Value>> {
+        let mut env = self;
+        while let Some(scope) = env.scope.as_ref().filter(|_| env.variables) {
+            match &scope.binding {
+                Binding::Variable(n, v) if *n == name => return Some(Cow::Owned(v.clone())),
+                _ => env = &scope.parent,
+            }
+        }
+        self.execution
+            .as_ref()
+            .and_then(|e| e.context.variable(name))
+            .map(Cow::Borrowed)
+    }
+
+    fn lookup_scope(&self, name: &str, arity: usize) -> Option<Callable<'a>> {
+        let mut env = self;
+        while let Some(scope) = &env.scope {
+            match &scope.binding {
+                Binding::Function(f) if f.name == name && f.params.len() == arity => {
+                    return Some(Callable::Function(f, env.clone()));
+                }
+                Binding::Closure(n, q, e) if *n == name && arity == 0 => {
+                    return Some(Callable::Closure(q, e.clone()));
+                }
+                Binding::Module(alias, m) => {
+                    let name = match alias {
+                        Some(alias) => name.strip_prefix(alias).and_then(|n| n.strip_prefix("::")),
+                        None => Some(name),
+                    };
+                    if let Some(c) = name.and_then(|n| m.lookup_scope(n, arity)) {
+                        return Some(c);
+                    }
+                }
+                _ => {}
+            }
+            env = &scope.parent;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::context::{Context, Limits, Tracer};
+
+    struct Silent;
+
+    impl Tracer for Silent {
+        fn trace(&self, _: &Query, _: &Value, _: &QueryResult) {}
+    }
+
+    /// How many times `f` runs when memoized twice in `env`.
+    fn evaluations(env: &Env<'_>, query: &Query) -> usize {
+        let calls = Cell::new(0);
+        let f = || {
+            calls.set(calls.get() + 1);
+            Ok(vec![json!(1)])
+        };
+        env.memoize(query, f).unwrap();
+        env.memoize(query, f).unwrap();
+        calls.get()
+    }
+
+    #[test]
+    fn memoize() {
+        let query = Query::Identity;
+        let context = Context::new();
+        let env = Env::new(Rc::new(Execution::new(&context)));
+        assert_eq!(1, evaluations(&env, &query));
+
+        // Neither without an execution to remember them in
+        assert_eq!(2, evaluations(&Env::default(), &query));
+
+        // Nor in the scope of a variable, even a function call from it
+        let query = Query::Identity;
+        let scope = env.bind(Binding::Variable("x", json!(1)));
+        assert_eq!(2, evaluations(&scope, &query));
+        let called = scope.clone().enter(&scope).unwrap();
+        assert_eq!(2, evaluations(&called, &query));
+        assert_eq!(1, evaluations(&scope.root(), &query));
+
+        // Nor when tracing, which must see every evaluation
+        let mut context = Context::new();
+        context.trace_with(Silent);
+        let env = Env::new(Rc::new(Execution::new(&context)));
+        assert_eq!(2, evaluations(&env, &query));
+    }
+
+    #[test]
+    fn enter() {
+        let mut context = Context::new();
+        context.limits(Limits {
+            depth: Some(1),
+            ..Default::default()
+        });
+        let env = Env::new(Rc::new(Execution::new(&context)));
+        let once = env.clone().enter(&env).unwrap();
+        assert_eq!(1, once.depth);
+        assert!(matches!(
+            env.clone().enter(&once),
+            Err(QueryError::LimitExceeded("depth"))
+        ));
+        // Depth follows the caller, not the scope being entered
+        assert!(once.clone().enter(&env).is_ok());
+        assert!(Env::default().enter(&once).is_ok());
+    }
+
+    #[test]
+    fn allocate() {
+        let mut context = Context::new();
+        context.limits(Limits {
+            memory: Some(2 * std::mem::size_of::<Value>()),
+            ..Default::default()
+        });
+        let env = Env::new(Rc::new(Execution::new(&context)));
+        let scope = env.bind(Binding::Variable("x", json!(1)));
+        assert!(env.allocate(Ok(vec![json!(1)])).is_ok());
+        // Every scope of an execution shares its accounting
+        assert!(scope.allocate(Ok(vec![json!(2)])).is_ok());
+        assert!(matches!(
+            env.allocate(Ok(vec![json!(3)])),
+            Err(QueryError::LimitExceeded("memory"))
+        ));
+        assert!(matches!(
+            env.allocate(Err(QueryError::Cancelled)),
+            Err(QueryError::Cancelled)
+        ));
+    }
+}
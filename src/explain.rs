@@ -0,0 +1,186 @@
+use std::fmt;
+
+use crate::{
+    construction::{Construct, Key},
+    format::Part,
+    index::Index,
+    query::Query,
+};
+
+/// A description of how a query is evaluated, as a tree of stages.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Plan {
+    /// The kind of stage, such as `chain` or `index`.
+    pub stage: &'static str,
+    /// Whether the stage may produce any number of outputs, rather than exactly one.
+    pub generator: bool,
+    /// Whether the stage only follows paths into its input, so it could run
+    /// over a document as it is read.
+    pub streamable: bool,
+    pub children: Vec<Plan>,
+}
+
+impl Query {
+    pub fn explain(&self) -> Plan {
+        let leaf = |stage, generator, streamable| Plan {
+            stage,
+            generator,
+            streamable,
+            children: Vec::new(),
+        };
+        let node = |stage, children: Vec<Plan>| Plan {
+            stage,
+            generator: children.iter().any(|c| c.generator),
+            streamable: children.iter().all(|c| c.streamable),
+            children,
+        };
+
+        match self {
+            Query::Empty => leaf("empty", true, true),
+            Query::Identity => leaf("identity", false, true),
+            Query::Index(Index::Slice(_)) => leaf("slice", false, true),
+            Query::Index(_) => leaf("index", false, true),
+            Query::Iterator => leaf("iterator", true, true),
+            Query::Recurse => leaf("recurse", true, false),
+            Query::Raw(_) => leaf("literal", false, false),
+            Query::Format(_) => leaf("format", false, false),
+            Query::Variable(_) => leaf("var", false, false),
+            Query::Split(split) => Plan {
+                generator: true,
+                ..node("split", vec![split.0.explain(), split.1.explain()])
+            },
+            Query::Chain(chain) => node("chain", vec![chain.0.explain(), chain.1.explain()]),
+            Query::Optional(opt) => Plan {
+                generator: true,
+                ..node("optional", vec![opt.0.explain()])
+            },
+            Query::Construct(Construct::Array(inner)) => Plan {
+                generator: false,
+                streamable: false,
+                ..node("array", vec![inner.explain()])
+            },
+            Query::Construct(Construct::Object(kvs)) => Plan {
+                streamable: false,
+                ..node(
+                    "object",
+                    kvs.iter()
+                        .flat_map(|(k, v)| match k {
+                            Key::Simple(_) => vec![v.explain()],
+                            Key::Query(q) => vec![q.explain(), v.explain()],
+                        })
+                        .collect(),
+                )
+            },
+            Query::Op(op) => Plan {
+                streamable: false,
+                ..node("op", vec![op.left.explain(), op.right.explain()])
+            },
+            Query::Update(update) => Plan {
+                streamable: false,
+                ..node(
+                    "update",
+                    vec![update.path.explain(), update.value.explain()],
+                )
+            },
+            Query::Template(t) => Plan {
+                streamable: false,
+                ..node(
+                    "template",
+                    t.parts
+                        .iter()
+                        .filter_map(|p| match p {
+                            Part::Query(q) => Some(q.explain()),
+                            Part::Literal(_) => None,
+                        })
+                        .collect(),
+                )
+            },
+            // What a call does depends on the definition it resolves to
+            Query::Call(call) => Plan {
+                generator: true,
+                streamable: false,
+                ..node("call", call.args.iter().map(Query::explain).collect())
+            },
+            Query::Define(define) => Plan {
+                streamable: false,
+                ..node(
+                    "def",
+                    vec![define.function.body.explain(), define.rest.explain()],
+                )
+            },
+            // One output for each output of `init`, whatever the source produces
+            Query::Reduce(reduce) => {
+                let init = reduce.init.explain();
+                Plan {
+                    generator: init.generator,
+                    streamable: false,
+                    ..node(
+                        "reduce",
+                        vec![reduce.source.explain(), init, reduce.update.explain()],
+                    )
+                }
+            }
+            Query::Spanned(s) => s.query.explain(),
+            Query::Import(import) => Plan {
+                streamable: false,
+                ..node("import", vec![import.rest.explain()])
+            },
+        }
+    }
+}
+
+impl fmt::Display for Plan {
+    /// One line per stage, indented under the stage it belongs to.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write(plan: &Plan, depth: usize, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:indent$}{}", "", plan.stage, indent = depth * 2)?;
+            if plan.generator {
+                write!(f, " (generator)")?;
+            }
+            if plan.streamable {
+                write!(f, " (streamable)")?;
+            }
+            writeln!(f)?;
+            plan.children
+                .iter()
+                .try_for_each(|c| write(c, depth + 1, f))
+        }
+        write(self, 0, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn explain(s: &str) -> Plan {
+        s.parse::<Query>().unwrap().explain()
+    }
+
+    #[test]
+    fn explain_plan() {
+        let plan = explain(".a[0]");
+        assert_eq!("chain", plan.stage);
+        assert!(!plan.generator && plan.streamable);
+
+        let plan = explain(".a[] | .b");
+        assert!(plan.generator && plan.streamable);
+        assert!(plan.children[0].generator);
+        assert!(!plan.children[1].generator);
+
+        let plan = explain("[.a[]]");
+        assert!(!plan.generator && !plan.streamable);
+
+        assert!(explain(".a, .b").generator);
+        assert!(!explain(".a + 1").streamable);
+        assert!(explain("def f: .; f").generator);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            "chain (generator) (streamable)\n  index (streamable)\n  iterator (generator) (streamable)\n",
+            explain(".a[]").to_string()
+        );
+    }
+}
@@ -0,0 +1,490 @@
+use nom::{
+    branch::alt,
+    bytes::complete::{is_not, tag},
+    character::complete::{alphanumeric1, char},
+    combinator::{map, opt},
+    multi::fold_many0,
+    sequence::{delimited, preceded},
+    IResult,
+};
+use serde_json::Value;
+
+use crate::{
+    describe,
+    env::Env,
+    number,
+    parse::{parse_pipe, ParseError, Parseable},
+    query::{Eval, Query},
+    raw::{parse_escape, Raw},
+    single, space, QueryError, QueryResult,
+};
+
+/// A `@name` string format, either applied to its input or to every
+/// interpolation of a string.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Format {
+    Text,
+    Json,
+    Html,
+    Uri,
+    Csv,
+    Tsv,
+    Sh,
+    Base64,
+    Base64d,
+    /// Any other name, formatted by what the context registers under it.
+    Custom(String),
+}
+
+/// A string literal containing `\(...)` interpolations.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Template {
+    pub format: Format,
+    pub parts: Vec<Part>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Part {
+    Literal(String),
+    Query(Query),
+}
+
+impl Format {
+    pub fn name(&self) -> &str {
+        match self {
+            Format::Custom(name) => name,
+            builtin => builtin.builtin_name(),
+        }
+    }
+
+    fn builtin_name(&self) -> &'static str {
+        match self {
+            Format::Text => "text",
+            Format::Json => "json",
+            Format::Html => "html",
+            Format::Uri => "uri",
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+            Format::Sh => "sh",
+            Format::Base64 => "base64",
+            Format::Base64d => "base64d",
+            Format::Custom(_) => "custom",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Format::Text,
+            Format::Json,
+            Format::Html,
+            Format::Uri,
+            Format::Csv,
+            Format::Tsv,
+            Format::Sh,
+            Format::Base64,
+            Format::Base64d,
+        ]
+        .iter()
+        .find(|f| f.name() == name)
+        .cloned()
+    }
+
+    /// The name's format, which is a custom one unless rq has its own.
+    pub fn named(name: &str) -> Self {
+        Self::from_name(name).unwrap_or_else(|| Format::Custom(name.to_string()))
+    }
+
+    /// Formats a value, which for a custom format needs the context it is
+    /// registered with and so always fails.
+    pub fn apply(&self, value: &Value) -> Result<String, QueryError> {
+        let s = match self {
+            Format::Text => to_string(value),
+            Format::Json => number::to_string(value),
+            Format::Html => to_string(value)
+                .chars()
+                .map(|c| match c {
+                    '<' => "&lt;".to_string(),
+                    '>' => "&gt;".to_string(),
+                    '&' => "&amp;".to_string(),
+                    '\'' => "&#39;".to_string(),
+                    '"' => "&quot;".to_string(),
+                    c => c.to_string(),
+                })
+                .collect(),
+            Format::Uri => to_string(value)
+                .bytes()
+                .map(|b| match b {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                        (b as char).to_string()
+                    }
+                    b => format!("%{:02X}", b),
+                })
+                .collect(),
+            Format::Csv => self
+                .row(value)?
+                .iter()
+                .map(|v| match v {
+                    Value::String(s) => Ok(format!("\"{}\"", s.replace('"', "\"\""))),
+                    v => self.cell(v),
+                })
+                .collect::<Result<Vec<_>, _>>()?
+                .join(","),
+            Format::Tsv => self
+                .row(value)?
+                .iter()
+                .map(|v| match v {
+                    Value::String(s) => Ok(s
+                        .replace('\\', "\\\\")
+                        .replace('\t', "\\t")
+                        .replace('\n', "\\n")
+                        .replace('\r', "\\r")),
+                    v => self.cell(v),
+                })
+                .collect::<Result<Vec<_>, _>>()?
+                .join("\t"),
+            Format::Sh => match value {
+                Value::Array(arr) => arr
+                    .iter()
+                    .map(|v| self.quote(v))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join(" "),
+                v => self.quote(v)?,
+            },
+            Format::Base64 => base64_encode(to_string(value).as_bytes()),
+            Format::Base64d => {
+                let bytes = base64_decode(&to_string(value))
+                    .ok_or_else(|| QueryError::Format(self.builtin_name(), describe(value)))?;
+                String::from_utf8_lossy(&bytes).into_owned()
+            }
+            Format::Custom(name) => return Err(QueryError::UnknownFormat(name.clone())),
+        };
+        Ok(s)
+    }
+
+    fn row<'v>(&self, value: &'v Value) -> Result<&'v Vec<Value>, QueryError> {
+        match value {
+            Value::Array(arr) => Ok(arr),
+            v => Err(QueryError::Format(self.builtin_name(), describe(v))),
+        }
+    }
+
+    fn cell(&self, value: &Value) -> Result<String, QueryError> {
+        match value {
+            Value::Null => Ok(String::new()),
+            Value::Bool(_) | Value::Number(_) => Ok(number::to_string(value)),
+            v => Err(QueryError::Format(self.builtin_name(), describe(v))),
+        }
+    }
+
+    fn quote(&self, value: &Value) -> Result<String, QueryError> {
+        match value {
+            Value::String(s) => Ok(format!("'{}'", s.replace('\'', "'\\''"))),
+            Value::Array(_) | Value::Object(_) => {
+                Err(QueryError::Format(self.builtin_name(), describe(value)))
+            }
+            v => Ok(number::to_string(v)),
+        }
+    }
+}
+
+/// Strings as they are and anything else as JSON text, like jq's `tostring`.
+pub(crate) fn to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        v => number::to_string(v),
+    }
+}
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate) fn base64_encode(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(BASE64[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+pub(crate) fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let digits = s
+        .trim_end_matches('=')
+        .bytes()
+        .map(|b| BASE64.iter().position(|c| *c == b).map(|p| p as u32))
+        .collect::<Option<Vec<_>>>()?;
+    if digits.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(digits.len() * 3 / 4);
+    for chunk in digits.chunks(4) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, d)| n | d << (18 - 6 * i));
+        for i in 0..chunk.len() - 1 {
+            bytes.push((n >> (16 - 8 * i)) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+impl Format {
+    fn apply_in(&self, env: &Env, value: &Value) -> Result<String, QueryError> {
+        match self {
+            Format::Custom(name) => match env.format(name) {
+                Some(f) => f(value),
+                None => Err(QueryError::UnknownFormat(name.clone())),
+            },
+            builtin => builtin.apply(value),
+        }
+    }
+}
+
+impl Eval for Format {
+    fn eval(&self, env: &Env, value: &Value) -> QueryResult {
+        single(Value::String(self.apply_in(env, value)?))
+    }
+}
+
+impl Eval for Template {
+    fn eval<'a>(&'a self, env: &Env<'a>, value: &Value) -> QueryResult {
+        // Like jq, later interpolations vary slowest
+        let mut strings = vec![String::new()];
+        for part in &self.parts {
+            strings = match part {
+                Part::Literal(s) => strings.into_iter().map(|acc| acc + s).collect(),
+                Part::Query(q) => {
+                    let mut next = Vec::new();
+                    for v in q.eval(env, value)? {
+                        let s = self.format.apply_in(env, &v)?;
+                        next.extend(strings.iter().map(|acc| acc.clone() + &s));
+                    }
+                    next
+                }
+            };
+        }
+        Ok(strings.into_iter().map(Value::String).collect())
+    }
+}
+
+impl Parseable for Format {
+    fn parser(input: &str) -> IResult<&str, Self, ParseError> {
+        map(preceded(char('@'), alphanumeric1), Format::named)(input)
+    }
+}
+
+impl Parseable for Template {
+    fn parser(input: &str) -> IResult<&str, Self, ParseError> {
+        let (input, format) = opt(space::after(Format::parser))(input)?;
+        let (input, parts) = delimited(
+            char('"'),
+            fold_many0(
+                alt((
+                    map(is_not("\"\\"), |s: &str| Part::Literal(s.to_string())),
+                    map(
+                        delimited(tag("\\("), space::around(parse_pipe), char(')')),
+                        Part::Query,
+                    ),
+                    map(preceded(char('\\'), parse_escape), |c| {
+                        Part::Literal(c.to_string())
+                    }),
+                )),
+                Vec::new,
+                |mut parts: Vec<Part>, part| {
+                    match (parts.last_mut(), part) {
+                        (Some(Part::Literal(s)), Part::Literal(t)) => s.push_str(&t),
+                        (_, part) => parts.push(part),
+                    }
+                    parts
+                },
+            ),
+            char('"'),
+        )(input)?;
+        Ok((
+            input,
+            Template {
+                format: format.unwrap_or(Format::Text),
+                parts,
+            },
+        ))
+    }
+}
+
+/// A string literal, which is only a template if it has interpolations or a format.
+pub(crate) fn parse_format(input: &str) -> IResult<&str, Query, ParseError> {
+    alt((
+        map(Template::parser, |t| match t.parts.as_slice() {
+            [] if t.format == Format::Text => Query::Raw(Raw(Value::String(String::new()))),
+            [Part::Literal(s)] if t.format == Format::Text => {
+                Query::Raw(Raw(Value::String(s.clone())))
+            }
+            _ => Query::Template(t),
+        }),
+        map(Format::parser, Query::Format),
+    ))(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{context::Context, query::Executable};
+
+    use super::*;
+
+    fn format(f: Format, v: &str) -> Result<String, QueryError> {
+        f.apply(&serde_json::from_str(v).unwrap())
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!("[1,\"a\"]", format(Format::Text, r#"[1,"a"]"#).unwrap());
+        assert_eq!("\"a\"", format(Format::Json, r#""a""#).unwrap());
+        assert_eq!(
+            "&lt;p class=&quot;x&quot;&gt;&amp;&#39;",
+            format(Format::Html, r#""<p class=\"x\">&'""#).unwrap()
+        );
+        assert_eq!(
+            "a%20b%2Fc%C3%A9~",
+            format(Format::Uri, r#""a b/cé~""#).unwrap()
+        );
+        assert_eq!(
+            r#"1,"a""b",,true"#,
+            format(Format::Csv, r#"[1, "a\"b", null, true]"#).unwrap()
+        );
+        assert_eq!(
+            "1\ta\\tb\\\\",
+            format(Format::Tsv, r#"[1, "a\tb\\"]"#).unwrap()
+        );
+        assert_eq!(
+            "'it'\\''s' 1",
+            format(Format::Sh, r#"["it's", 1]"#).unwrap()
+        );
+        assert!(format(Format::Csv, r#""a""#).is_err());
+        assert!(format(Format::Tsv, r#"[[1]]"#).is_err());
+        assert!(format(Format::Sh, r#"[{}]"#).is_err());
+    }
+
+    #[test]
+    fn base64() {
+        for (plain, encoded) in [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v")] {
+            assert_eq!(encoded, base64_encode(plain.as_bytes()));
+            assert_eq!(plain.as_bytes(), base64_decode(encoded).unwrap().as_slice());
+        }
+        assert_eq!(
+            "This is a message",
+            format(Format::Base64d, r#""VGhpcyBpcyBhIG1lc3NhZ2U=""#).unwrap()
+        );
+        assert!(format(Format::Base64d, r#""Zm9v!""#).is_err());
+        assert!(format(Format::Base64d, r#""Z""#).is_err());
+    }
+
+    #[test]
+    fn parse_template() {
+        assert!(Query::parse("\"\\(\"").is_err());
+        assert!(Query::parse("\"\\()\"").is_err());
+        assert_eq!(
+            Query::Format(Format::Custom("unknown".to_string())),
+            Query::parse("@unknown").unwrap()
+        );
+
+        assert_eq!(
+            Query::Format(Format::Base64d),
+            Query::parse("@base64d").unwrap()
+        );
+        assert_eq!(
+            Query::Template(Template {
+                format: Format::Uri,
+                parts: vec![
+                    Part::Literal("a=".to_string()),
+                    Part::Query(Query::Identity),
+                    Part::Literal("\n".to_string()),
+                ]
+            }),
+            Query::parse("@uri \"a=\\( . )\\n\"").unwrap()
+        );
+        assert_eq!(
+            Query::Raw(Raw(Value::String("a\n".to_string()))),
+            Query::parse("\"a\\n\"").unwrap()
+        );
+    }
+
+    #[test]
+    fn custom_format() {
+        let mut context = Context::new();
+        context.register_format("logfmt", |v| match v {
+            Value::Object(o) => Ok(o
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, to_string(v)))
+                .collect::<Vec<_>>()
+                .join(" ")),
+            v => Err(QueryError::Format("logfmt", describe(v))),
+        });
+        let v: Value = serde_json::from_str(r#"{"a": 1, "b": "x"}"#).unwrap();
+        let run = |s: &str| Query::parse(s).unwrap().execute_with(&v, &context);
+
+        assert_eq!(vec![Value::from("a=1 b=x")], run("@logfmt").unwrap());
+        assert_eq!(
+            vec![Value::from("level=info a=1 b=x")],
+            run(r#"@logfmt "level=info \(.)""#).unwrap()
+        );
+        assert_eq!(
+            vec![Value::from("x=1")],
+            run(r#"{x: 1} | @logfmt"#).unwrap()
+        );
+        assert!(matches!(
+            run("1 | @logfmt"),
+            Err(QueryError::Format("logfmt", _))
+        ));
+        assert_eq!(
+            "@other is not defined",
+            run("@other").unwrap_err().to_string()
+        );
+        assert!(Query::parse("@logfmt").unwrap().execute(&v).is_err());
+    }
+
+    #[test]
+    fn interpolation() {
+        let v: Value = serde_json::from_str(r#"{"name": "a b", "n": [1, 2]}"#).unwrap();
+
+        let q: Query = r#""user=\(.name) n=\(.n)""#.parse().unwrap();
+        assert_eq!(
+            r#""user=a b n=[1,2]""#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        let q: Query = r#"@uri "https://x.com/?q=\(.name)&n=\(.n[0])""#.parse().unwrap();
+        assert_eq!(
+            r#""https://x.com/?q=a%20b&n=1""#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        let q: Query = r#"@sh "echo \(.name)""#.parse().unwrap();
+        assert_eq!(r#""echo 'a b'""#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = r#"@base64 "user=\(.name)""#.parse().unwrap();
+        assert_eq!(r#""user=YSBi""#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = r#""\(.n[0], .n[1])-\("x", "y")""#.parse().unwrap();
+        let r: Vec<_> = q
+            .execute(&v)
+            .unwrap()
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        assert_eq!(vec![r#""1-x""#, r#""2-x""#, r#""1-y""#, r#""2-y""#], r);
+
+        let q: Query = ".n | @csv, @json".parse().unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!(r#""1,2""#, r[0].to_string());
+        assert_eq!(r#""[1,2]""#, r[1].to_string());
+    }
+}
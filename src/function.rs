@@ -0,0 +1,640 @@
+use nom::{
+    bytes::complete::tag,
+    character::complete::{char, multispace1},
+    combinator::{map, opt, recognize, verify},
+    multi::separated_list1,
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    IResult,
+};
+use serde_json::Value;
+use std::{borrow::Cow, iter, ops::Range, ptr};
+
+use crate::{
+    builtins::{self, Core},
+    combinator::Chain,
+    context::Builtin,
+    env::{Binding, Callable, Env},
+    owned,
+    parse::{parse_identifier, parse_pipe, ParseError, Parseable},
+    query::{fixed_path, iterate_results, not_paths, results, Eval, Query},
+    space, PathResult, QueryError, QueryIter, QueryResult, SharedResult,
+};
+
+/// Words which cannot be used as function names.
+pub(crate) const KEYWORDS: &[&str] = &[
+    "def", "import", "include", "if", "then", "elif", "else", "end", "as", "reduce", "foreach",
+    "try", "catch", "label", "and", "or", "__loc__",
+];
+
+/// `def name(param; ...): body;`
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function {
+    pub name: String,
+    /// Filter parameters, each of which is bound to the caller's argument.
+    pub params: Vec<String>,
+    pub body: Query,
+}
+
+/// A function definition in scope for the rest of the query.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Define {
+    pub function: Function,
+    pub rest: Query,
+}
+
+/// A call of a function by name and arity, optionally qualified as `module::name`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Call {
+    pub name: String,
+    pub args: Vec<Query>,
+}
+
+impl Call {
+    pub fn new(name: &str, args: Vec<Query>) -> Self {
+        Call {
+            name: name.to_string(),
+            args,
+        }
+    }
+}
+
+impl Define {
+    fn scope<'a>(&'a self, env: &Env<'a>) -> Env<'a> {
+        env.bind(Binding::Function(&self.function))
+    }
+}
+
+impl Eval for Define {
+    fn eval<'a>(&'a self, env: &Env<'a>, value: &Value) -> QueryResult {
+        self.rest.eval(&self.scope(env), value)
+    }
+
+    fn eval_owned<'a>(&'a self, env: &Env<'a>, value: Value) -> QueryResult {
+        self.rest.eval_owned(&self.scope(env), value)
+    }
+
+    fn eval_into<'a>(
+        &'a self,
+        env: &Env<'a>,
+        value: &Value,
+        output: &mut Vec<Value>,
+    ) -> Result<(), QueryError> {
+        self.rest.eval_into(&self.scope(env), value, output)
+    }
+
+    fn eval_iter<'a>(&'a self, env: &Env<'a>, value: Cow<'a, Value>) -> QueryIter<'a> {
+        self.rest.eval_iter(&self.scope(env), value)
+    }
+
+    fn eval_ref<'a, 'v>(&'a self, env: &Env<'a>, value: &'v Value) -> SharedResult<'v> {
+        self.rest.eval_ref(&self.scope(env), value)
+    }
+
+    fn eval_paths<'a>(&'a self, env: &Env<'a>, value: &Value) -> PathResult {
+        self.rest.eval_paths(&self.scope(env), value)
+    }
+}
+
+/// What a call evaluates once its name is resolved.
+enum Target<'a> {
+    Query(&'a Query, Env<'a>),
+    Builtin(&'a Builtin),
+    Core(Core),
+}
+
+impl Call {
+    /// Finds the body to evaluate and the scope to evaluate it in.
+    fn resolve<'a>(&'a self, env: &Env<'a>) -> Result<Target<'a>, QueryError> {
+        match env.lookup(&self.name, self.args.len()) {
+            Some(Callable::Function(f, scope)) => {
+                let scope = f
+                    .params
+                    .iter()
+                    .zip(&self.args)
+                    .fold(scope, |scope, (p, a)| {
+                        scope.bind(Binding::Closure(p, a, env.clone()))
+                    });
+                Ok(Target::Query(&f.body, scope.enter(env)?))
+            }
+            Some(Callable::Closure(q, scope)) => Ok(Target::Query(q, scope)),
+            Some(Callable::Builtin(f)) => Ok(Target::Builtin(f)),
+            None => builtins::lookup(&self.name, self.args.len())
+                .map(Target::Core)
+                .ok_or_else(|| QueryError::Undefined(self.name.clone(), self.args.len())),
+        }
+    }
+
+    /// Calls `f` with every combination of argument outputs, later arguments varying slowest.
+    pub(crate) fn apply<'a, F>(&'a self, env: &Env<'a>, value: &Value, f: F) -> QueryResult
+    where
+        F: Fn(&[Value], &Value) -> QueryResult,
+    {
+        let combinations = self.combinations(env, value)?;
+        iterate_results(combinations.iter().map(|args| f(args, value)))
+    }
+
+    /// Every combination of argument outputs, in the order `apply` uses them.
+    pub(crate) fn combinations<'a>(
+        &'a self,
+        env: &Env<'a>,
+        value: &Value,
+    ) -> Result<Vec<Vec<Value>>, QueryError> {
+        let mut combinations = vec![Vec::new()];
+        for arg in &self.args {
+            let mut next = Vec::new();
+            for v in arg.eval(env, value)? {
+                next.extend(combinations.iter().map(|args| {
+                    let mut args = args.clone();
+                    args.push(v.clone());
+                    args
+                }));
+            }
+            combinations = next;
+        }
+        Ok(combinations)
+    }
+}
+
+/// The stages of a function body which end in a call of another function,
+/// as each chain leading to it and the call itself.
+struct Tail<'a> {
+    body: &'a Query,
+    chains: Vec<&'a Chain>,
+    call: &'a Call,
+    /// The call as it appears at the end of the body, with its span.
+    last: &'a Query,
+    span: Option<&'a Range<usize>>,
+}
+
+impl<'a> Tail<'a> {
+    /// The call at the end of `body`, if it is of a definition or a filter
+    /// argument rather than a builtin.
+    fn of(body: &'a Query, scope: &Env<'a>) -> Option<Tail<'a>> {
+        let mut chains = Vec::new();
+        let mut query = body;
+        while let Query::Chain(chain) = query {
+            chains.push(&**chain);
+            query = &chain.1;
+        }
+        let (call, span) = match query {
+            Query::Call(call) => (call, None),
+            Query::Spanned(s) => match &s.query {
+                Query::Call(call) => (call, Some(&s.span)),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        match scope.lookup(&call.name, call.args.len()) {
+            Some(Callable::Function(..) | Callable::Closure(..)) if !scope.tracing() => {
+                Some(Tail {
+                    body,
+                    chains,
+                    call,
+                    last: query,
+                    span,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Evaluates the body of a function, and then the body of a function it
+/// ends by calling in place of it rather than within it, as long as every
+/// stage before the call had one output. Tail recursion such as
+/// `def f: . - 1 | f` then runs in a loop instead of using up the stack.
+fn eval_body<'a>(mut body: &'a Query, mut scope: Env<'a>, value: &Value) -> QueryResult {
+    let mut input = Cow::Borrowed(value);
+    // Where the current input is within the original one, and the span of
+    // the call whose body is being evaluated, which recursion would have
+    // added to any error
+    let mut path = Vec::new();
+    let mut span = None;
+    let mut current: Option<Tail> = None;
+    let mut prefixes = Vec::new();
+    let result = 'body: loop {
+        // A body calling itself ends in the same call again, after stages
+        // which follow the same paths
+        if !current.as_ref().is_some_and(|t| ptr::eq(t.body, body)) {
+            current = Tail::of(body, &scope);
+            prefixes = current
+                .iter()
+                .flat_map(|t| &t.chains)
+                .map(|chain| fixed_path(&chain.0))
+                .collect();
+        }
+        let tail = match &current {
+            Some(tail) => tail,
+            None => break body.eval(&scope, &input),
+        };
+        for (chain, prefix) in tail.chains.iter().zip(&prefixes) {
+            let mut outputs = match chain.0.eval(&scope, &input) {
+                Ok(outputs) => outputs,
+                Err(e) => break 'body Err(e),
+            };
+            if outputs.len() != 1 {
+                break 'body iterate_results(outputs.into_iter().enumerate().map(|(k, v)| {
+                    chain
+                        .1
+                        .eval_owned(&scope, v)
+                        .map_err(|e| chain.locate(&input, k, e))
+                }));
+            }
+            if let Some(prefix) = prefix {
+                path.extend(prefix.iter().cloned());
+            }
+            input = Cow::Owned(outputs.remove(0));
+        }
+        match scope.step().and_then(|_| tail.call.resolve(&scope)) {
+            Ok(Target::Query(next, next_scope)) => {
+                body = next;
+                scope = next_scope;
+                span = tail.span.or(span);
+            }
+            _ => break tail.last.eval(&scope, &input),
+        }
+    };
+    result.map_err(|e| match span {
+        Some(span) => e.at(path).within(span),
+        None => e.at(path),
+    })
+}
+
+impl Call {
+    fn eval_target<'a>(&'a self, target: Target<'a>, env: &Env<'a>, value: &Value) -> QueryResult {
+        match target {
+            Target::Query(body, scope) => eval_body(body, scope, value),
+            Target::Builtin(f) => self.apply(env, value, &**f),
+            Target::Core(f) => f(self, env, value),
+        }
+    }
+
+    /// The core function of this name which can take its input borrowed or owned.
+    fn shared(&self, target: &Target) -> Option<builtins::Shared> {
+        match target {
+            Target::Core(_) => builtins::shared(&self.name, self.args.len()),
+            _ => None,
+        }
+    }
+}
+
+impl Eval for Call {
+    fn eval<'a>(&'a self, env: &Env<'a>, value: &Value) -> QueryResult {
+        self.eval_target(self.resolve(env)?, env, value)
+    }
+
+    fn eval_owned<'a>(&'a self, env: &Env<'a>, value: Value) -> QueryResult {
+        let target = self.resolve(env)?;
+        if let Some(f) = self.shared(&target) {
+            let outputs = f(self, env, Cow::Owned(value))?;
+            return Ok(outputs.into_iter().map(Cow::into_owned).collect());
+        }
+        match target {
+            Target::Query(body, scope) if Tail::of(body, &scope).is_some() => {
+                eval_body(body, scope, &value)
+            }
+            Target::Query(body, scope) => body.eval_owned(&scope, value),
+            target => self.eval_target(target, env, &value),
+        }
+    }
+
+    fn eval_ref<'a, 'v>(&'a self, env: &Env<'a>, value: &'v Value) -> SharedResult<'v> {
+        let target = self.resolve(env)?;
+        if let Some(f) = self.shared(&target) {
+            return f(self, env, Cow::Borrowed(value));
+        }
+        match target {
+            Target::Query(body, scope) if Tail::of(body, &scope).is_none() => {
+                body.eval_ref(&scope, value)
+            }
+            target => owned(self.eval_target(target, env, value)),
+        }
+    }
+
+    fn eval_into<'a>(
+        &'a self,
+        env: &Env<'a>,
+        value: &Value,
+        output: &mut Vec<Value>,
+    ) -> Result<(), QueryError> {
+        match self.resolve(env)? {
+            Target::Query(body, scope) if Tail::of(body, &scope).is_some() => {
+                output.extend(eval_body(body, scope, value)?);
+                Ok(())
+            }
+            Target::Query(body, scope) => body.eval_into(&scope, value, output),
+            Target::Builtin(f) => {
+                output.extend(self.apply(env, value, &**f)?);
+                Ok(())
+            }
+            Target::Core(f) => {
+                output.extend(f(self, env, value)?);
+                Ok(())
+            }
+        }
+    }
+
+    fn eval_iter<'a>(&'a self, env: &Env<'a>, value: Cow<'a, Value>) -> QueryIter<'a> {
+        match self.resolve(env) {
+            Ok(Target::Query(body, scope)) if Tail::of(body, &scope).is_some() => {
+                results(eval_body(body, scope, &value))
+            }
+            Ok(Target::Query(body, scope)) => body.eval_iter(&scope, value),
+            Ok(Target::Builtin(f)) => results(self.apply(env, &value, &**f)),
+            Ok(Target::Core(f)) => {
+                if let Some(g) = builtins::generator(&self.name, self.args.len()) {
+                    return g(self, env, value);
+                }
+                match builtins::shared(&self.name, self.args.len()) {
+                    Some(f) => results(
+                        f(self, env, value).map(|o| o.into_iter().map(Cow::into_owned).collect()),
+                    ),
+                    None => results(f(self, env, &value)),
+                }
+            }
+            Err(e) => Box::new(iter::once(Err(e))),
+        }
+    }
+
+    fn eval_paths<'a>(&'a self, env: &Env<'a>, value: &Value) -> PathResult {
+        match self.resolve(env)? {
+            Target::Query(body, scope) => body.eval_paths(&scope, value),
+            Target::Builtin(f) => not_paths(self.apply(env, value, &**f)),
+            Target::Core(f) => not_paths(f(self, env, value)),
+        }
+    }
+}
+
+fn parse_name(input: &str) -> IResult<&str, &str, ParseError> {
+    verify(
+        recognize(pair(
+            parse_identifier,
+            opt(preceded(tag("::"), parse_identifier)),
+        )),
+        |s: &str| !KEYWORDS.contains(&s),
+    )(input)
+}
+
+impl Parseable for Call {
+    fn parser(input: &str) -> IResult<&str, Self, ParseError> {
+        let (input, name) = parse_name(input)?;
+        let (input, args) = opt(delimited(
+            char('('),
+            separated_list1(char(';'), space::around(parse_pipe)),
+            char(')'),
+        ))(input)?;
+        Ok((input, Call::new(name, args.unwrap_or_default())))
+    }
+}
+
+impl Parseable for Define {
+    fn parser(input: &str) -> IResult<&str, Self, ParseError> {
+        let (input, (name, params, body)) = tuple((
+            preceded(terminated(tag("def"), multispace1), parse_name),
+            opt(delimited(
+                space::before(char('(')),
+                separated_list1(char(';'), space::around(parse_identifier)),
+                char(')'),
+            )),
+            delimited(
+                space::around(char(':')),
+                parse_pipe,
+                space::around(char(';')),
+            ),
+        ))(input)?;
+        let (input, rest) = opt(parse_pipe)(input)?;
+
+        let function = Function {
+            name: name.to_string(),
+            params: params
+                .unwrap_or_default()
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            body,
+        };
+        Ok((
+            input,
+            Define {
+                function,
+                rest: rest.unwrap_or(Query::Empty),
+            },
+        ))
+    }
+}
+
+pub(crate) fn parse_define(input: &str) -> IResult<&str, Query, ParseError> {
+    map(Define::parser, |d| Query::Define(Box::new(d)))(input)
+}
+
+pub(crate) fn parse_call(input: &str) -> IResult<&str, Query, ParseError> {
+    map(Call::parser, Query::Call)(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::{context::Context, index::Index, query::Executable, single};
+
+    use super::*;
+
+    #[test]
+    fn parse_call() {
+        assert!(Query::parse("f(").is_err());
+        assert!(Query::parse("f()").is_err());
+        assert!(Query::parse("f(.;)").is_err());
+        assert!(Query::parse("def").is_err());
+
+        assert_eq!(
+            Query::Call(Call::new("f", vec![])),
+            Query::parse("f").unwrap()
+        );
+        assert_eq!(
+            Query::Call(Call::new("m::f", vec![Query::Identity, Query::Recurse])),
+            Query::parse("m::f(. ; ..)").unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_define() {
+        assert!(Query::parse("def f: .").is_err());
+        assert!(Query::parse("def f; f").is_err());
+        assert!(Query::parse("def if: .; if").is_err());
+        assert!(Query::parse("deff: .; f").is_err());
+
+        assert_eq!(
+            Query::Define(Box::new(Define {
+                function: Function {
+                    name: "f".to_string(),
+                    params: vec!["g".to_string(), "h".to_string()],
+                    body: Query::Call(Call::new("g", vec![])),
+                },
+                rest: Query::Call(Call::new("f", vec![Query::Identity, Query::Identity])),
+            })),
+            Query::parse("def f(g; h): g; f(.; .)").unwrap()
+        );
+        assert_eq!(
+            Query::Define(Box::new(Define {
+                function: Function {
+                    name: "f".to_string(),
+                    params: vec![],
+                    body: Query::Identity,
+                },
+                rest: Query::Empty,
+            })),
+            Query::parse("def f: .;").unwrap()
+        );
+    }
+
+    #[test]
+    fn call() {
+        let v: Value = serde_json::from_str(r#"{"a": 1, "b": 2}"#).unwrap();
+
+        let q: Query = "def f: .a; f".parse().unwrap();
+        assert_eq!(r#"1"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "def f(g): [g, g]; f(.b)".parse().unwrap();
+        assert_eq!(r#"[2,2]"#, q.execute(&v).unwrap()[0].to_string());
+
+        // Arguments are closures over the scope of the caller
+        let q: Query = "def g: .a; def f(g): g; def h: .b; f(h)".parse().unwrap();
+        assert_eq!(r#"2"#, q.execute(&v).unwrap()[0].to_string());
+
+        // Functions see earlier definitions but not later ones
+        let q: Query = "def f: .a; def g: f; def f: .b; g, f".parse().unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!(r#"1"#, r[0].to_string());
+        assert_eq!(r#"2"#, r[1].to_string());
+
+        let q: Query = "def f: .a; f(.)".parse().unwrap();
+        assert!(matches!(q.execute(&v), Err(QueryError::Undefined(n, 1)) if n == "f"));
+    }
+
+    #[test]
+    fn builtin() {
+        let mut context = Context::new();
+        context
+            .register("add", 2, |args, _| {
+                single(Value::from(
+                    args[0].as_i64().unwrap() + args[1].as_i64().unwrap(),
+                ))
+            })
+            .register("both", 0, |_, v| Ok(vec![v.clone(), v.clone()]));
+        let v: Value = serde_json::from_str(r#"{"a": 1, "b": 2}"#).unwrap();
+
+        let q: Query = "[add(.a, .b; 10, 20)]".parse().unwrap();
+        let r = q.execute_with(&v, &context).unwrap();
+        assert_eq!(r#"[[11,12,21,22]]"#, Value::Array(r).to_string());
+
+        let q: Query = "[.a | both]".parse().unwrap();
+        let r = q.execute_with(&v, &context).unwrap();
+        assert_eq!(r#"[[1,1]]"#, Value::Array(r).to_string());
+
+        // Definitions shadow builtins, which need the context
+        let q: Query = "def both: .b; both".parse().unwrap();
+        assert_eq!(r#"2"#, q.execute_with(&v, &context).unwrap()[0].to_string());
+        assert!(q.execute(&v).is_ok());
+        let q: Query = "both".parse().unwrap();
+        assert!(q.execute(&v).is_err());
+    }
+
+    #[test]
+    fn string_builtins() {
+        let v: Value = serde_json::from_str(r#"{"a": "Hello, World", "b": 1}"#).unwrap();
+        let output = |s: &str| {
+            let q: Query = s.parse().unwrap();
+            let r = q.execute(&v).unwrap();
+            assert_eq!(r, q.execute_owned(v.clone()).unwrap(), "{}", s);
+            let streamed: Result<Vec<_>, _> =
+                q.execute_stream(vec![v.clone()], &Context::new()).collect();
+            assert_eq!(r, streamed.unwrap(), "{}", s);
+            Value::from(r).to_string()
+        };
+        assert_eq!(
+            r#"[", World","Hello, World"]"#,
+            output(r#".a | ltrimstr("Hello", "x")"#)
+        );
+        assert_eq!(r#"["Hello, "]"#, output(r#".a | rtrimstr("World")"#));
+        assert_eq!(
+            r#"[1,{"a":"Hello, World","b":1}]"#,
+            output(r#".b, . | ltrimstr("H")"#)
+        );
+        assert_eq!(
+            r#"["hello, world","HELLO, WORLD"]"#,
+            output(".a | ascii_downcase, ascii_upcase")
+        );
+        assert_eq!(r#"["hello"]"#, output(".a[:5] | ascii_downcase"));
+
+        let q: Query = ".b | ascii_upcase".parse().unwrap();
+        assert_eq!(
+            "Cannot apply ascii_upcase to number (1) (at .b)",
+            q.execute(&v).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn tail_call() {
+        use crate::context::Limits;
+        let mut context = Context::new();
+        context.limits(Limits {
+            depth: Some(200_000),
+            ..Default::default()
+        });
+        // Far deeper than the stack would allow if each call recursed
+        let q: Query = "def f: . + 1 | f; f".parse().unwrap();
+        assert!(matches!(
+            q.execute_with(&Value::from(0), &context),
+            Err(QueryError::LimitExceeded("depth"))
+        ));
+        let r: Vec<_> = q.execute_stream(vec![Value::from(0)], &context).collect();
+        assert!(matches!(r[..], [Err(QueryError::LimitExceeded("depth"))]));
+
+        let v: Value = serde_json::from_str(r#"{"a": {"a": [{"a": 1}, 2]}}"#).unwrap();
+        let q: Query = "def f: .a? | f, .; [f]".parse().unwrap();
+        assert_eq!(
+            r#"[[[{"a":1},2],{"a":[{"a":1},2]}]]"#,
+            Value::from(q.execute(&v).unwrap()).to_string()
+        );
+        let q: Query = "def f: .a | f; f".parse().unwrap();
+        assert_eq!(
+            "Cannot index array ([{\"a\":1},2]) with \"a\" (at .a.a)",
+            q.execute(&v).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn default_depth() {
+        // Unoptimized, calls as deep as the limit need more stack than a
+        // test thread has
+        let recurse = thread::Builder::new().stack_size(256 << 20).spawn(|| {
+            let q: Query = "def f: [f]; f".parse().unwrap();
+            let context = Context::new();
+            (
+                q.execute(&Value::Null),
+                q.execute_with(&Value::Null, &context),
+            )
+        });
+        let (without, with) = recurse.unwrap().join().unwrap();
+        assert!(matches!(without, Err(QueryError::LimitExceeded("depth"))));
+        assert!(matches!(with, Err(QueryError::LimitExceeded("depth"))));
+    }
+
+    #[test]
+    fn recursive_call() {
+        let q: Query = "def f: .[]? | (f, .); [f]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[[1]]"#).unwrap();
+        assert_eq!(r#"[1,[1]]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q = Query::Define(Box::new(Define {
+            function: Function {
+                name: "f".to_string(),
+                params: vec![],
+                body: Query::Index(Index::String("a".to_string())),
+            },
+            rest: Query::Call(Call::new("f", vec![])),
+        }));
+        let v: Value = serde_json::from_str(r#"{"a": 1}"#).unwrap();
+        assert_eq!(r#"1"#, q.execute(&v).unwrap()[0].to_string());
+    }
+}
@@ -0,0 +1,622 @@
+use std::{
+    io::{self, BufRead, Read},
+    mem,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum GzipError {
+    #[error("Invalid gzip header")]
+    Header,
+    #[error("Invalid DEFLATE data: {0}")]
+    Deflate(&'static str),
+    #[error("Gzip checksum does not match its contents")]
+    Checksum,
+    #[error("Truncated gzip input")]
+    Truncated,
+    /// zstd is out of scope, being far more than DEFLATE to decompress.
+    #[error("zstd input is not supported, so decompress it first")]
+    Zstd,
+}
+
+impl From<GzipError> for io::Error {
+    fn from(e: GzipError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// The input as it was before compression if it starts as gzip does, or as
+/// it is otherwise.
+///
+/// zstd is recognised as well, but only to fail with [`GzipError::Zstd`]
+/// rather than as text which isn't JSON.
+pub fn decompress<R: BufRead>(mut reader: R) -> io::Result<Decompressed<R>> {
+    let start = reader.fill_buf()?;
+    if start.starts_with(&[0x1f, 0x8b]) {
+        let decoder = io::BufReader::new(GzDecoder::new(reader));
+        Ok(Decompressed::Gzip(Box::new(decoder)))
+    } else if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Err(GzipError::Zstd.into())
+    } else {
+        Ok(Decompressed::Plain(reader))
+    }
+}
+
+/// An input read by [`decompress`].
+pub enum Decompressed<R> {
+    Plain(R),
+    Gzip(Box<io::BufReader<GzDecoder<R>>>),
+}
+
+impl<R: BufRead> Read for Decompressed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Decompressed::Plain(r) => r.read(buf),
+            Decompressed::Gzip(r) => r.read(buf),
+        }
+    }
+}
+
+impl<R: BufRead> BufRead for Decompressed<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            Decompressed::Plain(r) => r.fill_buf(),
+            Decompressed::Gzip(r) => r.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, n: usize) {
+        match self {
+            Decompressed::Plain(r) => r.consume(n),
+            Decompressed::Gzip(r) => r.consume(n),
+        }
+    }
+}
+
+/// The furthest back DEFLATE may copy from.
+const WINDOW: usize = 32 * 1024;
+
+/// Reads gzip as the data it compresses, decompressing as it is read.
+///
+/// Members following one another, as `cat a.gz b.gz` makes, are read as one
+/// after the other. Nothing of a member is read out until its checksum
+/// matches, so each is held whole as it is decompressed.
+pub struct GzDecoder<R> {
+    bits: Bits<R>,
+    state: State,
+    /// Whether the block being read is the last of its member.
+    last: bool,
+    /// What has been decompressed, holding at least a window of what has
+    /// been read for copies to refer to.
+    history: Vec<u8>,
+    read: usize,
+    /// The end of what the checksum of its member has been checked for.
+    checked: usize,
+    crc: Crc,
+    size: u32,
+}
+
+enum State {
+    Member,
+    Block,
+    Stored(u16),
+    Codes(Huffman, Huffman),
+    Trailer,
+    Done,
+}
+
+impl<R: BufRead> GzDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        GzDecoder {
+            bits: Bits {
+                reader,
+                buffer: 0,
+                count: 0,
+            },
+            state: State::Member,
+            last: false,
+            history: Vec::new(),
+            read: 0,
+            checked: 0,
+            crc: Crc::new(),
+            size: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.history.push(byte);
+        self.crc.update(byte);
+        self.size = self.size.wrapping_add(1);
+    }
+
+    /// Moves on through the input, perhaps decompressing some of it.
+    fn step(&mut self) -> io::Result<()> {
+        self.state = match mem::replace(&mut self.state, State::Done) {
+            State::Member => {
+                self.header()?;
+                State::Block
+            }
+            State::Block if self.last => State::Trailer,
+            State::Block => {
+                self.last = self.bits.take(1)? == 1;
+                match self.bits.take(2)? {
+                    0 => {
+                        self.bits.align();
+                        let length = self.bits.take(16)? as u16;
+                        if self.bits.take(16)? as u16 != !length {
+                            return Err(GzipError::Deflate("stored length").into());
+                        }
+                        State::Stored(length)
+                    }
+                    1 => State::Codes(Huffman::fixed_literals(), Huffman::fixed_distances()),
+                    2 => {
+                        let (literals, distances) = self.dynamic()?;
+                        State::Codes(literals, distances)
+                    }
+                    _ => return Err(GzipError::Deflate("block type").into()),
+                }
+            }
+            State::Stored(0) => State::Block,
+            State::Stored(n) => {
+                // Aligned to a byte, so nothing is left over in `bits`
+                let chunk = self.bits.reader.fill_buf()?;
+                let taken = chunk.len().min(usize::from(n));
+                if taken == 0 {
+                    return Err(truncated());
+                }
+                let bytes = chunk[..taken].to_vec();
+                self.bits.reader.consume(taken);
+                bytes.into_iter().for_each(|b| self.push(b));
+                State::Stored(n - taken as u16)
+            }
+            State::Codes(literals, distances) => match self.codes(&literals, &distances)? {
+                true => State::Block,
+                false => State::Codes(literals, distances),
+            },
+            State::Trailer => {
+                self.bits.align();
+                let crc = self.bits.take(32)?;
+                let size = self.bits.take(32)?;
+                if crc != self.crc.finish() || size != self.size {
+                    return Err(GzipError::Checksum.into());
+                }
+                self.checked = self.history.len();
+                match self.bits.reader.fill_buf()?.is_empty() {
+                    true => State::Done,
+                    false => State::Member,
+                }
+            }
+            State::Done => State::Done,
+        };
+        Ok(())
+    }
+
+    fn header(&mut self) -> io::Result<()> {
+        let mut byte = || self.bits.take(8);
+        if (byte()?, byte()?, byte()?) != (0x1f, 0x8b, 8) {
+            return Err(GzipError::Header.into());
+        }
+        let flags = byte()?;
+        // The modification time, extra flags and operating system
+        for _ in 0..6 {
+            byte()?;
+        }
+        if flags & 4 != 0 {
+            let length = byte()? | byte()? << 8;
+            for _ in 0..length {
+                byte()?;
+            }
+        }
+        // The file name and comment, each ending with a nul byte
+        for flag in [8, 16] {
+            if flags & flag != 0 {
+                while byte()? != 0 {}
+            }
+        }
+        if flags & 2 != 0 {
+            byte()?;
+            byte()?;
+        }
+        self.last = false;
+        self.crc = Crc::new();
+        self.size = 0;
+        Ok(())
+    }
+
+    /// The codes of a block which describes its own.
+    fn dynamic(&mut self) -> io::Result<(Huffman, Huffman)> {
+        const ORDER: [usize; 19] = [
+            16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+        ];
+        let literals = self.bits.take(5)? as usize + 257;
+        let distances = self.bits.take(5)? as usize + 1;
+        let mut lengths = [0; 19];
+        for &i in ORDER.iter().take(self.bits.take(4)? as usize + 4) {
+            lengths[i] = self.bits.take(3)? as u8;
+        }
+        let code = Huffman::new(&lengths)?;
+
+        let mut lengths = Vec::with_capacity(literals + distances);
+        while lengths.len() < literals + distances {
+            let (length, repeat) = match code.decode(&mut self.bits)? {
+                symbol @ 0..=15 => (symbol as u8, 1),
+                16 => match lengths.last() {
+                    Some(&previous) => (previous, 3 + self.bits.take(2)?),
+                    None => return Err(GzipError::Deflate("repeated length").into()),
+                },
+                17 => (0, 3 + self.bits.take(3)?),
+                _ => (0, 11 + self.bits.take(7)?),
+            };
+            lengths.extend(std::iter::repeat_n(length, repeat as usize));
+        }
+        if lengths.len() > literals + distances || lengths[256] == 0 {
+            return Err(GzipError::Deflate("code lengths").into());
+        }
+        Ok((
+            Huffman::new(&lengths[..literals])?,
+            Huffman::new(&lengths[literals..])?,
+        ))
+    }
+
+    /// Decompresses some of a block, returning whether it has ended.
+    fn codes(&mut self, literals: &Huffman, distances: &Huffman) -> io::Result<bool> {
+        const LENGTH_BASES: [u16; 29] = [
+            3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99,
+            115, 131, 163, 195, 227, 258,
+        ];
+        const LENGTH_EXTRA: [u32; 29] = [
+            0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+        ];
+        const DISTANCE_BASES: [u16; 30] = [
+            1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025,
+            1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+        ];
+        const DISTANCE_EXTRA: [u32; 30] = [
+            0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12,
+            12, 13, 13,
+        ];
+        let until = self.history.len() + WINDOW;
+        while self.history.len() < until {
+            let code = match literals.decode(&mut self.bits)? {
+                literal @ 0..=255 => {
+                    self.push(literal as u8);
+                    continue;
+                }
+                256 => return Ok(true),
+                symbol => usize::from(symbol - 257),
+            };
+            if code >= LENGTH_BASES.len() {
+                return Err(GzipError::Deflate("length code").into());
+            }
+            let length =
+                usize::from(LENGTH_BASES[code]) + self.bits.take(LENGTH_EXTRA[code])? as usize;
+            let code = usize::from(distances.decode(&mut self.bits)?);
+            if code >= DISTANCE_BASES.len() {
+                return Err(GzipError::Deflate("distance code").into());
+            }
+            let distance =
+                usize::from(DISTANCE_BASES[code]) + self.bits.take(DISTANCE_EXTRA[code])? as usize;
+            if distance > self.history.len() {
+                return Err(GzipError::Deflate("distance too far back").into());
+            }
+            for _ in 0..length {
+                self.push(self.history[self.history.len() - distance]);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl<R: BufRead> Read for GzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read == self.checked {
+            if let State::Done = self.state {
+                return Ok(0);
+            }
+            if self.read > 2 * WINDOW {
+                let drained = self.read - WINDOW;
+                self.history.drain(..drained);
+                self.read = WINDOW;
+                self.checked = WINDOW;
+            }
+            self.step()?;
+        }
+        let n = buf.len().min(self.checked - self.read);
+        buf[..n].copy_from_slice(&self.history[self.read..self.read + n]);
+        self.read += n;
+        Ok(n)
+    }
+}
+
+/// The end of the input part way through a member, as the kind of error
+/// reading past the end gives.
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, GzipError::Truncated)
+}
+
+/// The input read from its lowest bit upwards, as DEFLATE packs it.
+struct Bits<R> {
+    reader: R,
+    buffer: u64,
+    count: u32,
+}
+
+impl<R: BufRead> Bits<R> {
+    fn take(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            let byte = match self.reader.fill_buf()?.first() {
+                Some(&b) => b,
+                None => return Err(truncated()),
+            };
+            self.reader.consume(1);
+            self.buffer |= u64::from(byte) << self.count;
+            self.count += 8;
+        }
+        let bits = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(bits as u32)
+    }
+
+    /// Skips to the next byte, since fewer than eight bits are ever left over.
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical Huffman code, as the number of codes of each length and the
+/// symbols in the order of their codes.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, GzipError> {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[usize::from(length)] += 1;
+        }
+        counts[0] = 0;
+        // No more codes of each length than are left unused by shorter ones
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(GzipError::Deflate("code lengths"));
+            }
+        }
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16)
+            .filter(|&s| lengths[usize::from(s)] != 0)
+            .collect();
+        symbols.sort_by_key(|&s| lengths[usize::from(s)]);
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn fixed_literals() -> Self {
+        let mut lengths = [8; 288];
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        Self::new(&lengths).unwrap_or_else(|_| unreachable!())
+    }
+
+    fn fixed_distances() -> Self {
+        Self::new(&[5; 30]).unwrap_or_else(|_| unreachable!())
+    }
+
+    fn decode<R: BufRead>(&self, bits: &mut Bits<R>) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &self.counts[1..] {
+            code |= bits.take(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(GzipError::Deflate("invalid code").into())
+    }
+}
+
+/// The CRC-32 which gzip checks its contents with.
+struct Crc(u32);
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = match c & 1 {
+                1 => 0xedb8_8320 ^ (c >> 1),
+                _ => c >> 1,
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+impl Crc {
+    fn new() -> Self {
+        Crc(!0)
+    }
+
+    fn update(&mut self, byte: u8) {
+        self.0 = CRC_TABLE[((self.0 ^ u32::from(byte)) & 0xff) as usize] ^ (self.0 >> 8);
+    }
+
+    fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `{"a": 1}\n{"a": 2}\n` in a block with the fixed codes.
+    const FIXED: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\xab\x56\x4a\x54\xb2\x52\x30\xac\xe5\xaa\x06\x33\x8c\x6a\xb9\x00\xce\x32\xde\xc1\x12\x00\x00\x00";
+
+    /// `{"a": "stored"}\n` in a stored block.
+    const STORED: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x04\x03\x01\x10\x00\xef\xff\x7b\x22\x61\x22\x3a\x20\x22\x73\x74\x6f\x72\x65\x64\x22\x7d\x0a\xbe\x5a\xa4\xc6\x10\x00\x00\x00";
+
+    fn gunzip(bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        decompress(bytes)?.read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    fn gzip_error(e: io::Error) -> Option<GzipError> {
+        e.into_inner()?.downcast::<GzipError>().ok().map(|e| *e)
+    }
+
+    /// A member holding `data` in stored blocks of at most `size` bytes.
+    fn stored(data: &[u8], size: usize) -> Vec<u8> {
+        let mut member = b"\x1f\x8b\x08\0\0\0\0\0\0\x03".to_vec();
+        let mut blocks = data.chunks(size).peekable();
+        if blocks.peek().is_none() {
+            member.extend(b"\x01\0\0\xff\xff");
+        }
+        while let Some(block) = blocks.next() {
+            member.push(blocks.peek().is_none() as u8);
+            member.extend((block.len() as u16).to_le_bytes());
+            member.extend((!(block.len() as u16)).to_le_bytes());
+            member.extend(block);
+        }
+        let mut crc = Crc::new();
+        data.iter().for_each(|&b| crc.update(b));
+        member.extend(crc.finish().to_le_bytes());
+        member.extend((data.len() as u32).to_le_bytes());
+        member
+    }
+
+    #[test]
+    fn stored_blocks() {
+        assert_eq!(b"{\"a\": \"stored\"}\n".to_vec(), gunzip(STORED).unwrap());
+        assert_eq!(b"".to_vec(), gunzip(&stored(b"", 10)).unwrap());
+
+        // Across several blocks, and more than a window of them
+        let data: Vec<u8> = (0..5 * WINDOW).map(|i| (i % 251) as u8).collect();
+        assert_eq!(data, gunzip(&stored(&data, 65535)).unwrap());
+        assert_eq!(data, gunzip(&stored(&data, 1000)).unwrap());
+
+        let mut corrupt = STORED.to_vec();
+        corrupt[13] ^= 1;
+        assert_eq!(
+            Some(GzipError::Deflate("stored length")),
+            gzip_error(gunzip(&corrupt).unwrap_err())
+        );
+    }
+
+    #[test]
+    fn fixed_codes() {
+        assert_eq!(b"{\"a\": 1}\n{\"a\": 2}\n".to_vec(), gunzip(FIXED).unwrap());
+    }
+
+    #[test]
+    fn dynamic_codes() {
+        let dynamic = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\x75\xd2\x2d\x0a\xc3\x50\x10\x45\x61\xdf\x65\x3c\x1d\xf1\x66\xe6\xfd\x66\x2b\xa5\xa2\xd0\x88\x88\x54\xc5\x85\xee\xbd\xa2\x30\x30\x85\x63\x8f\xfa\xb8\xdc\xfb\x95\xf6\x57\x5a\xf3\x92\xde\xcf\x63\x4b\x6b\xda\xcf\xed\xc8\xe9\xb3\xfc\xba\x84\x2e\xde\x35\x74\xf5\x6e\xa1\x9b\xf7\x12\x7a\xf1\x5e\x43\xaf\xde\x5b\xe8\xcd\x7b\x07\xe7\x00\xe7\x04\xa7\x64\x80\x8a\x80\x54\x14\xa8\x62\x60\x95\x42\xa3\x56\xd0\x4a\x23\x6e\x27\xee\x20\xee\x04\xae\x66\xe0\xaa\x00\x57\x95\x4e\x60\xc0\xd5\x02\x5c\xad\xc0\xd5\x46\xdc\x4e\xdc\x41\xdc\x09\x5c\xcb\x74\x5a\x01\xae\x29\x70\xcd\x80\x6b\x05\xb8\x56\x81\x6b\x8d\xb8\x9d\xb8\x83\xb8\xf3\x9f\xfb\xb8\x7d\x01\xd7\xaa\x3b\x72\xe0\x03\x00\x00";
+        let items: Vec<_> = (0..40)
+            .map(|i| format!("{{\"id\":{},\"name\":\"item{}\"}}", i, i % 7))
+            .collect();
+        let text = format!("[{}]\n", items.join(","));
+        assert_eq!(text.into_bytes(), gunzip(dynamic).unwrap());
+
+        // Copies from far back, decompressed to many windows
+        let mut long = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\xed\xc2\x31\x0d\x00\x00\x08\x03\xb0\x1f\x2d\x1c\xf8\x59\xf0\x6f\x03\x1b\x1c\x4d\x9b\x69".to_vec();
+        long.extend([0; 96]);
+        long.extend(b"\xe0\xad\xad\x03\x11\xc5\x85\xdb\xa4\x86\x01\x00");
+        let text = format!("[{}0]\n", "0,".repeat(50000));
+        assert_eq!(text.into_bytes(), gunzip(&long).unwrap());
+    }
+
+    #[test]
+    fn members() {
+        let mut members = FIXED.to_vec();
+        members.extend(STORED);
+        // With a file name
+        members.extend(b"\x1f\x8b\x08\x08\0\0\0\0\0\x03a.json\0");
+        members.extend(&stored(b"hi", 10)[10..]);
+        members.extend(stored(b"", 10));
+        assert_eq!(
+            b"{\"a\": 1}\n{\"a\": 2}\n{\"a\": \"stored\"}\nhi".to_vec(),
+            gunzip(&members).unwrap()
+        );
+
+        // What follows a member must be another
+        let mut trailing = FIXED.to_vec();
+        trailing.push(b'\n');
+        assert!(gunzip(&trailing).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        // Anything shorter than the magic number isn't taken as gzip
+        assert_eq!(b"\x1f".to_vec(), gunzip(&FIXED[..1]).unwrap());
+        for input in [FIXED, STORED] {
+            for end in 2..input.len() {
+                let e = gunzip(&input[..end]).unwrap_err();
+                assert_eq!(io::ErrorKind::UnexpectedEof, e.kind(), "at {}", end);
+                assert_eq!(Some(GzipError::Truncated), gzip_error(e), "at {}", end);
+            }
+        }
+    }
+
+    #[test]
+    fn checksums() {
+        for input in [FIXED, STORED] {
+            let trailer = input.len() - 8;
+            // The CRC-32 and then the size
+            for i in [trailer, trailer + 3, trailer + 4] {
+                let mut corrupt = input.to_vec();
+                corrupt[i] ^= 1;
+                assert_eq!(
+                    Some(GzipError::Checksum),
+                    gzip_error(gunzip(&corrupt).unwrap_err())
+                );
+
+                // Nothing of the member is read before it is checked
+                let mut decoder = decompress(&corrupt[..]).unwrap();
+                assert!(decoder.read(&mut [0; 4]).is_err());
+            }
+        }
+
+        // Nor anything of a later member, though earlier ones were checked
+        let mut corrupt = STORED.to_vec();
+        *corrupt.last_mut().unwrap() ^= 1;
+        let mut members = FIXED.to_vec();
+        members.extend(corrupt);
+        let mut decoder = decompress(&members[..]).unwrap();
+        let mut output = Vec::new();
+        assert!(decoder.read_to_end(&mut output).is_err());
+        assert_eq!(b"{\"a\": 1}\n{\"a\": 2}\n".to_vec(), output);
+    }
+
+    #[test]
+    fn not_gzip() {
+        assert_eq!(b"[1]".to_vec(), gunzip(b"[1]").unwrap());
+        assert_eq!(
+            "zstd input is not supported, so decompress it first",
+            gunzip(b"\x28\xb5\x2f\xfd\0").unwrap_err().to_string()
+        );
+        let mut header = FIXED.to_vec();
+        header[2] = 9;
+        assert_eq!(
+            Some(GzipError::Header),
+            gzip_error(gunzip(&header).unwrap_err())
+        );
+        let mut block = FIXED.to_vec();
+        block[10] |= 6;
+        assert_eq!(
+            Some(GzipError::Deflate("block type")),
+            gzip_error(gunzip(&block).unwrap_err())
+        );
+    }
+}
@@ -1,46 +1,110 @@
 use crate::{
-    null,
+    describe,
+    env::Env,
+    null, owned,
     parse::{ParseError, Parseable},
-    query::Executable,
+    query::Eval,
     range::Range,
-    single, space, type_str, QueryError, QueryResult,
+    raw::parse_string,
+    single, space, QueryError, QueryResult, SharedResult,
 };
 use nom::{
     branch::alt,
-    bytes::complete::take_while1,
     character::complete::{char, i32},
     combinator::map,
     sequence::delimited,
     IResult,
 };
 use serde_json::{Map, Value};
+use std::{borrow::Cow, ops};
 
+/// Object, array and slice indexing.
 #[derive(Debug, PartialEq, Clone)]
 pub enum Index {
+    /// `.foo` or `.["foo"]`
     String(String),
+    /// `.[0]`, where negative values count from the end
     Integer(i32),
+    /// `.[1:3]`
     Slice(Range),
 }
 
-impl Executable for Index {
-    fn execute(&self, v: &Value) -> QueryResult {
+impl Eval for Index {
+    fn eval<'a>(&'a self, env: &Env<'a>, v: &Value) -> QueryResult {
         match (v, self) {
             (Value::String(s), Index::Slice(r)) => {
-                let range = r.normalize(s.len());
-                let sliced = s[range].to_string();
+                let sliced = s[env.codepoints(r, s)].to_string();
                 single(Value::String(sliced))
             }
             (Value::Array(vec), Index::Slice(r)) => {
-                let range = r.normalize(vec.len());
+                let range = r.within(vec.len());
                 single(Value::Array(vec[range].to_vec()))
             }
             (Value::Object(map), Index::String(s)) => index_object(map, s),
             (Value::Array(arr), Index::Integer(i)) => index_array(arr, *i),
-            (v, Index::String(_)) => Err(QueryError::Index(type_str(v), "string")),
-            (v, Index::Integer(_)) => Err(QueryError::Index(type_str(v), "number")),
-            (v, Index::Slice(_)) => Err(QueryError::Index(type_str(v), "slice")),
+            (Value::Null, _) => null(),
+            (v, Index::String(s)) => Err(QueryError::Index(
+                describe(v),
+                Value::from(s.as_str()).to_string(),
+            )),
+            (v, Index::Integer(i)) => Err(QueryError::Index(describe(v), i.to_string())),
+            (v, Index::Slice(_)) => Err(QueryError::Index(describe(v), "a slice".to_string())),
         }
     }
+
+    fn eval_owned<'a>(&'a self, env: &Env<'a>, v: Value) -> QueryResult {
+        match (v, self) {
+            (Value::String(s), Index::Slice(r)) => {
+                let range = env.codepoints(r, &s);
+                single(substring(Cow::Owned(Value::String(s)), range).into_owned())
+            }
+            (Value::Array(mut vec), Index::Slice(r)) => {
+                let range = r.within(vec.len());
+                single(Value::Array(vec.drain(range).collect()))
+            }
+            (Value::Object(mut map), Index::String(s)) => {
+                single(map.remove(s).unwrap_or(Value::Null))
+            }
+            (Value::Array(mut arr), Index::Integer(i)) => match position(arr.len(), *i) {
+                Some(index) => single(arr.swap_remove(index)),
+                None => null(),
+            },
+            (v, _) => self.eval(env, &v),
+        }
+    }
+
+    fn eval_ref<'a, 'v>(&'a self, env: &Env<'a>, v: &'v Value) -> SharedResult<'v> {
+        let found = match (v, self) {
+            (Value::Object(map), Index::String(s)) => map.get(s),
+            (Value::Array(arr), Index::Integer(i)) => {
+                position(arr.len(), *i).and_then(|index| arr.get(index))
+            }
+            (Value::String(s), Index::Slice(r)) => {
+                return Ok(vec![substring(Cow::Borrowed(v), env.codepoints(r, s))]);
+            }
+            (Value::Array(arr), Index::Slice(r)) if r.within(arr.len()) == (0..arr.len()) => {
+                Some(v)
+            }
+            _ => return owned(self.eval(env, v)),
+        };
+        Ok(vec![found.map_or(Cow::Owned(Value::Null), Cow::Borrowed)])
+    }
+}
+
+/// The part of a string within a byte range, borrowing it if that is the
+/// whole string and cutting it down in place if it is owned.
+pub(crate) fn substring(value: Cow<'_, Value>, range: ops::Range<usize>) -> Cow<'_, Value> {
+    match value {
+        Cow::Owned(Value::String(mut s)) => {
+            s.truncate(range.end);
+            s.drain(..range.start);
+            Cow::Owned(Value::String(s))
+        }
+        Cow::Borrowed(Value::String(s)) if range != (0..s.len()) => {
+            Cow::Owned(Value::from(&s[range]))
+        }
+        v => v,
+    }
 }
 
 fn index_object(map: &Map<String, Value>, s: &str) -> QueryResult {
@@ -52,20 +116,18 @@ fn index_object(map: &Map<String, Value>, s: &str) -> QueryResult {
 }
 
 fn index_array(arr: &[Value], i: i32) -> QueryResult {
-    let index = if i < 0 {
-        let j = -i as usize;
-        if j >= arr.len() {
-            return null();
-        }
-        arr.len() - j
-    } else {
-        i as usize
-    };
+    match position(arr.len(), i).and_then(|index| arr.get(index)) {
+        Some(vv) => single(vv.clone()),
+        None => null(),
+    }
+}
 
-    if let Some(vv) = arr.get(index) {
-        single(vv.clone())
+/// The position of an index within an array, counting from the end if negative.
+pub(crate) fn position(len: usize, i: i32) -> Option<usize> {
+    if i < 0 {
+        len.checked_sub(-i as usize)
     } else {
-        null()
+        Some(i as usize).filter(|index| *index < len)
     }
 }
 
@@ -76,10 +138,7 @@ impl Parseable for Index {
             space::around(alt((
                 map(Range::parser, Index::Slice),
                 map(i32, Index::Integer),
-                map(
-                    delimited(char('"'), take_while1(|c| c != '"'), char('"')),
-                    |s: &str| Index::String(s.to_string()),
-                ),
+                map(parse_string, Index::String),
             ))),
             char(']'),
         )(input)
@@ -88,10 +147,32 @@ impl Parseable for Index {
 
 #[cfg(test)]
 mod tests {
-    use crate::query::Query;
+    use crate::query::{Executable, Query};
 
     use super::*;
 
+    #[test]
+    fn index_null() {
+        let v = Value::Null;
+        assert_eq!(
+            Value::Null,
+            Index::String("foo".to_string()).execute(&v).unwrap()[0]
+        );
+        assert_eq!(Value::Null, Index::Integer(0).execute(&v).unwrap()[0]);
+        assert_eq!(
+            Value::Null,
+            Index::Slice(Range::lower(1)).execute(&v).unwrap()[0]
+        );
+    }
+
+    #[test]
+    fn index_array_from_end() {
+        let v: Value = serde_json::from_str("[1, 2, 3]").unwrap();
+        assert_eq!(Value::from(1), Index::Integer(-3).execute(&v).unwrap()[0]);
+        assert_eq!(Value::Null, Index::Integer(-4).execute(&v).unwrap()[0]);
+        assert_eq!(Value::Null, Index::Integer(3).execute(&v).unwrap()[0]);
+    }
+
     #[test]
     fn parse_object_index() {
         assert!(Index::parse("foo").is_err());
@@ -113,6 +194,38 @@ mod tests {
             Query::Index(Index::String("foo".to_string())),
             Query::parse(".foo").unwrap()
         );
+        assert_eq!(
+            Query::Index(Index::String("foo_bar".to_string())),
+            Query::parse(".foo_bar").unwrap()
+        );
+        assert_eq!(
+            Query::Index(Index::String("_1".to_string())),
+            Query::parse("._1").unwrap()
+        );
+        assert!(Query::parse(".1a").is_err());
+    }
+
+    #[test]
+    fn parse_quoted_object_index() {
+        assert!(Query::parse(".\"foo").is_err());
+        assert!(Query::parse(". \"foo\"").is_err());
+
+        assert_eq!(
+            Query::Index(Index::String("foo bar".to_string())),
+            Query::parse(".\"foo bar\"").unwrap()
+        );
+        assert_eq!(
+            Query::Index(Index::String("「unicode」".to_string())),
+            Query::parse(".\"「unicode」\"").unwrap()
+        );
+        assert_eq!(
+            Query::Index(Index::String("a\"b".to_string())),
+            Query::parse(r#"."a\"b""#).unwrap()
+        );
+        assert_eq!(
+            Query::Index(Index::String("".to_string())),
+            Query::parse(".[\"\"]").unwrap()
+        );
     }
 
     #[test]
@@ -0,0 +1,243 @@
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_until, take_while, take_while_m_n},
+    character::complete::{char, digit0, digit1, hex_digit1, multispace1, none_of, one_of},
+    combinator::{eof, map, map_opt, opt, recognize, value},
+    multi::{many0, separated_list1},
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
+    IResult,
+};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum Json5Error {
+    #[error("Invalid JSON5 at line {0}: {1}")]
+    Syntax(usize, String),
+}
+
+/// Reads JSON5, a relaxed JSON allowing comments, trailing commas, unquoted
+/// keys, single quoted strings and hexadecimal numbers among other things.
+///
+/// `Infinity` and `NaN` are read as `null`, since JSON can't hold them.
+pub fn from_str(text: &str) -> Result<Value, Json5Error> {
+    match delimited(gap, parse_value, terminated(gap, eof))(text) {
+        Ok((_, v)) => Ok(v),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+            let line = text[..text.len() - e.input.len()].matches('\n').count() + 1;
+            let found = e.input.lines().next().unwrap_or_default().to_string();
+            Err(Json5Error::Syntax(line, found))
+        }
+        Err(nom::Err::Incomplete(_)) => unreachable!(),
+    }
+}
+
+/// Whitespace and comments.
+fn gap(input: &str) -> IResult<&str, ()> {
+    let line_comment = pair(tag("//"), take_while(|c| c != '\n'));
+    let block_comment = tuple((tag("/*"), take_until("*/"), tag("*/")));
+    value(
+        (),
+        many0(alt((
+            value((), multispace1),
+            value((), line_comment),
+            value((), block_comment),
+        ))),
+    )(input)
+}
+
+fn spaced<'a, O, F>(f: F) -> impl FnMut(&'a str) -> IResult<&'a str, O>
+where
+    F: FnMut(&'a str) -> IResult<&'a str, O>,
+{
+    delimited(gap, f, gap)
+}
+
+fn parse_value(input: &str) -> IResult<&str, Value> {
+    alt((
+        value(Value::Null, tag("null")),
+        value(Value::Bool(true), tag("true")),
+        value(Value::Bool(false), tag("false")),
+        map(string, Value::from),
+        number,
+        array,
+        object,
+    ))(input)
+}
+
+fn escape(input: &str) -> IResult<&str, Option<char>> {
+    let hex = |n| {
+        map_opt(
+            take_while_m_n(n, n, |c: char| c.is_ascii_hexdigit()),
+            move |h| u32::from_str_radix(h, 16).ok(),
+        )
+    };
+    let unicode = map_opt(
+        pair(
+            preceded(char('u'), hex(4)),
+            opt(preceded(tag("\\u"), hex(4))),
+        ),
+        |(high, low)| match (high, low) {
+            (0xd800..=0xdbff, Some(low @ 0xdc00..=0xdfff)) => {
+                char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))
+            }
+            (high, None) => char::from_u32(high),
+            _ => None,
+        },
+    );
+    preceded(
+        char('\\'),
+        alt((
+            // A line continuation, which stands for nothing
+            value(None, alt((tag("\r\n"), tag("\n"), tag("\r")))),
+            map(unicode, Some),
+            map(map_opt(preceded(char('x'), hex(2)), char::from_u32), Some),
+            map(one_of("btnfrv0"), |c| {
+                Some(match c {
+                    'b' => '\u{8}',
+                    't' => '\t',
+                    'n' => '\n',
+                    'f' => '\u{c}',
+                    'r' => '\r',
+                    'v' => '\u{b}',
+                    _ => '\0',
+                })
+            }),
+            map(none_of("123456789"), Some),
+        )),
+    )(input)
+}
+
+fn quoted(quote: char) -> impl FnMut(&str) -> IResult<&str, String> {
+    move |input| {
+        let plain = map(none_of(if quote == '"' { "\"\\\n" } else { "'\\\n" }), Some);
+        let (rest, chars) =
+            delimited(char(quote), many0(alt((plain, escape))), char(quote))(input)?;
+        Ok((rest, chars.into_iter().flatten().collect()))
+    }
+}
+
+fn string(input: &str) -> IResult<&str, String> {
+    alt((quoted('"'), quoted('\'')))(input)
+}
+
+fn number(input: &str) -> IResult<&str, Value> {
+    let (rest, sign) = opt(one_of("+-"))(input)?;
+    let negative = sign == Some('-');
+    let hex = map_opt(preceded(alt((tag("0x"), tag("0X"))), hex_digit1), |h| {
+        u64::from_str_radix(h, 16).ok()
+    });
+    let exponent = tuple((one_of("eE"), opt(one_of("+-")), digit1));
+    let decimal = recognize(pair(
+        alt((
+            recognize(pair(digit1, opt(pair(char('.'), digit0)))),
+            recognize(pair(char('.'), digit1)),
+        )),
+        opt(exponent),
+    ));
+    alt((
+        value(Value::Null, alt((tag("Infinity"), tag("NaN")))),
+        map_opt(hex, move |n| match negative {
+            true => 0i64.checked_sub_unsigned(n).map(Value::from),
+            false => Some(Value::from(n)),
+        }),
+        map_opt(decimal, move |d: &str| {
+            // The same number as JSON has to write it
+            let mut text = String::from(if negative { "-" } else { "" });
+            if d.starts_with('.') {
+                text.push('0');
+            }
+            let mut chars = d.chars().peekable();
+            while let Some(c) = chars.next() {
+                text.push(c);
+                if c == '.' && !chars.peek().is_some_and(char::is_ascii_digit) {
+                    text.push('0');
+                }
+            }
+            serde_json::from_str(&text).ok()
+        }),
+    ))(rest)
+}
+
+/// Items between brackets, separated by commas and perhaps followed by one.
+fn items<'a, O, F>(
+    open: char,
+    item: F,
+    close: char,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<O>>
+where
+    F: FnMut(&'a str) -> IResult<&'a str, O>,
+{
+    delimited(
+        pair(char(open), gap),
+        map(
+            opt(terminated(
+                separated_list1(spaced(char(',')), item),
+                opt(spaced(char(','))),
+            )),
+            Option::unwrap_or_default,
+        ),
+        pair(gap, char(close)),
+    )
+}
+
+fn array(input: &str) -> IResult<&str, Value> {
+    map(items('[', parse_value, ']'), Value::Array)(input)
+}
+
+fn identifier(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        take_while_m_n(1, 1, |c: char| c.is_alphabetic() || c == '_' || c == '$'),
+        take_while(|c: char| c.is_alphanumeric() || c == '_' || c == '$'),
+    ))(input)
+}
+
+fn object(input: &str) -> IResult<&str, Value> {
+    let key = alt((string, map(identifier, String::from)));
+    let entry = separated_pair(key, spaced(char(':')), parse_value);
+    map(items('{', entry, '}'), |entries| {
+        Value::Object(entries.into_iter().collect::<Map<_, _>>())
+    })(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn read_json5() {
+        let text = r#"
+// A config file
+{
+  name: 'rq', /* unquoted keys */
+  "quoted": "a\
+b\x41\u00e9",
+  $n: [+1, -.5, 2., 0x1F, -0xa, Infinity,],
+  nested: {ok: true, off: false, none: null,},
+}
+"#;
+        assert_eq!(
+            Ok(json!({
+                "name": "rq",
+                "quoted": "abA\u{e9}",
+                "$n": [1, -0.5, 2.0, 31, -10, null],
+                "nested": {"ok": true, "off": false, "none": null}
+            })),
+            from_str(text)
+        );
+        assert_eq!(Ok(json!("it's")), from_str(r#"'it\'s'"#));
+        assert_eq!(Ok(json!([{}])), from_str("[ {} ]"));
+        assert_eq!(Ok(Some(1000.0)), from_str("1e3").map(|v| v.as_f64()));
+
+        assert!(from_str("[,]").is_err());
+        assert!(from_str("[1,,]").is_err());
+        assert!(from_str("{a b: 1}").is_err());
+        assert!(from_str("1 2").is_err());
+        assert_eq!(
+            Err(Json5Error::Syntax(1, "a:".to_string())),
+            from_str("{a:\n}")
+        );
+    }
+}
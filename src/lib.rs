@@ -1,36 +1,203 @@
 use serde_json::Value;
+use std::{borrow::Cow, ops::Range};
 use thiserror::Error;
 
-mod combinator;
-mod construction;
-mod index;
-mod operators;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod bson;
+pub mod builder;
+mod builtins;
+pub mod bytes;
+pub mod cbor;
+pub mod combinator;
+pub mod construction;
+pub mod context;
+pub mod csv;
+mod encode;
+mod env;
+pub mod explain;
+pub mod format;
+pub mod function;
+pub mod gzip;
+pub mod index;
+#[cfg(feature = "json5")]
+pub mod json5;
+pub mod module;
+pub mod msgpack;
+pub mod number;
+pub mod operators;
+mod optimize;
+mod order;
+mod parallel;
 pub mod parse;
+pub mod patch;
+pub mod pointer;
 pub mod query;
-mod range;
-mod raw;
+pub mod range;
+pub mod raw;
+pub mod reduce;
+pub mod set;
 mod space;
+pub mod span;
+pub mod stream;
+mod strict;
+pub mod text;
+pub mod toml;
+pub mod update;
+pub mod urlencoded;
+pub mod value;
+#[cfg(feature = "xml")]
+pub mod xml;
 
 pub type QueryResult = Result<Vec<Value>, QueryError>;
 
+/// Results produced one at a time, in the order of a [`QueryResult`].
+pub type QueryIter<'a> = Box<dyn Iterator<Item = Result<Value, QueryError>> + 'a>;
+
+/// Results which may borrow from the input rather than copying it.
+pub type SharedResult<'v> = Result<Vec<Cow<'v, Value>>, QueryError>;
+
+/// Results along with the path within the input that each was found at.
+pub type PathResult = Result<Vec<(Vec<Value>, Value)>, QueryError>;
+
 #[derive(Error, Debug)]
 pub enum QueryError {
     #[error("Cannot index {0} with {1}")]
-    Index(&'static str, &'static str),
+    Index(String, String),
+    /// An assignment so far past the end of an array that jq refuses it.
+    #[error("Array index too large")]
+    IndexTooLarge,
     #[error("Cannot iterate over {0}")]
-    Iterate(&'static str),
+    Iterate(String),
     #[error("Cannot use {0} as object key")]
-    ObjectKey(&'static str),
+    ObjectKey(String),
     #[error("Numerical operation was not possible")]
     Numerical,
-    #[error("Cannot {0} {1} and {2}")]
-    Operation(&'static str, &'static str, &'static str),
+    #[error("{1} and {2} cannot be {0}")]
+    Operation(&'static str, String, String),
+    /// `a / 0` or `a % 0`, worded as jq words each of them.
+    #[error("{1} and {2} cannot be {0} because the divisor is zero")]
+    DivideByZero(&'static str, String, String),
+    #[error("{0}/{1} is not defined")]
+    Undefined(String, usize),
+    #[error("Cannot format {1} as {0}")]
+    Format(&'static str, String),
+    #[error("Cannot apply {0} to {1}")]
+    Apply(&'static str, String),
+    #[error("@{0} is not defined")]
+    UnknownFormat(String),
+    #[error("${0} is not defined")]
+    Variable(String),
+    #[error("Exceeded the {0} limit")]
+    LimitExceeded(&'static str),
+    #[error("Execution was cancelled")]
+    Cancelled,
+    #[error("Expected {0} output but there were {1}")]
+    Outputs(&'static str, usize),
+    #[error("Only paths into the input can be executed as it is read")]
+    Streaming,
+    #[error("No more inputs")]
+    NoMoreInputs,
+    /// `halt` or `halt_error`, ending the whole execution with an exit code
+    /// and a message for the caller to report. Errors are not suppressed by
+    /// `?`.
+    #[error("Halted with exit code {0}")]
+    Halt(i32, Option<Value>),
+    #[error("Cannot parse input: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Cannot parse {1} as {0}")]
+    Parse(&'static str, String),
+    #[error("Cannot parse CSV: {0}")]
+    Csv(#[from] csv::CsvError),
+    #[error("Invalid path expression with result {0}")]
+    Path(String),
+    #[error("Invalid JSON pointer: {0}")]
+    Pointer(String),
+    /// An error from a value at the given path within the input.
+    #[error("{} (at {})", .1, path_str(.0))]
+    At(Vec<Value>, Box<QueryError>),
+    /// An error from the part of the query text in the given byte range.
+    #[error("{} at {}..{} of the query", .1, .0.start, .0.end)]
+    In(Range<usize>, Box<QueryError>),
+}
+
+impl QueryError {
+    /// The error with `prefix` added before the path it occurred at.
+    ///
+    /// Only errors caused by a value are located, not those about the
+    /// query or its execution as a whole.
+    pub(crate) fn at(self, mut prefix: Vec<Value>) -> QueryError {
+        match self {
+            _ if prefix.is_empty() => self,
+            QueryError::At(path, e) => {
+                prefix.extend(path);
+                QueryError::At(prefix, e)
+            }
+            QueryError::In(span, e) => QueryError::In(span, Box::new(e.at(prefix))),
+            QueryError::Index(..)
+            | QueryError::IndexTooLarge
+            | QueryError::Iterate(_)
+            | QueryError::ObjectKey(_)
+            | QueryError::Numerical
+            | QueryError::Operation(..)
+            | QueryError::DivideByZero(..)
+            | QueryError::Format(..)
+            | QueryError::Apply(..)
+            | QueryError::Parse(..)
+            | QueryError::Csv(_)
+            | QueryError::Path(_) => QueryError::At(prefix, Box::new(self)),
+            e => e,
+        }
+    }
+
+    /// The error annotated with the span of the query it came from, unless
+    /// it already is, or it is about the execution as a whole.
+    pub(crate) fn within(self, span: &Range<usize>) -> QueryError {
+        match self {
+            QueryError::In(..)
+            | QueryError::LimitExceeded(_)
+            | QueryError::Cancelled
+            | QueryError::Halt(..) => self,
+            e => QueryError::In(span.clone(), Box::new(e)),
+        }
+    }
+
+    /// The byte range of the query text the error came from, if the query
+    /// was parsed with [spans](parse::ParseOptions::spans).
+    pub fn span(&self) -> Option<Range<usize>> {
+        match self {
+            QueryError::In(span, _) => Some(span.clone()),
+            QueryError::At(_, e) => e.span(),
+            _ => None,
+        }
+    }
+}
+
+/// A path such as `.foo[0]`, in the syntax of a query.
+fn path_str(path: &[Value]) -> String {
+    if path.is_empty() {
+        return ".".to_string();
+    }
+    path.iter()
+        .map(|p| match p {
+            Value::String(s) if is_identifier(s) => format!(".{}", s),
+            p => format!("[{}]", p),
+        })
+        .collect()
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
 }
 
-pub(crate) fn type_str(v: &Value) -> &'static str {
+fn type_str(v: &Value) -> &'static str {
     match v {
         Value::Null => "null",
-        Value::Bool(_) => "bool",
+        Value::Bool(_) => "boolean",
         Value::Number(_) => "number",
         Value::String(_) => "string",
         Value::Array(_) => "array",
@@ -38,6 +205,20 @@ pub(crate) fn type_str(v: &Value) -> &'static str {
     }
 }
 
+/// The type of a value followed by its JSON text, shortened if it is long.
+pub(crate) fn describe(v: &Value) -> String {
+    let text = number::to_string(v);
+    match text.char_indices().nth(11) {
+        Some((end, _)) => format!("{} ({}...)", type_str(v), &text[..end]),
+        None => format!("{} ({})", type_str(v), text),
+    }
+}
+
+/// Whether a value counts as true in a condition, which is anything but `false` or `null`.
+pub(crate) fn truthy(v: &Value) -> bool {
+    !matches!(v, Value::Null | Value::Bool(false))
+}
+
 pub(crate) fn single(value: Value) -> QueryResult {
     Ok(vec![value])
 }
@@ -50,10 +231,18 @@ pub(crate) fn empty() -> QueryResult {
     Ok(Vec::new())
 }
 
+pub(crate) fn owned<'v>(result: QueryResult) -> SharedResult<'v> {
+    Ok(result?.into_iter().map(Cow::Owned).collect())
+}
+
 // Tests are taken from examples at https://stedolan.github.io/jq/manual
 #[cfg(test)]
 mod tests {
-    use crate::query::{Executable, Query};
+    use crate::{
+        context::Context,
+        query::{Executable, Query},
+        QueryError,
+    };
     use serde_json::Value;
 
     #[test]
@@ -77,6 +266,21 @@ mod tests {
         assert_eq!(r#"42"#, q.execute(&v).unwrap()[0].to_string());
     }
 
+    #[test]
+    fn quoted_object_index() {
+        let v: Value =
+            serde_json::from_str(r#"{"foo bar": {"x-y": 1}, "「unicode」": 2}"#).unwrap();
+
+        let q: Query = r#"."foo bar"."x-y""#.parse().unwrap();
+        assert_eq!(r#"1"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = r#"."「unicode」""#.parse().unwrap();
+        assert_eq!(r#"2"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = r#"."missing"?"#.parse().unwrap();
+        assert_eq!(r#"null"#, q.execute(&v).unwrap()[0].to_string());
+    }
+
     #[test]
     fn optional_object_index() {
         let q: Query = ".foo?".parse().unwrap();
@@ -96,6 +300,25 @@ mod tests {
         assert_eq!(r#"[]"#, q.execute(&v).unwrap()[0].to_string());
     }
 
+    #[test]
+    fn optional_chaining() {
+        let q: Query = ".a?.b?.c?".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"a": {"b": {"c": 1}}}"#).unwrap();
+        assert_eq!(r#"1"#, q.execute(&v).unwrap()[0].to_string());
+
+        let v: Value = serde_json::from_str(r#"{"x": 1}"#).unwrap();
+        assert_eq!(r#"null"#, q.execute(&v).unwrap()[0].to_string());
+
+        let v: Value = serde_json::from_str(r#"{"a": {"b": [1]}}"#).unwrap();
+        assert!(q.execute(&v).unwrap().is_empty());
+
+        let q: Query = ".a?.b.c?".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"a": [1]}"#).unwrap();
+        assert!(q.execute(&v).is_err());
+        let v: Value = serde_json::from_str(r#"{"a": {"b": "c"}}"#).unwrap();
+        assert!(q.execute(&v).unwrap().is_empty());
+    }
+
     #[test]
     fn array_index() {
         let q: Query = ".[0]".parse().unwrap();
@@ -146,6 +369,64 @@ mod tests {
 
         let q: Query = ".[-2:]".parse().unwrap();
         assert_eq!(r#"["d","e"]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = ".[3:1]".parse().unwrap();
+        assert_eq!("[]", q.execute(&v).unwrap()[0].to_string());
+    }
+
+    #[test]
+    fn slice_codepoints() {
+        let v = Value::from("héllo🦀world");
+        let context = Context::new();
+        let output = |s: &str| {
+            let q: Query = s.parse().unwrap();
+            let r = q.execute(&v).unwrap();
+            let streamed: Result<Vec<_>, _> = q.execute_stream(vec![v.clone()], &context).collect();
+            assert_eq!(r, streamed.unwrap(), "{}", s);
+            Value::from(r).to_string()
+        };
+        assert_eq!(r#"["ll"]"#, output(".[2:4]"));
+        assert_eq!(r#"["é","o🦀w","🦀"]"#, output(".[1:2], .[4:7], .[-6:-5]"));
+        assert_eq!(r#"["","",""]"#, output(".[4:2], .[100:], .[-2:3]"));
+        assert_eq!(r#"["ll","🦀w"]"#, output(".[1:] | .[1:3], .[4:6]"));
+        assert_eq!(
+            "[11,6,3]",
+            output("length, (.[5:] | length), (.[:3] | length)")
+        );
+
+        // Long strings are sliced by the codepoints the execution keeps
+        let v = Value::from("héllo🦀world".repeat(100));
+        let q: Query = ".[1089:1094], .[-6:-5], (.[5:] | .[1000:1001]), .[1094:]"
+            .parse()
+            .unwrap();
+        let r = q.execute_with(&v, &context).unwrap();
+        assert_eq!(r, q.execute(&v).unwrap());
+        assert_eq!(
+            r#"["héllo","🦀","o","🦀world"]"#,
+            Value::from(r).to_string()
+        );
+    }
+
+    #[test]
+    fn length() {
+        let output = |s: &str| {
+            let q: Query = s.parse().unwrap();
+            Value::from(q.execute(&Value::Null).unwrap()).to_string()
+        };
+        assert_eq!(
+            "[0,2,3,1.5,2]",
+            output(
+                r#"length, ("🦀é" | length), (0 - 3 | length), (0 - 1.5 | length), ([1, [2, 3]] | length)"#
+            )
+        );
+        assert_eq!("[1]", output("{a: {b: 1}} | length"));
+        assert!(matches!(
+            "true | length"
+                .parse::<Query>()
+                .unwrap()
+                .execute(&Value::Null),
+            Err(QueryError::Apply("length", _))
+        ));
     }
 
     #[test]
@@ -259,6 +540,103 @@ mod tests {
         assert_eq!(r#"["json"]"#, q.execute(&v).unwrap()[0].to_string());
     }
 
+    #[test]
+    fn multiplication() {
+        let output = |s: &str| {
+            let q: Query = s.parse().unwrap();
+            Value::from(q.execute(&Value::Null).unwrap()).to_string()
+        };
+        assert_eq!("[6,-1.5]", output("2 * 3, 0.5 * -3"));
+        assert_eq!(
+            r#"["abab","abab","ab","ab","ab",null,null]"#,
+            output(r#""ab" * 2, 2 * "ab", "ab" * 1, "ab" * 1.5, "ab" * 0.5, "ab" * 0, "ab" * -1"#)
+        );
+        assert_eq!(r#"[""]"#, output(r#""" * 1e19"#));
+        assert!(r#""ab" * 5e18"#.parse::<Query>().unwrap().execute(&Value::Null).is_err());
+
+        // Only values which are objects on both sides are merged
+        assert_eq!(
+            r#"[{"a":{"b":1,"c":2},"d":[3],"e":{"f":4},"g":null}]"#,
+            output(
+                r#"{"a": {"b": 1}, "d": {"x": 0}, "e": [0], "g": {"h": 5}}
+                    * {"a": {"c": 2}, "d": [3], "e": {"f": 4}, "g": null}"#
+            )
+        );
+        assert_eq!(
+            r#"[{"a":{}}]"#,
+            output(r#"{"a": {"b": 1}} * {"a": {}} | .a |= {}"#)
+        );
+
+        let error = |s: &str| {
+            let q: Query = s.parse().unwrap();
+            q.execute(&Value::Null).unwrap_err().to_string()
+        };
+        assert_eq!(
+            r#"object ({"a":1}) and null (null) cannot be multiplied"#,
+            error(r#"{"a": 1} * null"#)
+        );
+        assert_eq!(
+            "null (null) and number (2) cannot be multiplied",
+            error("null * 2")
+        );
+        assert_eq!(
+            "null (null) and null (null) cannot be multiplied",
+            error("null * null")
+        );
+        assert_eq!(
+            "array ([1]) and number (2) cannot be multiplied",
+            error("[1] * 2")
+        );
+        assert_eq!(
+            r#"string ("a") and string ("b") cannot be multiplied"#,
+            error(r#""a" * "b""#)
+        );
+        assert_eq!(
+            "boolean (true) and number (1) cannot be multiplied",
+            error("true * 1")
+        );
+    }
+
+    #[test]
+    fn string_division() {
+        let output = |s: &str, v: &str| {
+            let q: Query = s.parse().unwrap();
+            let v = Value::from(v);
+            Value::from(q.execute(&v).unwrap()).to_string()
+        };
+        assert_eq!(r#"[["h","é","🦀"]]"#, output(r#". / """#, "hé🦀"));
+        assert_eq!(r#"[["a, b"]]"#, output(r#". / ";""#, "a, b"));
+        assert_eq!(r#"[["a","","b",""]]"#, output(r#". / ",""#, "a,,b,"));
+        assert_eq!(r#"[["","a"]]"#, output(r#". / ",""#, ",a"));
+        assert_eq!(r#"[["",""]]"#, output(r#". / "ab""#, "ab"));
+        assert_eq!(r#"[[],[]]"#, output(r#". / ",", . / """#, ""));
+    }
+
+    #[test]
+    fn large_numbers() {
+        let q: Query = ". + 1, . - 9223372036854775807 * 2".parse().unwrap();
+        let v: Value = serde_json::from_str("18446744073709551614").unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!("18446744073709551615", r[0].to_string());
+        assert_eq!("0", r[1].to_string());
+
+        let q: Query = ". + 2".parse().unwrap();
+        assert_eq!(
+            "1.8446744073709552e+19",
+            q.execute(&v).unwrap()[0].to_string()
+        );
+    }
+
+    #[cfg(feature = "arbitrary_precision")]
+    #[test]
+    fn arbitrary_precision() {
+        let q: Query = ".a, 100000000000000000000001".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"a": 3.141592653589793238462643}"#).unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!("3.141592653589793238462643", r[0].to_string());
+        assert_eq!("100000000000000000000001", r[1].to_string());
+    }
+
     #[test]
     fn other_operators() {
         let q: Query = "10 / . * 3".parse().unwrap();
@@ -281,6 +659,14 @@ mod tests {
             q.execute(&v).unwrap()[0].to_string()
         );
 
+        let q: Query = "7 % 2, -7 % 2, 7.9 % 2.5 + 0.5, 1 / 4, 6 / 3"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            r#"[1,-1,1.5,0.25,2]"#,
+            Value::from(q.execute(&Value::Null).unwrap()).to_string()
+        );
+
         // TODO: (optional) grouping syntax
         // let q: Query = r#".[] | (1 / .)?"#.parse().unwrap();
         // let v: Value = serde_json::from_str(r#""a, b,c,d, e""#).unwrap();
@@ -288,4 +674,45 @@ mod tests {
         // assert_eq!(r#"1"#, r[0].to_string());
         // assert_eq!(r#"-1"#, r[1].to_string());
     }
+
+    #[test]
+    fn error_messages() {
+        let v: Value =
+            serde_json::from_str(r#"{"a": [1, {"b": "a long string value"}], "c": 2}"#).unwrap();
+        let error = |s: &str| {
+            s.parse::<Query>()
+                .unwrap()
+                .execute(&v)
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(r#"Cannot index number (2) with "d" (at .c)"#, error(".c.d"));
+        assert_eq!(r#"Cannot iterate over number (2) (at .c)"#, error(".c[]"));
+        assert_eq!(
+            r#"string ("a long str...) and number (1) cannot be added (at .a[1].b)"#,
+            error(".a[1].b | . + 1")
+        );
+        assert_eq!(
+            r#"Cannot index number (1) with "b" (at .a[0])"#,
+            error(".a[] | .b")
+        );
+        assert_eq!(
+            r#"Cannot index string ("a long str...) with 0 (at .a[1].b)"#,
+            error(r#".a | .[1] | ."b" | .[0]"#)
+        );
+        assert_eq!("$x is not defined", error(".a | $x"));
+        assert_eq!(
+            "number (2) and number (0) cannot be divided because the divisor is zero (at .c)",
+            error(".c | . / 0")
+        );
+        assert_eq!(
+            "number (1) and number (0) cannot be divided (remainder) because the divisor is zero",
+            error("1 % 0")
+        );
+        assert_eq!(
+            "number (5) and number (0.5) cannot be divided (remainder) because the divisor is zero",
+            error("5 % 0.5")
+        );
+    }
 }
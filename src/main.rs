@@ -1,50 +1,1224 @@
-use rq::query::{Executable, Query};
-use serde_json::Value;
+use rq::{
+    bson, cbor,
+    context::Context,
+    csv::{self, CsvOptions},
+    gzip, msgpack,
+    number::{self, JqNumbers},
+    parse::ParseOptions,
+    query::{Executable, Query},
+    stream, toml, urlencoded, QueryError,
+};
+use serde_core::Serialize;
+use serde_json::{
+    ser::{CompactFormatter, Formatter, PrettyFormatter},
+    Map, Serializer, Value,
+};
 use std::{
-    env,
-    io::{self, Read},
+    cell::Cell,
+    collections::VecDeque,
+    env, fmt, fs,
+    io::{self, BufRead, BufWriter, IsTerminal, Write},
+    iter,
+    path::Path,
+    process,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
 };
 
+use profile::Profile;
+use style::Styled;
+
+mod profile;
+mod repl;
+mod style;
+
+/// The flags given on the command line, spelled as `jq` spells them.
+#[derive(Debug, Default, PartialEq, Clone)]
+struct Options {
+    /// `-r`, printing strings without quotes
+    raw_output: bool,
+    /// `-R`, taking each line of input as a string
+    raw_input: bool,
+    /// `-n`, executing once against `null` and leaving the inputs to `input` and `inputs`
+    null_input: bool,
+    /// `-s`, taking every input together as an array, or as one string with `-R`
+    slurp: bool,
+    /// `--ndjson`, reading one document from each line, skipping those which
+    /// aren't JSON, and writing each result as soon as it is found
+    ndjson: bool,
+    /// `--seq`, reading and writing RFC 7464 sequences, where each document
+    /// starts with an RS character, and skipping those which aren't JSON
+    seq: bool,
+    /// `--stream`, taking the `[path, leaf]` events of `tostream` for each
+    /// document as it is read rather than the documents themselves
+    stream: bool,
+    /// `--profile`, reporting where the time and memory went afterwards
+    profile: bool,
+    /// `--parallel n`, evaluating `.[] | f` over arrays of at least `n`
+    /// elements on several threads
+    #[cfg(feature = "parallel")]
+    parallel: Option<usize>,
+    /// `-e`, exiting with a status which depends on the last result
+    exit_status: bool,
+    /// `--input-format name`, how each input is written
+    input_format: Format,
+    /// `--output-format name`, how each result is written
+    output_format: Format,
+    /// `--delimiter c`, separating the fields of CSV or TSV input
+    delimiter: Option<char>,
+    /// `--quote c`, or no quoting at all if empty, for CSV or TSV input
+    quote: Option<Option<char>>,
+    /// `--no-header`, reading each row of CSV or TSV input as an array
+    no_header: bool,
+    layout: Layout,
+    /// `-S`, writing the keys of every object in order
+    sort_keys: bool,
+    /// `-a`, escaping every character in strings which isn't ASCII
+    ascii: bool,
+    /// `-C` or `-M`, or whether the output is a terminal if neither is given
+    color: Option<bool>,
+    /// `-i`, replacing each of `files` with the results for it
+    in_place: bool,
+    /// `--backup suffix`, keeping each file edited in place under its name
+    /// with the suffix added
+    backup: Option<String>,
+    files: Vec<String>,
+    /// `-f file`, reading the query from a file rather than the arguments
+    from_file: Option<String>,
+    /// `--expr query`, each run on the results of the query before it
+    expressions: Vec<String>,
+    /// `--arg name value` and `--argjson name value`, each bound to `$name`
+    named: Map<String, Value>,
+    /// What follows the query after `--args` or `--jsonargs`
+    positional: Vec<Value>,
+}
+
+/// How each result is laid out when it is printed.
+#[derive(Debug, PartialEq, Clone)]
+enum Layout {
+    /// `-c`, on a single line
+    Compact,
+    /// Across lines, with each level indented by the given text
+    Indent(String),
+}
+
+/// The languages which inputs can be read from and results written in.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+enum Format {
+    #[default]
+    Json,
+    /// Each input taken whole as a single document
+    Toml,
+    /// Each input taken whole as an array of its rows, and only read
+    Csv,
+    Tsv,
+    #[cfg(feature = "xml")]
+    Xml,
+    /// Each input taken whole as the values one after another within it
+    Msgpack,
+    Cbor,
+    /// Each input taken whole as the documents one after another within it,
+    /// and only read
+    Bson,
+    /// Each line of the input as a document
+    Urlencoded,
+    /// Each input taken whole as a single document, and only read
+    #[cfg(feature = "json5")]
+    Json5,
+    /// Each input taken whole as the rows of its record batches, and only
+    /// read
+    #[cfg(feature = "arrow")]
+    Arrow,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "json" => Ok(Format::Json),
+            "toml" => Ok(Format::Toml),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            #[cfg(feature = "xml")]
+            "xml" => Ok(Format::Xml),
+            "msgpack" => Ok(Format::Msgpack),
+            "cbor" => Ok(Format::Cbor),
+            "bson" => Ok(Format::Bson),
+            "urlencoded" => Ok(Format::Urlencoded),
+            #[cfg(feature = "json5")]
+            "json5" => Ok(Format::Json5),
+            #[cfg(feature = "arrow")]
+            "arrow" => Ok(Format::Arrow),
+            _ => Err(format!("Unknown format {}", name)),
+        }
+    }
+
+    /// Whether results can be written in the format as well as read.
+    fn writable(self) -> bool {
+        match self {
+            Format::Csv | Format::Tsv | Format::Bson => false,
+            #[cfg(feature = "json5")]
+            Format::Json5 => false,
+            #[cfg(feature = "arrow")]
+            Format::Arrow => false,
+            _ => true,
+        }
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Indent("  ".to_string())
+    }
+}
+
+/// The stack queries run on, with room for calls to nest as deeply as the
+/// limit allows even in a build which isn't optimized.
+const STACK: usize = 64 << 20;
+
 fn main() {
-    let mut value_input = String::new();
-    let stdin = io::stdin();
-    let mut handle = stdin.lock();
-    if let Err(e) = handle.read_to_string(&mut value_input) {
-        eprintln!("Failed to read stdin: {:?}", e.kind());
+    let status = thread::Builder::new()
+        .stack_size(STACK)
+        .spawn(run)
+        .map_or_else(|_| run(), |t| t.join().unwrap_or(101));
+    process::exit(status);
+}
+
+/// Runs as the arguments say, giving the exit status as `jq` would: 2 for bad
+/// arguments or input, 3 for a query which doesn't parse, 5 for a query which
+/// fails, or the code given to `halt` or `halt_error`.
+fn run() -> i32 {
+    let mut args = env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("repl") {
+        return match args.nth(1) {
+            Some(path) => start_repl(&path),
+            None => fail("repl takes the file to explore"),
+        };
+    }
+    let (mut options, query_input) = match parse_args(args) {
+        Ok(parsed) => parsed,
+        Err(e) => return fail(e),
     };
+    options.color = Some(options.color.unwrap_or_else(|| io::stdout().is_terminal()));
 
-    let value: Value = match serde_json::from_str(&value_input) {
-        Ok(v) => v,
+    if options.profile {
+        profile::count_memory();
+    }
+    let started = Instant::now();
+    let parse_options = ParseOptions {
+        spans: true,
+        ..Default::default()
+    };
+    let query = match Query::parse_with(&query_input, &parse_options) {
+        Ok(q) => q,
         Err(e) => {
-            return eprintln!(
-                "Failed to parse document: {:?} at line {} column {}",
-                e.classify(),
-                e.line(),
-                e.column()
-            )
+            eprintln!("Failed to parse query string: {}", e);
+            return 3;
         }
     };
 
-    let query_input = match env::args().nth(1) {
-        Some(q) => q,
-        None => return eprintln!("No query string provided"),
+    // Inputs are only read as they are needed, which with -n may be never
+    let skip = options.ndjson || options.seq;
+    let unreadable = &Cell::new(false);
+    let source = match options.stream && !options.raw_input {
+        true => gzip::decompress(io::BufReader::new(io::stdin())).map(read_events),
+        false => gzip::decompress(io::stdin().lock()).map(|r| read_inputs(r, &options)),
     };
+    let source = match source {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Failed to read input: {}", e);
+            return 2;
+        }
+    };
+    let inputs = source
+        .map_while(move |r| match r {
+            Ok(value) => Some(Some(value)),
+            Err(e) => {
+                eprintln!("{}", e);
+                unreadable.set(true);
+                // A bad line of NDJSON or part of a sequence is skipped, where a
+                // bad document ends the input
+                Some(None).filter(|_| skip)
+            }
+        })
+        .flatten();
 
-    let query: Query = match query_input.parse() {
-        Ok(q) => q,
-        Err(e) => return eprintln!("Failed to parse query string: {}", e),
+    let mut context = Context::new();
+    // Raw input is slurped as it is read, into a single string
+    context
+        .slurp(options.slurp && !options.raw_input)
+        .null_input(options.null_input)
+        .var("ARGS", options.args());
+    for (name, value) in &options.named {
+        context.var(name, value.clone());
+    }
+    #[cfg(feature = "parallel")]
+    context.parallel(options.parallel);
+    let profile = Profile::default();
+    if options.profile {
+        context.trace_with(profile.clone());
+    }
+    let parsed = started.elapsed();
+    if options.in_place {
+        let mut status = 0;
+        for path in &options.files {
+            if let Err(e) = edit_in_place(path, &query, &context, &options) {
+                status = fail(e);
+            }
+        }
+        return status;
+    }
+
+    let stdout = io::stdout();
+    let mut output = BufWriter::new(stdout.lock());
+    let mut last = None;
+    let mut status = 0;
+    let (mut count, mut writing) = (0, Duration::ZERO);
+    for result in query.execute_stream(inputs, &context) {
+        match result {
+            Ok(value) => {
+                let write_started = Instant::now();
+                let written = write_value(&mut output, &value, &options);
+                if let Err(e) = written.and_then(|_| match options.ndjson {
+                    true => output.flush(),
+                    false => Ok(()),
+                }) {
+                    return fail(format!("Failed to write output: {}", e));
+                }
+                writing += write_started.elapsed();
+                count += 1;
+                last = Some(value);
+            }
+            Err(QueryError::Halt(code, message)) => {
+                // Whatever was written before halting still comes first
+                drop(output.flush());
+                halt(message);
+                return code;
+            }
+            Err(e) => {
+                report(&query_input, &e);
+                status = 5;
+            }
+        }
+    }
+    drop(output);
+    if options.profile {
+        let executing = started.elapsed() - parsed - writing;
+        eprintln!("Parsing the query took {:.3?}", parsed);
+        eprintln!("Reading input and executing took {:.3?}", executing);
+        eprintln!("Writing {} results took {:.3?}", count, writing);
+        eprintln!("At most {} bytes were in use", profile::peak_memory());
+        eprint!("{}", profile.report(&query_input, 20));
+    }
+    match status {
+        0 if unreadable.get() => 2,
+        0 if options.exit_status => exit_status(last.as_ref()),
+        status => status,
+    }
+}
+
+/// Reports a failure of the arguments, the input or the output.
+fn fail<E: fmt::Display>(e: E) -> i32 {
+    eprintln!("{}", e);
+    2
+}
+
+/// Reports the message of `halt_error`, which is written as it is when it
+/// is a string and as JSON on its own line otherwise.
+fn halt(message: Option<Value>) {
+    match message {
+        None => {}
+        Some(Value::String(s)) => eprint!("{}", s),
+        Some(v) => eprintln!("{}", v),
+    }
+}
+
+/// The status for `-e`: 1 if the last result was false or null, and 4 if
+/// there were none.
+fn exit_status(last: Option<&Value>) -> i32 {
+    match last {
+        None => 4,
+        Some(Value::Null) | Some(Value::Bool(false)) => 1,
+        Some(_) => 0,
+    }
+}
+
+/// Explores the documents of a file with queries typed one after another.
+fn start_repl(path: &str) -> i32 {
+    let options = Options {
+        color: Some(io::stdout().is_terminal()),
+        ..Options::default()
+    };
+    let documents = fs::read(path)
+        .map_err(|e| format!("Failed to read {}: {:?}", path, e.kind()))
+        .and_then(|text| read_inputs(&text[..], &options).collect::<Result<Vec<_>, _>>());
+    let documents = match documents {
+        Ok(documents) => documents,
+        Err(e) => return fail(e),
+    };
+    let stdout = io::stdout();
+    match repl::session(&documents, io::stdin().lock(), &mut stdout.lock(), &options) {
+        Ok(()) => 0,
+        Err(e) => fail(format!("Failed to write output: {}", e)),
+    }
+}
+
+/// Replaces a file with the results of executing a query on each of its
+/// documents, leaving it as it was if the query fails.
+///
+/// The results are written to a file alongside which is then renamed over the
+/// original, so the file is never left half written.
+fn edit_in_place(
+    path: &str,
+    query: &Query,
+    context: &Context,
+    options: &Options,
+) -> Result<(), String> {
+    let failed = |e: io::Error| format!("Failed to edit {}: {}", path, e);
+    let text = fs::read_to_string(path).map_err(failed)?;
+    let documents = read_inputs(text.as_bytes(), options)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{} in {}", e, path))?;
+
+    let mut output = Vec::new();
+    let options = Options {
+        color: Some(false),
+        ..options.clone()
+    };
+    for result in query.execute_stream(documents, context) {
+        let value = result.map_err(|e| format!("Failed to execute query on {}: {}", path, e))?;
+        write_value(&mut output, &value, &options).map_err(failed)?;
+    }
+
+    let path = Path::new(path);
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(".rq-{}.tmp", process::id()));
+    let written = fs::File::create(&temporary)
+        .and_then(|mut file| {
+            file.write_all(&output)?;
+            file.sync_all()?;
+            fs::set_permissions(&temporary, fs::metadata(path)?.permissions())
+        })
+        .and_then(|_| match &options.backup {
+            Some(suffix) => {
+                let mut backup = path.as_os_str().to_owned();
+                backup.push(suffix);
+                fs::copy(path, backup).map(drop)
+            }
+            None => Ok(()),
+        })
+        .and_then(|_| fs::rename(&temporary, path));
+    written.map_err(|e| {
+        let _ = fs::remove_file(&temporary);
+        failed(e)
+    })
+}
+
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<(Options, String), String> {
+    let mut options = Options::default();
+    let mut query = None;
+    let mut positional = None;
+    let mut args: VecDeque<String> = args.collect();
+    while let Some(arg) = args.pop_front() {
+        match arg.as_str() {
+            "-r" | "--raw-output" => options.raw_output = true,
+            "-R" | "--raw-input" => options.raw_input = true,
+            "-s" | "--slurp" => options.slurp = true,
+            "-n" | "--null-input" => options.null_input = true,
+            "-e" | "--exit-status" => options.exit_status = true,
+            "--seq" => options.seq = true,
+            "--profile" => options.profile = true,
+            "--stream" => options.stream = true,
+            #[cfg(feature = "parallel")]
+            "--parallel" => {
+                let n = args.pop_front().and_then(|n| n.parse::<usize>().ok());
+                options.parallel = Some(n.ok_or("--parallel takes a number of elements")?);
+            }
+            "--ndjson" => {
+                options.ndjson = true;
+                options.layout = Layout::Compact;
+            }
+            "--input-format" | "--output-format" => {
+                let name = args
+                    .pop_front()
+                    .ok_or_else(|| format!("{} takes the name of a format", arg))?;
+                let format = Format::from_name(&name)?;
+                match arg.as_str() {
+                    "--input-format" => options.input_format = format,
+                    _ if !format.writable() => {
+                        return Err(format!("Cannot write results as {}", name))
+                    }
+                    _ => options.output_format = format,
+                }
+            }
+            "--delimiter" => {
+                let delimiter = args.pop_front().and_then(|d| single_char(&d).flatten());
+                options.delimiter = Some(delimiter.ok_or("--delimiter takes a character")?);
+            }
+            "--quote" => {
+                let quote = args.pop_front().and_then(|q| single_char(&q));
+                options.quote = Some(quote.ok_or("--quote takes a character or nothing")?);
+            }
+            "--no-header" => options.no_header = true,
+            "-c" | "--compact-output" => options.layout = Layout::Compact,
+            "-a" | "--ascii-output" => options.ascii = true,
+            "-S" | "--sort-keys" => options.sort_keys = true,
+            "-C" | "--color-output" => options.color = Some(true),
+            "-M" | "--monochrome-output" => options.color = Some(false),
+            "--tab" => options.layout = Layout::Indent("\t".to_string()),
+            "--indent" => {
+                let n = args
+                    .pop_front()
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .ok_or("--indent takes a number of spaces")?;
+                if n > 7 {
+                    return Err("Cannot indent more than 7 spaces".to_string());
+                }
+                options.layout = match n {
+                    0 => Layout::Compact,
+                    n => Layout::Indent(" ".repeat(n)),
+                };
+            }
+            "--arg" | "--argjson" => {
+                let (name, text) = match (args.pop_front(), args.pop_front()) {
+                    (Some(name), Some(text)) => (name, text),
+                    _ => return Err(format!("{} takes a name and a value", arg)),
+                };
+                let value = match arg.as_str() {
+                    "--arg" => Value::from(text),
+                    _ => json_arg(&text)?,
+                };
+                options.named.insert(name, value);
+            }
+            "-i" | "--in-place" => options.in_place = true,
+            "--backup" => options.backup = Some(args.pop_front().ok_or("--backup takes a suffix")?),
+            "-f" | "--from-file" => {
+                options.from_file = Some(args.pop_front().ok_or("-f takes a file name")?);
+            }
+            "--expr" => {
+                let expression = args.pop_front().ok_or("--expr takes a query")?;
+                options.expressions.push(expression);
+            }
+            "--args" => positional = Some(false),
+            "--jsonargs" => positional = Some(true),
+            // Combined short flags such as `-rc`
+            a if a.len() > 2 && a.starts_with('-') && a[1..].chars().all(char::is_alphabetic) => {
+                for c in a[1..].chars().rev() {
+                    args.push_front(format!("-{}", c));
+                }
+            }
+            a if a.starts_with('-') && a.len() > 1 => return Err(format!("Unknown option {}", a)),
+            _ if query.is_none()
+                && options.from_file.is_none()
+                && options.expressions.is_empty() =>
+            {
+                query = Some(arg)
+            }
+            _ => match positional {
+                Some(false) => options.positional.push(Value::from(arg)),
+                Some(true) => options.positional.push(json_arg(&arg)?),
+                None => options.files.push(arg),
+            },
+        }
+    }
+    // Files are only taken, and then needed, with -i
+    if options.in_place == options.files.is_empty() {
+        return Err(match options.files.first() {
+            Some(file) => format!("Unexpected argument {}", file),
+            None => "-i takes the files to edit".to_string(),
+        });
+    }
+    let first = match (&options.from_file, query) {
+        (Some(path), None) => Some(
+            fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read query from {}: {:?}", path, e.kind()))?,
+        ),
+        (Some(_), Some(arg)) => return Err(format!("Unexpected argument {}", arg)),
+        (None, query) => query,
+    };
+    let mut stages: Vec<String> = first.into_iter().collect();
+    stages.extend(options.expressions.iter().cloned());
+    let query = match stages.len() {
+        0 => return Err("No query string provided".to_string()),
+        1 => stages.remove(0),
+        // Each stage ends its own line so that a comment can't run into the next
+        _ => stages
+            .iter()
+            .map(|stage| format!("({}\n)", stage))
+            .collect::<Vec<_>>()
+            .join(" | "),
+    };
+    Ok((options, query))
+}
+
+/// The only character of `text`, or `None` within if it is empty.
+fn single_char(text: &str) -> Option<Option<char>> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (c, None) => Some(c),
+        _ => None,
+    }
+}
+
+fn json_arg(text: &str) -> Result<Value, String> {
+    number::from_slice(text.as_bytes())
+        .map_err(|e| format!("Invalid JSON argument {}: {}", text, e))
+}
+
+impl Options {
+    /// `$ARGS`, holding the arguments given by name and by position.
+    fn args(&self) -> Value {
+        let mut args = Map::new();
+        args.insert(
+            "positional".to_string(),
+            Value::from(self.positional.clone()),
+        );
+        args.insert("named".to_string(), Value::Object(self.named.clone()));
+        Value::Object(args)
+    }
+
+    /// How CSV or TSV input is read, as set by the flags.
+    fn csv_options(&self) -> CsvOptions {
+        let defaults = match self.input_format {
+            Format::Tsv => CsvOptions::tsv(),
+            _ => CsvOptions::default(),
+        };
+        CsvOptions {
+            delimiter: self.delimiter.unwrap_or(defaults.delimiter),
+            quote: self.quote.unwrap_or(defaults.quote),
+            header: !self.no_header,
+            ..defaults
+        }
+    }
+}
+
+/// Each document of the input, or each of its lines as a string with `-R`,
+/// read as they are needed.
+fn read_inputs<'r, R: BufRead + 'r>(
+    mut reader: R,
+    options: &Options,
+) -> Box<dyn Iterator<Item = Result<Value, String>> + 'r> {
+    let failed = |e: io::Error| format!("Failed to read input: {}", e);
+    match (options.raw_input, options.slurp) {
+        (true, true) => {
+            let mut text = String::new();
+            let read = reader.read_to_string(&mut text).map(|_| Value::from(text));
+            Box::new(iter::once(read.map_err(failed)))
+        }
+        (true, false) => Box::new(
+            reader
+                .lines()
+                .map(move |l| l.map(Value::from).map_err(failed)),
+        ),
+        _ if options.input_format != Format::Json => {
+            let mut bytes = Vec::new();
+            let read = reader.read_to_end(&mut bytes).map_err(failed);
+            match read.and_then(|_| read_documents(&bytes, options)) {
+                Ok(documents) => Box::new(documents.into_iter().map(Ok)),
+                Err(e) => Box::new(iter::once(Err(e))),
+            }
+        }
+        _ if options.seq => {
+            let mut part = Vec::new();
+            let mut n = 0;
+            Box::new(iter::from_fn(move || loop {
+                part.clear();
+                match reader.read_until(RS, &mut part) {
+                    Ok(0) => return None,
+                    Ok(_) => n += 1,
+                    Err(e) => return Some(Err(failed(e))),
+                }
+                let text = part.strip_suffix(&[RS]).unwrap_or(&part);
+                if !text.iter().all(u8::is_ascii_whitespace) {
+                    return Some(number::from_slice(text).map_err(|e| {
+                        format!(
+                            "Failed to parse sequence item {}: {:?}",
+                            n - 1,
+                            e.classify()
+                        )
+                    }));
+                }
+            }))
+        }
+        // Only one line is held at a time, however long the input is, and
+        // each is read into the buffer the last one was
+        _ if options.ndjson => {
+            let mut line = String::new();
+            let mut n = 0;
+            Box::new(iter::from_fn(move || loop {
+                line.clear();
+                n += 1;
+                match reader.read_line(&mut line) {
+                    Ok(0) => return None,
+                    Ok(_) if line.trim().is_empty() => continue,
+                    Ok(_) => {
+                        return Some(number::from_slice(line.as_bytes()).map_err(|e| {
+                            format!("Failed to parse line {}: {:?}", n, e.classify())
+                        }))
+                    }
+                    Err(e) => return Some(Err(failed(e))),
+                }
+            }))
+        }
+        // Documents may follow one another with or without whitespace between
+        _ => Box::new(stream::documents(reader).map(|r| {
+            r.map_err(|e| match e {
+                // Such as gzip which ends part way through
+                QueryError::Json(e) if e.is_io() => format!("Failed to read input: {}", e),
+                QueryError::Json(e) => format!(
+                    "Failed to parse document: {:?} at line {} column {}",
+                    e.classify(),
+                    e.line(),
+                    e.column()
+                ),
+                e => e.to_string(),
+            })
+        })),
+    }
+}
+
+/// The documents of a whole input in a format other than JSON.
+fn read_documents(bytes: &[u8], options: &Options) -> Result<Vec<Value>, String> {
+    let text = || std::str::from_utf8(bytes).map_err(|_| "Input is not UTF-8".to_string());
+    let document = match options.input_format {
+        Format::Msgpack => return msgpack::from_slice(bytes).map_err(|e| e.to_string()),
+        Format::Cbor => return cbor::from_slice(bytes).map_err(|e| e.to_string()),
+        Format::Bson => return bson::from_slice(bytes).map_err(|e| e.to_string()),
+        #[cfg(feature = "arrow")]
+        Format::Arrow => return rq::arrow::from_slice(bytes).map_err(|e| e.to_string()),
+        Format::Urlencoded => {
+            return text()?
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(|line| urlencoded::from_str(line).map_err(|e| e.to_string()))
+                .collect()
+        }
+        Format::Toml => toml::from_str(text()?).map_err(|e| e.to_string()),
+        Format::Csv | Format::Tsv => {
+            csv::from_str(text()?, &options.csv_options()).map_err(|e| e.to_string())
+        }
+        #[cfg(feature = "xml")]
+        Format::Xml => rq::xml::from_str(text()?).map_err(|e| e.to_string()),
+        #[cfg(feature = "json5")]
+        Format::Json5 => rq::json5::from_str(text()?).map_err(|e| e.to_string()),
+        Format::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
     };
+    Ok(vec![document?])
+}
+
+/// The events of `tostream` for each document of `reader`, read on another
+/// thread a little ahead of when they are needed.
+fn read_events<R: BufRead + Send + 'static>(
+    reader: R,
+) -> Box<dyn Iterator<Item = Result<Value, String>>> {
+    let (sender, receiver) = mpsc::sync_channel(1024);
+    thread::spawn(move || {
+        let events = sender.clone();
+        // Once the receiver is gone nothing more is needed, and nothing is sent
+        let read = stream::read_events(reader, |e| drop(events.send(Ok(e))));
+        if let Err(e) = read {
+            drop(sender.send(Err(e.to_string())));
+        }
+    });
+    Box::new(receiver.into_iter())
+}
 
-    let results = match query.execute(&value) {
-        Ok(r) => r,
-        Err(e) => return eprintln!("Failed to execute query: {}", e),
+/// The record separator starting each document of a JSON text sequence.
+const RS: u8 = 0x1e;
+
+fn write_value<W: Write>(output: &mut W, value: &Value, options: &Options) -> io::Result<()> {
+    let encoded = match options.output_format {
+        Format::Toml => Some(
+            toml::to_string(value)
+                .map(String::into_bytes)
+                .map_err(|e| e.to_string()),
+        ),
+        #[cfg(feature = "xml")]
+        Format::Xml => Some(
+            rq::xml::to_string(value)
+                .map(String::into_bytes)
+                .map_err(|e| e.to_string()),
+        ),
+        Format::Msgpack => Some(Ok(msgpack::to_vec(value))),
+        Format::Cbor => Some(Ok(cbor::to_vec(value))),
+        Format::Urlencoded => Some(
+            urlencoded::to_string(value)
+                .map(|text| format!("{}\n", text).into_bytes())
+                .map_err(|e| e.to_string()),
+        ),
+        _ => None,
+    };
+    if let Some(encoded) = encoded {
+        let encoded = encoded.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        return output.write_all(&encoded);
+    }
+    if options.seq {
+        output.write_all(&[RS])?;
+    }
+    let sorted;
+    let value = match options.sort_keys {
+        true => {
+            sorted = sort_keys(value);
+            &sorted
+        }
+        false => value,
     };
+    match value {
+        Value::String(s) if options.raw_output && options.ascii => {
+            style::write_ascii(output, s)?;
+            writeln!(output)
+        }
+        Value::String(s) if options.raw_output => writeln!(output, "{}", s),
+        v => {
+            let (color, ascii) = (options.color == Some(true), options.ascii);
+            match &options.layout {
+                Layout::Compact => serialize(
+                    output,
+                    v,
+                    Styled::new(JqNumbers(CompactFormatter), color, ascii),
+                )?,
+                Layout::Indent(indent) => {
+                    let formatter = JqNumbers(PrettyFormatter::with_indent(indent.as_bytes()));
+                    serialize(output, v, Styled::new(formatter, color, ascii))?
+                }
+            }
+            writeln!(output)
+        }
+    }
+}
+
+/// A value with the keys of every object within it in order, whatever order
+/// its maps keep them in.
+fn sort_keys(value: &Value) -> Value {
+    match value {
+        Value::Object(o) => {
+            let mut entries: Vec<_> = o.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k.clone(), sort_keys(v)))
+                    .collect(),
+            )
+        }
+        Value::Array(a) => Value::Array(a.iter().map(sort_keys).collect()),
+        v => v.clone(),
+    }
+}
+
+fn serialize<W: Write, F: Formatter>(
+    output: &mut W,
+    value: &Value,
+    formatter: F,
+) -> io::Result<()> {
+    Ok(value.serialize(&mut Serializer::with_formatter(output, formatter))?)
+}
+
+fn report(query_input: &str, e: &QueryError) {
+    eprintln!("Failed to execute query: {}", e);
+    if let Some(span) = e.span() {
+        // Underline the part of the query responsible, on the line it starts
+        let start = query_input[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let end = query_input[span.start..]
+            .find('\n')
+            .map_or(query_input.len(), |i| span.start + i);
+        let before = query_input[start..span.start].chars().count();
+        let width = query_input[span.start..span.end.min(end)]
+            .chars()
+            .count()
+            .max(1);
+        eprintln!("  {}", &query_input[start..end]);
+        eprintln!("  {}{}", " ".repeat(before), "^".repeat(width));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<(Options, String), String> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    fn inputs(text: &str, options: &Options) -> Result<Vec<Value>, String> {
+        read_inputs(text.as_bytes(), options).collect()
+    }
 
-    if results.is_empty() {
-        println!("No results")
+    fn written(value: Value, options: &Options) -> String {
+        let mut output = Vec::new();
+        write_value(&mut output, &value, options).unwrap();
+        String::from_utf8(output).unwrap()
     }
-    for result in results {
-        let pretty = serde_json::to_string_pretty(&result).unwrap();
-        println!("{}", pretty);
+
+    #[test]
+    fn parse_flags() {
+        let (options, query) = args(&["-r", ".a"]).unwrap();
+        assert!(options.raw_output && !options.raw_input);
+        assert_eq!(".a", query);
+
+        let (options, _) = args(&["-rR", "."]).unwrap();
+        assert!(options.raw_output && options.raw_input);
+        let (options, _) = args(&["--raw-input", "."]).unwrap();
+        assert!(options.raw_input);
+        let (options, _) = args(&["-s", "."]).unwrap();
+        assert!(options.slurp && !options.raw_input);
+        let (options, _) = args(&["-nr", "."]).unwrap();
+        assert!(options.null_input && options.raw_output && !options.slurp);
+        let (options, _) = args(&["-en", "."]).unwrap();
+        assert!(options.exit_status && options.null_input);
+        assert_eq!(None, args(&["."]).unwrap().0.color);
+        assert_eq!(Some(true), args(&["-C", "."]).unwrap().0.color);
+        assert_eq!(Some(false), args(&["-CM", "."]).unwrap().0.color);
+
+        let (options, query) = args(&[
+            "--arg",
+            "a",
+            "-x",
+            "--argjson",
+            "b",
+            "[1]",
+            ".",
+            "--args",
+            "c",
+            "d",
+        ])
+        .unwrap();
+        assert_eq!(".", query);
+        assert_eq!(
+            serde_json::json!({
+                "positional": ["c", "d"],
+                "named": {"a": "-x", "b": [1]},
+            }),
+            options.args()
+        );
+        let (options, _) = args(&["--jsonargs", ".", "1", "{}"]).unwrap();
+        assert_eq!(serde_json::json!([1, {}]), Value::from(options.positional));
+        assert!(args(&["--jsonargs", ".", "x"]).is_err());
+        assert!(args(&["--argjson", "a", "x", "."]).is_err());
+        assert!(args(&[".", "--arg", "a"]).is_err());
+
+        let layout = |a: &[&str]| args(a).map(|(options, _)| options.layout);
+        assert_eq!(Layout::Indent("  ".to_string()), layout(&["."]).unwrap());
+        assert_eq!(Layout::Compact, layout(&["-cr", "."]).unwrap());
+        assert_eq!(
+            Layout::Indent("\t".to_string()),
+            layout(&["--tab", "."]).unwrap()
+        );
+        assert_eq!(
+            Layout::Indent("    ".to_string()),
+            layout(&["--indent", "4", "."]).unwrap()
+        );
+        assert_eq!(Layout::Compact, layout(&["--indent", "0", "."]).unwrap());
+        assert!(layout(&["--indent", "8", "."]).is_err());
+        assert!(layout(&["--indent", "."]).is_err());
+
+        let path = env::temp_dir().join(format!("rq-from-file-{}.jq", process::id()));
+        fs::write(&path, "# The first key\n.a\n").unwrap();
+        let path = path.to_str().unwrap();
+        let (options, query) = args(&["-f", path, "--args", "b"]).unwrap();
+        assert_eq!("# The first key\n.a\n", query);
+        assert_eq!(vec![Value::from("b")], options.positional);
+        assert!(args(&[".", "-f", path]).is_err());
+        fs::remove_file(path).unwrap();
+        assert!(args(&["-f", path]).is_err());
+        assert!(args(&["-f"]).is_err());
+
+        let (options, query) = args(&["--expr", ".a", "--expr", ".b # c", "--args", "d"]).unwrap();
+        assert_eq!("(.a\n) | (.b # c\n)", query);
+        assert_eq!(vec![Value::from("d")], options.positional);
+        let (_, query) = args(&[".a", "--expr", ".b"]).unwrap();
+        assert_eq!("(.a\n) | (.b\n)", query);
+        assert!(args(&["--expr"]).is_err());
+
+        assert!(args(&[]).is_err());
+        assert!(args(&["-x", "."]).is_err());
+        assert!(args(&[".", "."]).is_err());
+    }
+
+    #[test]
+    fn raw_input_and_output() {
+        let raw = Options {
+            raw_output: true,
+            raw_input: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![Value::from("{\"a\": 1}"), Value::from("b")],
+            inputs("{\"a\": 1}\nb\n", &raw).unwrap()
+        );
+        assert_eq!(
+            vec![Value::from(1), Value::from("b")],
+            inputs("1\"b\"", &Options::default()).unwrap()
+        );
+        assert_eq!(
+            "Failed to parse document: Syntax at line 1 column 3",
+            inputs("1 b", &Options::default()).unwrap_err()
+        );
+        let (ndjson, _) = args(&["--ndjson", "."]).unwrap();
+        assert_eq!(Layout::Compact, ndjson.layout);
+        assert_eq!(
+            vec![
+                Ok(serde_json::json!({"a": 1})),
+                Err("Failed to parse line 3: Eof".to_string()),
+                Ok(Value::from(2)),
+            ],
+            read_inputs("{\"a\": 1}\n\n{\"a\"\n2\n".as_bytes(), &ndjson).collect::<Vec<_>>()
+        );
+
+        let (seq, _) = args(&["--seq", "."]).unwrap();
+        assert_eq!(
+            vec![
+                Ok(serde_json::json!({"a": 1})),
+                Err("Failed to parse sequence item 2: Eof".to_string()),
+                Ok(Value::from(2)),
+            ],
+            read_inputs("\x1e{\"a\": 1}\n\x1e{\"a\"\n\x1e2\n".as_bytes(), &seq).collect::<Vec<_>>()
+        );
+        assert_eq!("\x1e2\n", written(Value::from(2), &seq));
+
+        assert!(args(&["--stream", "."]).unwrap().0.stream);
+        assert!(args(&["--profile", "."]).unwrap().0.profile);
+        #[cfg(feature = "parallel")]
+        {
+            assert_eq!(
+                Some(100),
+                args(&["--parallel", "100", "."]).unwrap().0.parallel
+            );
+            assert!(args(&["--parallel", "x", "."]).is_err());
+        }
+        assert_eq!(
+            vec![
+                Ok(serde_json::json!([["a"], 1])),
+                Ok(serde_json::json!([["a"]])),
+                Err("Cannot parse input: EOF while parsing a list at line 1 column 1".to_string()),
+            ],
+            read_events("{\"a\": 1} [".as_bytes()).collect::<Vec<_>>()
+        );
+
+        let slurp = Options {
+            raw_input: true,
+            slurp: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![Value::from("a\nb\n")],
+            inputs("a\nb\n", &slurp).unwrap()
+        );
+
+        assert_eq!("a\"b\n", written(Value::from("a\"b"), &raw));
+        assert_eq!(
+            "\"a\\\"b\"\n",
+            written(Value::from("a\"b"), &Options::default())
+        );
+        assert_eq!("[\n  1\n]\n", written(serde_json::json!([1]), &raw));
+    }
+
+    #[test]
+    fn in_place() {
+        let path = env::temp_dir().join(format!("rq-in-place-{}.json", process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "{\"a\": 1}\n{\"a\": 2}\n").unwrap();
+        let (options, query) = args(&["-i", "-c", ".a |= . + 1", path, "--backup", "~"]).unwrap();
+        assert_eq!(vec![path.to_string()], options.files);
+        let query: Query = query.parse().unwrap();
+        let context = Context::new();
+
+        edit_in_place(path, &query, &context, &options).unwrap();
+        assert_eq!("{\"a\":2}\n{\"a\":3}\n", fs::read_to_string(path).unwrap());
+        let backup = format!("{}~", path);
+        assert_eq!(
+            "{\"a\": 1}\n{\"a\": 2}\n",
+            fs::read_to_string(&backup).unwrap()
+        );
+
+        // A failure leaves the file as it was
+        let query: Query = ".a[]".parse().unwrap();
+        assert!(edit_in_place(path, &query, &context, &options).is_err());
+        assert_eq!("{\"a\":2}\n{\"a\":3}\n", fs::read_to_string(path).unwrap());
+        fs::remove_file(path).unwrap();
+        fs::remove_file(backup).unwrap();
+
+        assert!(args(&["-i", "."]).is_err());
+        assert!(args(&[".", "file"]).is_err());
+    }
+
+    #[test]
+    fn formats() {
+        let (options, _) =
+            args(&["--input-format", "toml", "--output-format", "toml", "."]).unwrap();
+        assert_eq!(Format::Toml, options.input_format);
+        assert_eq!(Format::Toml, options.output_format);
+        assert!(args(&["--input-format", "yaml", "."]).is_err());
+
+        let text = "[package]\nname = \"rq\"\nversion = \"0.1.0\"\n";
+        let v = serde_json::json!({"package": {"name": "rq", "version": "0.1.0"}});
+        assert_eq!(Ok(vec![v.clone()]), inputs(text, &options));
+        assert_eq!(text, written(v, &options));
+        assert!(inputs("[package", &options).is_err());
+        assert!(write_value(&mut Vec::new(), &Value::from(1), &options).is_err());
+
+        let (options, _) = args(&["--input-format", "csv", "--delimiter", ";", "."]).unwrap();
+        assert_eq!(
+            Ok(vec![serde_json::json!([{"a": "1", "b": "x;y"}])]),
+            inputs("a;b\n1;\"x;y\"\n", &options)
+        );
+        let (options, _) =
+            args(&["--input-format", "tsv", "--no-header", "--quote", "'", "."]).unwrap();
+        assert_eq!(
+            Ok(vec![serde_json::json!([["a", "b\tc"]])]),
+            inputs("a\t'b\tc'\n", &options)
+        );
+        assert!(inputs(
+            "a,b\n1\n",
+            &args(&["--input-format", "csv", "."]).unwrap().0
+        )
+        .is_err());
+        assert!(args(&["--output-format", "csv", "."]).is_err());
+        assert!(args(&["--delimiter", "ab", "."]).is_err());
+
+        let (options, _) = args(&[
+            "--input-format",
+            "msgpack",
+            "--output-format",
+            "msgpack",
+            ".",
+        ])
+        .unwrap();
+        let bytes = b"\x81\xa1a\x01\x02";
+        let documents: Result<Vec<_>, _> = read_inputs(&bytes[..], &options).collect();
+        assert_eq!(
+            Ok(vec![serde_json::json!({"a": 1}), Value::from(2)]),
+            documents
+        );
+        let mut output = Vec::new();
+        write_value(&mut output, &serde_json::json!({"a": 1}), &options).unwrap();
+        assert_eq!(&bytes[..4], &output[..]);
+        assert!(inputs("\u{92}", &options).is_err());
+
+        let (options, _) =
+            args(&["--input-format", "cbor", "--output-format", "cbor", "."]).unwrap();
+        let bytes = b"\xa1\x61a\x01\x02";
+        let documents: Result<Vec<_>, _> = read_inputs(&bytes[..], &options).collect();
+        assert_eq!(
+            Ok(vec![serde_json::json!({"a": 1}), Value::from(2)]),
+            documents
+        );
+        let mut output = Vec::new();
+        write_value(&mut output, &serde_json::json!({"a": 1}), &options).unwrap();
+        assert_eq!(&bytes[..4], &output[..]);
+
+        let (options, _) = args(&["--input-format", "bson", "."]).unwrap();
+        let bytes = b"\x0c\0\0\0\x10a\0\x01\0\0\0\0";
+        let documents: Result<Vec<_>, _> = read_inputs(&bytes[..], &options).collect();
+        assert_eq!(Ok(vec![serde_json::json!({"a": 1})]), documents);
+        assert!(args(&["--output-format", "bson", "."]).is_err());
+
+        let (options, _) = args(&[
+            "--input-format",
+            "urlencoded",
+            "--output-format",
+            "urlencoded",
+            ".",
+        ])
+        .unwrap();
+        let v = serde_json::json!({"a": "1", "b": ["x", "y"]});
+        assert_eq!(
+            Ok(vec![v.clone(), serde_json::json!({"c": ""})]),
+            inputs("a=1&b[]=x&b[]=y\n\nc\n", &options)
+        );
+        assert_eq!("a=1&b[]=x&b[]=y\n", written(v, &options));
+
+        #[cfg(feature = "arrow")]
+        {
+            let (options, _) = args(&["--input-format", "arrow", "."]).unwrap();
+            let bytes = b"\xff\xff\xff\xff\0\0\0\0";
+            let documents: Result<Vec<_>, _> = read_inputs(&bytes[..], &options).collect();
+            assert_eq!(Ok(vec![]), documents);
+            assert!(args(&["--output-format", "arrow", "."]).is_err());
+        }
+
+        #[cfg(feature = "json5")]
+        {
+            let (options, _) = args(&["--input-format", "json5", "."]).unwrap();
+            let v = serde_json::json!({"a": [1]});
+            assert_eq!(Ok(vec![v]), inputs("// comment\n{a: [1,],}", &options));
+            assert!(args(&["--output-format", "json5", "."]).is_err());
+        }
+
+        #[cfg(feature = "xml")]
+        {
+            let (options, _) =
+                args(&["--input-format", "xml", "--output-format", "xml", "."]).unwrap();
+            let v = serde_json::json!({"a": {"@b": "1", "c": "d"}});
+            assert_eq!(
+                Ok(vec![v.clone()]),
+                inputs("<a b='1'><c>d</c></a>", &options)
+            );
+            assert_eq!("<a b=\"1\">\n  <c>d</c>\n</a>\n", written(v, &options));
+        }
+    }
+
+    #[test]
+    fn exit_statuses() {
+        assert_eq!(0, exit_status(Some(&Value::from(0))));
+        assert_eq!(1, exit_status(Some(&Value::Null)));
+        assert_eq!(1, exit_status(Some(&Value::Bool(false))));
+        assert_eq!(4, exit_status(None));
+    }
+
+    #[test]
+    fn layouts() {
+        let v = serde_json::json!({"a": [1]});
+        let layout = |layout| Options {
+            layout,
+            ..Default::default()
+        };
+        assert_eq!(
+            "{\"a\":[1]}\n",
+            written(v.clone(), &layout(Layout::Compact))
+        );
+        assert_eq!(
+            "{\n\t\"a\": [\n\t\t1\n\t]\n}\n",
+            written(v.clone(), &layout(Layout::Indent("\t".to_string())))
+        );
+        // Floats are written as jq writes them, unless kept as they were read
+        #[cfg(not(feature = "arbitrary_precision"))]
+        {
+            let numbers = serde_json::json!([1.0, -0.0, 1e10, 1e-5, 2.5]);
+            assert_eq!(
+                "[1,-0,10000000000,1e-05,2.5]\n",
+                written(numbers.clone(), &layout(Layout::Compact))
+            );
+            assert_eq!(
+                "[\n  1,\n  -0,\n  10000000000,\n  1e-05,\n  2.5\n]\n",
+                written(numbers, &Options::default())
+            );
+        }
+        let (sorted, _) = args(&["-Sc", "."]).unwrap();
+        assert!(sorted.sort_keys);
+        assert_eq!(
+            "[{\"a\":{\"b\":1,\"c\":2},\"é\":3}]\n",
+            written(
+                serde_json::json!([{"é": 3, "a": {"c": 2, "b": 1}}]),
+                &sorted
+            )
+        );
+        let ascii = Options {
+            ascii: true,
+            raw_output: true,
+            ..layout(Layout::Compact)
+        };
+        assert!(args(&["-a", "."]).unwrap().0.ascii);
+        assert_eq!("x\\u00e9\n", written(Value::from("xé"), &ascii));
+        assert_eq!(
+            "[\"x\\u00e9\"]\n",
+            written(serde_json::json!(["xé"]), &ascii)
+        );
+        let colored = Options {
+            color: Some(true),
+            ..layout(Layout::Compact)
+        };
+        assert!(written(v, &colored).starts_with("\x1b[1;39m{"));
     }
 }
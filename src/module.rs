@@ -0,0 +1,274 @@
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{char, multispace1},
+    combinator::{map, opt},
+    sequence::{preceded, terminated, tuple},
+    IResult,
+};
+use serde_json::Value;
+
+use crate::{
+    env::{Binding, Env},
+    parse::{parse_identifier, preprocess, ParseError, ParseOptions, Parseable},
+    query::{Eval, Query},
+    raw::parse_string,
+    space, PathResult, QueryError, QueryIter, QueryResult,
+};
+
+/// A library of definitions made available to the rest of the query.
+///
+/// The library itself is loaded from the search path when the query is
+/// parsed, so `module` holds its definitions ending in [`Query::Empty`].
+#[derive(Debug, PartialEq, Clone)]
+pub struct Import {
+    pub path: String,
+    /// The `name` of `import "path" as name`, or `None` for `include "path"`.
+    pub alias: Option<String>,
+    pub module: Query,
+    pub rest: Query,
+}
+
+impl Import {
+    fn scope<'a>(&'a self, env: &Env<'a>) -> Env<'a> {
+        let module = scope(&self.module, env.root());
+        env.bind(Binding::Module(self.alias.as_deref(), module))
+    }
+}
+
+impl Eval for Import {
+    fn eval<'a>(&'a self, env: &Env<'a>, value: &Value) -> QueryResult {
+        self.rest.eval(&self.scope(env), value)
+    }
+
+    fn eval_owned<'a>(&'a self, env: &Env<'a>, value: Value) -> QueryResult {
+        self.rest.eval_owned(&self.scope(env), value)
+    }
+
+    fn eval_into<'a>(
+        &'a self,
+        env: &Env<'a>,
+        value: &Value,
+        output: &mut Vec<Value>,
+    ) -> Result<(), QueryError> {
+        self.rest.eval_into(&self.scope(env), value, output)
+    }
+
+    fn eval_iter<'a>(&'a self, env: &Env<'a>, value: Cow<'a, Value>) -> QueryIter<'a> {
+        self.rest.eval_iter(&self.scope(env), value)
+    }
+
+    fn eval_paths<'a>(&'a self, env: &Env<'a>, value: &Value) -> PathResult {
+        self.rest.eval_paths(&self.scope(env), value)
+    }
+}
+
+/// Binds every definition of a library in order, so later ones can refer to earlier ones.
+fn scope<'a>(mut query: &'a Query, mut env: Env<'a>) -> Env<'a> {
+    loop {
+        match query {
+            Query::Define(define) => {
+                env = env.bind(Binding::Function(&define.function));
+                query = &define.rest;
+            }
+            Query::Import(import) => {
+                let module = scope(&import.module, env.root());
+                env = env.bind(Binding::Module(import.alias.as_deref(), module));
+                query = &import.rest;
+            }
+            _ => return env,
+        }
+    }
+}
+
+impl Parseable for Import {
+    fn parser(input: &str) -> IResult<&str, Self, ParseError> {
+        let (input, (path, alias)) = terminated(
+            alt((
+                tuple((
+                    preceded(terminated(tag("import"), multispace1), parse_string),
+                    map(
+                        preceded(
+                            space::around(tag("as")),
+                            map(parse_identifier, str::to_string),
+                        ),
+                        Some,
+                    ),
+                )),
+                map(
+                    preceded(terminated(tag("include"), multispace1), parse_string),
+                    |path| (path, None),
+                ),
+            )),
+            space::around(char(';')),
+        )(input)?;
+        let (input, rest) = opt(Query::parser)(input)?;
+
+        Ok((
+            input,
+            Import {
+                path,
+                alias,
+                module: Query::Empty,
+                rest: rest.unwrap_or(Query::Empty),
+            },
+        ))
+    }
+}
+
+/// Loads the library of every import at the start of the query from the search path.
+pub(crate) fn resolve(
+    mut query: &mut Query,
+    options: &ParseOptions,
+    origin: Option<&Path>,
+    loading: &mut Vec<PathBuf>,
+) -> Result<(), ParseError> {
+    while let Query::Import(import) = query {
+        let error = |reason: &str| ParseError::Module(import.path.clone(), reason.to_string());
+        let file = locate(&import.path, options, origin).ok_or_else(|| error("not found"))?;
+        if loading.contains(&file) {
+            return Err(error("circular import"));
+        }
+
+        let text = fs::read_to_string(&file).map_err(|e| error(&e.to_string()))?;
+        let mut module = Query::parse(&preprocess(&text, options)?)?;
+        if !is_library(&module) {
+            return Err(error("only definitions are allowed"));
+        }
+
+        let dir = file.parent().map(Path::to_path_buf);
+        loading.push(file);
+        resolve(&mut module, options, dir.as_deref(), loading)?;
+        loading.pop();
+
+        import.module = module;
+        query = &mut import.rest;
+    }
+    Ok(())
+}
+
+/// Finds `path.jq` or `path/last.jq` under each search directory, where a
+/// directory of `.` or starting with `./` is relative to the importing file.
+fn locate(path: &str, options: &ParseOptions, origin: Option<&Path>) -> Option<PathBuf> {
+    let last = Path::new(path).file_name()?.to_str()?;
+    options
+        .library_paths
+        .iter()
+        .map(|dir| match (dir.strip_prefix("."), origin) {
+            (Ok(relative), Some(origin)) => origin.join(relative),
+            _ => dir.clone(),
+        })
+        .flat_map(|dir| {
+            vec![
+                dir.join(format!("{}.jq", path)),
+                dir.join(path).join(format!("{}.jq", last)),
+            ]
+        })
+        .find(|file| file.is_file())
+}
+
+fn is_library(query: &Query) -> bool {
+    match query {
+        Query::Empty => true,
+        Query::Define(define) => is_library(&define.rest),
+        Query::Import(import) => is_library(&import.rest),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use crate::query::Executable;
+
+    use super::*;
+
+    fn library(name: &str, files: &[(&str, &str)]) -> ParseOptions {
+        let dir = env::temp_dir().join(format!("rq-{}-{}", name, std::process::id()));
+        for (file, text) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        ParseOptions {
+            library_paths: vec![dir],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_import() {
+        assert!(Query::parse("import \"a\";").is_err());
+        assert!(Query::parse("include \"a\" as a;").is_err());
+        assert!(Query::parse(". | import \"a\" as a; .").is_err());
+
+        assert_eq!(
+            Query::Import(Box::new(Import {
+                path: "a".to_string(),
+                alias: Some("a".to_string()),
+                module: Query::Empty,
+                rest: Query::Import(Box::new(Import {
+                    path: "b/c".to_string(),
+                    alias: None,
+                    module: Query::Empty,
+                    rest: Query::Identity,
+                })),
+            })),
+            Query::parse("import \"a\" as a; include \"b/c\"; .").unwrap()
+        );
+    }
+
+    #[test]
+    fn import() {
+        let options = library(
+            "import",
+            &[
+                ("a.jq", "def f: .a; def g: f + 1;"),
+                ("b/b.jq", "# helpers\ninclude \"./a\"; def h: g * 2;"),
+            ],
+        );
+        let v: Value = serde_json::from_str(r#"{"a": 1}"#).unwrap();
+
+        let q = Query::parse_with("import \"a\" as lib; lib::g", &options).unwrap();
+        assert_eq!(r#"2"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q = Query::parse_with("include \"a\"; [f, g]", &options).unwrap();
+        assert_eq!(r#"[1,2]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q = Query::parse_with("import \"b\" as b; b::h, b::f", &options).unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!(r#"4"#, r[0].to_string());
+        assert_eq!(r#"1"#, r[1].to_string());
+
+        // Imports come first, and only see their own definitions
+        assert!(Query::parse_with("def f: .b; import \"a\" as a; a::g", &options).is_err());
+        let q = Query::parse_with("import \"a\" as a; def f: 5; a::g", &options).unwrap();
+        assert_eq!(r#"2"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q = Query::parse_with("import \"a\" as a; g", &options).unwrap();
+        assert!(q.execute(&v).is_err());
+    }
+
+    #[test]
+    fn import_errors() {
+        let options = library(
+            "errors",
+            &[("loop.jq", "include \"loop\";"), ("expr.jq", "def f: .; f")],
+        );
+        let error = |q: &str| match Query::parse_with(q, &options) {
+            Err(ParseError::Module(_, reason)) => reason,
+            r => panic!("unexpected {:?}", r),
+        };
+
+        assert_eq!("not found", error("import \"missing\" as m; ."));
+        assert_eq!("circular import", error("include \"loop\"; ."));
+        assert_eq!("only definitions are allowed", error("include \"expr\"; ."));
+    }
+}
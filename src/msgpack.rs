@@ -0,0 +1,338 @@
+use serde_json::{Map, Number, Value};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum MsgpackError {
+    #[error("Unexpected end of MessagePack input")]
+    Eof,
+    #[error("Invalid MessagePack marker 0x{0:02x}")]
+    Marker(u8),
+    #[error("MessagePack string is not UTF-8")]
+    Utf8,
+    #[error("Cannot read MessagePack extension type {0}")]
+    Extension(i8),
+    #[error("MessagePack nested more than {} deep", MAX_DEPTH)]
+    Depth,
+}
+
+/// How many arrays and maps deep a value can be, as deep as serde_json
+/// reads JSON.
+const MAX_DEPTH: usize = 128;
+
+/// Reads each of the MessagePack values one after another in `bytes`.
+///
+/// Binary data is read as an array of its bytes, map keys which aren't
+/// strings as their JSON text, and timestamps as seconds since 1970.
+pub fn from_slice(mut bytes: &[u8]) -> Result<Vec<Value>, MsgpackError> {
+    let mut values = Vec::new();
+    while !bytes.is_empty() {
+        values.push(read(&mut bytes, 0)?);
+    }
+    Ok(values)
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], MsgpackError> {
+    if bytes.len() < n {
+        return Err(MsgpackError::Eof);
+    }
+    let (taken, rest) = bytes.split_at(n);
+    *bytes = rest;
+    Ok(taken)
+}
+
+/// A big-endian unsigned integer of `n` bytes.
+fn uint(bytes: &mut &[u8], n: usize) -> Result<u64, MsgpackError> {
+    Ok(take(bytes, n)?
+        .iter()
+        .fold(0, |acc, &b| (acc << 8) | u64::from(b)))
+}
+
+/// A big-endian signed integer of `n` bytes.
+fn int(bytes: &mut &[u8], n: usize) -> Result<i64, MsgpackError> {
+    let shift = 64 - 8 * n as u32;
+    Ok(((uint(bytes, n)? << shift) as i64) >> shift)
+}
+
+fn float(f: f64) -> Value {
+    Number::from_f64(f).map_or(Value::Null, Value::Number)
+}
+
+/// Reads a value within `depth` arrays and maps.
+fn read(bytes: &mut &[u8], depth: usize) -> Result<Value, MsgpackError> {
+    let marker = take(bytes, 1)?[0];
+    Ok(match marker {
+        0x00..=0x7f => Value::from(marker),
+        0x80..=0x8f => map(bytes, usize::from(marker & 0x0f), depth)?,
+        0x90..=0x9f => array(bytes, usize::from(marker & 0x0f), depth)?,
+        0xa0..=0xbf => string(bytes, usize::from(marker & 0x1f))?,
+        0xc0 => Value::Null,
+        0xc2 => Value::Bool(false),
+        0xc3 => Value::Bool(true),
+        0xc4..=0xc6 => {
+            let n = uint(bytes, 1 << (marker - 0xc4))? as usize;
+            Value::from(take(bytes, n)?.to_vec())
+        }
+        0xc7..=0xc9 => {
+            // The length of the data comes before the type
+            let n = uint(bytes, 1 << (marker - 0xc7))? as usize;
+            extension(bytes, n)?
+        }
+        0xca => float(f64::from(f32::from_bits(uint(bytes, 4)? as u32))),
+        0xcb => float(f64::from_bits(uint(bytes, 8)?)),
+        0xcc..=0xcf => Value::from(uint(bytes, 1 << (marker - 0xcc))?),
+        0xd0..=0xd3 => Value::from(int(bytes, 1 << (marker - 0xd0))?),
+        0xd4..=0xd8 => extension(bytes, 1 << (marker - 0xd4))?,
+        0xd9..=0xdb => {
+            let n = uint(bytes, 1 << (marker - 0xd9))? as usize;
+            string(bytes, n)?
+        }
+        0xdc | 0xdd => {
+            let n = uint(bytes, 2 << (marker - 0xdc))? as usize;
+            array(bytes, n, depth)?
+        }
+        0xde | 0xdf => {
+            let n = uint(bytes, 2 << (marker - 0xde))? as usize;
+            map(bytes, n, depth)?
+        }
+        0xe0..=0xff => Value::from(marker as i8),
+        marker => return Err(MsgpackError::Marker(marker)),
+    })
+}
+
+/// The type of extension for timestamps, the only one MessagePack defines.
+const TIMESTAMP: i8 = -1;
+
+/// An extension of `n` bytes of data after its type, which can only be read
+/// if it is a timestamp.
+fn extension(bytes: &mut &[u8], n: usize) -> Result<Value, MsgpackError> {
+    let kind = int(bytes, 1)? as i8;
+    let mut data = take(bytes, n)?;
+    let (nanoseconds, seconds) = match (kind, n) {
+        (TIMESTAMP, 4) => (0, uint(&mut data, 4)? as i64),
+        (TIMESTAMP, 8) => {
+            let n = uint(&mut data, 8)?;
+            (n >> 34, (n & ((1 << 34) - 1)) as i64)
+        }
+        (TIMESTAMP, 12) => (uint(&mut data, 4)?, int(&mut data, 8)?),
+        _ => return Err(MsgpackError::Extension(kind)),
+    };
+    Ok(match nanoseconds {
+        0 => Value::from(seconds),
+        1..=999_999_999 => float(seconds as f64 + nanoseconds as f64 / 1e9),
+        _ => return Err(MsgpackError::Extension(kind)),
+    })
+}
+
+fn string(bytes: &mut &[u8], n: usize) -> Result<Value, MsgpackError> {
+    let s = std::str::from_utf8(take(bytes, n)?).map_err(|_| MsgpackError::Utf8)?;
+    Ok(Value::from(s))
+}
+
+fn array(bytes: &mut &[u8], n: usize, depth: usize) -> Result<Value, MsgpackError> {
+    if depth >= MAX_DEPTH {
+        return Err(MsgpackError::Depth);
+    }
+    // The count can't be trusted to reserve space, but each value takes a byte
+    let mut a = Vec::with_capacity(n.min(bytes.len()));
+    for _ in 0..n {
+        a.push(read(bytes, depth + 1)?);
+    }
+    Ok(Value::Array(a))
+}
+
+fn map(bytes: &mut &[u8], n: usize, depth: usize) -> Result<Value, MsgpackError> {
+    if depth >= MAX_DEPTH {
+        return Err(MsgpackError::Depth);
+    }
+    let mut o = Map::new();
+    for _ in 0..n {
+        let key = match read(bytes, depth + 1)? {
+            Value::String(k) => k,
+            k => k.to_string(),
+        };
+        o.insert(key, read(bytes, depth + 1)?);
+    }
+    Ok(Value::Object(o))
+}
+
+/// Writes a value as MessagePack, each number in as few bytes as it fits.
+pub fn to_vec(value: &Value) -> Vec<u8> {
+    let mut output = Vec::new();
+    write(&mut output, value);
+    output
+}
+
+/// Writes a marker followed by a length in the smallest of the sizes which
+/// `markers` gives for 8, 16 and 32 bit lengths.
+fn write_len(output: &mut Vec<u8>, markers: [Option<u8>; 3], n: usize) {
+    match markers {
+        [Some(m), ..] if n <= 0xff => output.extend([m, n as u8]),
+        [_, Some(m), _] if n <= 0xffff => {
+            output.push(m);
+            output.extend((n as u16).to_be_bytes());
+        }
+        [.., Some(m)] => {
+            output.push(m);
+            output.extend((n as u32).to_be_bytes());
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn write(output: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => output.push(0xc0),
+        Value::Bool(b) => output.push(if *b { 0xc3 } else { 0xc2 }),
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(u), _) if u <= 0x7f => output.push(u as u8),
+            (Some(u), _) if u <= 0xff => output.extend([0xcc, u as u8]),
+            (Some(u), _) if u <= 0xffff => {
+                output.push(0xcd);
+                output.extend((u as u16).to_be_bytes());
+            }
+            (Some(u), _) if u <= 0xffff_ffff => {
+                output.push(0xce);
+                output.extend((u as u32).to_be_bytes());
+            }
+            (Some(u), _) => {
+                output.push(0xcf);
+                output.extend(u.to_be_bytes());
+            }
+            (_, Some(i)) if i >= -32 => output.push(i as u8),
+            (_, Some(i)) if i >= i64::from(i8::MIN) => output.extend([0xd0, i as u8]),
+            (_, Some(i)) if i >= i64::from(i16::MIN) => {
+                output.push(0xd1);
+                output.extend((i as i16).to_be_bytes());
+            }
+            (_, Some(i)) if i >= i64::from(i32::MIN) => {
+                output.push(0xd2);
+                output.extend((i as i32).to_be_bytes());
+            }
+            (_, Some(i)) => {
+                output.push(0xd3);
+                output.extend(i.to_be_bytes());
+            }
+            _ => {
+                output.push(0xcb);
+                output.extend(n.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        },
+        Value::String(s) => {
+            match s.len() {
+                n if n <= 0x1f => output.push(0xa0 | n as u8),
+                n => write_len(output, [Some(0xd9), Some(0xda), Some(0xdb)], n),
+            }
+            output.extend(s.as_bytes());
+        }
+        Value::Array(a) => {
+            match a.len() {
+                n if n <= 0x0f => output.push(0x90 | n as u8),
+                n => write_len(output, [None, Some(0xdc), Some(0xdd)], n),
+            }
+            for v in a {
+                write(output, v);
+            }
+        }
+        Value::Object(o) => {
+            match o.len() {
+                n if n <= 0x0f => output.push(0x80 | n as u8),
+                n => write_len(output, [None, Some(0xde), Some(0xdf)], n),
+            }
+            for (k, v) in o {
+                write(output, &Value::from(k.as_str()));
+                write(output, v);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn read_msgpack() {
+        // {"a": [1, -1, true, nil], "bc": 1.5} then 300
+        let bytes = b"\x82\xa1a\x94\x01\xff\xc3\xc0\xa2bc\xcb\x3f\xf8\0\0\0\0\0\0\xcd\x01\x2c";
+        assert_eq!(
+            Ok(vec![
+                json!({"a": [1, -1, true, null], "bc": 1.5}),
+                json!(300)
+            ]),
+            from_slice(bytes)
+        );
+        assert_eq!(Ok(vec![json!(-200)]), from_slice(b"\xd1\xff\x38"));
+        assert_eq!(Ok(vec![json!([1, 2])]), from_slice(b"\xc4\x02\x01\x02"));
+        assert_eq!(Ok(vec![json!({"1": 2})]), from_slice(b"\x81\x01\x02"));
+        assert_eq!(Ok(vec![json!(0.5)]), from_slice(b"\xca\x3f\0\0\0"));
+
+        assert_eq!(Err(MsgpackError::Eof), from_slice(b"\x92\x01"));
+        assert_eq!(Err(MsgpackError::Marker(0xc1)), from_slice(b"\xc1"));
+        assert_eq!(Err(MsgpackError::Utf8), from_slice(b"\xa1\xff"));
+        assert_eq!(
+            Err(MsgpackError::Extension(1)),
+            from_slice(b"\xd6\x01\0\0\0\0")
+        );
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(
+            Ok(vec![json!(1_577_836_800)]),
+            from_slice(b"\xd6\xff\x5e\x0b\xe1\x00")
+        );
+        // Half a second later, in 30 bits of nanoseconds and 34 of seconds
+        let n = 500_000_000u64 << 34 | 1_577_836_800;
+        let bytes = [&[0xd7, 0xff][..], &n.to_be_bytes()].concat();
+        assert_eq!(Ok(vec![json!(1_577_836_800.5)]), from_slice(&bytes));
+        // Before 1970, with its seconds signed
+        let bytes = [
+            &[0xc7, 12, 0xff][..],
+            &250_000_000u32.to_be_bytes(),
+            &(-2i64).to_be_bytes(),
+        ]
+        .concat();
+        assert_eq!(Ok(vec![json!(-1.75)]), from_slice(&bytes));
+
+        assert_eq!(
+            Err(MsgpackError::Extension(-1)),
+            from_slice(b"\xd5\xff\0\0")
+        );
+        // More nanoseconds than a second has
+        let bytes = [&[0xd7, 0xff][..], &u64::MAX.to_be_bytes()].concat();
+        assert_eq!(Err(MsgpackError::Extension(-1)), from_slice(&bytes));
+    }
+
+    #[test]
+    fn depth() {
+        let nested = |n: usize, marker: u8| [vec![marker; n], vec![0]].concat();
+        let deepest = (0..MAX_DEPTH).fold(json!(0), |v, _| json!([v]));
+        assert_eq!(Ok(vec![deepest]), from_slice(&nested(MAX_DEPTH, 0x91)));
+        assert_eq!(
+            Err(MsgpackError::Depth),
+            from_slice(&nested(MAX_DEPTH + 1, 0x91))
+        );
+        // Too deep to recurse into at all
+        assert_eq!(Err(MsgpackError::Depth), from_slice(&nested(200_000, 0x91)));
+        assert_eq!(
+            Err(MsgpackError::Depth),
+            from_slice(&[0x81, 0].repeat(100_000))
+        );
+    }
+
+    #[test]
+    fn write_msgpack() {
+        let v = json!({
+            "n": [0, 127, 255, 65535, 4294967296u64, -32, -33, -129, -32769, -2147483649i64, 0.25],
+            "s": "x".repeat(40),
+            "a": (0..20).collect::<Vec<_>>(),
+            "o": {}
+        });
+        let bytes = to_vec(&v);
+        assert_eq!(Ok(vec![v]), from_slice(&bytes));
+        assert_eq!(b"\x81\xa1a\xd0\xdf".to_vec(), to_vec(&json!({"a": -33})));
+        assert_eq!(b"\xd9\x28".to_vec(), to_vec(&json!("x".repeat(40)))[..2]);
+    }
+}
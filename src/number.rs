@@ -0,0 +1,293 @@
+use serde_core::Serialize;
+use serde_json::{
+    ser::{CompactFormatter, Formatter},
+    Number, Serializer, Value,
+};
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, Read, Write},
+    iter, mem,
+};
+
+/// A float as jq writes it: the shortest digits which read back as the same
+/// number, without a fraction if it has none, and with an exponent of at
+/// least two digits once it is very large or small, such as `1e+300`.
+pub fn format(n: f64) -> String {
+    if n.is_nan() {
+        return "null".to_string();
+    }
+    let n = n.clamp(f64::MIN, f64::MAX);
+    let scientific = format!("{:e}", n.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    // Where the decimal point falls after the first `point` digits
+    let point = exponent + 1;
+    let len = digits.len() as i32;
+
+    let mut text = String::new();
+    if n.is_sign_negative() {
+        text.push('-');
+    }
+    if point <= -4 || point > len + 15 {
+        text.push_str(&digits[..1]);
+        if len > 1 {
+            text.push('.');
+            text.push_str(&digits[1..]);
+        }
+        let sign = if exponent < 0 { '-' } else { '+' };
+        let _ = write!(text, "e{}{:02}", sign, exponent.abs());
+    } else if point <= 0 {
+        text.push_str("0.");
+        text.extend(iter::repeat_n('0', -point as usize));
+        text.push_str(&digits);
+    } else if point >= len {
+        text.push_str(&digits);
+        text.extend(iter::repeat_n('0', (point - len) as usize));
+    } else {
+        let (whole, fraction) = digits.split_at(point as usize);
+        text.push_str(whole);
+        text.push('.');
+        text.push_str(fraction);
+    }
+    text
+}
+
+/// Writes JSON as the inner formatter does, but with floats as jq writes
+/// them.
+pub struct JqNumbers<F>(pub F);
+
+impl<F: Formatter> Formatter for JqNumbers<F> {
+    fn write_f64<W: ?Sized + Write>(&mut self, writer: &mut W, value: f64) -> io::Result<()> {
+        writer.write_all(format(value).as_bytes())
+    }
+
+    fn begin_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.0.begin_array(writer)
+    }
+
+    fn end_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.0.end_array(writer)
+    }
+
+    fn begin_array_value<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        self.0.begin_array_value(writer, first)
+    }
+
+    fn end_array_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.0.end_array_value(writer)
+    }
+
+    fn begin_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.0.begin_object(writer)
+    }
+
+    fn end_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.0.end_object(writer)
+    }
+
+    fn begin_object_key<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        self.0.begin_object_key(writer, first)
+    }
+
+    fn end_object_key<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.0.end_object_key(writer)
+    }
+
+    fn begin_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.0.begin_object_value(writer)
+    }
+
+    fn end_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.0.end_object_value(writer)
+    }
+}
+
+/// The compact JSON text of a value, with floats as jq writes them.
+pub fn to_string(value: &Value) -> String {
+    let mut text = Vec::new();
+    let mut serializer = Serializer::with_formatter(&mut text, JqNumbers(CompactFormatter));
+    // Writing to a Vec can't fail, nor can serializing a Value
+    let _ = value.serialize(&mut serializer);
+    String::from_utf8(text).unwrap_or_default()
+}
+
+/// The largest float, which jq reads a number too large for a float as.
+const LARGEST: &str = "1.7976931348623157e+308";
+
+/// The number of some JSON text, as jq reads it: as the largest float of
+/// its sign if it is too large for a float.
+pub fn parse(text: &str) -> Option<Number> {
+    match text.parse::<f64>() {
+        Ok(f) if f.is_infinite() => Number::from_f64(f.clamp(f64::MIN, f64::MAX)),
+        _ => text.parse().ok(),
+    }
+}
+
+/// The document of some JSON text, with numbers read as jq reads them.
+pub fn from_slice(text: &[u8]) -> serde_json::Result<Value> {
+    serde_json::from_reader(Clamped::new(text))
+}
+
+/// JSON text as it is read, but with each number too large for a float
+/// written as the largest float of its sign, which is how jq reads them.
+pub struct Clamped<R> {
+    reader: R,
+    /// What has been read but not yet read out, from `position` on.
+    output: Vec<u8>,
+    position: usize,
+    /// The number being read, which may go on into the next read.
+    number: Vec<u8>,
+    string: bool,
+    escaped: bool,
+}
+
+impl<R: Read> Clamped<R> {
+    pub fn new(reader: R) -> Self {
+        Clamped {
+            reader,
+            output: Vec::new(),
+            position: 0,
+            number: Vec::new(),
+            string: false,
+            escaped: false,
+        }
+    }
+
+    fn push(&mut self, b: u8) {
+        if !self.number.is_empty() {
+            if matches!(b, b'0'..=b'9' | b'.' | b'e' | b'E' | b'+' | b'-') {
+                self.number.push(b);
+                return;
+            }
+            self.end_number();
+        }
+        if self.string {
+            match b {
+                _ if self.escaped => self.escaped = false,
+                b'\\' => self.escaped = true,
+                b'"' => self.string = false,
+                _ => {}
+            }
+        } else if b == b'"' {
+            self.string = true;
+        } else if b == b'-' || b.is_ascii_digit() {
+            self.number.push(b);
+            return;
+        }
+        self.output.push(b);
+    }
+
+    fn end_number(&mut self) {
+        let number = mem::take(&mut self.number);
+        let float = std::str::from_utf8(&number)
+            .ok()
+            .and_then(|s| s.parse::<f64>().ok());
+        match float {
+            Some(f) if f.is_infinite() => {
+                if f < 0.0 {
+                    self.output.push(b'-');
+                }
+                self.output.extend(LARGEST.as_bytes());
+            }
+            _ => self.output.extend(number),
+        }
+    }
+}
+
+impl<R: Read> Read for Clamped<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = buf.len().min(available.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for Clamped<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.position == self.output.len() {
+            self.output.clear();
+            self.position = 0;
+            let mut chunk = [0; 8192];
+            match self.reader.read(&mut chunk)? {
+                0 if self.number.is_empty() => break,
+                0 => self.end_number(),
+                n => chunk[..n].iter().for_each(|&b| self.push(b)),
+            }
+        }
+        Ok(&self.output[self.position..])
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.position = (self.position + n).min(self.output.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_float() {
+        assert_eq!("1", format(1.0));
+        assert_eq!("0", format(0.0));
+        assert_eq!("-0", format(-0.0));
+        assert_eq!("3.25", format(3.25));
+        assert_eq!("-2.5", format(-2.5));
+        assert_eq!("10000000000", format(1e10));
+        assert_eq!("1234567.5", format(1234567.5));
+        assert_eq!("0.0001", format(1e-4));
+        assert_eq!("1e-05", format(1e-5));
+        assert_eq!("1.5e-07", format(1.5e-7));
+        assert_eq!("1e+17", format(1e17));
+        assert_eq!("12345678901234567000", format(12345678901234567890.0));
+        assert_eq!("1e+300", format(1e300));
+        assert_eq!("1.7976931348623157e+308", format(f64::INFINITY));
+        assert_eq!("null", format(f64::NAN));
+    }
+
+    // With arbitrary precision, numbers are written as they were read
+    #[cfg(not(feature = "arbitrary_precision"))]
+    #[test]
+    fn json_text() {
+        let v: Value = serde_json::from_str(r#"{"a": [1.0, -0, 1e10, 2.5], "b": 3}"#).unwrap();
+        assert_eq!(r#"{"a":[1,-0,10000000000,2.5],"b":3}"#, to_string(&v));
+        assert_eq!("\"x\"", to_string(&Value::from("x")));
+    }
+
+    #[test]
+    fn clamp_large_numbers() {
+        let largest = Some(f64::MAX);
+        assert_eq!(largest, parse("1e1000").and_then(|n| n.as_f64()));
+        assert_eq!(Some(-f64::MAX), parse("-1E+400").and_then(|n| n.as_f64()));
+        assert_eq!(Some(1.5), parse("1.5").and_then(|n| n.as_f64()));
+        assert_eq!(None, parse("1e"));
+
+        let read = |text: &str| to_string(&from_slice(text.as_bytes()).unwrap());
+        assert_eq!(
+            r#"[1.7976931348623157e+308,-1.7976931348623157e+308,"1e1000",2]"#,
+            read(r#"[1e1000, -1E+400, "1e1000", 2]"#)
+        );
+        assert_eq!(
+            r#"{"a\"1e999":1.7976931348623157e+308}"#,
+            read(r#"{"a\"1e999": 1e999}"#)
+        );
+        assert_eq!("1.7976931348623157e+308", read(&"9".repeat(400)));
+
+        // A number split between reads, then one at the very end
+        let split = io::Read::chain(&b"[1e"[..], &b"1000] 1e1000"[..]);
+        let mut text = String::new();
+        Clamped::new(split).read_to_string(&mut text).unwrap();
+        assert_eq!("[1.7976931348623157e+308] 1.7976931348623157e+308", text);
+    }
+}
@@ -1,14 +1,20 @@
-use std::iter::FromIterator;
+use std::{convert::TryFrom, iter::FromIterator};
 
 use crate::{
+    describe,
+    env::Env,
     null,
+    order::{compare, equal},
     parse::{parse_init, ParseError, Parseable},
-    query::{iterate_results, Executable, Query},
-    single, space, type_str, QueryError, QueryResult,
+    query::{iterate_results, Eval, Query},
+    single, space,
+    span::spanned,
+    QueryError, QueryResult,
 };
 use itertools::Itertools;
 use nom::{
     branch::alt,
+    bytes::complete::tag,
     character::complete::char,
     combinator::{opt, value},
     sequence::pair,
@@ -16,6 +22,7 @@ use nom::{
 };
 use serde_json::{Map, Number, Value};
 
+/// The arithmetic operator of an [`Op`].
 #[derive(Debug, PartialEq, Clone)]
 pub enum Sign {
     Add,
@@ -23,6 +30,12 @@ pub enum Sign {
     Mul,
     Div,
     Mod,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
 }
 
 impl Parseable for Sign {
@@ -33,10 +46,24 @@ impl Parseable for Sign {
             value(Sign::Div, char('/')),
             value(Sign::Mod, char('%')),
             value(Sign::Mul, char('*')),
+            comparison,
         )))(input)
     }
 }
 
+/// A comparison of two values, which doesn't chain.
+fn comparison(input: &str) -> IResult<&str, Sign, ParseError> {
+    alt((
+        value(Sign::Eq, tag("==")),
+        value(Sign::Ne, tag("!=")),
+        value(Sign::Le, tag("<=")),
+        value(Sign::Ge, tag(">=")),
+        value(Sign::Lt, char('<')),
+        value(Sign::Gt, char('>')),
+    ))(input)
+}
+
+/// A binary operation applied to every combination of left and right outputs.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Op {
     pub left: Query,
@@ -44,10 +71,10 @@ pub struct Op {
     pub right: Query,
 }
 
-impl Executable for Op {
-    fn execute(&self, value: &Value) -> QueryResult {
-        let ls = self.left.execute(value)?;
-        let rs = self.right.execute(value)?;
+impl Eval for Op {
+    fn eval<'a>(&'a self, env: &Env<'a>, value: &Value) -> QueryResult {
+        let ls = self.left.eval(env, value)?;
+        let rs = self.right.eval(env, value)?;
 
         iterate_results(
             ls.into_iter()
@@ -64,12 +91,20 @@ fn operate(sign: &Sign, l: &Value, r: &Value) -> QueryResult {
         Sign::Mul => mul(l, r),
         Sign::Div => div(l, r),
         Sign::Mod => modulus(l, r),
+        Sign::Lt => single(Value::Bool(compare(l, r).is_lt())),
+        Sign::Le => single(Value::Bool(compare(l, r).is_le())),
+        Sign::Gt => single(Value::Bool(compare(l, r).is_gt())),
+        Sign::Ge => single(Value::Bool(compare(l, r).is_ge())),
+        Sign::Eq => single(Value::Bool(equal(l, r))),
+        Sign::Ne => single(Value::Bool(!equal(l, r))),
     }
 }
 
 fn add(l: &Value, r: &Value) -> QueryResult {
     match (l, r) {
-        (Value::Number(n), Value::Number(m)) => combine_numbers(n, m, |a, b| a + b, |a, b| a + b),
+        (Value::Number(n), Value::Number(m)) => {
+            combine_numbers(n, m, |a, b| a.checked_add(b), |a, b| a + b)
+        }
         (Value::String(s), Value::String(t)) => {
             single(Value::String(chain_collect(&s.chars(), &t.chars())))
         }
@@ -77,63 +112,89 @@ fn add(l: &Value, r: &Value) -> QueryResult {
         (Value::Object(o), Value::Object(p)) => single(Value::Object(chain_collect(o, p))),
         (Value::Null, Value::Null) => null(),
         (v, Value::Null) | (Value::Null, v) => single(v.clone()),
-        (v, vv) => Err(QueryError::Operation("add", type_str(v), type_str(vv))),
+        (v, vv) => Err(QueryError::Operation("added", describe(v), describe(vv))),
     }
 }
 
 fn sub(l: &Value, r: &Value) -> QueryResult {
     match (l, r) {
-        (Value::Number(n), Value::Number(m)) => combine_numbers(n, m, |a, b| a - b, |a, b| a - b),
+        (Value::Number(n), Value::Number(m)) => {
+            combine_numbers(n, m, |a, b| a.checked_sub(b), |a, b| a - b)
+        }
         (Value::Array(a), Value::Array(b)) => single(Value::Array(
-            a.clone().into_iter().filter(|v| !b.contains(v)).collect(),
+            a.iter()
+                .filter(|v| !b.iter().any(|w| equal(v, w)))
+                .cloned()
+                .collect(),
         )),
         (Value::Null, Value::Null) => null(),
         (v, Value::Null) => single(v.clone()),
-        (v, vv) => Err(QueryError::Operation("subtract", type_str(v), type_str(vv))),
+        (v, vv) => Err(QueryError::Operation(
+            "subtracted",
+            describe(v),
+            describe(vv),
+        )),
     }
 }
 
 fn mul(l: &Value, r: &Value) -> QueryResult {
     match (l, r) {
-        (Value::Number(n), Value::Number(m)) => combine_numbers(n, m, |a, b| a * b, |a, b| a * b),
-        (Value::String(str), Value::Number(num)) => {
-            let i = num.as_u64().ok_or(QueryError::Numerical)? as usize;
-            if i == 0 {
-                null()
-            } else {
-                single(Value::String(
-                    std::iter::repeat(str.clone()).take(i).collect(),
-                ))
-            }
+        (Value::Number(n), Value::Number(m)) => {
+            combine_numbers(n, m, |a, b| a.checked_mul(b), |a, b| a * b)
         }
+        (Value::String(s), Value::Number(n)) | (Value::Number(n), Value::String(s)) => repeat(s, n),
         (Value::Object(o), Value::Object(p)) => single(multiply_objects(o, p)),
-        (Value::Null, Value::Null) => null(),
-        (v, Value::Null) | (Value::Null, v) => single(v.clone()),
-        (v, vv) => Err(QueryError::Operation("multiply", type_str(v), type_str(vv))),
+        (v, vv) => Err(QueryError::Operation(
+            "multiplied",
+            describe(v),
+            describe(vv),
+        )),
     }
 }
 
 fn div(l: &Value, r: &Value) -> QueryResult {
     match (l, r) {
-        (Value::Number(n), Value::Number(m)) => divide_numbers(n, m, |a, b| a / b, |a, b| a / b),
-        (Value::String(s), Value::String(t)) => single(Value::Array(
-            s.split(t).map(|s| Value::String(s.to_string())).collect(),
-        )),
+        (Value::Number(_), Value::Number(m)) if m.as_f64() == Some(0.0) => Err(
+            QueryError::DivideByZero("divided", describe(l), describe(r)),
+        ),
+        (Value::Number(n), Value::Number(m)) => {
+            divide_numbers(n, m, |a, b| a.checked_div(b), |a, b| a / b)
+        }
+        (Value::String(s), Value::String(t)) => single(Value::Array(split(s, t))),
         (Value::Null, Value::Null) => null(),
         (v, Value::Null) => single(v.clone()),
-        (v, vv) => Err(QueryError::Operation("divide", type_str(v), type_str(vv))),
+        (v, vv) => Err(QueryError::Operation("divided", describe(v), describe(vv))),
+    }
+}
+
+/// The parts of a string between each separator, as jq splits it: into
+/// codepoints if the separator is empty, and into no parts at all if the
+/// string is.
+fn split(s: &str, separator: &str) -> Vec<Value> {
+    if s.is_empty() {
+        Vec::new()
+    } else if separator.is_empty() {
+        s.chars().map(|c| Value::from(c.to_string())).collect()
+    } else {
+        s.split(separator).map(Value::from).collect()
     }
 }
 
 fn modulus(l: &Value, r: &Value) -> QueryResult {
     match (l, r) {
-        (Value::Number(n), Value::Number(m)) => divide_numbers(n, m, |a, b| a % b, |a, b| a % b),
+        // As in jq, the remainder is of both sides truncated to integers
+        (Value::Number(_), Value::Number(m)) if m.as_f64().map(f64::trunc) == Some(0.0) => Err(
+            QueryError::DivideByZero("divided (remainder)", describe(l), describe(r)),
+        ),
+        (Value::Number(n), Value::Number(m)) => {
+            combine_numbers(n, m, |a, b| a.checked_rem(b), |a, b| a.trunc() % b.trunc())
+        }
         (Value::Null, Value::Null) => null(),
         (v, Value::Null) => single(v.clone()),
         (v, vv) => Err(QueryError::Operation(
-            "divide (remainder)",
-            type_str(v),
-            type_str(vv),
+            "divided (remainder)",
+            describe(v),
+            describe(vv),
         )),
     }
 }
@@ -143,35 +204,37 @@ where
     T: IntoIterator<Item = I> + Clone,
     O: FromIterator<I>,
 {
-    a.clone().into_iter().chain(b.clone().into_iter()).collect()
+    a.clone().into_iter().chain(b.clone()).collect()
 }
 
-fn combine_numbers<F64, I64>(n: &Number, m: &Number, i: I64, f: F64) -> QueryResult
+/// Integers are combined exactly while the result fits in an `i64` or `u64`,
+/// and as floats otherwise.
+fn combine_numbers<F64, I128>(n: &Number, m: &Number, i: I128, f: F64) -> QueryResult
 where
-    I64: Fn(i64, i64) -> i64,
+    I128: Fn(i128, i128) -> Option<i128>,
     F64: Fn(f64, f64) -> f64,
 {
-    let num = match (n.as_i64(), m.as_i64()) {
-        (Some(n), Some(m)) => Some(Number::from(i(n, m))),
-        _ => match (n.as_f64(), m.as_f64()) {
-            (Some(n), Some(m)) => Number::from_f64(f(n, m)),
-            _ => None,
-        },
+    let exact = match (integer(n), integer(m)) {
+        (Some(n), Some(m)) => i(n, m).and_then(from_integer),
+        _ => None,
     };
+    let num = exact.or_else(|| match (n.as_f64(), m.as_f64()) {
+        (Some(n), Some(m)) => Number::from_f64(f(n, m)),
+        _ => None,
+    });
     single(Value::Number(num.ok_or(QueryError::Numerical)?))
 }
 
-fn divide_numbers<F64, I64>(n: &Number, m: &Number, i: I64, f: F64) -> QueryResult
+/// Integers which divide exactly stay integers. The divisor is never zero,
+/// as dividing by zero is an error before this.
+fn divide_numbers<F64, I128>(n: &Number, m: &Number, i: I128, f: F64) -> QueryResult
 where
-    I64: Fn(i64, i64) -> i64,
+    I128: Fn(i128, i128) -> Option<i128>,
     F64: Fn(f64, f64) -> f64,
 {
-    let num = match (n.as_i64(), m.as_i64()) {
-        (Some(_), Some(m)) if m == 0 => None,
-        (Some(n), Some(m)) if n % m == 0 => Some(Number::from(i(n, m))),
-        (Some(n), Some(m)) => Number::from_f64(f(n as f64, m as f64)),
+    let num = match (integer(n), integer(m)) {
+        (Some(n), Some(m)) if n % m == 0 => i(n, m).and_then(from_integer),
         _ => match (n.as_f64(), m.as_f64()) {
-            (Some(_), Some(m)) if m == 0f64 => None,
             (Some(n), Some(m)) => Number::from_f64(f(n, m)),
             _ => None,
         },
@@ -179,6 +242,36 @@ where
     single(Value::Number(num.ok_or(QueryError::Numerical)?))
 }
 
+fn integer(n: &Number) -> Option<i128> {
+    n.as_i64()
+        .map(i128::from)
+        .or_else(|| n.as_u64().map(i128::from))
+}
+
+fn from_integer(i: i128) -> Option<Number> {
+    i64::try_from(i)
+        .map(Number::from)
+        .or_else(|_| u64::try_from(i).map(Number::from))
+        .ok()
+}
+
+/// A string repeated as jq repeats it: null for a count of zero or less,
+/// and otherwise at least once, with a fraction of one more dropped.
+fn repeat(s: &str, n: &Number) -> QueryResult {
+    let count = n.as_f64().ok_or(QueryError::Numerical)?;
+    if count <= 0.0 {
+        return null();
+    }
+    let copies = (count - 1.0) as usize + 1;
+    match s.len().checked_mul(copies) {
+        Some(len) if len <= isize::MAX as usize => single(Value::String(s.repeat(copies))),
+        _ => Err(QueryError::Numerical),
+    }
+}
+
+/// Objects merged as jq merges them, where the values of a key in both are
+/// merged only if both are objects, and otherwise the right one replaces
+/// the left.
 fn multiply_objects(l: &Map<String, Value>, r: &Map<String, Value>) -> Value {
     let mut map = l.clone();
     for (k, v) in r.into_iter() {
@@ -191,7 +284,26 @@ fn multiply_objects(l: &Map<String, Value>, r: &Map<String, Value>) -> Value {
     Value::Object(map)
 }
 
+pub(crate) fn parse_compare(input: &str) -> IResult<&str, Query, ParseError> {
+    spanned(parse_compare_op)(input)
+}
+
+fn parse_compare_op(input: &str) -> IResult<&str, Query, ParseError> {
+    let (input, left) = parse_add(input)?;
+    let (input, opt) = opt(pair(space::around(comparison), parse_add))(input)?;
+
+    if let Some((sign, right)) = opt {
+        Ok((input, Query::Op(Box::new(Op { left, sign, right }))))
+    } else {
+        Ok((input, left))
+    }
+}
+
 pub(crate) fn parse_add(input: &str) -> IResult<&str, Query, ParseError> {
+    spanned(parse_add_op)(input)
+}
+
+fn parse_add_op(input: &str) -> IResult<&str, Query, ParseError> {
     let (input, left) = parse_mul(input)?;
     let (input, opt) = opt(pair(
         space::around(alt((
@@ -209,6 +321,10 @@ pub(crate) fn parse_add(input: &str) -> IResult<&str, Query, ParseError> {
 }
 
 pub(crate) fn parse_div(input: &str) -> IResult<&str, Query, ParseError> {
+    spanned(parse_div_op)(input)
+}
+
+fn parse_div_op(input: &str) -> IResult<&str, Query, ParseError> {
     let (input, left) = parse_init(input)?;
     let (input, opt) = opt(pair(
         space::around(alt((
@@ -226,6 +342,10 @@ pub(crate) fn parse_div(input: &str) -> IResult<&str, Query, ParseError> {
 }
 
 pub(crate) fn parse_mul(input: &str) -> IResult<&str, Query, ParseError> {
+    spanned(parse_mul_op)(input)
+}
+
+fn parse_mul_op(input: &str) -> IResult<&str, Query, ParseError> {
     let (input, left) = parse_div(input)?;
     let (input, opt) = opt(pair(space::around(value(Sign::Mul, char('*'))), parse_mul))(input)?;
 
@@ -0,0 +1,295 @@
+use crate::{
+    combinator::{Chain, Optional, Split},
+    construction::{Construct, Key},
+    format::{Format, Part, Template},
+    function::{Call, Define},
+    module::Import,
+    operators::Op,
+    query::{Executable, Query},
+    raw::Raw,
+    reduce::Reduce,
+    span::Spanned,
+    update::Update,
+};
+use serde_json::Value;
+
+impl Query {
+    /// Rewrites the query into one with the same results which does less work,
+    /// by folding parts which don't depend on the input into literals and
+    /// removing stages which do nothing.
+    ///
+    /// Paths such as `.a | .b` are left as they are, since a chain already
+    /// borrows through each step without copying. So is `limit`, which only
+    /// takes as many outputs from its generator as it needs.
+    pub fn optimize(self) -> Query {
+        let query = match self {
+            Query::Split(split) => {
+                let Split(left, right) = *split;
+                match (left.optimize(), right.optimize()) {
+                    (Query::Empty, q) | (q, Query::Empty) => q,
+                    (left, right) => Query::Split(Box::new(Split(left, right))),
+                }
+            }
+            Query::Chain(chain) => {
+                let Chain(left, right) = *chain;
+                match (left.optimize(), right.optimize()) {
+                    (Query::Identity, q) | (q, Query::Identity) => q,
+                    (Query::Empty, _) => Query::Empty,
+                    (left, right) => Query::Chain(Box::new(Chain(left, right))),
+                }
+            }
+            Query::Optional(opt) => match opt.0.optimize() {
+                q @ (Query::Raw(_) | Query::Identity | Query::Empty | Query::Optional(_)) => q,
+                q => Query::Optional(Box::new(Optional(q))),
+            },
+            Query::Construct(Construct::Array(inner)) => {
+                Query::Construct(Construct::Array(Box::new(inner.optimize())))
+            }
+            Query::Construct(Construct::Object(kvs)) => Query::Construct(Construct::Object(
+                kvs.into_iter()
+                    .map(|(k, v)| {
+                        let k = match k {
+                            Key::Query(q) => match q.optimize() {
+                                Query::Raw(Raw(Value::String(s))) => Key::Simple(s),
+                                q => Key::Query(q),
+                            },
+                            k => k,
+                        };
+                        (k, v.optimize())
+                    })
+                    .collect(),
+            )),
+            Query::Op(op) => {
+                let Op { left, sign, right } = *op;
+                Query::Op(Box::new(Op {
+                    left: left.optimize(),
+                    sign,
+                    right: right.optimize(),
+                }))
+            }
+            Query::Update(update) => {
+                let Update {
+                    path,
+                    assign,
+                    value,
+                } = *update;
+                Query::Update(Box::new(Update {
+                    path: path.optimize(),
+                    assign,
+                    value: value.optimize(),
+                }))
+            }
+            Query::Call(call) => Query::Call(Call::new(
+                &call.name,
+                call.args.into_iter().map(Query::optimize).collect(),
+            )),
+            Query::Define(define) => {
+                let Define { mut function, rest } = *define;
+                function.body = function.body.optimize();
+                Query::Define(Box::new(Define {
+                    function,
+                    rest: rest.optimize(),
+                }))
+            }
+            Query::Reduce(reduce) => {
+                let Reduce {
+                    source,
+                    name,
+                    init,
+                    update,
+                } = *reduce;
+                Query::Reduce(Box::new(Reduce {
+                    source: source.optimize(),
+                    name,
+                    init: init.optimize(),
+                    update: update.optimize(),
+                }))
+            }
+            Query::Import(import) => {
+                let import = *import;
+                Query::Import(Box::new(Import {
+                    rest: import.rest.optimize(),
+                    ..import
+                }))
+            }
+            Query::Template(t) => Query::Template(Template {
+                parts: t
+                    .parts
+                    .into_iter()
+                    .map(|p| match p {
+                        Part::Query(q) => Part::Query(q.optimize()),
+                        p => p,
+                    })
+                    .collect(),
+                ..t
+            }),
+            Query::Spanned(s) => match s.query.optimize() {
+                // Nothing to report a span for if it cannot fail
+                q @ (Query::Raw(_) | Query::Identity | Query::Empty) => q,
+                query => Query::Spanned(Box::new(Spanned { query, ..*s })),
+            },
+            q => q,
+        };
+        fold(query)
+    }
+}
+
+/// Replaces a query which ignores its input with the single value it produces.
+fn fold(query: Query) -> Query {
+    if matches!(query, Query::Raw(_)) || !constant(&query) {
+        return query;
+    }
+    match query.execute(&Value::Null) {
+        Ok(mut values) if values.len() == 1 => Query::Raw(Raw(values.remove(0))),
+        _ => query,
+    }
+}
+
+/// Whether the query produces the same results for any input, without
+/// depending on any definitions, variables or context.
+fn constant(query: &Query) -> bool {
+    match query {
+        Query::Raw(_) => true,
+        Query::Split(split) => constant(&split.0) && constant(&split.1),
+        Query::Chain(chain) => constant(&chain.0) && pure(&chain.1),
+        Query::Construct(Construct::Array(inner)) => constant(inner),
+        Query::Construct(Construct::Object(kvs)) => kvs.iter().all(|(k, v)| {
+            constant(v)
+                && match k {
+                    Key::Simple(_) => true,
+                    Key::Query(q) => constant(q),
+                }
+        }),
+        Query::Op(op) => constant(&op.left) && constant(&op.right),
+        Query::Template(t) => {
+            !matches!(t.format, Format::Custom(_))
+                && t.parts.iter().all(|p| match p {
+                    Part::Literal(_) => true,
+                    Part::Query(q) => constant(q),
+                })
+        }
+        Query::Spanned(s) => constant(&s.query),
+        _ => false,
+    }
+}
+
+/// Whether the results of the query depend only on its input.
+pub(crate) fn pure(query: &Query) -> bool {
+    match query {
+        Query::Empty
+        | Query::Identity
+        | Query::Index(_)
+        | Query::Iterator
+        | Query::Recurse
+        | Query::Raw(_) => true,
+        // A custom format is up to the context
+        Query::Format(f) => !matches!(f, Format::Custom(_)),
+        Query::Split(split) => pure(&split.0) && pure(&split.1),
+        Query::Chain(chain) => pure(&chain.0) && pure(&chain.1),
+        Query::Optional(opt) => pure(&opt.0),
+        Query::Construct(Construct::Array(inner)) => pure(inner),
+        Query::Construct(Construct::Object(kvs)) => kvs.iter().all(|(k, v)| {
+            pure(v)
+                && match k {
+                    Key::Simple(_) => true,
+                    Key::Query(q) => pure(q),
+                }
+        }),
+        Query::Op(op) => pure(&op.left) && pure(&op.right),
+        Query::Update(update) => pure(&update.path) && pure(&update.value),
+        Query::Template(t) => {
+            !matches!(t.format, Format::Custom(_))
+                && t.parts.iter().all(|p| match p {
+                    Part::Literal(_) => true,
+                    Part::Query(q) => pure(q),
+                })
+        }
+        Query::Spanned(s) => pure(&s.query),
+        Query::Call(_)
+        | Query::Define(_)
+        | Query::Import(_)
+        | Query::Variable(_)
+        | Query::Reduce(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn optimize(s: &str) -> Query {
+        s.parse::<Query>().unwrap().optimize()
+    }
+
+    #[test]
+    fn fold_constants() {
+        let literal = |s: &str| Query::Raw(Raw(serde_json::from_str(s).unwrap()));
+        assert_eq!(literal("7"), optimize("1 + 2 * 3"));
+        assert_eq!(literal(r#""ab""#), optimize(r#""a" + "b""#));
+        assert_eq!(literal(r#"[1,2]"#), optimize("[1, 2]"));
+        assert_eq!(literal(r#"{"a":2}"#), optimize(r#"{a: (1 + 1)}"#));
+        assert_eq!(literal(r#"2"#), optimize("[1, 2] | .[1]"));
+        assert_eq!(literal(r#""x 3""#), optimize(r#""x \(1 + 2)""#));
+
+        // Multiple outputs, errors and anything depending on the input are kept
+        assert_eq!("1, 2".parse::<Query>().unwrap(), optimize("1, 2"));
+        assert_eq!("1 / 0".parse::<Query>().unwrap(), optimize("1 / 0"));
+        assert_eq!(".a + 1".parse::<Query>().unwrap(), optimize(".a + 1"));
+        assert_eq!(
+            Query::literal(vec![1]).pipe(Query::call("f", vec![])),
+            optimize("[1] | f")
+        );
+    }
+
+    #[test]
+    fn purity() {
+        let pure = |s: &str| pure(&s.parse().unwrap());
+        assert!(pure(r#".a | "x \(.b)", @base64 "\(.c)", @json"#));
+        // A custom format is up to the context, however it is written
+        assert!(!pure("@custom"));
+        assert!(!pure(r#"@custom "x \(.b)""#));
+        assert!(!pure(r#"[.[] | @custom "\(.)"]"#));
+        assert!(!pure(".a | f"));
+
+        // So a path into a variable formatted by it isn't remembered
+        use crate::context::Context;
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let mut context = Context::new();
+        context.var("x", 1).register_format("count", move |v| {
+            counted.fetch_add(1, Ordering::Relaxed);
+            Ok(v.to_string())
+        });
+        let q: Query = r#"[.[] | $x | @count "\(.)"]"#.parse().unwrap();
+        q.execute_with(&serde_json::json!([1, 2, 3]), &context)
+            .unwrap();
+        assert_eq!(3, calls.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn remove_stages() {
+        assert_eq!(optimize(".a"), optimize(". | .a | ."));
+        assert_eq!(
+            Query::Identity,
+            Query::Empty.comma(Query::identity()).optimize()
+        );
+        assert_eq!(Query::Empty, Query::Empty.key("a").optimize());
+        assert_eq!(
+            Query::field("a").optional(),
+            Query::field("a").optional().optional().optimize()
+        );
+
+        let v: Value = serde_json::from_str(r#"{"a": [1, 2]}"#).unwrap();
+        for s in &[".a | . | .[]", "[.a[] * (2 + 3)]", "{(\"k\"): .a}"] {
+            let q: Query = s.parse().unwrap();
+            assert_eq!(
+                q.execute(&v).unwrap(),
+                q.clone().optimize().execute(&v).unwrap()
+            );
+        }
+    }
+}
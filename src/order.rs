@@ -0,0 +1,198 @@
+use serde_json::{Map, Number, Value};
+use std::cmp::Ordering;
+
+/// Orders any two values as jq does: null, then false, true, numbers,
+/// strings, arrays and objects. Arrays are compared element by element, and
+/// objects by their sorted keys and then by the value of each key in turn.
+pub(crate) fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(n), Value::Number(m)) => compare_numbers(n, m),
+        (Value::String(s), Value::String(t)) => s.cmp(t),
+        (Value::Array(a), Value::Array(b)) => compare_arrays(a, b),
+        (Value::Object(a), Value::Object(b)) => compare_objects(a, b),
+        (a, b) => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// Whether two values are the same as jq compares them, which is as numbers
+/// for numbers, so `1` equals `1.0`, and regardless of the order of keys.
+pub(crate) fn equal(a: &Value, b: &Value) -> bool {
+    compare(a, b).is_eq()
+}
+
+fn rank(v: &Value) -> u8 {
+    match v {
+        Value::Null => 0,
+        Value::Bool(false) => 1,
+        Value::Bool(true) => 2,
+        Value::Number(_) => 3,
+        Value::String(_) => 4,
+        Value::Array(_) => 5,
+        Value::Object(_) => 6,
+    }
+}
+
+/// Integers are compared exactly, and anything else as floats.
+fn compare_numbers(n: &Number, m: &Number) -> Ordering {
+    let integer = |n: &Number| {
+        n.as_i64()
+            .map(i128::from)
+            .or_else(|| n.as_u64().map(i128::from))
+    };
+    match (integer(n), integer(m)) {
+        (Some(n), Some(m)) => n.cmp(&m),
+        _ => match (n.as_f64(), m.as_f64()) {
+            (Some(n), Some(m)) => n.partial_cmp(&m).unwrap_or(Ordering::Equal),
+            _ => Ordering::Equal,
+        },
+    }
+}
+
+fn compare_arrays(a: &[Value], b: &[Value]) -> Ordering {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| compare(a, b))
+        .find(|o| o.is_ne())
+        .unwrap_or_else(|| a.len().cmp(&b.len()))
+}
+
+fn compare_objects(a: &Map<String, Value>, b: &Map<String, Value>) -> Ordering {
+    let (keys, other) = (sorted_keys(a), sorted_keys(b));
+    keys.cmp(&other).then_with(|| {
+        keys.iter()
+            .map(|k| compare(&a[k.as_str()], &b[k.as_str()]))
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    })
+}
+
+fn sorted_keys(o: &Map<String, Value>) -> Vec<&String> {
+    let mut keys: Vec<&String> = o.keys().collect();
+    keys.sort();
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        query::{Executable, Query},
+        QueryError,
+    };
+
+    #[test]
+    fn total_order() {
+        let ascending = [
+            json!(null),
+            json!(false),
+            json!(true),
+            json!(-1.5),
+            json!(0),
+            json!(18446744073709551615u64),
+            json!(""),
+            json!("A"),
+            json!("a"),
+            json!("é"),
+            json!([]),
+            json!([0]),
+            json!([0, null]),
+            json!([1]),
+            json!({}),
+            json!({"a": 2}),
+            json!({"a": 1, "b": 0}),
+            json!({"a": 2, "b": 0}),
+            json!({"b": 0}),
+        ];
+        for (i, a) in ascending.iter().enumerate() {
+            for (j, b) in ascending.iter().enumerate() {
+                assert_eq!(i.cmp(&j), compare(a, b), "{} and {}", a, b);
+            }
+        }
+        assert_eq!(Ordering::Equal, compare(&json!(1), &json!(1.0)));
+        assert_eq!(
+            Ordering::Less,
+            compare(&json!(9007199254740992i64), &json!(9007199254740993i64))
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        let output = |s: &str| {
+            let q: Query = s.parse().unwrap();
+            Value::from(q.execute(&Value::Null).unwrap()).to_string()
+        };
+        assert_eq!(
+            "[true,false,true,true,false,true]",
+            output("1 < 2, 2 < 1, 1 <= 1, 1 >= 1.0, null > false, {} > [1]")
+        );
+        assert_eq!("[true,false]", output(r#""a" < "b", [1, 2] <= [1]"#));
+        assert_eq!(
+            "[true,false,true]",
+            output("1 + 1 > 1, (.a = (1 < 0) | .a), 2 >= 1")
+        );
+        assert_eq!("[[false,true]]", output("[1, 0 < 1]| [.[0] < 0, .[1]]"));
+        assert!("1 < 2 < 3".parse::<Query>().is_err());
+    }
+
+    #[test]
+    fn equality() {
+        let v = json!({"a": [1, {"b": 2.0, "c": null}], "d": [1, 1.0, 2, "1"]});
+        let output = |s: &str| {
+            let q: Query = s.parse().unwrap();
+            Value::from(q.execute(&v).unwrap()).to_string()
+        };
+        assert_eq!("[true,true,true]", output("1 == 1.0, 1.5 != 1, 0 == -0"));
+        assert_eq!(
+            "[true,false]",
+            output(r#".a == [1.0, {"c": null, "b": 2}], .a == [1, {"b": 2}]"#)
+        );
+        assert_eq!(
+            "[false,false,false,true]",
+            output(r#"null == false, 0 == false, "1" == 1, [1] != {}"#)
+        );
+        assert_eq!("[[true,true,false,false]]", output("[.d[] == 1]"));
+        assert_eq!(r#"[[2,"1"]]"#, output(".d - [1]"));
+        assert_eq!("[true]", output(".a[1] == {b: 2, c: null}"));
+    }
+
+    #[test]
+    fn sorting_builtins() {
+        let v = json!([3, "b", null, [1], {"a": 1}, true, 1, "a", false, 1.0, {"a": 0}]);
+        let run = |s: &str| s.parse::<Query>().unwrap().execute(&v);
+        let output = |s: &str| Value::from(run(s).unwrap()).to_string();
+        assert_eq!(
+            r#"[[null,false,true,1,1.0,3,"a","b",[1],{"a":0},{"a":1}]]"#,
+            output("sort")
+        );
+        assert_eq!(r#"[null,{"a":1}]"#, output("min, max"));
+        assert_eq!(
+            r#"[[null,false,true,1,3,"a","b",[1],{"a":0},{"a":1}]]"#,
+            output("unique")
+        );
+
+        let v = json!([{"a": 2, "b": 1}, {"a": 1, "b": 2}, {"a": 2, "b": 3}]);
+        let run = |s: &str| s.parse::<Query>().unwrap().execute(&v);
+        let output = |s: &str| Value::from(run(s).unwrap()).to_string();
+        assert_eq!("[[2,1,3]]", output("[sort_by(.a)[] | .b]"));
+        assert_eq!("[[2,3,1]]", output("[sort_by(.a, 0 - .b)[] | .b]"));
+        assert_eq!("[2,3]", output("min_by(.a).b, max_by(.a).b"));
+        assert_eq!("[[2,1]]", output("[unique_by(.a)[] | .b]"));
+
+        let empty = json!([]);
+        let q: Query = "min, max, sort, unique".parse().unwrap();
+        assert_eq!(
+            "[null,null,[],[]]",
+            Value::from(q.execute(&empty).unwrap()).to_string()
+        );
+        assert!(matches!(
+            "sort".parse::<Query>().unwrap().execute(&json!(1)),
+            Err(QueryError::Apply("sort", _))
+        ));
+        assert!(matches!(
+            "max_by(.a)".parse::<Query>().unwrap().execute(&json!("a")),
+            Err(QueryError::Apply("max_by", _))
+        ));
+    }
+}
@@ -0,0 +1,188 @@
+use serde_json::Value;
+use std::thread;
+
+#[cfg(feature = "parallel")]
+use crate::{
+    combinator::Chain,
+    env::Env,
+    optimize::pure,
+    query::{iterate_results, Eval},
+};
+use crate::{
+    query::{Executable, Query},
+    QueryResult,
+};
+
+impl Query {
+    /// Executes against each of the inputs, spread over as many threads as
+    /// the machine has cores, producing the results of each input in order.
+    pub fn execute_par(&self, inputs: &[Value]) -> Vec<QueryResult> {
+        spread(inputs, || |v: &Value| self.execute(v))
+    }
+}
+
+/// `f` applied to each of the inputs in order, over as many threads as the
+/// machine has cores, where `start` makes `f` once on each thread.
+fn spread<'i, S, F>(inputs: &'i [Value], start: S) -> Vec<QueryResult>
+where
+    S: Fn() -> F + Sync,
+    F: FnMut(&'i Value) -> QueryResult,
+{
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    if threads < 2 || inputs.len() < 2 {
+        return inputs.iter().map(start()).collect();
+    }
+
+    let size = inputs.len().div_ceil(threads);
+    let start = &start;
+    thread::scope(|scope| {
+        let handles: Vec<_> = inputs
+            .chunks(size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(start()).collect::<Vec<_>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    })
+}
+
+#[cfg(feature = "parallel")]
+impl Chain {
+    /// The results of `.[] | f` with `f` evaluated for the elements over
+    /// several threads, if the array is long enough and `f` only depends on
+    /// its input, since the scope of the execution can't be shared between
+    /// threads. Each thread is bound by the limits of the execution.
+    pub(crate) fn eval_par<'a>(&'a self, env: &Env<'a>, value: &Value) -> Option<QueryResult> {
+        fn iterator(query: &Query) -> bool {
+            match query {
+                Query::Iterator => true,
+                Query::Spanned(s) => iterator(&s.query),
+                _ => false,
+            }
+        }
+        let threshold = env.parallel()?;
+        match value {
+            Value::Array(arr) if arr.len() >= threshold && iterator(&self.0) && pure(&self.1) => {
+                let worker = env.worker()?;
+                let results = spread(arr, || {
+                    let env = Env::within(&worker);
+                    move |v: &Value| self.1.eval(&env, v)
+                });
+                let results = results.into_iter().enumerate();
+                Some(iterate_results(
+                    results.map(|(k, r)| r.map_err(|e| self.locate(value, k, e))),
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn execute_par() {
+        let inputs: Vec<Value> = (0..100).map(|i| serde_json::json!({ "a": i })).collect();
+        let q: Query = ".a * 2".parse().unwrap();
+        let results = q.execute_par(&inputs);
+        assert_eq!(100, results.len());
+        for (i, r) in results.iter().enumerate() {
+            assert_eq!(vec![Value::from(i * 2)], *r.as_ref().unwrap());
+        }
+
+        let q: Query = ".a[]".parse().unwrap();
+        assert!(q.execute_par(&inputs).iter().all(Result::is_err));
+        assert!(q.execute_par(&[]).is_empty());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn eval_par() {
+        use crate::{context::Context, parse::ParseOptions};
+
+        let mut serial = Context::new();
+        serial.var("x", 1);
+        let mut context = serial.clone();
+        context.parallel(Some(10));
+        let v: Value = (0..100).map(|i| serde_json::json!({ "a": i })).collect();
+        for s in &[
+            ".[] | .a * 2",
+            ".[] | [.a, .a + 1]",
+            ".[:5] | .[] | .a",
+            ".[] | .a + $x",
+        ] {
+            let q: Query = s.parse().unwrap();
+            let expected = q.execute_with(&v, &serial).unwrap();
+            assert_eq!(expected, q.execute_with(&v, &context).unwrap(), "{}", s);
+            let r: Result<Vec<_>, _> = q.execute_stream(vec![v.clone()], &context).collect();
+            assert_eq!(expected, r.unwrap(), "{}", s);
+        }
+
+        // The first error is reported, where it was within the input
+        let options = ParseOptions {
+            spans: true,
+            ..Default::default()
+        };
+        let q = Query::parse_with(".[] | .a[0]", &options).unwrap();
+        let e = q.execute_with(&v, &context).unwrap_err();
+        assert_eq!(
+            q.execute_with(&v, &serial).unwrap_err().to_string(),
+            e.to_string()
+        );
+        assert_eq!(Some(8..11), e.span());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn eval_par_limits() {
+        use crate::{
+            context::{Context, Limits},
+            QueryError,
+        };
+        use std::sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        };
+
+        let v: Value = (0..100).map(|i| serde_json::json!({ "a": i })).collect();
+        let q: Query = ".[] | [.a, .a + 1]".parse().unwrap();
+        let mut context = Context::new();
+        context.parallel(Some(10));
+
+        // Memory is accounted for across every thread, just as it is on one
+        let unit = std::mem::size_of::<Value>();
+        let fits = |context: &Context| q.execute_with(&v, context).is_ok();
+        let budgets: Vec<_> = [0, 100, 200, 300, 400, 1000].map(|n| n * unit).into();
+        let mut serial = Context::new();
+        for &max in &budgets {
+            let limits = Limits {
+                memory: Some(max),
+                ..Default::default()
+            };
+            context.limits(limits.clone());
+            serial.limits(limits);
+            assert_eq!(fits(&serial), fits(&context), "{}", max);
+        }
+        assert!(!fits(serial.limits(Limits {
+            memory: Some(budgets[2]),
+            ..Default::default()
+        })));
+        assert!(fits(serial.limits(Limits {
+            memory: Some(budgets[5]),
+            ..Default::default()
+        })));
+
+        // The threads are cancelled along with the execution
+        let flag = Arc::new(AtomicBool::new(true));
+        context.limits(Limits::default()).cancel_with(flag.clone());
+        assert!(matches!(
+            q.execute_with(&v, &context),
+            Err(QueryError::Cancelled)
+        ));
+        flag.store(false, Ordering::Relaxed);
+        assert!(q.execute_with(&v, &context).is_ok());
+    }
+}
@@ -1,22 +1,30 @@
 use crate::{
     combinator::{chain, optional, Chain, Split},
     construction::Construct,
+    format::parse_format,
+    function::{parse_call, parse_define},
     index::Index,
-    operators::parse_add,
+    module::{resolve, Import},
     query::Query,
-    raw::Raw,
+    raw::{parse_string, Raw},
+    reduce::parse_reduce,
     space,
+    span::{self, spanned},
+    strict,
+    update::parse_update,
 };
 
 use nom::{
     branch::alt,
     bytes::complete::tag,
-    character::complete::{alphanumeric1, char},
-    combinator::{all_consuming, map, opt, value},
+    character::complete::{alpha1, alphanumeric1, char},
+    combinator::{all_consuming, map, opt, recognize, value},
     error::{self, ErrorKind},
-    sequence::preceded,
+    multi::many0,
+    sequence::{delimited, pair, preceded},
     IResult,
 };
+use std::{borrow::Cow, path::PathBuf};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -25,6 +33,47 @@ pub enum ParseError {
     Incomplete(String),
     #[error("Invalid format: {0:?} at {1}")]
     InvalidFormat(ErrorKind, String),
+    #[error("Query exceeds the maximum nesting depth of {0}")]
+    Depth(usize),
+    #[error("Cannot load module {0:?}: {1}")]
+    Module(String, String),
+    #[error("Cannot decode query: {0}")]
+    Encoding(String),
+    #[error("{0} is not part of jq")]
+    Extension(String),
+}
+
+/// Controls which queries are accepted by [`Query::parse_with`].
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Treat an empty program as `.`, as jq does, rather than as `empty`.
+    pub empty_identity: bool,
+    /// Reject the builtins and syntax which jq doesn't have: functions such
+    /// as `mergepatch` and `tobytes`, JSON Pointer paths, formats registered
+    /// with the context, and comments.
+    pub strict: bool,
+    /// The deepest allowed nesting of parentheses, brackets and braces.
+    pub max_depth: Option<usize>,
+    /// Whether `#` starts a comment running to the end of the line.
+    pub comments: bool,
+    /// The directories searched by `import` and `include`.
+    pub library_paths: Vec<PathBuf>,
+    /// Whether to record which part of the text each query came from, so
+    /// errors can report it through [`QueryError::span`](crate::QueryError::span).
+    pub spans: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            empty_identity: false,
+            strict: false,
+            max_depth: None,
+            comments: true,
+            library_paths: Vec::new(),
+            spans: false,
+        }
+    }
 }
 
 impl From<nom::Err<ParseError>> for ParseError {
@@ -46,12 +95,96 @@ impl error::ParseError<&str> for ParseError {
     }
 }
 
+impl<E> error::FromExternalError<&str, E> for ParseError {
+    fn from_external_error(input: &str, kind: ErrorKind, _: E) -> Self {
+        ParseError::InvalidFormat(kind, input.to_string())
+    }
+}
+
 impl std::str::FromStr for Query {
     type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Query::parse(s)
+        Query::parse_with(s, &ParseOptions::default())
+    }
+}
+
+impl Query {
+    pub fn parse_with(input: &str, options: &ParseOptions) -> Result<Query, ParseError> {
+        let input = preprocess(input, options)?;
+        let mut query = if options.spans {
+            span::record(&input, Query::parse)?
+        } else {
+            Query::parse(&input)?
+        };
+        resolve(&mut query, options, None, &mut Vec::new())?;
+        if options.strict {
+            strict::check(&query)?;
+        }
+        if options.empty_identity && query == Query::Empty {
+            return Ok(Query::Identity);
+        }
+        Ok(query)
+    }
+}
+
+/// Blanks out comments and enforces the nesting limit in a single scan,
+/// before any recursive parsing takes place.
+pub(crate) fn preprocess<'a>(
+    input: &'a str,
+    options: &ParseOptions,
+) -> Result<Cow<'a, str>, ParseError> {
+    let mut output = None;
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut comment = false;
+
+    for (i, c) in input.char_indices() {
+        if comment {
+            if c == '\n' {
+                comment = false;
+            } else {
+                let s: &mut String = output.get_or_insert_with(|| input[..i].to_string());
+                s.push_str(&" ".repeat(c.len_utf8()));
+                continue;
+            }
+        } else if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else {
+            match c {
+                '"' => in_string = true,
+                '#' if options.strict => return Err(ParseError::Extension("A comment".into())),
+                '#' if options.comments => {
+                    comment = true;
+                    let s: &mut String = output.get_or_insert_with(|| input[..i].to_string());
+                    s.push(' ');
+                    continue;
+                }
+                '(' | '[' | '{' => {
+                    depth += 1;
+                    if let Some(max) = options.max_depth.filter(|max| depth > *max) {
+                        return Err(ParseError::Depth(max));
+                    }
+                }
+                ')' | ']' | '}' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        if let Some(s) = output.as_mut() {
+            s.push(c);
+        }
     }
+
+    Ok(match output {
+        Some(s) => Cow::Owned(s),
+        None => Cow::Borrowed(input),
+    })
 }
 
 pub trait Parseable: Sized {
@@ -65,14 +198,23 @@ pub trait Parseable: Sized {
 
 impl Parseable for Query {
     fn parser(input: &str) -> IResult<&str, Self, ParseError> {
-        if input.is_empty() {
-            return Ok((input, Query::Empty));
+        if input.trim().is_empty() {
+            return Ok(("", Query::Empty));
         }
-        parse_pipe(input)
+        alt((
+            map(space::before(Import::parser), |i| {
+                Query::Import(Box::new(i))
+            }),
+            parse_pipe,
+        ))(input)
     }
 }
 
 pub(crate) fn parse_pipe(input: &str) -> IResult<&str, Query, ParseError> {
+    alt((space::before(parse_define), parse_pipe_chain))(input)
+}
+
+fn parse_pipe_chain(input: &str) -> IResult<&str, Query, ParseError> {
     let (input, curr) = parse_split(input)?;
     let (input, opt) = opt(preceded(space::around(char('|')), parse_pipe))(input)?;
     if let Some(next) = opt {
@@ -83,7 +225,7 @@ pub(crate) fn parse_pipe(input: &str) -> IResult<&str, Query, ParseError> {
 }
 
 pub(crate) fn parse_split(input: &str) -> IResult<&str, Query, ParseError> {
-    let (input, left) = parse_add(input)?;
+    let (input, left) = parse_update(input)?;
     let (input, opt) = opt(preceded(space::around(char(',')), parse_split))(input)?;
     if let Some(right) = opt {
         Ok((input, Query::Split(Box::new(Split(left, right)))))
@@ -93,20 +235,35 @@ pub(crate) fn parse_split(input: &str) -> IResult<&str, Query, ParseError> {
 }
 
 pub(crate) fn parse_init(input: &str) -> IResult<&str, Query, ParseError> {
-    space::around(alt((
-        chain(alt((
+    space::around(spanned(alt((
+        chain(spanned(alt((
             parse_index_shorthand,
-            map(Construct::parser, Query::Contruct),
+            map(Construct::parser, Query::Construct),
+            optional(delimited(char('('), space::around(parse_pipe), char(')'))),
             preceded(char('.'), alt((parse_index, parse_iterator))),
-        ))),
+        )))),
+        chain(spanned(optional(parse_format))),
         map(Raw::parser, Query::Raw),
+        chain(spanned(optional(parse_reduce))),
+        chain(spanned(optional(parse_call))),
+        chain(spanned(optional(parse_variable))),
         value(Query::Recurse, tag("..")),
         value(Query::Identity, char('.')),
-    )))(input)
+    ))))(input)
+}
+
+fn parse_variable(input: &str) -> IResult<&str, Query, ParseError> {
+    map(preceded(char('$'), parse_identifier), |name| {
+        Query::Variable(name.to_string())
+    })(input)
 }
 
 pub(crate) fn parse_chain(input: &str) -> IResult<&str, Query, ParseError> {
-    chain(alt((parse_index_shorthand, parse_index, parse_iterator)))(input)
+    chain(spanned(alt((
+        parse_index_shorthand,
+        parse_index,
+        parse_iterator,
+    ))))(input)
 }
 
 fn parse_index(input: &str) -> IResult<&str, Query, ParseError> {
@@ -114,9 +271,21 @@ fn parse_index(input: &str) -> IResult<&str, Query, ParseError> {
 }
 
 fn parse_index_shorthand(input: &str) -> IResult<&str, Query, ParseError> {
-    optional(map(preceded(char('.'), alphanumeric1), |s: &str| {
-        Query::Index(Index::String(s.to_string()))
-    }))(input)
+    optional(map(
+        preceded(
+            char('.'),
+            alt((map(parse_identifier, str::to_string), parse_string)),
+        ),
+        |s| Query::Index(Index::String(s)),
+    ))(input)
+}
+
+/// A name such as `foo_bar`, which may not start with a digit.
+pub(crate) fn parse_identifier(input: &str) -> IResult<&str, &str, ParseError> {
+    recognize(pair(
+        alt((alpha1, tag("_"))),
+        many0(alt((alphanumeric1, tag("_")))),
+    ))(input)
 }
 
 fn parse_iterator(input: &str) -> IResult<&str, Query, ParseError> {
@@ -144,4 +313,72 @@ mod tests {
 
         assert_eq!(Query::Iterator, ".[]".parse().unwrap());
     }
+
+    #[test]
+    fn group() {
+        assert!("()".parse::<Query>().is_err());
+        assert!("(.".parse::<Query>().is_err());
+
+        assert_eq!(Query::Identity, "( . )".parse().unwrap());
+        assert_eq!(
+            Query::Chain(Box::new(Chain(
+                Query::Optional(Box::new(crate::combinator::Optional(Query::Split(
+                    Box::new(Split(Query::Identity, Query::Recurse))
+                )))),
+                Query::Iterator
+            ))),
+            "(., ..)?[]".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(Query::Identity, ". # comment".parse().unwrap());
+        assert_eq!(
+            Query::Raw(Raw(serde_json::json!("# not a comment"))),
+            r##""# not a comment""##.parse().unwrap()
+        );
+        assert_eq!(
+            Query::Split(Box::new(Split(Query::Identity, Query::Recurse))),
+            ". , # first\n.. # second\n".parse().unwrap()
+        );
+
+        let options = ParseOptions {
+            comments: false,
+            ..Default::default()
+        };
+        assert!(Query::parse_with(". # comment", &options).is_err());
+    }
+
+    #[test]
+    fn max_depth() {
+        let options = ParseOptions {
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        assert!(Query::parse_with("[[.]]", &options).is_ok());
+        assert!(Query::parse_with("[{a: [.]}]", &options).is_err());
+        assert!(Query::parse_with(r#""[[[""#, &options).is_ok());
+    }
+
+    #[test]
+    fn empty_identity() {
+        let options = ParseOptions {
+            empty_identity: true,
+            ..Default::default()
+        };
+        assert_eq!(Query::Identity, Query::parse_with("", &options).unwrap());
+        assert_eq!(
+            Query::Identity,
+            Query::parse_with(" # .", &options).unwrap()
+        );
+        assert_eq!(
+            Query::parse(".a").unwrap(),
+            Query::parse_with(".a", &options).unwrap()
+        );
+        assert_eq!(
+            Query::Empty,
+            Query::parse_with("", &Default::default()).unwrap()
+        );
+    }
 }
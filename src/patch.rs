@@ -0,0 +1,196 @@
+use serde_json::{json, Map, Value};
+
+use crate::{
+    order::equal,
+    pointer::escape,
+    query::{Executable, Query},
+    QueryResult,
+};
+
+/// The operations of a JSON Patch (RFC 6902) which turn `before` into `after`.
+///
+/// Only `add`, `remove` and `replace` are used, and elements are added to and
+/// removed from the end of arrays rather than moved.
+pub fn diff(before: &Value, after: &Value) -> Value {
+    let mut ops = Vec::new();
+    diff_into("", before, after, &mut ops);
+    Value::Array(ops)
+}
+
+fn diff_into(pointer: &str, before: &Value, after: &Value, ops: &mut Vec<Value>) {
+    match (before, after) {
+        (Value::Object(b), Value::Object(a)) => {
+            for (k, v) in b {
+                let pointer = format!("{}/{}", pointer, escape(k));
+                match a.get(k) {
+                    Some(w) => diff_into(&pointer, v, w, ops),
+                    None => ops.push(json!({ "op": "remove", "path": pointer })),
+                }
+            }
+            for (k, w) in a.iter().filter(|(k, _)| !b.contains_key(*k)) {
+                let pointer = format!("{}/{}", pointer, escape(k));
+                ops.push(json!({ "op": "add", "path": pointer, "value": w }));
+            }
+        }
+        (Value::Array(b), Value::Array(a)) => {
+            for (i, (v, w)) in b.iter().zip(a).enumerate() {
+                diff_into(&format!("{}/{}", pointer, i), v, w, ops);
+            }
+            for (i, w) in a.iter().enumerate().skip(b.len()) {
+                let pointer = format!("{}/{}", pointer, i);
+                ops.push(json!({ "op": "add", "path": pointer, "value": w }));
+            }
+            // Last first, so each position is still there when it is removed
+            for i in (a.len()..b.len()).rev() {
+                let pointer = format!("{}/{}", pointer, i);
+                ops.push(json!({ "op": "remove", "path": pointer }));
+            }
+        }
+        // As `==` compares them, so `1` and `1.0` are no change
+        (b, a) if !equal(b, a) => ops.push(json!({ "op": "replace", "path": pointer, "value": a })),
+        _ => {}
+    }
+}
+
+/// Applies a JSON Merge Patch (RFC 7386) to `target`.
+///
+/// Unlike `*`, a null in the patch removes its key, and anything other than
+/// an object replaces the value it is merged into.
+pub fn merge(target: &mut Value, patch: &Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        v => {
+            *target = v.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(map) = target {
+        for (k, v) in patch {
+            if v.is_null() {
+                map.remove(k);
+            } else {
+                merge(map.entry(k.clone()).or_insert(Value::Null), v);
+            }
+        }
+    }
+}
+
+impl Query {
+    /// Executes the query, producing for each output the JSON Patch which
+    /// turns the input into it, such as the changes made by `.a = 1`.
+    pub fn execute_patch(&self, value: &Value) -> QueryResult {
+        Ok(self
+            .execute(value)?
+            .iter()
+            .map(|output| diff(value, output))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_values() {
+        let before = json!({"a": 1, "b": [1, 2, 3], "c": {"d/e": true}, "f": null});
+        let after = json!({"a": 2, "b": [1, 5], "c": {"d/e": true, "g": []}});
+        assert_eq!(
+            json!([
+                { "op": "replace", "path": "/a", "value": 2 },
+                { "op": "replace", "path": "/b/1", "value": 5 },
+                { "op": "remove", "path": "/b/2" },
+                { "op": "add", "path": "/c/g", "value": [] },
+                { "op": "remove", "path": "/f" },
+            ]),
+            diff(&before, &after)
+        );
+
+        assert_eq!(json!([]), diff(&before, &before));
+        assert_eq!(
+            json!([{ "op": "replace", "path": "", "value": 1 }]),
+            diff(&before, &json!(1))
+        );
+        assert_eq!(
+            json!([
+                { "op": "add", "path": "/1", "value": 2 },
+                { "op": "add", "path": "/2", "value": 3 },
+            ]),
+            diff(&json!([1]), &json!([1, 2, 3]))
+        );
+        assert_eq!(
+            json!([
+                { "op": "remove", "path": "/2" },
+                { "op": "remove", "path": "/1" },
+            ]),
+            diff(&json!([1, 2, 3]), &json!([1]))
+        );
+        let numbers: Value = serde_json::from_str("[1.0, 1e2]").unwrap();
+        assert_eq!(json!([]), diff(&json!([1, 100]), &numbers));
+    }
+
+    #[test]
+    fn merge_patch() {
+        let merged = |target: Value, patch: Value| {
+            let mut target = target;
+            merge(&mut target, &patch);
+            target
+        };
+        assert_eq!(
+            json!({"a": "z", "c": {"d": "e"}}),
+            merged(
+                json!({"a": "b", "c": {"d": "e", "f": "g"}}),
+                json!({"a": "z", "c": {"f": null}})
+            )
+        );
+        assert_eq!(json!({}), merged(json!({"a": 1}), json!({"a": null})));
+        assert_eq!(json!([1]), merged(json!({"a": [2]}), json!([1])));
+        assert_eq!(
+            json!({"a": {"b": 1}}),
+            merged(json!([1]), json!({"a": {"b": 1, "c": null}}))
+        );
+        assert_eq!(
+            json!({"a": [3]}),
+            merged(json!({"a": [1, 2]}), json!({"a": [3]}))
+        );
+
+        let q: Query = r#"mergepatch({"a": null, "b": {"c": 1}}), . * {"a": null}"#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            vec![
+                json!({"b": {"c": 1, "d": 2}}),
+                json!({"a": null, "b": {"d": 2}})
+            ],
+            q.execute(&json!({"a": 1, "b": {"d": 2}})).unwrap()
+        );
+    }
+
+    #[test]
+    fn execute_patch() {
+        let v = json!({"users": [{"active": 0}, {"active": 1}], "n": 1});
+        let patch = |s: &str| s.parse::<Query>().unwrap().execute_patch(&v).unwrap();
+
+        assert_eq!(
+            vec![json!([{ "op": "replace", "path": "/users/0/active", "value": 1 }])],
+            patch(".users[].active = 1")
+        );
+        assert_eq!(
+            vec![
+                json!([{ "op": "replace", "path": "/n", "value": 2 }]),
+                json!([{ "op": "replace", "path": "/n", "value": 3 }]),
+            ],
+            patch(".n = (2, 3)")
+        );
+        assert_eq!(
+            vec![json!([
+                { "op": "replace", "path": "/n", "value": 2 },
+                { "op": "add", "path": "/m", "value": 2 },
+            ])],
+            patch(".n |= . + 1 | .m = .n")
+        );
+    }
+}
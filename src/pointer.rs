@@ -0,0 +1,189 @@
+use serde_json::Value;
+use std::convert::TryFrom;
+
+use crate::{describe, QueryError};
+
+/// Converts a path such as `["a", 0]` to the JSON Pointer `/a/0` of RFC 6901.
+///
+/// Only keys and indices which aren't negative can be part of a pointer.
+pub fn path_to_pointer(path: &[Value]) -> Result<String, QueryError> {
+    path.iter()
+        .map(|p| match p {
+            Value::String(s) => Ok(format!("/{}", escape(s))),
+            Value::Number(n) if n.is_u64() => Ok(format!("/{}", n)),
+            p => Err(QueryError::Pointer(format!(
+                "{} cannot be part of a pointer",
+                describe(p)
+            ))),
+        })
+        .collect()
+}
+
+/// A key as a token of a pointer, where `~` and `/` are escaped.
+pub(crate) fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Converts a JSON Pointer such as `/a/0` to a path such as `["a", 0]`.
+///
+/// A pointer doesn't say whether `0` is a key or an index, so any token that
+/// is an index is taken to be one.
+pub fn pointer_to_path(pointer: &str) -> Result<Vec<Value>, QueryError> {
+    Ok(tokens(pointer)?
+        .into_iter()
+        .map(|t| match index(&t) {
+            Some(i) => Value::from(i),
+            None => Value::from(t),
+        })
+        .collect())
+}
+
+/// The path a pointer refers to within `value`, taking each token as a key
+/// or an index depending on what it is applied to.
+pub(crate) fn resolve(pointer: &str, value: &Value) -> Result<Vec<Value>, QueryError> {
+    let mut path = Vec::new();
+    let mut current = Some(value);
+    for t in tokens(pointer)? {
+        let key = match (current, index(&t)) {
+            (Some(Value::Array(arr)), Some(i)) => {
+                current = usize::try_from(i).ok().and_then(|i| arr.get(i));
+                Value::from(i)
+            }
+            (Some(Value::Object(map)), _) => {
+                current = map.get(&t);
+                Value::from(t)
+            }
+            (_, i) => {
+                current = None;
+                i.map_or_else(|| Value::from(t), Value::from)
+            }
+        };
+        path.push(key);
+    }
+    Ok(path)
+}
+
+/// The unescaped tokens of a pointer.
+fn tokens(pointer: &str) -> Result<Vec<String>, QueryError> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    let invalid = || QueryError::Pointer(format!("{:?} is not a pointer", pointer));
+    let rest = pointer.strip_prefix('/').ok_or_else(invalid)?;
+    rest.split('/')
+        .map(|t| {
+            let mut token = String::new();
+            let mut chars = t.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '~' => match chars.next() {
+                        Some('0') => token.push('~'),
+                        Some('1') => token.push('/'),
+                        _ => return Err(invalid()),
+                    },
+                    c => token.push(c),
+                }
+            }
+            Ok(token)
+        })
+        .collect()
+}
+
+/// An array index, written without leading zeros.
+fn index(token: &str) -> Option<u64> {
+    let digits = !token.is_empty() && token.bytes().all(|b| b.is_ascii_digit());
+    if digits && (token == "0" || !token.starts_with('0')) {
+        token.parse().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn convert() {
+        let path = vec![json!("a/b"), json!("c~d"), json!(0), json!("")];
+        assert_eq!("/a~1b/c~0d/0/", path_to_pointer(&path).unwrap());
+        assert_eq!(path, pointer_to_path("/a~1b/c~0d/0/").unwrap());
+        assert_eq!("", path_to_pointer(&[]).unwrap());
+        assert!(pointer_to_path("").unwrap().is_empty());
+        assert_eq!(
+            vec![json!("01"), json!("-")],
+            pointer_to_path("/01/-").unwrap()
+        );
+
+        assert!(path_to_pointer(&[json!(-1)]).is_err());
+        assert!(path_to_pointer(&[json!({"start": 1, "end": null})]).is_err());
+        assert!(pointer_to_path("a").is_err());
+        assert!(pointer_to_path("/a~2").is_err());
+    }
+
+    #[test]
+    fn resolve_pointer() {
+        let v = json!({"0": [1, {"1": 2}]});
+        assert_eq!(
+            vec![json!("0"), json!(1), json!("1")],
+            resolve("/0/1/1", &v).unwrap()
+        );
+        assert_eq!(vec![json!("x"), json!(0)], resolve("/x/0", &v).unwrap());
+    }
+
+    #[test]
+    fn setpath() {
+        use crate::query::{Executable, Query};
+
+        let v = json!({"a": [1, {"b/c": 2}]});
+        let setpath = |s: &str| {
+            let q: Query = s.parse().unwrap();
+            q.execute(&v).map(|r| Value::from(r).to_string())
+        };
+        assert_eq!(
+            r#"[{"a":[1,{"b/c":3}]},{"a":[1,{"b/c":3}]}]"#,
+            setpath(r#"setpath(["a", 1, "b/c"]; 3), setpath("/a/1/b~1c"; 3)"#).unwrap()
+        );
+        assert_eq!(
+            r#"[{"a":[1,{"b/c":2}],"x":[[null,1]]}]"#,
+            setpath(r#"setpath("/x/0/1"; 1)"#).unwrap()
+        );
+        assert_eq!(r#"[5]"#, setpath(r#"setpath(""; 5)"#).unwrap());
+
+        assert!(setpath(r#"setpath("/a/x"; 1)"#).is_err());
+        assert!(setpath(r#"setpath("a"; 1)"#).is_err());
+        assert!(setpath(r#"setpath(1; 1)"#).is_err());
+    }
+
+    #[test]
+    fn getpath() {
+        use crate::query::{Executable, Query};
+
+        let v = json!({"a": [1, {"b/c": 2}], "d": null});
+        let getpath = |s: &str| {
+            let q: Query = s.parse().unwrap();
+            q.execute(&v).map(|r| Value::from(r).to_string())
+        };
+        assert_eq!(
+            r#"[2,2]"#,
+            getpath(r#"getpath(["a", 1, "b/c"]), getpath("/a/1/b~1c")"#).unwrap()
+        );
+        assert_eq!(
+            r#"[null,null,null]"#,
+            getpath(r#"getpath(["x", 0]), getpath("/d/e"), getpath("/a/5")"#).unwrap()
+        );
+        assert_eq!(
+            r#"[[{"b/c":2}]]"#,
+            getpath(r#"getpath(["a", {"start": 1}])"#).unwrap()
+        );
+        assert_eq!(
+            r#"[{"a":[1,{"b/c":2}],"d":null}]"#,
+            getpath(r#"getpath("")"#).unwrap()
+        );
+
+        assert!(getpath(r#"getpath("/a/b")"#).is_err());
+        assert!(getpath(r#"getpath("a")"#).is_err());
+        assert!(getpath(r#"getpath(1)"#).is_err());
+    }
+}
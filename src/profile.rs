@@ -0,0 +1,167 @@
+use rq::{context::Tracer, query::Query, QueryResult};
+use serde_json::Value;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::HashMap,
+    fmt::Write,
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// The system allocator, keeping count of the memory in use once `--profile`
+/// asks it to.
+struct Counting;
+
+static COUNTING: AtomicBool = AtomicBool::new(false);
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() && COUNTING.load(Ordering::Relaxed) {
+            let in_use = IN_USE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(in_use, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        if COUNTING.load(Ordering::Relaxed) {
+            // Memory allocated before counting began may be freed after it
+            let _ = IN_USE.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                Some(n.saturating_sub(layout.size()))
+            });
+        }
+    }
+}
+
+/// Starts counting the memory in use.
+pub fn count_memory() {
+    COUNTING.store(true, Ordering::Relaxed);
+}
+
+/// The most memory in use at once since counting began.
+pub fn peak_memory() -> usize {
+    PEAK.load(Ordering::Relaxed)
+}
+
+/// How often each part of the query ran, how many values it produced and
+/// how long it took, including the parts within it.
+///
+/// Parts of the query are told apart by their spans, so the query must be
+/// parsed with spans.
+#[derive(Clone, Default)]
+pub struct Profile {
+    stages: Arc<Mutex<Stages>>,
+}
+
+#[derive(Default)]
+struct Stages {
+    /// When each stage still running began.
+    running: Vec<Instant>,
+    stats: HashMap<Range<usize>, Stats>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Stats {
+    pub calls: usize,
+    pub outputs: usize,
+    pub time: Duration,
+}
+
+impl Tracer for Profile {
+    fn begin(&self, query: &Query, _: &Value) {
+        if let Query::Spanned(_) = query {
+            self.stages.lock().unwrap().running.push(Instant::now());
+        }
+    }
+
+    fn trace(&self, query: &Query, _: &Value, result: &QueryResult) {
+        if let Query::Spanned(s) = query {
+            let mut stages = self.stages.lock().unwrap();
+            let began = stages.running.pop();
+            let stats = stages.stats.entry(s.span.clone()).or_default();
+            stats.calls += 1;
+            stats.outputs += result.as_ref().map_or(0, Vec::len);
+            stats.time += began.map_or(Duration::ZERO, |b| b.elapsed());
+        }
+    }
+}
+
+impl Profile {
+    /// The statistics of each part of the query which ran, slowest first.
+    pub fn stats(&self) -> Vec<(Range<usize>, Stats)> {
+        let stages = self.stages.lock().unwrap();
+        let mut stats: Vec<_> = stages
+            .stats
+            .iter()
+            .map(|(span, stats)| (span.clone(), stats.clone()))
+            .collect();
+        stats.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(a.0.start.cmp(&b.0.start)));
+        stats
+    }
+
+    /// A table of the statistics of each part of `query_input`, slowest
+    /// first, showing no more than `limit` of them.
+    pub fn report(&self, query_input: &str, limit: usize) -> String {
+        let mut report = format!("{:>12} {:>8} {:>8}  stage\n", "time", "calls", "outputs");
+        for (span, stats) in self.stats().into_iter().take(limit) {
+            // Only the first line of a stage spread over several
+            let text = query_input[span].lines().next().unwrap_or_default();
+            let _ = writeln!(
+                report,
+                "{:>12} {:>8} {:>8}  {}",
+                format!("{:.3?}", stats.time),
+                stats.calls,
+                stats.outputs,
+                text
+            );
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rq::{context::Context, parse::ParseOptions, query::Executable};
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn profile() {
+        let text = ".a[] | .b";
+        let options = ParseOptions {
+            spans: true,
+            ..Default::default()
+        };
+        let query = Query::parse_with(text, &options).unwrap();
+        let profile = Profile::default();
+        let mut context = Context::new();
+        context.trace_with(profile.clone());
+        let v = json!({"a": [{"b": 1}, {"b": 2}, {}]});
+        query.execute_with(&v, &context).unwrap();
+
+        let stats: HashMap<_, _> = profile
+            .stats()
+            .into_iter()
+            .map(|(span, stats)| (&text[span], (stats.calls, stats.outputs)))
+            .collect();
+        assert_eq!(Some(&(1, 3)), stats.get(".a[]"));
+        assert_eq!(Some(&(3, 3)), stats.get(".b"));
+        assert_eq!(Some(&(1, 1)), stats.get(".a"));
+
+        let report = profile.report(text, 2);
+        assert_eq!(3, report.lines().count());
+        assert!(report.starts_with("        time    calls  outputs  stage\n"));
+    }
+}
@@ -1,83 +1,1305 @@
 use crate::{
     combinator::{Chain, Optional, Split},
     construction::Construct,
-    empty,
+    context::{Context, Execution},
+    describe, empty,
+    env::Env,
+    format::{Format, Template},
+    function::{Call, Define},
     index::Index,
+    module::Import,
+    number,
     operators::Op,
+    owned,
+    range::Range,
     raw::Raw,
-    single, type_str, QueryError, QueryResult,
+    reduce::Reduce,
+    single,
+    span::Spanned,
+    stream, truthy,
+    update::Update,
+    PathResult, QueryError, QueryIter, QueryResult, SharedResult,
 };
-use serde_json::Value;
+use serde_json::{json, Value};
+use std::{borrow::Cow, cell::Cell, io, iter, rc::Rc};
 
+/// The parsed representation of a `jq` filter.
+///
+/// Every node can be built directly, inspected or rewritten, and is executed
+/// against a JSON value through [`Executable`].
+///
+/// A query holds no state of its own, since each execution keeps its own, so
+/// it is `Send` and `Sync` and one parsed query can be shared between threads.
 #[derive(Debug, PartialEq, Clone)]
 pub enum Query {
+    /// The empty program, which produces no output.
     Empty,
+    /// `.`
     Identity,
+    /// `.foo`, `.[0]`, `.["foo"]` or `.[1:3]`
     Index(Index),
+    /// `.[]`
     Iterator,
+    /// `..`
     Recurse,
+    /// `a, b`
     Split(Box<Split>),
+    /// `a | b`, including implicit chains such as `.foo.bar`
     Chain(Box<Chain>),
-    Contruct(Construct),
+    /// `[...]` or `{...}`
+    Construct(Construct),
+    /// `a?`
     Optional(Box<Optional>),
+    /// A literal value such as `"foo"`, `42` or `null`
     Raw(Raw),
+    /// `a + b`, `a - b`, `a * b`, `a / b` or `a % b`
     Op(Box<Op>),
+    /// `path = value` or `path |= f`
+    Update(Box<Update>),
+    /// `name` or `name(a; b)`
+    Call(Call),
+    /// `def name: body; rest`
+    Define(Box<Define>),
+    /// `import "path" as name; rest` or `include "path"; rest`
+    Import(Box<Import>),
+    /// `@base64`, formatting its input as a string
+    Format(Format),
+    /// `"a \(.b) c"` or `@base64 "a \(.b) c"`
+    Template(Template),
+    /// `$name`
+    Variable(String),
+    /// `reduce source as $name (init; update)`
+    Reduce(Box<Reduce>),
+    /// Any query, with where it was in the parsed text
+    Spanned(Box<Spanned>),
+}
+
+/// Something a query can be executed against, either a value or its JSON text.
+pub trait Input {
+    fn value(&self) -> Result<Cow<'_, Value>, QueryError>;
+}
+
+impl Input for str {
+    fn value(&self) -> Result<Cow<'_, Value>, QueryError> {
+        self.as_bytes().value()
+    }
+}
+
+impl Input for String {
+    fn value(&self) -> Result<Cow<'_, Value>, QueryError> {
+        self.as_str().value()
+    }
+}
+
+impl Input for [u8] {
+    fn value(&self) -> Result<Cow<'_, Value>, QueryError> {
+        Ok(Cow::Owned(number::from_slice(self)?))
+    }
 }
 
 pub trait Executable {
-    fn execute(&self, value: &Value) -> QueryResult;
+    fn execute<I: Input + ?Sized>(&self, value: &I) -> QueryResult;
+
+    /// Executes with the builtins and other state of the given context.
+    fn execute_with<I: Input + ?Sized>(&self, value: &I, context: &Context) -> QueryResult;
+
+    /// Executes against an input which is no longer needed, moving parts of
+    /// it into the results instead of copying them.
+    fn execute_owned(&self, value: Value) -> QueryResult;
+
+    /// Appends the results to `output`, which may hold some of them if an error occurs.
+    fn execute_into<I: Input + ?Sized>(
+        &self,
+        value: &I,
+        output: &mut Vec<Value>,
+    ) -> Result<(), QueryError>;
+
+    /// Produces results only as they are consumed, ending after the first error.
+    fn execute_iter<'a, I: Input + ?Sized>(&'a self, value: &'a I) -> QueryIter<'a>;
+
+    /// Executes without copying the parts of the input that paths such as
+    /// `.foo[0]` or `.[]` lead to, borrowing them in the results instead.
+    fn execute_ref<'v>(&self, value: &'v Value) -> SharedResult<'v>;
+
+    /// Produces each result along with the path within the input it was
+    /// found at, failing for results which aren't part of the input.
+    fn execute_paths<I: Input + ?Sized>(&self, value: &I) -> PathResult;
+
+    /// Passes each result to `sink` as soon as it is produced, rather than
+    /// collecting them, stopping at the first error.
+    fn execute_sink<I, F>(&self, value: &I, sink: F) -> Result<(), QueryError>
+    where
+        I: Input + ?Sized,
+        F: FnMut(Value);
+
+    /// The first result, without producing any others.
+    fn first<I: Input + ?Sized>(&self, value: &I) -> Result<Option<Value>, QueryError>;
+
+    /// The only result, failing if there are none or several.
+    fn single<I: Input + ?Sized>(&self, value: &I) -> Result<Value, QueryError>;
+
+    /// The result if there is one, failing if there are several.
+    fn single_or_none<I: Input + ?Sized>(&self, value: &I) -> Result<Option<Value>, QueryError>;
+
+    /// Whether the first result is true as a condition, which is anything
+    /// but `false` or `null`, without producing any others.
+    ///
+    /// A query with no results doesn't match.
+    fn matches<I: Input + ?Sized>(&self, value: &I) -> Result<bool, QueryError>;
+
+    /// Executes once for each document of `inputs`, which `input` and `inputs`
+    /// also take documents from, producing results only as they are consumed.
+    ///
+    /// If the context [slurps](Context::slurp), it executes once against an
+    /// array of all the documents instead, and with [`Context::null_input`]
+    /// it executes once against `null`.
+    ///
+    /// An error ends the results of one document but not of the next.
+    fn execute_stream<'a, I>(&'a self, inputs: I, context: &'a Context) -> QueryIter<'a>
+    where
+        I: IntoIterator<Item = Value>,
+        I::IntoIter: 'a;
+
+    /// Executes like [`Executable::execute_stream`] over the JSON documents
+    /// of `reader`, only reading each one when its results are needed.
+    ///
+    /// Text which isn't JSON ends the results with an error.
+    fn execute_reader<'a, R: io::Read + 'a>(
+        &'a self,
+        reader: R,
+        context: &'a Context,
+    ) -> QueryIter<'a>;
 }
 
-impl Executable for Query {
-    fn execute(&self, value: &Value) -> QueryResult {
+/// Evaluation of a query node within a scope of definitions.
+pub(crate) trait Eval {
+    fn eval<'a>(&'a self, env: &Env<'a>, value: &Value) -> QueryResult;
+
+    fn eval_owned<'a>(&'a self, env: &Env<'a>, value: Value) -> QueryResult {
+        self.eval(env, &value)
+    }
+
+    fn eval_into<'a>(
+        &'a self,
+        env: &Env<'a>,
+        value: &Value,
+        output: &mut Vec<Value>,
+    ) -> Result<(), QueryError> {
+        output.extend(self.eval(env, value)?);
+        Ok(())
+    }
+
+    fn eval_iter<'a>(&'a self, env: &Env<'a>, value: Cow<'a, Value>) -> QueryIter<'a> {
+        results(self.eval(env, &value))
+    }
+
+    fn eval_ref<'a, 'v>(&'a self, env: &Env<'a>, value: &'v Value) -> SharedResult<'v> {
+        owned(self.eval(env, value))
+    }
+
+    /// Only queries which follow paths into their input have paths for
+    /// their results, so anything else fails if it produces a result.
+    fn eval_paths<'a>(&'a self, env: &Env<'a>, value: &Value) -> PathResult {
+        not_paths(self.eval(env, value))
+    }
+}
+
+impl<T: Eval> Executable for T {
+    fn execute<I: Input + ?Sized>(&self, value: &I) -> QueryResult {
+        self.eval(&Env::default(), &*value.value()?)
+    }
+
+    fn execute_with<I: Input + ?Sized>(&self, value: &I, context: &Context) -> QueryResult {
+        let execution = Rc::new(Execution::new(context));
+        let results = self.eval(&Env::new(execution.clone()), &*value.value()?)?;
+        execution.check_outputs(results.len())?;
+        Ok(results)
+    }
+
+    fn execute_owned(&self, value: Value) -> QueryResult {
+        self.eval_owned(&Env::default(), value)
+    }
+
+    fn execute_into<I: Input + ?Sized>(
+        &self,
+        value: &I,
+        output: &mut Vec<Value>,
+    ) -> Result<(), QueryError> {
+        self.eval_into(&Env::default(), &*value.value()?, output)
+    }
+
+    fn execute_iter<'a, I: Input + ?Sized>(&'a self, value: &'a I) -> QueryIter<'a> {
+        match value.value() {
+            Ok(value) => fuse(self.eval_iter(&Env::default(), value)),
+            Err(e) => Box::new(iter::once(Err(e))),
+        }
+    }
+
+    fn execute_ref<'v>(&self, value: &'v Value) -> SharedResult<'v> {
+        self.eval_ref(&Env::default(), value)
+    }
+
+    fn execute_paths<I: Input + ?Sized>(&self, value: &I) -> PathResult {
+        self.eval_paths(&Env::default(), &*value.value()?)
+    }
+
+    fn execute_sink<I, F>(&self, value: &I, mut sink: F) -> Result<(), QueryError>
+    where
+        I: Input + ?Sized,
+        F: FnMut(Value),
+    {
+        let value = value.value()?;
+        for r in self.eval_iter(&Env::default(), Cow::Borrowed(&*value)) {
+            sink(r?);
+        }
+        Ok(())
+    }
+
+    fn first<I: Input + ?Sized>(&self, value: &I) -> Result<Option<Value>, QueryError> {
+        self.execute_iter(value).next().transpose()
+    }
+
+    fn single<I: Input + ?Sized>(&self, value: &I) -> Result<Value, QueryError> {
+        exactly_one(self.execute(value)?)
+    }
+
+    fn single_or_none<I: Input + ?Sized>(&self, value: &I) -> Result<Option<Value>, QueryError> {
+        let mut values = self.execute(value)?;
+        match values.len() {
+            0 | 1 => Ok(values.pop()),
+            n => Err(QueryError::Outputs("at most one", n)),
+        }
+    }
+
+    fn matches<I: Input + ?Sized>(&self, value: &I) -> Result<bool, QueryError> {
+        match self.execute_iter(value).next() {
+            Some(r) => Ok(truthy(&r?)),
+            None => Ok(false),
+        }
+    }
+
+    fn execute_stream<'a, I>(&'a self, inputs: I, context: &'a Context) -> QueryIter<'a>
+    where
+        I: IntoIterator<Item = Value>,
+        I::IntoIter: 'a,
+    {
+        let execution = Rc::new(Execution::with_inputs(
+            context,
+            Box::new(inputs.into_iter()),
+        ));
+        let env = Env::new(execution.clone());
+        let outputs = execution
+            .documents()
+            .flat_map(move |value| fuse(self.eval_iter(&env, Cow::Owned(value))));
+
+        // Limits, cancellation and halting apply to the whole stream rather than each document
+        let mut count = 0;
+        Box::new(outputs.scan(false, move |ended, r| {
+            if *ended {
+                return None;
+            }
+            count += 1;
+            let r = execution.check_outputs(count).and(r);
+            *ended = matches!(
+                r,
+                Err(QueryError::LimitExceeded(_) | QueryError::Cancelled | QueryError::Halt(..))
+            );
+            Some(r)
+        }))
+    }
+
+    fn execute_reader<'a, R: io::Read + 'a>(
+        &'a self,
+        reader: R,
+        context: &'a Context,
+    ) -> QueryIter<'a> {
+        let error = Rc::new(Cell::new(None));
+        let documents = {
+            let error = error.clone();
+            stream::documents(reader).map_while(move |r| r.map_err(|e| error.set(Some(e))).ok())
+        };
+        let failure = iter::from_fn(move || error.take().map(Err));
+        Box::new(self.execute_stream(documents, context).chain(failure))
+    }
+}
+
+/// Ends the results after the first error.
+fn fuse(iter: QueryIter) -> QueryIter {
+    Box::new(iter.scan(false, |failed, r| {
+        if *failed {
+            return None;
+        }
+        *failed = r.is_err();
+        Some(r)
+    }))
+}
+
+impl Query {
+    fn eval_node<'a>(&'a self, env: &Env<'a>, value: &Value) -> QueryResult {
         match self {
             Query::Empty => empty(),
             Query::Identity => single(value.clone()),
             Query::Iterator => iterate(value),
             Query::Recurse => recurse(value),
-            Query::Index(i) => i.execute(value),
-            Query::Split(split) => split.execute(value),
-            Query::Chain(chain) => chain.execute(value),
-            Query::Contruct(c) => c.execute(value),
-            Query::Optional(opt) => opt.execute(value),
-            Query::Raw(r) => r.execute(value),
-            Query::Op(op) => op.execute(value),
+            Query::Index(i) => i.eval(env, value),
+            Query::Split(split) => split.eval(env, value),
+            Query::Chain(chain) if chain.invariant() => {
+                env.memoize(self, || chain.eval(env, value))
+            }
+            Query::Chain(chain) => chain.eval(env, value),
+            Query::Construct(c) => env.allocate(c.eval(env, value)),
+            Query::Optional(opt) => opt.eval(env, value),
+            Query::Raw(r) => r.eval(env, value),
+            Query::Op(op) => env.allocate(op.eval(env, value)),
+            Query::Update(update) => env.allocate(update.eval(env, value)),
+            Query::Call(call) => call.eval(env, value),
+            Query::Define(define) => define.eval(env, value),
+            Query::Import(import) => import.eval(env, value),
+            Query::Format(f) => f.eval(env, value),
+            Query::Template(t) => env.allocate(t.eval(env, value)),
+            Query::Variable(name) => match env.variable(name) {
+                Some(v) => single(v.into_owned()),
+                None => Err(QueryError::Variable(name.clone())),
+            },
+            Query::Reduce(reduce) => reduce.eval(env, value),
+            Query::Spanned(s) => s.eval(env, value),
+        }
+    }
+}
+
+impl Eval for Query {
+    fn eval<'a>(&'a self, env: &Env<'a>, value: &Value) -> QueryResult {
+        env.step()?;
+        env.begin(self, value);
+        let result = self.eval_node(env, value);
+        env.trace(self, value, result)
+    }
+
+    fn eval_owned<'a>(&'a self, env: &Env<'a>, value: Value) -> QueryResult {
+        env.step()?;
+        if env.tracing() {
+            return self.eval(env, &value);
+        }
+        match self {
+            Query::Identity => single(value),
+            Query::Iterator => match value {
+                Value::Array(arr) => Ok(arr),
+                Value::Object(map) => Ok(map.into_iter().map(|(_, v)| v).collect()),
+                v => Err(QueryError::Iterate(describe(&v))),
+            },
+            Query::Index(i) => i.eval_owned(env, value),
+            Query::Split(split) => split.eval_owned(env, value),
+            Query::Chain(chain) if !chain.invariant() => chain.eval_owned(env, value),
+            Query::Optional(opt) => opt.eval_owned(env, value),
+            Query::Call(call) => call.eval_owned(env, value),
+            Query::Define(define) => define.eval_owned(env, value),
+            Query::Reduce(reduce) => reduce.eval_owned(env, value),
+            Query::Import(import) => import.eval_owned(env, value),
+            Query::Spanned(s) => s.eval_owned(env, value),
+            q => q.eval(env, &value),
+        }
+    }
+
+    fn eval_into<'a>(
+        &'a self,
+        env: &Env<'a>,
+        value: &Value,
+        output: &mut Vec<Value>,
+    ) -> Result<(), QueryError> {
+        env.step()?;
+        if env.tracing() {
+            output.extend(self.eval(env, value)?);
+            return Ok(());
+        }
+        match self {
+            Query::Empty => Ok(()),
+            Query::Identity => {
+                output.push(value.clone());
+                Ok(())
+            }
+            Query::Split(split) => split.eval_into(env, value, output),
+            Query::Chain(chain) if !chain.invariant() => chain.eval_into(env, value, output),
+            Query::Call(call) => call.eval_into(env, value, output),
+            Query::Define(define) => define.eval_into(env, value, output),
+            Query::Import(import) => import.eval_into(env, value, output),
+            Query::Spanned(s) => s.eval_into(env, value, output),
+            q => {
+                output.extend(q.eval(env, value)?);
+                Ok(())
+            }
+        }
+    }
+
+    fn eval_iter<'a>(&'a self, env: &Env<'a>, value: Cow<'a, Value>) -> QueryIter<'a> {
+        if let Err(e) = env.step() {
+            return Box::new(iter::once(Err(e)));
+        }
+        if env.tracing() {
+            return results(self.eval(env, &value));
+        }
+        match self {
+            Query::Empty => Box::new(iter::empty()),
+            Query::Identity => Box::new(iter::once(Ok(value.into_owned()))),
+            Query::Iterator => match value {
+                Cow::Borrowed(Value::Array(arr)) => Box::new(arr.iter().cloned().map(Ok)),
+                Cow::Borrowed(Value::Object(map)) => Box::new(map.values().cloned().map(Ok)),
+                value => results(self.eval_owned(env, value.into_owned())),
+            },
+            Query::Recurse => match value {
+                Cow::Borrowed(v) => Box::new(Descendants::new(v).cloned().map(Ok)),
+                Cow::Owned(v) => {
+                    let mut stack = vec![v];
+                    Box::new(iter::from_fn(move || {
+                        let v = stack.pop()?;
+                        match &v {
+                            Value::Array(arr) => stack.extend(arr.iter().rev().cloned()),
+                            Value::Object(map) => stack.extend(map.values().rev().cloned()),
+                            _ => {}
+                        }
+                        Some(Ok(v))
+                    }))
+                }
+            },
+            Query::Split(split) => split.eval_iter(env, value),
+            Query::Chain(chain) if !chain.invariant() => chain.eval_iter(env, value),
+            Query::Call(call) => call.eval_iter(env, value),
+            Query::Define(define) => define.eval_iter(env, value),
+            Query::Reduce(reduce) => reduce.eval_iter(env, value),
+            Query::Import(import) => import.eval_iter(env, value),
+            Query::Spanned(s) => s.eval_iter(env, value),
+            q => results(q.eval(env, &value)),
+        }
+    }
+
+    fn eval_ref<'a, 'v>(&'a self, env: &Env<'a>, value: &'v Value) -> SharedResult<'v> {
+        env.step()?;
+        if env.tracing() {
+            return owned(self.eval(env, value));
+        }
+        match self {
+            Query::Identity => Ok(vec![Cow::Borrowed(value)]),
+            Query::Iterator => match value {
+                Value::Array(arr) => Ok(arr.iter().map(Cow::Borrowed).collect()),
+                Value::Object(map) => Ok(map.values().map(Cow::Borrowed).collect()),
+                v => Err(QueryError::Iterate(describe(v))),
+            },
+            Query::Recurse => {
+                let mut output = Vec::new();
+                descend(value, &mut output);
+                Ok(output.into_iter().map(Cow::Borrowed).collect())
+            }
+            Query::Index(i) => i.eval_ref(env, value),
+            Query::Split(split) => split.eval_ref(env, value),
+            Query::Chain(chain) if !chain.invariant() => chain.eval_ref(env, value),
+            Query::Optional(opt) => opt.eval_ref(env, value),
+            Query::Call(call) => call.eval_ref(env, value),
+            Query::Define(define) => define.eval_ref(env, value),
+            Query::Spanned(s) => s.eval_ref(env, value),
+            q => owned(q.eval(env, value)),
+        }
+    }
+
+    fn eval_paths<'a>(&'a self, env: &Env<'a>, value: &Value) -> PathResult {
+        env.step()?;
+        match self {
+            Query::Empty => Ok(Vec::new()),
+            Query::Identity => Ok(vec![(Vec::new(), value.clone())]),
+            Query::Iterator => match value {
+                Value::Array(arr) => Ok(arr
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| (vec![Value::from(i)], v.clone()))
+                    .collect()),
+                Value::Object(map) => Ok(map
+                    .iter()
+                    .map(|(k, v)| (vec![Value::from(k.as_str())], v.clone()))
+                    .collect()),
+                v => Err(QueryError::Iterate(describe(v))),
+            },
+            Query::Recurse => {
+                let mut output = Vec::new();
+                descend_paths(Vec::new(), value, &mut output);
+                Ok(output)
+            }
+            Query::Index(i) => {
+                let key = match i {
+                    Index::String(s) => Value::from(s.as_str()),
+                    Index::Integer(i) => Value::from(*i),
+                    Index::Slice(Range(from, to)) => json!({ "start": from, "end": to }),
+                };
+                Ok(i.eval(env, value)?
+                    .into_iter()
+                    .map(|v| (vec![key.clone()], v))
+                    .collect())
+            }
+            Query::Split(split) => split.eval_paths(env, value),
+            Query::Chain(chain) => chain.eval_paths(env, value),
+            Query::Optional(opt) => opt.eval_paths(env, value),
+            Query::Call(call) => call.eval_paths(env, value),
+            Query::Define(define) => define.eval_paths(env, value),
+            Query::Import(import) => import.eval_paths(env, value),
+            Query::Spanned(s) => s.eval_paths(env, value),
+            q => not_paths(q.eval(env, value)),
         }
     }
 }
 
+pub(crate) fn exactly_one(mut values: Vec<Value>) -> Result<Value, QueryError> {
+    match values.len() {
+        1 => Ok(values.remove(0)),
+        n => Err(QueryError::Outputs("exactly one", n)),
+    }
+}
+
+/// Fails on the first result, as none of them came from the input.
+pub(crate) fn not_paths(result: QueryResult) -> PathResult {
+    match result?.first() {
+        Some(v) => Err(QueryError::Path(describe(v))),
+        None => Ok(Vec::new()),
+    }
+}
+
+pub(crate) fn results<'a>(result: QueryResult) -> QueryIter<'a> {
+    match result {
+        Ok(values) => Box::new(values.into_iter().map(Ok)),
+        Err(e) => Box::new(iter::once(Err(e))),
+    }
+}
+
 fn iterate(v: &Value) -> QueryResult {
     match v {
         Value::Array(arr) => Ok(arr.clone()),
-        Value::Object(map) => Ok(map.values().into_iter().cloned().collect()),
-        v => Err(QueryError::Iterate(type_str(v))),
+        Value::Object(map) => Ok(map.values().cloned().collect()),
+        v => Err(QueryError::Iterate(describe(v))),
     }
 }
 
 fn recurse(v: &Value) -> QueryResult {
+    let mut output = Vec::new();
+    descend(v, &mut output);
+    Ok(output.into_iter().cloned().collect())
+}
+
+/// Collects the value and everything within it, parents before children.
+pub(crate) fn descend<'v>(v: &'v Value, output: &mut Vec<&'v Value>) {
+    output.extend(Descendants::new(v));
+}
+
+/// The values `descend` collects, found one at a time from a stack of those
+/// still to visit.
+pub(crate) struct Descendants<'v>(Vec<&'v Value>);
+
+impl<'v> Descendants<'v> {
+    pub fn new(v: &'v Value) -> Self {
+        Descendants(vec![v])
+    }
+}
+
+impl<'v> Iterator for Descendants<'v> {
+    type Item = &'v Value;
+
+    fn next(&mut self) -> Option<&'v Value> {
+        let v = self.0.pop()?;
+        match v {
+            Value::Array(arr) => self.0.extend(arr.iter().rev()),
+            Value::Object(map) => self.0.extend(map.values().rev()),
+            _ => {}
+        }
+        Some(v)
+    }
+}
+
+/// Collects the value and everything within it as `descend` does, with
+/// their paths below `path`.
+fn descend_paths(path: Vec<Value>, v: &Value, output: &mut Vec<(Vec<Value>, Value)>) {
     let children: Vec<_> = match v {
-        Value::Array(arr) => arr.iter().collect(),
-        Value::Object(map) => map.values().into_iter().collect(),
-        vv => return single(vv.clone()),
+        Value::Array(arr) => arr
+            .iter()
+            .enumerate()
+            .map(|(i, vv)| (Value::from(i), vv))
+            .collect(),
+        Value::Object(map) => map
+            .iter()
+            .map(|(k, vv)| (Value::from(k.as_str()), vv))
+            .collect(),
+        _ => Vec::new(),
     };
+    output.push((path.clone(), v.clone()));
+    for (key, vv) in children {
+        let mut path = path.clone();
+        path.push(key);
+        descend_paths(path, vv, output);
+    }
+}
 
-    let mut res = vec![v.clone()];
-    res.extend(iterate_results(children.iter().map(|vv| recurse(vv)))?);
-    Ok(res)
+/// The path to each output of a query which only follows paths into its
+/// input, in the order of its outputs.
+pub(crate) fn paths(query: &Query, value: &Value) -> Option<Vec<Vec<Value>>> {
+    match query {
+        Query::Empty => Some(Vec::new()),
+        Query::Identity => Some(vec![Vec::new()]),
+        Query::Index(Index::String(s)) => Some(vec![vec![Value::from(s.as_str())]]),
+        Query::Index(Index::Integer(i)) => Some(vec![vec![Value::from(*i)]]),
+        Query::Iterator => match value {
+            Value::Array(arr) => Some((0..arr.len()).map(|i| vec![Value::from(i)]).collect()),
+            Value::Object(map) => Some(map.keys().map(|k| vec![Value::from(k.as_str())]).collect()),
+            _ => None,
+        },
+        Query::Split(split) => {
+            let mut output = paths(&split.0, value)?;
+            output.extend(paths(&split.1, value)?);
+            Some(output)
+        }
+        Query::Chain(chain) => {
+            let mut output = Vec::new();
+            let prefixes = paths(&chain.0, value)?;
+            for (prefix, v) in prefixes.into_iter().zip(chain.0.execute_ref(value).ok()?) {
+                for path in paths(&chain.1, &v)? {
+                    output.push(prefix.iter().cloned().chain(path).collect());
+                }
+            }
+            Some(output)
+        }
+        Query::Spanned(s) => paths(&s.query, value),
+        _ => None,
+    }
 }
 
-pub(crate) fn iterate_values<'a, I: IntoIterator<Item = &'a Value>>(
-    iter: I,
-    next: &Query,
-) -> QueryResult {
-    iterate_results(iter.into_iter().map(|vv| next.execute(vv)))
+/// The path a query follows into any input, found without evaluating it,
+/// if it only indexes by fixed keys.
+pub(crate) fn fixed_path(query: &Query) -> Option<Vec<Value>> {
+    match query {
+        Query::Identity => Some(Vec::new()),
+        Query::Index(Index::String(s)) => Some(vec![Value::from(s.as_str())]),
+        Query::Index(Index::Integer(i)) => Some(vec![Value::from(*i)]),
+        Query::Chain(chain) => {
+            let mut path = fixed_path(&chain.0)?;
+            path.extend(fixed_path(&chain.1)?);
+            Some(path)
+        }
+        Query::Spanned(s) => fixed_path(&s.query),
+        _ => None,
+    }
 }
 
+/// Every output of each result in turn, or the first error.
+///
+/// The first result is extended with the rest rather than collecting them
+/// into a new vector, so a single result is passed on as it is.
 pub(crate) fn iterate_results<I: IntoIterator<Item = QueryResult>>(iter: I) -> QueryResult {
-    Ok(iter
-        .into_iter()
-        .collect::<Result<Vec<_>, _>>()?
-        .into_iter()
-        .flatten()
-        .collect())
+    let mut iter = iter.into_iter();
+    let mut output = match iter.next() {
+        Some(r) => r?,
+        None => return Ok(Vec::new()),
+    };
+    for r in iter {
+        output.extend(r?);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::{Limits, Tracer},
+        range::Range,
+    };
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn send_sync() {
+        fn shared<T: Send + Sync>() {}
+        shared::<Query>();
+        shared::<crate::explain::Plan>();
+        shared::<crate::text::Filter>();
+        shared::<Context>();
+        shared::<QueryError>();
+
+        let q: Arc<Query> = Arc::new(".a[] * 2".parse().unwrap());
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let q = Arc::clone(&q);
+                thread::spawn(move || q.execute(&json!({ "a": [i, i + 1] })).unwrap())
+            })
+            .collect();
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(
+                json!([2 * i, 2 * i + 2]),
+                Value::from(handle.join().unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn build_ast() {
+        let q = Query::Chain(Box::new(Chain(
+            Query::Index(Index::String("foo".to_string())),
+            Query::Index(Index::Slice(Range::lower(1))),
+        )));
+        assert_eq!(q, ".foo[1:]".parse().unwrap());
+
+        let v: Value = serde_json::from_str(r#"{"foo": [1, 2, 3]}"#).unwrap();
+        assert_eq!(r#"[2,3]"#, q.execute(&v).unwrap()[0].to_string());
+    }
+
+    #[test]
+    fn execute_owned() {
+        let v: Value = serde_json::from_str(r#"{"a": [1, {"b": 2}], "c": "cd"}"#).unwrap();
+        for s in &[
+            ".",
+            ".a",
+            ".a[1].b",
+            ".a[-1]",
+            ".a[5]",
+            ".a[1:]",
+            ".c[1:]",
+            ".[]",
+            ".a[]",
+            ".a, .c",
+            ".a[]?.b?",
+            "def f: .a[0]; f",
+            "[.a[0], .c]",
+        ] {
+            let q: Query = s.parse().unwrap();
+            assert_eq!(q.execute(&v).unwrap(), q.execute_owned(v.clone()).unwrap());
+        }
+
+        let q: Query = ".c[0]".parse().unwrap();
+        assert!(q.execute_owned(v).is_err());
+    }
+
+    #[test]
+    fn execute_iter() {
+        let v: Value = serde_json::from_str(r#"{"a": [{"b": 1}, 2], "c": [3]}"#).unwrap();
+        for s in &[
+            ".",
+            ".a[]",
+            ".a, .c",
+            ".[] | .[0]",
+            "def f: .c[]; f, f",
+            "[.a[]]",
+        ] {
+            let q: Query = s.parse().unwrap();
+            let results: Result<Vec<_>, _> = q.execute_iter(&v).collect();
+            assert_eq!(q.execute(&v).unwrap(), results.unwrap());
+        }
+
+        // Later outputs are never evaluated if not consumed
+        let q: Query = ".a[] | .b".parse().unwrap();
+        assert!(q.execute(&v).is_err());
+        let mut iter = q.execute_iter(&v);
+        assert_eq!(Value::from(1), iter.next().unwrap().unwrap());
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+
+        let q: Query = ".c[], .a[].b".parse().unwrap();
+        assert_eq!(Value::from(3), q.execute_iter(&v).next().unwrap().unwrap());
+
+        // Generators only produce as many outputs as are taken
+        let q: Query = "range(1e18) | range(1; 1e18)".parse().unwrap();
+        assert_eq!(3, q.execute_iter(&v).take(3).count());
+        let q: Query = ".. | range(.)?".parse().unwrap();
+        let results: Result<Vec<_>, _> = q.execute_iter(&v).collect();
+        assert_eq!(q.execute(&v).unwrap(), *results.as_ref().unwrap());
+        assert_eq!(
+            r#"[0,0,1,0,1,2]"#,
+            Value::from(results.unwrap()).to_string()
+        );
+    }
+
+    #[test]
+    fn first_and_limit() {
+        let evaluated = Arc::new(AtomicUsize::new(0));
+        let mut context = Context::new();
+        let counter = evaluated.clone();
+        context.register("count", 0, move |_, v| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            single(v.clone())
+        });
+        let v: Value = serde_json::from_str(r#"{"a": [{"b": 1}, 2], "c": [3, 4, 5]}"#).unwrap();
+        let output = |s: &str| {
+            let q: Query = s.parse().unwrap();
+            let r = q.execute_with(&v, &context).unwrap();
+            let streamed: Result<Vec<_>, _> = q.execute_stream(vec![v.clone()], &context).collect();
+            assert_eq!(r, streamed.unwrap(), "{}", s);
+            Value::from(r).to_string()
+        };
+        assert_eq!("[1]", output("first(.a[] | .b)"));
+        assert_eq!("[]", output("first(.c[] | .x?)"));
+        assert_eq!("[[3,4]]", output("[limit(2; .c[])]"));
+        assert_eq!("[[3,3,4,5]]", output("[limit(1, 10, 0; .c[])]"));
+        assert_eq!("[[3,4,5]]", output("[limit(-1; .c[])]"));
+        assert_eq!("[0,1,2]", output("limit(3; range(1e18))"));
+        assert!("limit(null; .c[])"
+            .parse::<Query>()
+            .unwrap()
+            .execute(&v)
+            .is_err());
+
+        // The argument stops being evaluated once enough outputs are taken
+        evaluated.store(0, Ordering::SeqCst);
+        output("first(.c[] | count), limit(2; .c[] | count)");
+        assert_eq!(6, evaluated.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn variables() {
+        let mut context = Context::new();
+        context
+            .var("key", "b")
+            .var("obj", serde_json::json!({"c": [1]}));
+        let v: Value = serde_json::from_str(r#"{"a": {"b": 2}}"#).unwrap();
+
+        let q: Query = "$key, $obj.c[0]".parse().unwrap();
+        assert_eq!(
+            vec![Value::from("b"), Value::from(1)],
+            q.execute_with(&v, &context).unwrap()
+        );
+
+        let q: Query = "$missing".parse().unwrap();
+        assert!(matches!(
+            q.execute_with(&v, &context),
+            Err(QueryError::Variable(n)) if n == "missing"
+        ));
+        assert!("$".parse::<Query>().is_err());
+    }
+
+    #[test]
+    fn memoize() {
+        let mut context = Context::new();
+        context.var("config", serde_json::json!({"t": [1, 2]}));
+        let v: Value = serde_json::from_str(r#"[0, 1]"#).unwrap();
+        let queries: Vec<Query> = [".[] | [., $config.t[]]", "$config.t", ".[0] | $config.t"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let execution = Rc::new(Execution::new(&context));
+        let env = Env::new(execution.clone());
+
+        let streamed: Result<Vec<_>, _> = queries[0].eval_iter(&env, Cow::Borrowed(&v)).collect();
+        assert_eq!(
+            r#"[[0,1,2],[1,1,2]]"#,
+            Value::from(queries[0].eval(&env, &v).unwrap()).to_string()
+        );
+        assert_eq!(queries[0].eval(&env, &v).unwrap(), streamed.unwrap());
+
+        // Paths into a variable are evaluated once for the execution
+        queries[1].eval(&env, &v).unwrap();
+        let remembered = execution.memoize(&queries[1], || unreachable!()).unwrap();
+        assert_eq!(vec![serde_json::json!([1, 2])], remembered);
+        assert_eq!(remembered, queries[2].eval(&env, &v).unwrap());
+    }
+
+    #[test]
+    fn limits() {
+        let limited = |limits: Limits| {
+            let mut context = Context::new();
+            context.limits(limits);
+            context
+        };
+        let v: Value = serde_json::from_str(r#"[1, 2, 3]"#).unwrap();
+        let exceeded = |q: &str, context: &Context| {
+            let q: Query = q.parse().unwrap();
+            match q.execute_with(&v, context) {
+                Err(QueryError::LimitExceeded(limit)) => limit,
+                r => panic!("unexpected {:?}", r),
+            }
+        };
+
+        let context = limited(Limits {
+            depth: Some(10),
+            ..Default::default()
+        });
+        assert_eq!("depth", exceeded("def f: f; f", &context));
+        let q: Query = "def f: .[0]; def g: f; g".parse().unwrap();
+        assert!(q.execute_with(&v, &context).is_ok());
+
+        let context = limited(Limits {
+            outputs: Some(2),
+            ..Default::default()
+        });
+        assert_eq!("output", exceeded(".[]", &context));
+
+        let context = limited(Limits {
+            memory: Some(1000),
+            ..Default::default()
+        });
+        assert_eq!("memory", exceeded("def f: [., .] | f; f", &context));
+
+        let mut context = limited(Limits {
+            time: Some(Duration::from_millis(10)),
+            ..Default::default()
+        });
+        context.register("slow", 0, |_, v| {
+            thread::sleep(Duration::from_millis(20));
+            single(v.clone())
+        });
+        assert_eq!("time", exceeded("slow | .[0]", &context));
+    }
+
+    #[test]
+    fn cancel() {
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut context = Context::new();
+        let cancel = flag.clone();
+        context
+            .cancel_with(flag.clone())
+            .register("cancel", 0, move |_, v| {
+                cancel.store(true, Ordering::Relaxed);
+                single(v.clone())
+            });
+        let v = Value::from(1);
+
+        let q: Query = ". | cancel".parse().unwrap();
+        assert!(q.execute_with(&v, &context).is_ok());
+        assert!(matches!(
+            q.execute_with(&v, &context),
+            Err(QueryError::Cancelled)
+        ));
+
+        flag.store(false, Ordering::Relaxed);
+        let q: Query = "cancel | .".parse().unwrap();
+        assert!(matches!(
+            q.execute_with(&v, &context),
+            Err(QueryError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn execute_reader() {
+        let context = Context::new();
+        let q: Query = ".a".parse().unwrap();
+        let r: Vec<_> = q
+            .execute_reader(&br#"{"a": 1}{"a": [2]} {"a""#[..], &context)
+            .collect();
+        assert_eq!(3, r.len());
+        assert_eq!(Value::from(1), *r[0].as_ref().unwrap());
+        assert_eq!(r#"[2]"#, r[1].as_ref().unwrap().to_string());
+        assert!(matches!(r[2], Err(QueryError::Json(_))));
+
+        let q: Query = "[., inputs]".parse().unwrap();
+        let r: Result<Vec<_>, _> = q.execute_reader("1\n2\n3".as_bytes(), &context).collect();
+        assert_eq!(r#"[[1,2,3]]"#, Value::Array(r.unwrap()).to_string());
+    }
+
+    #[test]
+    fn execute_stream() {
+        let context = Context::new();
+        let inputs = (1..=5).map(Value::from);
+
+        let q: Query = ".".parse().unwrap();
+        let r: Vec<_> = q.execute_stream(inputs.clone(), &context).collect();
+        assert_eq!(5, r.len());
+
+        // Documents taken by input are skipped by the stream
+        let q: Query = "[., input]".parse().unwrap();
+        let r: Vec<_> = q.execute_stream(inputs.clone(), &context).collect();
+        assert_eq!(3, r.len());
+        assert_eq!(r#"[3,4]"#, r[1].as_ref().unwrap().to_string());
+        assert!(matches!(r[2], Err(QueryError::NoMoreInputs)));
+
+        let q: Query = "[., inputs]".parse().unwrap();
+        let r: Result<Vec<_>, _> = q.execute_stream(inputs.clone(), &context).collect();
+        assert_eq!(r#"[[1,2,3,4,5]]"#, Value::Array(r.unwrap()).to_string());
+
+        // Errors only end the results of their own document
+        let q: Query = ".[0]".parse().unwrap();
+        let inputs = vec![Value::from(1), serde_json::json!([2])];
+        let r: Vec<_> = q.execute_stream(inputs, &context).collect();
+        assert!(r[0].is_err());
+        assert_eq!(Value::from(2), *r[1].as_ref().unwrap());
+
+        // Later documents are only read as results are consumed
+        let q: Query = ".".parse().unwrap();
+        let mut read = 0;
+        let inputs = (1..).map(|i| {
+            read += 1;
+            Value::from(i)
+        });
+        assert_eq!(3, q.execute_stream(inputs, &context).take(3).count());
+        assert_eq!(3, read);
+
+        let q: Query = "inputs".parse().unwrap();
+        let mut read = 0;
+        let inputs = (1..).map(|i| {
+            read += 1;
+            Value::from(i)
+        });
+        assert_eq!(2, q.execute_stream(inputs, &context).take(2).count());
+        assert_eq!(3, read);
+
+        let q: Query = "..".parse().unwrap();
+        let r: Result<Vec<_>, _> = q
+            .execute_stream(vec![serde_json::json!({"a": [1]})], &context)
+            .collect();
+        assert_eq!(r#"[{"a":[1]},[1],1]"#, Value::Array(r.unwrap()).to_string());
+    }
+
+    #[test]
+    fn slurp() {
+        let mut context = Context::new();
+        context.slurp(true);
+        let inputs = (1..=3).map(Value::from);
+
+        let q: Query = ".[1], [inputs]".parse().unwrap();
+        let r: Result<Vec<_>, _> = q.execute_stream(inputs, &context).collect();
+        assert_eq!(r#"[2,[]]"#, Value::Array(r.unwrap()).to_string());
+
+        let q: Query = ".".parse().unwrap();
+        let r: Result<Vec<_>, _> = q.execute_stream(iter::empty(), &context).collect();
+        assert_eq!(r#"[[]]"#, Value::Array(r.unwrap()).to_string());
+    }
+
+    #[test]
+    fn null_input() {
+        let mut context = Context::new();
+        context.null_input(true);
+        let inputs = (1..=3).map(Value::from);
+
+        let q: Query = "[range(3)], [range(1; 3)], input, [inputs]"
+            .parse()
+            .unwrap();
+        let r: Result<Vec<_>, _> = q.execute_stream(inputs.clone(), &context).collect();
+        assert_eq!(
+            r#"[[0,1,2],[1,2],1,[2,3]]"#,
+            Value::Array(r.unwrap()).to_string()
+        );
+
+        context.slurp(true);
+        let q: Query = "., input".parse().unwrap();
+        let r: Result<Vec<_>, _> = q.execute_stream(inputs, &context).collect();
+        assert_eq!(r#"[null,[1,2,3]]"#, Value::Array(r.unwrap()).to_string());
+    }
+
+    #[test]
+    fn trace() {
+        #[derive(Default)]
+        struct Log(Mutex<Vec<String>>);
+        impl Tracer for Arc<Log> {
+            fn trace(&self, query: &Query, input: &Value, result: &QueryResult) {
+                let output = match result {
+                    Ok(values) => Value::Array(values.clone()).to_string(),
+                    Err(e) => e.to_string(),
+                };
+                let stage = query.explain().stage;
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} {} {}", stage, input, output));
+            }
+        }
+
+        let log = Arc::new(Log::default());
+        let mut context = Context::new();
+        context.trace_with(log.clone());
+        let q: Query = ".a[] | .b".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"a": [{"b": 1}, 2]}"#).unwrap();
+        assert!(q.execute_with(&v, &context).is_err());
+
+        assert_eq!(
+            vec![
+                r#"index {"a":[{"b":1},2]} [[{"b":1},2]]"#,
+                r#"iterator [{"b":1},2] [{"b":1},2]"#,
+                r#"chain {"a":[{"b":1},2]} [{"b":1},2]"#,
+                r#"index {"b":1} [1]"#,
+                r#"index 2 Cannot index number (2) with "b""#,
+                r#"chain {"a":[{"b":1},2]} Cannot index number (2) with "b" (at .a[1])"#,
+            ],
+            *log.0.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn trace_begin() {
+        #[derive(Default)]
+        struct Depth(Mutex<(usize, usize)>);
+        impl Tracer for Arc<Depth> {
+            fn begin(&self, _: &Query, _: &Value) {
+                let mut depth = self.0.lock().unwrap();
+                depth.0 += 1;
+                depth.1 = depth.1.max(depth.0);
+            }
+            fn trace(&self, _: &Query, _: &Value, _: &QueryResult) {
+                self.0.lock().unwrap().0 -= 1;
+            }
+        }
+
+        let depth = Arc::new(Depth::default());
+        let mut context = Context::new();
+        context.trace_with(depth.clone());
+        let q: Query = ".a | .b".parse().unwrap();
+        q.execute_with(&Value::Null, &context).unwrap();
+        // Every stage which began has finished, within the chain around them
+        assert_eq!((0, 2), *depth.0.lock().unwrap());
+    }
+
+    #[test]
+    fn execute_text() {
+        let q: Query = ".a[1]".parse().unwrap();
+        assert_eq!(Value::from(2), q.execute(r#"{"a": [1, 2]}"#).unwrap()[0]);
+        assert_eq!(
+            Value::from(2),
+            q.execute(&br#"{"a": [1, 2]}"#[..]).unwrap()[0]
+        );
+        assert_eq!(
+            Value::from(2),
+            q.execute_iter(r#"{"a": [1, 2]}"#).next().unwrap().unwrap()
+        );
+
+        assert!(matches!(q.execute("{\"a\""), Err(QueryError::Json(_))));
+        assert!(q.execute_iter("[").next().unwrap().is_err());
+    }
+
+    #[test]
+    fn execute_sink() {
+        let v: Value = serde_json::from_str(r#"{"a": [1, 2, "c"]}"#).unwrap();
+        let mut output = Vec::new();
+        let q: Query = ".a[], .a[1]".parse().unwrap();
+        q.execute_sink(&v, |v| output.push(v)).unwrap();
+        assert_eq!(r#"[1,2,"c",2]"#, Value::Array(output).to_string());
+
+        // Results before an error are still passed on
+        let mut output = Vec::new();
+        let q: Query = ".a[] | . - 1".parse().unwrap();
+        assert!(q.execute_sink(&v, |v| output.push(v)).is_err());
+        assert_eq!(r#"[0,1]"#, Value::Array(output).to_string());
+    }
+
+    #[test]
+    fn accessors() {
+        let v: Value = serde_json::from_str(r#"{"a": [1, 2]}"#).unwrap();
+        let q = |s: &str| s.parse::<Query>().unwrap();
+
+        assert_eq!(
+            Some(Value::from(1)),
+            q(".a[], (.a | . - 1)").first(&v).unwrap()
+        );
+        assert_eq!(None, q(".a[] | .[]?").first(&v).unwrap());
+        assert!(q(".a - 1").first(&v).is_err());
+
+        assert_eq!(Value::from(2), q(".a[1]").single(&v).unwrap());
+        assert_eq!(
+            "Expected exactly one output but there were 2",
+            q(".a[]").single(&v).unwrap_err().to_string()
+        );
+        assert!(matches!(
+            q(".a[] | .[]?").single(&v),
+            Err(QueryError::Outputs(_, 0))
+        ));
+
+        assert_eq!(Some(Value::from(1)), q(".a[0]").single_or_none(&v).unwrap());
+        assert_eq!(None, q(".a[] | .[]?").single_or_none(&v).unwrap());
+        assert!(matches!(
+            q(".a[]").single_or_none(&v),
+            Err(QueryError::Outputs("at most one", 2))
+        ));
+    }
+
+    #[test]
+    fn matches() {
+        let v: Value = serde_json::from_str(r#"{"a": [false, 0], "b": null}"#).unwrap();
+        let matches = |s: &str| s.parse::<Query>().unwrap().matches(&v);
+
+        assert!(matches(".a").unwrap());
+        assert!(matches(".a[1]").unwrap());
+        assert!(!matches(".a[0]").unwrap());
+        assert!(!matches(".b").unwrap());
+        assert!(!matches(".c").unwrap());
+        assert!(!matches(".a[] | .[]?").unwrap());
+
+        // Only the first result is produced
+        assert!(matches(".a[1], (.b | . - 1)").unwrap());
+        assert!(matches(".b - 1, .a").is_err());
+    }
+
+    #[test]
+    fn execute_ref() {
+        let v: Value =
+            serde_json::from_str(r#"{"a": [1, {"b": [2]}], "c": 3, "d": "TEXT"}"#).unwrap();
+        let borrowed = |s: &str| {
+            let q: Query = s.parse().unwrap();
+            let results = q.execute_ref(&v).unwrap();
+            assert_eq!(
+                q.execute(&v).unwrap(),
+                results
+                    .iter()
+                    .map(|r| r.clone().into_owned())
+                    .collect::<Vec<_>>(),
+                "{}",
+                s
+            );
+            results.iter().all(|r| matches!(r, Cow::Borrowed(_)))
+        };
+        assert!(borrowed(".a[1].b"));
+        assert!(borrowed(".a[], .c"));
+        assert!(borrowed("(.a | .[1] | .b[0])?"));
+        assert!(borrowed(".."));
+        assert!(borrowed(".a[0:], .d[:10]"));
+        assert!(borrowed(
+            r#".d | ltrimstr("x"), rtrimstr("t"), ascii_upcase"#
+        ));
+        assert!(borrowed(r#"def f: ltrimstr("x"); .d | f"#));
+        assert!(!borrowed(".d[1:]"));
+        assert!(!borrowed(r#".d | ltrimstr("T")"#));
+        assert!(!borrowed(".d | ascii_downcase"));
+        assert!(!borrowed(".a[1:] | .[0].b"));
+        assert!(!borrowed(".c + 1"));
+        assert!(!borrowed(".x"));
+    }
+
+    #[test]
+    fn execute_paths() {
+        let v: Value = serde_json::from_str(r#"{"a": [1, {"b": 2}], "c": null}"#).unwrap();
+        let paths = |s: &str| {
+            let q: Query = s.parse().unwrap();
+            let r = q.execute_paths(&v).unwrap();
+            let (paths, values): (Vec<_>, Vec<_>) = r.into_iter().unzip();
+            assert_eq!(q.execute(&v).unwrap(), values, "{}", s);
+            Value::from(paths).to_string()
+        };
+        assert_eq!(r#"[[]]"#, paths("."));
+        assert_eq!(r#"[["a",1,"b"]]"#, paths(".a[1].b"));
+        assert_eq!(r#"[["a",0],["a",1],["c"]]"#, paths(".a[], .c"));
+        assert_eq!(r#"[["c","d"]]"#, paths(".c.d"));
+        assert_eq!(r#"[["a",{"end":null,"start":1}]]"#, paths(".a[1:]"));
+        assert_eq!(r#"[["a",1,"b"]]"#, paths("def f: .a[]; f | .b?"));
+        assert_eq!(
+            r#"[[],["a"],["a",0],["a",1],["a",1,"b"],["c"]]"#,
+            paths("..")
+        );
+
+        let q: Query = r#".a[] | . + "x""#.parse().unwrap();
+        assert!(matches!(
+            q.execute_paths(&v),
+            Err(QueryError::At(path, e)) if path == vec![Value::from("a"), Value::from(0)]
+                && matches!(*e, QueryError::Operation(..))
+        ));
+        let q: Query = ".a[0] | 1".parse().unwrap();
+        assert_eq!(
+            "Invalid path expression with result number (1) (at .a[0])",
+            q.execute_paths(&v).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn execute_into() {
+        let v: Value = serde_json::from_str(r#"{"a": [1, 2]}"#).unwrap();
+        let mut output = vec![Value::Null];
+        for s in &[".a[]", ".a, .a[0]", "def f: .a[1]; f", ".."] {
+            let q: Query = s.parse().unwrap();
+            q.execute_into(&v, &mut output).unwrap();
+        }
+        assert_eq!(
+            r#"[null,1,2,[1,2],1,2,{"a":[1,2]},[1,2],1,2]"#,
+            Value::Array(output).to_string()
+        );
+    }
 }
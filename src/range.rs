@@ -6,10 +6,13 @@ use nom::{
     IResult,
 };
 
+use std::iter;
+
 use crate::parse::{ParseError, Parseable};
 
+/// The bounds of a slice, where a missing bound extends to the start or end.
 #[derive(Debug, PartialEq, Clone)]
-pub struct Range(Option<i32>, Option<i32>);
+pub struct Range(pub Option<i32>, pub Option<i32>);
 
 impl Range {
     pub fn new(bounds: (i32, i32)) -> Self {
@@ -27,12 +30,7 @@ impl Range {
     pub fn normalize(&self, len: usize) -> std::ops::Range<usize> {
         let normalize_bound = |bound: i32| {
             if bound < 0 {
-                let u = -bound as usize;
-                if u > len {
-                    0
-                } else {
-                    len - u
-                }
+                len.saturating_sub(-bound as usize)
             } else {
                 let u = bound as usize;
                 if u > len {
@@ -44,12 +42,50 @@ impl Range {
         };
 
         match (self.0.map(normalize_bound), self.1.map(normalize_bound)) {
-            (None, None) => unreachable!(),
+            (None, None) => 0..len,
             (None, Some(u)) => 0..u,
             (Some(l), None) => l..len,
             (Some(l), Some(u)) => l..u,
         }
     }
+
+    /// The bounds within `len`, where one which ends before it starts is
+    /// empty, so that it can always be used to index.
+    pub fn within(&self, len: usize) -> std::ops::Range<usize> {
+        let range = self.normalize(len);
+        range.start..range.end.max(range.start)
+    }
+
+    /// The byte range of the codepoints of `s` within the bounds. Outside
+    /// ASCII, the codepoints are only counted when a bound is from the end,
+    /// and are otherwise walked only as far as the upper bound.
+    pub fn codepoints(&self, s: &str) -> std::ops::Range<usize> {
+        if s.is_ascii() {
+            return self.within(s.len());
+        }
+        let (from, upto) = if [self.0, self.1].iter().flatten().any(|b| *b < 0) {
+            let range = self.within(s.chars().count());
+            (range.start, Some(range.end))
+        } else {
+            let from = self.0.unwrap_or(0) as usize;
+            (from, self.1.map(|u| (u as usize).max(from)))
+        };
+        let mut bytes = s.char_indices().map(|(i, _)| i).chain(iter::once(s.len()));
+        let start = bytes.nth(from).unwrap_or(s.len());
+        let end = match upto {
+            Some(upto) if upto > from => bytes.nth(upto - from - 1).unwrap_or(s.len()),
+            Some(_) => start,
+            None => s.len(),
+        };
+        start..end
+    }
+
+    /// The byte range within the bounds of a string whose codepoints start
+    /// at `boundaries`, which ends with the length of the string.
+    pub fn between(&self, boundaries: &[usize]) -> std::ops::Range<usize> {
+        let range = self.within(boundaries.len() - 1);
+        boundaries[range.start]..boundaries[range.end]
+    }
 }
 
 impl Parseable for Range {
@@ -67,6 +103,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::reversed_empty_ranges)]
     fn normalize_full() {
         assert_eq!(1..3, Range::new((1, 3)).normalize(10));
         assert_eq!(1..3, Range::new((1, 10)).normalize(3));
@@ -93,6 +130,45 @@ mod tests {
         assert_eq!(0..0, Range::upper(-100).normalize(10));
     }
 
+    #[test]
+    fn normalize_unbounded() {
+        assert_eq!(0..10, Range(None, None).normalize(10));
+    }
+
+    #[test]
+    fn within() {
+        assert_eq!(1..3, Range::new((1, 3)).within(10));
+        assert_eq!(3..3, Range::new((3, 2)).within(10));
+        assert_eq!(8..8, Range::new((-2, -3)).within(10));
+        assert_eq!(10..10, Range::new((100, 1)).within(10));
+    }
+
+    #[test]
+    fn codepoints() {
+        let s = "héllo🦀world";
+        let boundaries: Vec<_> = s.char_indices().map(|(i, _)| i).chain([s.len()]).collect();
+        let slice = |r: Range| {
+            assert_eq!(r.codepoints(s), r.between(&boundaries), "{:?}", r);
+            &s[r.codepoints(s)]
+        };
+        assert_eq!("ll", slice(Range::new((2, 4))));
+        assert_eq!("é", slice(Range::new((1, 2))));
+        assert_eq!("o🦀w", slice(Range::new((4, 7))));
+        assert_eq!("🦀world", slice(Range::lower(5)));
+        assert_eq!("héllo", slice(Range::upper(5)));
+        assert_eq!("rld", slice(Range::lower(-3)));
+        assert_eq!("🦀", slice(Range::new((-6, -5))));
+        assert_eq!("héllo🦀wor", slice(Range::upper(-2)));
+        assert_eq!("", slice(Range::new((4, 2))));
+        assert_eq!("", slice(Range::new((-2, 3))));
+        assert_eq!("", slice(Range::lower(100)));
+        assert_eq!(s, slice(Range::new((-100, 100))));
+        assert_eq!(0..0, Range(None, None).codepoints(""));
+        assert_eq!(0..0, Range(None, None).between(&[0]));
+        assert_eq!(2..4, Range::new((2, 4)).codepoints("hello"));
+        assert_eq!(3..5, Range::lower(-2).codepoints("hello"));
+    }
+
     #[test]
     fn parse() {
         assert!(Range::parse(":").is_err());
@@ -1,25 +1,31 @@
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_while},
-    character::complete::{char, i32},
-    combinator::{map, opt, value},
-    number::complete::float,
-    sequence::delimited,
+    bytes::complete::{is_not, tag, take_while_m_n},
+    character::complete::{char, digit1, one_of, satisfy},
+    combinator::{map, map_opt, map_res, not, opt, recognize, value, verify},
+    error::ErrorKind,
+    multi::fold_many0,
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
 };
-use serde_json::{Number, Value};
+use serde_json::{value::RawValue, Number, Value};
+use std::{borrow::Cow, collections::BTreeMap};
 
 use crate::{
+    env::Env,
+    index::{position, Index},
+    number,
     parse::{ParseError, Parseable},
-    query::Executable,
-    single, QueryResult,
+    query::{iterate_results, Eval, Executable, Input, Query},
+    single, QueryError, QueryResult,
 };
 
+/// A literal JSON value.
 #[derive(Debug, PartialEq, Clone)]
-pub struct Raw(Value);
+pub struct Raw(pub Value);
 
-impl Executable for Raw {
-    fn execute(&self, _: &Value) -> QueryResult {
+impl Eval for Raw {
+    fn eval(&self, _: &Env, _: &Value) -> QueryResult {
         single(self.0.clone())
     }
 }
@@ -28,33 +34,244 @@ impl Parseable for Raw {
     fn parser(input: &str) -> IResult<&str, Self, ParseError> {
         map(
             alt((
-                map(
-                    delimited(char('"'), take_while(|c| c != '"'), char('"')),
-                    |s: &str| Value::String(s.to_string()),
-                ),
+                map(parse_string, Value::String),
                 map(parse_number, Value::Number),
-                value(Value::Null, tag("null")),
+                value(Value::Null, keyword("null")),
+                value(Value::Bool(true), keyword("true")),
+                value(Value::Bool(false), keyword("false")),
             )),
             Raw,
         )(input)
     }
 }
 
-fn parse_number(input: &str) -> IResult<&str, Number, ParseError> {
-    let (input, i) = i32(input)?;
-    let (input, opt) = opt(float)(input)?;
-    if let Some(n) = opt {
-        let n = (i as f32) + n;
-        Ok((input, Number::from_f64(n as f64).unwrap()))
+/// A word which doesn't go on into a longer name, such as `true` but not
+/// the start of `trueish`.
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str, ParseError> {
+    terminated(
+        tag(word),
+        not(satisfy(|c: char| c.is_alphanumeric() || c == '_')),
+    )
+}
+
+impl Input for RawValue {
+    fn value(&self) -> Result<Cow<'_, Value>, QueryError> {
+        self.get().value()
+    }
+}
+
+impl Query {
+    /// Executes against unparsed JSON, only parsing the parts of it that
+    /// paths at the start of the query such as `.a.b[0]` lead to.
+    pub fn execute_raw(&self, raw: &RawValue) -> QueryResult {
+        let (path, rest) = match self {
+            Query::Chain(chain) if selects(&chain.0) => (&chain.0, Some(&chain.1)),
+            q if selects(q) => (q, None),
+            q => return q.execute(raw),
+        };
+        match select(path, raw) {
+            Some(raws) => iterate_results(raws.into_iter().map(|(path, r)| {
+                match rest {
+                    Some(rest) => rest.execute_raw(r),
+                    None => single(number::from_slice(r.get().as_bytes())?),
+                }
+                .map_err(|e| e.at(path))
+            })),
+            // Let the query produce whatever it would for a value of this type
+            None => self.execute(raw),
+        }
+    }
+
+    /// Executes against JSON text, only building values for the fields which
+    /// the query's leading paths, such as `.a.b, .c[0]`, project out of it.
+    pub fn execute_bytes(&self, bytes: &[u8]) -> QueryResult {
+        let raw: &RawValue = serde_json::from_slice(bytes)?;
+        self.execute_raw(raw)
+    }
+}
+
+/// Whether the query only follows paths which [`select`] can take.
+fn selects(query: &Query) -> bool {
+    match query {
+        Query::Identity
+        | Query::Iterator
+        | Query::Index(Index::String(_))
+        | Query::Index(Index::Integer(_)) => true,
+        Query::Chain(chain) => selects(&chain.0) && selects(&chain.1),
+        Query::Split(split) => selects(&split.0) && selects(&split.1),
+        _ => false,
+    }
+}
+
+/// The unparsed values which the path leads to and where they are, if the
+/// JSON has the structure the path expects.
+fn select<'r>(query: &Query, raw: &'r RawValue) -> Option<Vec<(Vec<Value>, &'r RawValue)>> {
+    let null = || serde_json::from_str::<&'static RawValue>("null").unwrap();
+    Some(match query {
+        Query::Identity => vec![(Vec::new(), raw)],
+        Query::Index(Index::String(s)) => {
+            let map: BTreeMap<String, &RawValue> = serde_json::from_str(raw.get()).ok()?;
+            let found = map.get(s).copied().unwrap_or_else(null);
+            vec![(vec![Value::from(s.as_str())], found)]
+        }
+        Query::Index(Index::Integer(i)) => {
+            let arr: Vec<&RawValue> = serde_json::from_str(raw.get()).ok()?;
+            let found = position(arr.len(), *i).map(|index| arr[index]);
+            vec![(vec![Value::from(*i)], found.unwrap_or_else(null))]
+        }
+        Query::Iterator => match serde_json::from_str::<Vec<&RawValue>>(raw.get()) {
+            Ok(arr) => arr
+                .into_iter()
+                .enumerate()
+                .map(|(i, r)| (vec![Value::from(i)], r))
+                .collect(),
+            Err(_) => serde_json::from_str::<BTreeMap<String, &RawValue>>(raw.get())
+                .ok()?
+                .into_iter()
+                .map(|(k, r)| (vec![Value::from(k)], r))
+                .collect(),
+        },
+        Query::Chain(chain) => {
+            let mut output = Vec::new();
+            for (prefix, r) in select(&chain.0, raw)? {
+                for (path, rr) in select(&chain.1, r)? {
+                    output.push((prefix.iter().cloned().chain(path).collect(), rr));
+                }
+            }
+            output
+        }
+        Query::Split(split) => {
+            let mut output = select(&split.0, raw)?;
+            output.extend(select(&split.1, raw)?);
+            output
+        }
+        _ => return None,
+    })
+}
+
+/// A double-quoted string literal with JSON escape sequences.
+pub(crate) fn parse_string(input: &str) -> IResult<&str, String, ParseError> {
+    delimited(
+        char('"'),
+        fold_many0(
+            alt((
+                map(is_not("\"\\"), |s: &str| s.to_string()),
+                map(preceded(char('\\'), parse_escape), |c| c.to_string()),
+            )),
+            String::new,
+            |mut acc, s| {
+                acc.push_str(&s);
+                acc
+            },
+        ),
+        char('"'),
+    )(input)
+}
+
+pub(crate) fn parse_escape(input: &str) -> IResult<&str, char, ParseError> {
+    alt((
+        value('"', char('"')),
+        value('\\', char('\\')),
+        value('/', char('/')),
+        value('\u{08}', char('b')),
+        value('\u{0C}', char('f')),
+        value('\n', char('n')),
+        value('\r', char('r')),
+        value('\t', char('t')),
+        preceded(char('u'), parse_unicode),
+    ))(input)
+}
+
+fn parse_unicode(input: &str) -> IResult<&str, char, ParseError> {
+    let (input, high) = parse_hex(input)?;
+    let (input, code) = if (0xD800..0xDC00).contains(&high) {
+        // Characters outside the basic plane are written as a surrogate pair
+        let (input, low) = preceded(
+            tag("\\u"),
+            verify(parse_hex, |low| (0xDC00..0xE000).contains(low)),
+        )(input)?;
+        (input, 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
     } else {
-        Ok((input, Number::from(i)))
+        (input, high)
+    };
+    match char::from_u32(code) {
+        Some(c) => Ok((input, c)),
+        None => Err(nom::Err::Error(ParseError::InvalidFormat(
+            ErrorKind::Char,
+            input.to_string(),
+        ))),
     }
 }
 
+fn parse_hex(input: &str) -> IResult<&str, u32, ParseError> {
+    map_res(
+        take_while_m_n(4, 4, |c: char| c.is_ascii_hexdigit()),
+        |s: &str| u32::from_str_radix(s, 16),
+    )(input)
+}
+
+/// Numbers keep the precision of their text, all of it with the
+/// `arbitrary_precision` feature, up to the largest float.
+fn parse_number(input: &str) -> IResult<&str, Number, ParseError> {
+    map_opt(
+        recognize(tuple((
+            opt(char('-')),
+            digit1,
+            opt(pair(char('.'), digit1)),
+            opt(tuple((one_of("eE"), opt(one_of("+-")), digit1))),
+        ))),
+        number::parse,
+    )(input)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn execute_raw() {
+        let text = r#"{"a": {"b": [1, {"c": "d"}]}, "e\u0066": [{"g": 2}, {"g": 3}], "h": 4}"#;
+        let raw: Box<RawValue> = serde_json::from_str(text).unwrap();
+        let v: Value = serde_json::from_str(text).unwrap();
+        for s in &[
+            ".",
+            ".a.b[1]",
+            ".a.b[-1].c",
+            ".ef[] | .g",
+            ".ef[].g * 2",
+            ".x.y",
+            ".[]",
+            ".h.i",
+            ".a.b[0][]",
+            "[.a.b[]]",
+            ".a.b[0], .h, .ef[1]",
+            "(.a.b, .ef) | .[1]",
+            "(.a, .h) | .b",
+        ] {
+            let q: Query = s.parse().unwrap();
+            match q.execute(&v) {
+                Ok(r) => assert_eq!(r, q.execute_raw(&raw).unwrap(), "{}", s),
+                Err(e) => assert_eq!(
+                    e.to_string(),
+                    q.execute_raw(&raw).unwrap_err().to_string(),
+                    "{}",
+                    s
+                ),
+            }
+        }
+        assert_eq!(
+            Value::from(4),
+            ".h".parse::<Query>().unwrap().execute(&*raw).unwrap()[0]
+        );
+
+        let q: Query = ".a.b[1].c, .h".parse().unwrap();
+        assert_eq!(
+            q.execute(&v).unwrap(),
+            q.execute_bytes(text.as_bytes()).unwrap()
+        );
+        assert!(q.execute_bytes(b"{").is_err());
+    }
+
     #[test]
     fn parse_raw_string() {
         assert!(Raw::parse("foo").is_err());
@@ -71,6 +288,45 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_raw_string_escapes() {
+        assert!(Raw::parse(r#""\""#).is_err());
+        assert!(Raw::parse(r#""\x""#).is_err());
+        assert!(Raw::parse(r#""\u12""#).is_err());
+        assert!(Raw::parse(r#""\ud83e""#).is_err());
+
+        assert_eq!(
+            Raw(Value::String("a\"b\\c\n/".to_string())),
+            Raw::parse(r#""a\"b\\c\n\/""#).unwrap()
+        );
+        assert_eq!(
+            Raw(Value::String("é🦀".to_string())),
+            Raw::parse(r#""\u00e9\ud83e\udd80""#).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_raw_bool() {
+        assert_eq!(Raw(Value::Bool(true)), Raw::parse("true").unwrap());
+        assert_eq!(Raw(Value::Bool(false)), Raw::parse("false").unwrap());
+        assert!(Raw::parse("trueish").is_err());
+
+        let q: Query = "def false_: 1; [true, false, false_]".parse().unwrap();
+        assert_eq!(
+            "[true,false,1]",
+            q.execute(&Value::Null).unwrap()[0].to_string()
+        );
+    }
+
+    #[test]
+    fn parse_raw_null() {
+        assert_eq!(Raw(Value::Null), Raw::parse("null").unwrap());
+        assert!(Raw::parse("nullable").is_err());
+
+        let q: Query = "def null_: 1; [null, null_]".parse().unwrap();
+        assert_eq!("[null,1]", q.execute(&Value::Null).unwrap()[0].to_string());
+    }
+
     #[test]
     fn parse_raw_number() {
         assert!(Raw::parse("--4").is_err());
@@ -85,5 +341,15 @@ mod tests {
             Raw(Value::Number(Number::from_f64(0.5).unwrap())),
             Raw::parse("0.5").unwrap()
         );
+        assert_eq!(
+            Raw(serde_json::from_str("-1.5e3").unwrap()),
+            Raw::parse("-1.5e3").unwrap()
+        );
+        assert_eq!(
+            Raw(Value::Number(Number::from(u64::MAX))),
+            Raw::parse("18446744073709551615").unwrap()
+        );
+        // As jq reads it, too large for a float
+        assert_eq!(Raw(Value::from(f64::MAX)), Raw::parse("1e1000").unwrap());
     }
 }
@@ -0,0 +1,210 @@
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{alphanumeric1, char},
+    combinator::{map, not},
+    sequence::{delimited, preceded, separated_pair, terminated, tuple},
+    IResult,
+};
+use serde_json::Value;
+use std::{borrow::Cow, iter};
+
+use crate::{
+    env::{Binding, Env},
+    optimize::pure,
+    parse::{parse_identifier, parse_init, parse_pipe, ParseError, Parseable},
+    query::{results, Eval, Query},
+    space, QueryError, QueryIter, QueryResult,
+};
+
+/// `reduce source as $name (init; update)`, which starts from each output of
+/// `init` and replaces it with the last output of `update` for each output
+/// of the source, bound to `$name`.
+///
+/// An update with no output leaves `null`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Reduce {
+    pub source: Query,
+    pub name: String,
+    pub init: Query,
+    pub update: Query,
+}
+
+impl Reduce {
+    /// Takes the outputs of the source only as each is folded in, so that a
+    /// source such as `inputs` is never held all at once.
+    fn fold<'a>(&'a self, env: &Env<'a>, value: Cow<'a, Value>) -> QueryResult {
+        let states = self.init.eval(env, &value)?;
+        let inputs = iter::repeat_n(value, states.len());
+        states
+            .into_iter()
+            .zip(inputs)
+            .map(|(mut state, value)| {
+                for x in self.source.eval_iter(env, value) {
+                    state = self.step(env, state, x?)?;
+                }
+                Ok(state)
+            })
+            .collect()
+    }
+
+    fn step<'a>(&'a self, env: &Env<'a>, state: Value, x: Value) -> Result<Value, QueryError> {
+        let scope = env.bind(Binding::Variable(&self.name, x));
+        let mut outputs = self.update.eval_owned(&scope, state)?;
+        Ok(outputs.pop().unwrap_or(Value::Null))
+    }
+}
+
+impl Eval for Reduce {
+    /// A source which only follows paths into the input is borrowed from
+    /// it, rather than copying the whole input to stream the source from.
+    fn eval<'a>(&'a self, env: &Env<'a>, value: &Value) -> QueryResult {
+        if !pure(&self.source) {
+            return self.fold(env, Cow::Owned(value.clone()));
+        }
+        let mut output = Vec::new();
+        for mut state in self.init.eval(env, value)? {
+            for x in self.source.eval_ref(env, value)? {
+                state = self.step(env, state, x.into_owned())?;
+            }
+            output.push(state);
+        }
+        Ok(output)
+    }
+
+    fn eval_owned<'a>(&'a self, env: &Env<'a>, value: Value) -> QueryResult {
+        self.fold(env, Cow::Owned(value))
+    }
+
+    fn eval_iter<'a>(&'a self, env: &Env<'a>, value: Cow<'a, Value>) -> QueryIter<'a> {
+        results(self.fold(env, value))
+    }
+}
+
+impl Parseable for Reduce {
+    fn parser(input: &str) -> IResult<&str, Self, ParseError> {
+        let keyword = terminated(tag("reduce"), not(word));
+        let (input, (source, name, (init, update))) = tuple((
+            preceded(keyword, parse_init),
+            preceded(
+                tag("as"),
+                space::before(preceded(char('$'), parse_identifier)),
+            ),
+            delimited(
+                space::around(char('(')),
+                separated_pair(
+                    space::around(parse_pipe),
+                    char(';'),
+                    space::around(parse_pipe),
+                ),
+                char(')'),
+            ),
+        ))(input)?;
+        Ok((
+            input,
+            Reduce {
+                source,
+                name: name.to_string(),
+                init,
+                update,
+            },
+        ))
+    }
+}
+
+/// Part of an identifier, which can't follow a keyword directly.
+fn word(input: &str) -> IResult<&str, &str, ParseError> {
+    alt((alphanumeric1, tag("_")))(input)
+}
+
+pub(crate) fn parse_reduce(input: &str) -> IResult<&str, Query, ParseError> {
+    map(Reduce::parser, |r| Query::Reduce(Box::new(r)))(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::{context::Context, query::Executable, raw::Raw, single};
+
+    #[test]
+    fn parse_reduce() {
+        assert!(Query::parse("reduce .[] as $x (0)").is_err());
+        assert!(Query::parse("reduce .[] as x (0; .)").is_err());
+        assert!(Query::parse("reducex as $x (0; .)").is_err());
+
+        assert_eq!(
+            Query::Reduce(Box::new(Reduce {
+                source: Query::Iterator,
+                name: "x".to_string(),
+                init: Query::Raw(Raw(json!(0))),
+                update: Query::Variable("x".to_string()),
+            })),
+            Query::parse("reduce .[] as $x ( 0 ; $x )").unwrap()
+        );
+        assert_eq!(
+            Query::parse("reduce .[] as $x (0; $x)").unwrap(),
+            Query::parse("reduce.[]as$x(0;$x)").unwrap()
+        );
+    }
+
+    #[test]
+    fn reduce() {
+        let mut context = Context::new();
+        context.var("x", 100);
+        let v = json!([{"a": 1}, {"a": 2}, {"a": 3}]);
+        let output = |s: &str| {
+            let q: Query = s.parse().unwrap();
+            let r = q.execute_with(&v, &context).unwrap();
+            let streamed: Result<Vec<_>, _> = q.execute_stream(vec![v.clone()], &context).collect();
+            assert_eq!(r, streamed.unwrap(), "{}", s);
+            Value::from(r).to_string()
+        };
+        assert_eq!("[6]", output("reduce .[] as $y (0; . + $y.a)"));
+        assert_eq!("[6,16]", output("reduce .[].a as $y (0, 10; . + $y)"));
+        assert_eq!("[[1,2,3]]", output("reduce .[] as $y (null; . + [$y.a])"));
+        assert_eq!("[3]", output("reduce .[] as $y (0; $y.a, 1, $y.a)"));
+        assert_eq!("[null]", output("reduce .[] as $y (0; .a?)"));
+        assert_eq!("[0]", output("reduce .[0][] as $y (0; . + 1) | . - 1"));
+
+        // Bindings are lexical and shadow the variables of the context
+        assert_eq!(
+            "[100,6,100]",
+            output("$x, reduce .[].a as $x (0; . + $x), $x")
+        );
+        assert_eq!(
+            "[12]",
+            output("reduce .[].a as $y (0; def f: $y * 2; . + f)")
+        );
+        assert_eq!(
+            "[[[1,1],[1,2],[2,1],[2,2]]]",
+            output("reduce range(1; 3) as $i (null; reduce range(1; 3) as $j (.; . + [[$i, $j]]))")
+        );
+    }
+
+    #[test]
+    fn reduce_inputs() {
+        // Each document is only taken when it is folded in
+        let taken = Arc::new(AtomicUsize::new(0));
+        let mut context = Context::new();
+        let counter = taken.clone();
+        context.null_input(true).register("taken", 0, move |_, _| {
+            single(Value::from(counter.load(Ordering::SeqCst)))
+        });
+        let counter = taken.clone();
+        let inputs = (1..=3).map(move |i| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Value::from(i)
+        });
+        let q: Query = "reduce inputs as $x (null; . + [[$x, taken]])"
+            .parse()
+            .unwrap();
+        let r: Result<Vec<_>, _> = q.execute_stream(inputs, &context).collect();
+        assert_eq!(json!([[1, 1], [2, 2], [3, 3]]), r.unwrap()[0]);
+    }
+}
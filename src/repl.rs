@@ -0,0 +1,101 @@
+use rq::query::{Executable, Query};
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+
+use crate::{write_value, Options};
+
+/// Reads queries a line at a time from `input`, writing the results of each
+/// against the documents to `output`, until `:quit` or the end of the input.
+///
+/// A line starting with `|` continues the last query, so a query can be
+/// refined a step at a time, and `:history` lists the queries so far.
+pub fn session<R: BufRead, W: Write>(
+    documents: &[Value],
+    mut input: R,
+    output: &mut W,
+    options: &Options,
+) -> io::Result<()> {
+    let mut history: Vec<String> = Vec::new();
+    let mut line = String::new();
+    loop {
+        write!(output, "rq> ")?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return writeln!(output);
+        }
+        let text = match line.trim() {
+            "" => continue,
+            ":q" | ":quit" => return Ok(()),
+            ":history" => {
+                for (i, query) in history.iter().enumerate() {
+                    writeln!(output, "{:>4}  {}", i + 1, query)?;
+                }
+                continue;
+            }
+            text if text.starts_with('|') => match history.last() {
+                Some(last) => format!("{} {}", last, text),
+                None => text[1..].to_string(),
+            },
+            text => text.to_string(),
+        };
+
+        match text.parse::<Query>() {
+            Ok(query) => {
+                for document in documents {
+                    match query.execute(document) {
+                        Ok(values) => {
+                            for value in &values {
+                                write_value(output, value, options)?;
+                            }
+                        }
+                        Err(e) => writeln!(output, "Failed to execute query: {}", e)?,
+                    }
+                }
+                history.push(text);
+            }
+            Err(e) => writeln!(output, "Failed to parse query string: {}", e)?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::Layout;
+
+    #[test]
+    fn repl_session() {
+        let options = Options {
+            layout: Layout::Compact,
+            ..Default::default()
+        };
+        let input = ".a\n| .[0]\n\n.a[\n:history\n.b.c\n:quit\n.a\n";
+        let mut output = Vec::new();
+        session(
+            &[json!({"a": [1, 2], "b": 3})],
+            input.as_bytes(),
+            &mut output,
+            &options,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let mut lines = output.split("rq> ").skip(1);
+        assert_eq!(Some("[1,2]\n"), lines.next());
+        assert_eq!(Some("1\n"), lines.next());
+        assert_eq!(Some(""), lines.next());
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("Failed to parse query string"));
+        assert_eq!(Some("   1  .a\n   2  .a | .[0]\n"), lines.next());
+        assert_eq!(
+            Some("Failed to execute query: Cannot index number (3) with \"c\" (at .b)\n"),
+            lines.next()
+        );
+        assert_eq!(Some(""), lines.next());
+        assert_eq!(None, lines.next());
+    }
+}
@@ -0,0 +1,291 @@
+use serde_json::Value;
+use std::{borrow::Cow, iter::FromIterator, rc::Rc};
+
+use crate::{
+    combinator::Chain,
+    context::{Context, Execution},
+    env::Env,
+    index::Index,
+    query::{descend, Eval, Query},
+    span::Spanned,
+    QueryResult,
+};
+
+/// Many queries executed together against each input, which follow the
+/// paths they start with, such as `.a.b` in `.a.b[0]` and `.a.b | length`,
+/// only once between them, and descend through the input with `..` only
+/// once for every place they do so from.
+///
+/// The results are the same as executing each query on its own.
+#[derive(Debug, Clone, Default)]
+pub struct QuerySet {
+    queries: Vec<Query>,
+    root: Node,
+}
+
+/// The queries which continue from one path into the input.
+#[derive(Debug, Clone, Default)]
+struct Node {
+    /// Each query ending here, by its position in the set, with what of it
+    /// is left after the path.
+    queries: Vec<(usize, Query)>,
+    children: Vec<(Index, Node)>,
+}
+
+impl QuerySet {
+    pub fn new(queries: Vec<Query>) -> Self {
+        let mut root = Node::default();
+        for (i, q) in queries.iter().enumerate() {
+            let (path, rest) = decompose(q.clone());
+            let mut node = &mut root;
+            for index in path {
+                let position = match node.children.iter().position(|(i, _)| *i == index) {
+                    Some(position) => position,
+                    None => {
+                        node.children.push((index, Node::default()));
+                        node.children.len() - 1
+                    }
+                };
+                node = &mut node.children[position].1;
+            }
+            node.queries.push((i, rest));
+        }
+        QuerySet { queries, root }
+    }
+
+    pub fn queries(&self) -> &[Query] {
+        &self.queries
+    }
+
+    /// The results of every query, in the order they were given.
+    pub fn execute(&self, value: &Value) -> Vec<QueryResult> {
+        self.execute_in(&Env::default(), value)
+    }
+
+    /// The results of every query, with the builtins, variables and limits
+    /// of `context`.
+    ///
+    /// The queries run as one execution, so the limits on time and memory
+    /// are of all of them together, where that on outputs is of each.
+    pub fn execute_with(&self, value: &Value, context: &Context) -> Vec<QueryResult> {
+        let execution = Rc::new(Execution::new(context));
+        let env = Env::new(execution.clone());
+        self.execute_in(&env, value)
+            .into_iter()
+            .map(|result| {
+                let outputs = result?;
+                execution.check_outputs(outputs.len())?;
+                Ok(outputs)
+            })
+            .collect()
+    }
+
+    fn execute_in<'a>(&'a self, env: &Env<'a>, value: &Value) -> Vec<QueryResult> {
+        let mut results: Vec<Option<QueryResult>> = self.queries.iter().map(|_| None).collect();
+        self.run(env, &self.root, value, value, &mut results);
+        results.into_iter().flatten().collect()
+    }
+
+    fn run<'a>(
+        &'a self,
+        env: &Env<'a>,
+        node: &'a Node,
+        root: &Value,
+        value: &Value,
+        results: &mut [Option<QueryResult>],
+    ) {
+        let mut descent = None;
+        for (i, rest) in &node.queries {
+            let result = match rest {
+                Query::Recurse => Ok(descendants(&mut descent, value)
+                    .iter()
+                    .map(|v| (*v).clone())
+                    .collect()),
+                Query::Chain(chain) if chain.0 == Query::Recurse => {
+                    descendants(&mut descent, value)
+                        .iter()
+                        .try_fold(Vec::new(), |mut output, v| {
+                            output.extend(chain.1.eval(env, v)?);
+                            Ok(output)
+                        })
+                }
+                rest => rest.eval(env, value),
+            };
+            // Errors are reported just as the whole query would report them
+            results[*i] = Some(result.or_else(|_| self.queries[*i].eval(env, root)));
+        }
+
+        for (index, child) in &node.children {
+            match index.eval_ref(env, value) {
+                Ok(mut found) => match found.pop() {
+                    Some(Cow::Borrowed(v)) => self.run(env, child, root, v, results),
+                    Some(Cow::Owned(v)) => self.run(env, child, root, &v, results),
+                    None => self.run(env, child, root, &Value::Null, results),
+                },
+                Err(_) => child.each(&mut |i| results[i] = Some(self.queries[i].eval(env, root))),
+            }
+        }
+    }
+}
+
+impl Node {
+    fn each(&self, f: &mut impl FnMut(usize)) {
+        self.queries.iter().for_each(|(i, _)| f(*i));
+        self.children.iter().for_each(|(_, child)| child.each(f));
+    }
+}
+
+impl FromIterator<Query> for QuerySet {
+    fn from_iter<I: IntoIterator<Item = Query>>(iter: I) -> Self {
+        QuerySet::new(iter.into_iter().collect())
+    }
+}
+
+/// Every value within `value` as `..` finds them, found the first time they are needed.
+fn descendants<'v, 'd>(
+    descent: &'d mut Option<Vec<&'v Value>>,
+    value: &'v Value,
+) -> &'d [&'v Value] {
+    descent.get_or_insert_with(|| {
+        let mut output = Vec::new();
+        descend(value, &mut output);
+        output
+    })
+}
+
+/// Splits the keys and indices a query starts with from the rest of it.
+fn decompose(query: Query) -> (Vec<Index>, Query) {
+    match query {
+        Query::Index(index @ (Index::String(_) | Index::Integer(_))) => {
+            (vec![index], Query::Identity)
+        }
+        // The span only matters to errors, which the whole query reports
+        Query::Spanned(spanned) => {
+            let Spanned { span, query } = *spanned;
+            match decompose(query) {
+                (path, query) if path.is_empty() => {
+                    (path, Query::Spanned(Box::new(Spanned { span, query })))
+                }
+                decomposed => decomposed,
+            }
+        }
+        Query::Chain(chain) => {
+            let Chain(left, right) = *chain;
+            match decompose(left) {
+                (mut path, Query::Identity) => {
+                    let (rest, right) = decompose(right);
+                    path.extend(rest);
+                    (path, right)
+                }
+                (path, left) => (path, Query::Chain(Box::new(Chain(left, right)))),
+            }
+        }
+        q => (Vec::new(), q),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{context::Limits, parse::ParseOptions, query::Executable, QueryError};
+
+    use super::*;
+
+    #[test]
+    fn decompose_paths() {
+        let q = |s: &str| s.parse::<Query>().unwrap();
+        let (path, rest) = decompose(q(".a.b[0] | .[]"));
+        assert_eq!(
+            vec![
+                Index::String("a".to_string()),
+                Index::String("b".to_string()),
+                Index::Integer(0)
+            ],
+            path
+        );
+        assert_eq!(Query::Iterator, rest);
+
+        assert_eq!((Vec::new(), q(".[] | .a")), decompose(q(".[] | .a")));
+        assert_eq!(
+            (vec![Index::String("a".to_string())], q(".[1:] | .b")),
+            decompose(q(".a[1:].b"))
+        );
+
+        let options = ParseOptions {
+            spans: true,
+            ..Default::default()
+        };
+        let spanned = Query::parse_with(".a.b | length", &options).unwrap();
+        let (path, rest) = decompose(spanned);
+        assert_eq!(
+            vec![
+                Index::String("a".to_string()),
+                Index::String("b".to_string())
+            ],
+            path
+        );
+        assert!(matches!(rest, Query::Spanned(_)));
+    }
+
+    #[test]
+    fn execute_set() {
+        let v = json!({"a": {"b": [1, {"c": 2}], "d": 3}, "e": "f"});
+        let texts = [
+            ".a.b[0]",
+            ".a.b | .[1]",
+            ".a.d + 1",
+            "..",
+            ".. | .c?",
+            ".a | .. | .c?",
+            ".a.b[1].c",
+            ".e.x",
+            ".e[0]",
+            ".a.b[0] | .[]",
+            ".missing.x",
+            ".a, .e",
+        ];
+        let queries: Vec<Query> = texts.iter().map(|s| s.parse().unwrap()).collect();
+        let set: QuerySet = queries.iter().cloned().collect();
+
+        let results = set.execute(&v);
+        assert_eq!(queries.len(), results.len());
+        for (q, r) in queries.iter().zip(results) {
+            match (q.execute(&v), r) {
+                (Ok(expected), Ok(actual)) => assert_eq!(expected, actual, "{:?}", q),
+                (Err(expected), Err(actual)) => {
+                    assert_eq!(expected.to_string(), actual.to_string())
+                }
+                (expected, actual) => panic!("{:?}: {:?} != {:?}", q, expected, actual),
+            }
+        }
+
+        assert!(QuerySet::default().execute(&v).is_empty());
+    }
+
+    #[test]
+    fn execute_with_context() {
+        let v = json!({"a": {"b": 1}});
+        let mut context = Context::new();
+        context
+            .var("x", 2)
+            .register("double", 0, |_, v| Ok(vec![json!(v.as_i64().unwrap() * 2)]))
+            .limits(Limits {
+                outputs: Some(1),
+                ..Default::default()
+            });
+        let set: QuerySet = [".a.b | double", ".a | $x", ".a.b | ., ."]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+
+        let results = set.execute_with(&v, &context);
+        assert_eq!(Some(&vec![json!(2)]), results[0].as_ref().ok());
+        assert_eq!(Some(&vec![json!(2)]), results[1].as_ref().ok());
+        assert!(matches!(
+            results[2],
+            Err(QueryError::LimitExceeded("output"))
+        ));
+        assert!(set.execute(&v)[1].is_err());
+    }
+}
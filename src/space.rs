@@ -1,4 +1,4 @@
-use nom::{character::complete::space0, IResult};
+use nom::{character::complete::multispace0, IResult};
 
 use crate::parse::ParseError;
 
@@ -7,7 +7,7 @@ where
     F: FnMut(&'a str) -> IResult<&'a str, O, ParseError>,
 {
     move |input: &'a str| {
-        let (input, _) = space0(input)?;
+        let (input, _) = multispace0(input)?;
         f(input)
     }
 }
@@ -18,7 +18,7 @@ where
 {
     move |input: &'a str| {
         let (input, o) = f(input)?;
-        let (input, _) = space0(input)?;
+        let (input, _) = multispace0(input)?;
         Ok((input, o))
     }
 }
@@ -0,0 +1,131 @@
+use nom::IResult;
+use serde_json::Value;
+use std::{borrow::Cow, cell::Cell, ops::Range};
+
+use crate::{
+    env::Env,
+    parse::ParseError,
+    query::{Eval, Query},
+    PathResult, QueryError, QueryIter, QueryResult, SharedResult,
+};
+
+/// A query together with the byte range of the text it was parsed from,
+/// which any error it produces is annotated with.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Spanned {
+    pub span: Range<usize>,
+    pub query: Query,
+}
+
+impl Eval for Spanned {
+    fn eval<'a>(&'a self, env: &Env<'a>, value: &Value) -> QueryResult {
+        self.query
+            .eval(env, value)
+            .map_err(|e| e.within(&self.span))
+    }
+
+    fn eval_owned<'a>(&'a self, env: &Env<'a>, value: Value) -> QueryResult {
+        self.query
+            .eval_owned(env, value)
+            .map_err(|e| e.within(&self.span))
+    }
+
+    fn eval_into<'a>(
+        &'a self,
+        env: &Env<'a>,
+        value: &Value,
+        output: &mut Vec<Value>,
+    ) -> Result<(), QueryError> {
+        self.query
+            .eval_into(env, value, output)
+            .map_err(|e| e.within(&self.span))
+    }
+
+    fn eval_iter<'a>(&'a self, env: &Env<'a>, value: Cow<'a, Value>) -> QueryIter<'a> {
+        Box::new(
+            self.query
+                .eval_iter(env, value)
+                .map(move |r| r.map_err(|e| e.within(&self.span))),
+        )
+    }
+
+    fn eval_ref<'a, 'v>(&'a self, env: &Env<'a>, value: &'v Value) -> SharedResult<'v> {
+        self.query
+            .eval_ref(env, value)
+            .map_err(|e| e.within(&self.span))
+    }
+
+    fn eval_paths<'a>(&'a self, env: &Env<'a>, value: &Value) -> PathResult {
+        self.query
+            .eval_paths(env, value)
+            .map_err(|e| e.within(&self.span))
+    }
+}
+
+thread_local! {
+    /// The addresses of the text being parsed, while spans are recorded.
+    static SOURCE: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+/// Runs the parser `f` over `input`, recording spans relative to its start.
+pub(crate) fn record<T>(input: &str, f: impl FnOnce(&str) -> T) -> T {
+    let start = input.as_ptr() as usize;
+    let previous = SOURCE.with(|s| s.replace(Some((start, start + input.len()))));
+    let output = f(input);
+    SOURCE.with(|s| s.set(previous));
+    output
+}
+
+/// Wraps the parsed query in its span, unless spans aren't being recorded,
+/// or it already has one.
+pub(crate) fn spanned<'a, F>(mut f: F) -> impl FnMut(&'a str) -> IResult<&'a str, Query, ParseError>
+where
+    F: FnMut(&'a str) -> IResult<&'a str, Query, ParseError>,
+{
+    move |input: &'a str| {
+        let (rest, query) = f(input)?;
+        let from = input.as_ptr() as usize;
+        let query = match SOURCE.with(Cell::get) {
+            Some((start, end)) if (start..=end).contains(&from) => {
+                if matches!(query, Query::Spanned(_)) {
+                    return Ok((rest, query));
+                }
+                let text = &input[..input.len() - rest.len()];
+                let offset = from - start + text.len() - text.trim_start().len();
+                let span = offset..offset + text.trim().len();
+                Query::Spanned(Box::new(Spanned { span, query }))
+            }
+            _ => query,
+        };
+        Ok((rest, query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse::ParseOptions, query::Executable};
+
+    use super::*;
+
+    fn span(query: &str, value: &str) -> Option<Range<usize>> {
+        let options = ParseOptions {
+            spans: true,
+            ..Default::default()
+        };
+        let q = Query::parse_with(query, &options).unwrap();
+        let v: Value = serde_json::from_str(value).unwrap();
+        q.execute(&v).unwrap_err().span()
+    }
+
+    #[test]
+    fn error_spans() {
+        assert_eq!(Some(2..4), span(".a.b", r#"{"a": 1}"#));
+        assert_eq!(Some(0..2), span(".a.b", "1"));
+        assert_eq!(Some(6..11), span(".[] | 1 + . ", r#"["x"]"#));
+        assert_eq!(Some(6..17), span("[1] | {(.[0]): 2}", "null"));
+        assert_eq!(Some(10..11), span("def f: 1; g # comment", "null"));
+
+        let q: Query = ".a.b".parse().unwrap();
+        assert_eq!(None, q.execute(&serde_json::json!(1)).unwrap_err().span());
+    }
+}
@@ -0,0 +1,640 @@
+use serde_core::de::{
+    self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor,
+};
+use serde_json::{Map, Value};
+use std::{
+    fmt,
+    io::{self, BufRead},
+    iter,
+};
+
+use crate::{
+    env::Env,
+    index::Index,
+    number::Clamped,
+    query::{Executable, Query},
+    update, QueryError,
+};
+
+/// One step of a path a query follows.
+#[derive(Debug, PartialEq, Clone)]
+enum Step {
+    Key(String),
+    Index(usize),
+    Any,
+}
+
+impl Query {
+    /// Executes a query which only follows keys, indices which aren't
+    /// negative and `.[]` over the JSON document of `reader` as it is read,
+    /// passing each result to `sink`.
+    ///
+    /// Only the values the query produces are held in memory, and the rest of
+    /// the document is skipped over, so the document may be far larger than
+    /// memory. The results come in the order they appear in the document, so
+    /// `.b, .a` produces `.a` first, and missing keys and indices produce
+    /// their nulls once the object or array they are missing from ends.
+    pub fn execute_streaming<R, F>(&self, reader: R, mut sink: F) -> Result<(), QueryError>
+    where
+        R: io::Read,
+        F: FnMut(Value),
+    {
+        let patterns = patterns(self).ok_or(QueryError::Streaming)?;
+        let mut state = State {
+            remainders: patterns
+                .iter()
+                .map(|steps| (0..=steps.len()).map(|d| remainder(&steps[d..])).collect())
+                .collect(),
+            patterns,
+            sink: &mut sink,
+            error: None,
+        };
+        let active = (0..state.patterns.len()).map(|p| (p, 0)).collect();
+
+        let mut de = serde_json::Deserializer::from_reader(Clamped::new(reader));
+        let result = At {
+            state: &mut state,
+            active,
+        }
+        .deserialize(&mut de)
+        .and_then(|_| de.end());
+        match (state.error, result) {
+            (Some(e), _) => Err(e),
+            (None, result) => Ok(result?),
+        }
+    }
+}
+
+/// The paths a query follows, or `None` if it does anything else.
+fn patterns(query: &Query) -> Option<Vec<Vec<Step>>> {
+    match query {
+        Query::Empty => Some(Vec::new()),
+        Query::Identity => Some(vec![Vec::new()]),
+        Query::Index(Index::String(s)) => Some(vec![vec![Step::Key(s.clone())]]),
+        Query::Index(Index::Integer(i)) if *i >= 0 => Some(vec![vec![Step::Index(*i as usize)]]),
+        Query::Iterator => Some(vec![vec![Step::Any]]),
+        Query::Split(split) => {
+            let mut output = patterns(&split.0)?;
+            output.extend(patterns(&split.1)?);
+            Some(output)
+        }
+        Query::Chain(chain) => {
+            let rights = patterns(&chain.1)?;
+            Some(
+                patterns(&chain.0)?
+                    .into_iter()
+                    .flat_map(|left| {
+                        rights
+                            .iter()
+                            .map(move |right| left.iter().chain(right).cloned().collect())
+                    })
+                    .collect(),
+            )
+        }
+        Query::Spanned(s) => patterns(&s.query),
+        _ => None,
+    }
+}
+
+/// The query following the rest of a path from a value already read.
+fn remainder(steps: &[Step]) -> Query {
+    steps.iter().fold(Query::Identity, |q, step| {
+        let next = match step {
+            Step::Key(k) => Query::Index(Index::String(k.clone())),
+            Step::Index(i) => Query::Index(Index::Integer(*i as i32)),
+            Step::Any => Query::Iterator,
+        };
+        match q {
+            Query::Identity => next,
+            q => q.pipe(next),
+        }
+    })
+}
+
+struct State<'f> {
+    patterns: Vec<Vec<Step>>,
+    /// The query for the rest of each pattern after each of its steps.
+    remainders: Vec<Vec<Query>>,
+    sink: &'f mut dyn FnMut(Value),
+    error: Option<QueryError>,
+}
+
+impl State<'_> {
+    /// Finishes each active pattern within a value which has been read.
+    fn emit<E: de::Error>(&mut self, active: &[(usize, usize)], value: &Value) -> Result<(), E> {
+        for (p, depth) in active {
+            match self.remainders[*p][*depth].execute(value) {
+                Ok(values) => values.into_iter().for_each(|v| (self.sink)(v)),
+                Err(e) => {
+                    self.error = Some(e);
+                    return Err(E::custom("query failed"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A value within the document, with each pattern which leads to it and how
+/// many of its steps have been taken.
+struct At<'s, 'f> {
+    state: &'s mut State<'f>,
+    active: Vec<(usize, usize)>,
+}
+
+impl<'f> At<'_, 'f> {
+    /// The patterns which lead on to a child, with their leftovers, where
+    /// `matches` says whether a step leads to it.
+    fn children<F: Fn(&Step) -> bool>(&self, matches: F) -> Vec<(usize, usize)> {
+        self.active
+            .iter()
+            .filter(|(p, depth)| matches(&self.state.patterns[*p][*depth]))
+            .map(|(p, depth)| (*p, depth + 1))
+            .collect()
+    }
+
+    fn child(&mut self, active: Vec<(usize, usize)>) -> At<'_, 'f> {
+        At {
+            state: &mut *self.state,
+            active,
+        }
+    }
+
+    fn leaf<E: de::Error>(self, value: Value) -> Result<(), E> {
+        self.state.emit(&self.active, &value)
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for At<'_, '_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        let patterns = &self.state.patterns;
+        if self
+            .active
+            .iter()
+            .any(|(p, depth)| *depth == patterns[*p].len())
+        {
+            // Every pattern needs no more than the whole value
+            let value = serde_core::Deserialize::deserialize(deserializer)?;
+            return self.leaf(value);
+        }
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for At<'_, '_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a JSON value")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        self.leaf(Value::Null)
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<(), E> {
+        self.leaf(Value::Bool(b))
+    }
+
+    fn visit_i64<E: de::Error>(self, i: i64) -> Result<(), E> {
+        self.leaf(Value::from(i))
+    }
+
+    fn visit_u64<E: de::Error>(self, u: u64) -> Result<(), E> {
+        self.leaf(Value::from(u))
+    }
+
+    fn visit_f64<E: de::Error>(self, f: f64) -> Result<(), E> {
+        self.leaf(Value::from(f))
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<(), E> {
+        self.leaf(Value::from(s))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<(), A::Error> {
+        let mut missing: Vec<_> = self
+            .active
+            .iter()
+            .filter(|(p, depth)| self.state.patterns[*p][*depth] != Step::Any)
+            .cloned()
+            .collect();
+        while let Some(key) = map.next_key::<String>()? {
+            #[cfg(feature = "arbitrary_precision")]
+            if key == "$serde_json::private::Number" {
+                let number: String = map.next_value()?;
+                return match number.parse() {
+                    Ok(n) => self.leaf(Value::Number(n)),
+                    Err(e) => Err(de::Error::custom(e)),
+                };
+            }
+
+            let is_key = |s: &Step| *s == Step::Key(key.clone());
+            let active = self.children(|s| *s == Step::Any || is_key(s));
+            let patterns = &self.state.patterns;
+            missing.retain(|(p, depth)| !is_key(&patterns[*p][*depth]));
+            if active.is_empty() {
+                map.next_value::<IgnoredAny>()?;
+            } else {
+                map.next_value_seed(self.child(active))?;
+            }
+        }
+        // Missing keys are null, and indices are errors, as for an empty object
+        self.state.emit(&missing, &Value::Object(Map::new()))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+        let mut len = 0;
+        loop {
+            let active = self.children(|s| matches!(s, Step::Any) || *s == Step::Index(len));
+            let more = if active.is_empty() {
+                seq.next_element::<IgnoredAny>()?.is_some()
+            } else {
+                seq.next_element_seed(self.child(active))?.is_some()
+            };
+            if !more {
+                break;
+            }
+            len += 1;
+        }
+        let missing: Vec<_> = self
+            .active
+            .iter()
+            .filter(|(p, depth)| match &self.state.patterns[*p][*depth] {
+                Step::Any => false,
+                Step::Index(i) => *i >= len,
+                Step::Key(_) => true,
+            })
+            .cloned()
+            .collect();
+        self.state.emit(&missing, &Value::Array(Vec::new()))
+    }
+}
+
+/// Each JSON document of `reader` in turn, whether whitespace separates
+/// them or each follows straight after the last, as in `{"a":1}{"a":2}`.
+///
+/// Text which isn't JSON ends the documents with an error.
+pub fn documents<R: io::Read>(reader: R) -> impl Iterator<Item = Result<Value, QueryError>> {
+    let mut documents =
+        serde_json::Deserializer::from_reader(Clamped::new(reader)).into_iter::<Value>();
+    let mut failed = false;
+    iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+        let document = documents.next()?;
+        failed = document.is_err();
+        Some(document.map_err(QueryError::from))
+    })
+}
+
+/// Reads each JSON document of `reader` as the events of `tostream`,
+/// passing each to `sink` as it is read, so that no more than the path to
+/// the current value is held in memory.
+///
+/// Each scalar or empty array or object is a `[path, value]` event, and the
+/// end of every other array or object a `[path]` event with the path to its
+/// last value.
+pub fn read_events<R, F>(reader: R, mut sink: F) -> Result<(), QueryError>
+where
+    R: BufRead,
+    F: FnMut(Value),
+{
+    let mut reader = Clamped::new(reader);
+    loop {
+        // Each document has its own deserializer, so the end of the input
+        // is found by looking past the whitespace between them
+        let rest = reader.fill_buf().map_err(serde_json::Error::io)?;
+        if rest.is_empty() {
+            return Ok(());
+        }
+        let blank = rest.iter().take_while(|b| b.is_ascii_whitespace()).count();
+        if blank > 0 {
+            reader.consume(blank);
+            continue;
+        }
+        let mut de = serde_json::Deserializer::from_reader(&mut reader);
+        Events {
+            path: &mut Vec::new(),
+            sink: &mut sink,
+        }
+        .deserialize(&mut de)?;
+    }
+}
+
+/// The events of `tostream` for a value.
+pub(crate) fn events(value: &Value, path: &mut Vec<Value>, output: &mut Vec<Value>) {
+    let children: Box<dyn Iterator<Item = (Value, &Value)>> = match value {
+        Value::Array(a) if !a.is_empty() => {
+            Box::new(a.iter().enumerate().map(|(i, v)| (Value::from(i), v)))
+        }
+        Value::Object(o) if !o.is_empty() => {
+            Box::new(o.iter().map(|(k, v)| (Value::from(k.as_str()), v)))
+        }
+        leaf => return output.push(Value::from(vec![Value::from(path.clone()), leaf.clone()])),
+    };
+    for (step, child) in children {
+        path.push(step);
+        events(child, path, output);
+        path.pop();
+    }
+    output.push(closing(path, last_step(value)));
+}
+
+fn last_step(value: &Value) -> Value {
+    match value {
+        Value::Array(a) => Value::from(a.len() - 1),
+        Value::Object(o) => Value::from(o.keys().next_back().map_or("", |k| k.as_str())),
+        _ => Value::Null,
+    }
+}
+
+/// The event ending an array or object at `path`, with its last step.
+fn closing(path: &[Value], last: Value) -> Value {
+    let mut path = path.to_vec();
+    path.push(last);
+    Value::from(vec![Value::from(path)])
+}
+
+/// Rebuilds the values which a stream of `tostream` events describes.
+#[derive(Debug, Default)]
+pub(crate) struct Rebuild {
+    partial: Option<Value>,
+}
+
+impl Rebuild {
+    /// Takes the next event, producing a value once it is complete.
+    pub(crate) fn push(&mut self, env: &Env, event: &Value) -> Result<Option<Value>, QueryError> {
+        let invalid = || QueryError::Path(crate::describe(event));
+        let (path, leaf) = match event.as_array().map(Vec::as_slice) {
+            Some([Value::Array(path), leaf]) => (path, Some(leaf)),
+            Some([Value::Array(path)]) => (path, None),
+            _ => return Err(invalid()),
+        };
+        match (path.len(), leaf) {
+            (0, Some(leaf)) => Ok(Some(leaf.clone())),
+            (0, None) => Err(invalid()),
+            (_, Some(leaf)) => {
+                let partial = self.partial.get_or_insert(Value::Null);
+                update::set(env, partial, path, leaf.clone())?;
+                Ok(None)
+            }
+            (1, None) => Ok(Some(self.partial.take().unwrap_or(Value::Null))),
+            (_, None) => Ok(None),
+        }
+    }
+}
+
+/// A value within a document read as events, at `path`.
+struct Events<'s> {
+    path: &'s mut Vec<Value>,
+    sink: &'s mut dyn FnMut(Value),
+}
+
+impl Events<'_> {
+    fn leaf<E: de::Error>(self, value: Value) -> Result<(), E> {
+        (self.sink)(Value::from(vec![Value::from(self.path.clone()), value]));
+        Ok(())
+    }
+
+    fn close<E: de::Error>(self, last: Value) -> Result<(), E> {
+        (self.sink)(closing(self.path, last));
+        Ok(())
+    }
+
+    fn child(&mut self) -> Events<'_> {
+        Events {
+            path: &mut *self.path,
+            sink: &mut *self.sink,
+        }
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for Events<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Events<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a JSON value")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        self.leaf(Value::Null)
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<(), E> {
+        self.leaf(Value::Bool(b))
+    }
+
+    fn visit_i64<E: de::Error>(self, i: i64) -> Result<(), E> {
+        self.leaf(Value::from(i))
+    }
+
+    fn visit_u64<E: de::Error>(self, u: u64) -> Result<(), E> {
+        self.leaf(Value::from(u))
+    }
+
+    fn visit_f64<E: de::Error>(self, f: f64) -> Result<(), E> {
+        self.leaf(Value::from(f))
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<(), E> {
+        self.leaf(Value::from(s))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<(), A::Error> {
+        let mut last = None;
+        while let Some(key) = map.next_key::<String>()? {
+            #[cfg(feature = "arbitrary_precision")]
+            if key == "$serde_json::private::Number" {
+                let number: String = map.next_value()?;
+                return match number.parse() {
+                    Ok(n) => self.leaf(Value::Number(n)),
+                    Err(e) => Err(de::Error::custom(e)),
+                };
+            }
+
+            self.path.push(Value::from(key));
+            map.next_value_seed(self.child())?;
+            last = self.path.pop();
+        }
+        match last {
+            Some(last) => self.close(last),
+            None => self.leaf(Value::Object(Map::new())),
+        }
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+        let mut len = 0;
+        loop {
+            self.path.push(Value::from(len));
+            let more = seq.next_element_seed(self.child())?.is_some();
+            self.path.pop();
+            if !more {
+                break;
+            }
+            len += 1;
+        }
+        match len {
+            0 => self.leaf(Value::Array(Vec::new())),
+            len => self.close(Value::from(len - 1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn stream(query: &str, text: &str) -> Result<Vec<Value>, QueryError> {
+        let mut output = Vec::new();
+        let q: Query = query.parse().unwrap();
+        q.execute_streaming(text.as_bytes(), |v| output.push(v))?;
+        Ok(output)
+    }
+
+    #[test]
+    fn patterns_of_queries() {
+        let p = |s: &str| patterns(&s.parse().unwrap());
+        assert_eq!(
+            Some(vec![
+                vec![Step::Key("a".to_string()), Step::Any],
+                vec![Step::Key("a".to_string()), Step::Index(1)],
+            ]),
+            p(".a | .[], .[1]")
+        );
+        assert_eq!(Some(vec![vec![]]), p("."));
+        assert_eq!(None, p(".[-1]"));
+        assert_eq!(None, p(".a + 1"));
+        assert_eq!(None, p(".."));
+    }
+
+    #[test]
+    fn execute_streaming() {
+        let text = r#"{"a": [1, {"b": 2}, [3]], "c": {"d": "e"}, "f": null}"#;
+        let v: Value = serde_json::from_str(text).unwrap();
+        for q in &[
+            ".",
+            ".a",
+            ".a[1].b",
+            ".a[]",
+            ".a[2][0]",
+            ".c.d",
+            ".c[]",
+            ".a[5]",
+            ".missing.x",
+            ".f.x",
+            ".c | .d",
+        ] {
+            let expected = q.parse::<Query>().unwrap().execute(&v).unwrap();
+            assert_eq!(expected, stream(q, text).unwrap(), "{}", q);
+        }
+
+        // Results come in the order of the document
+        assert_eq!(
+            vec![json!([1, {"b": 2}, [3]]), json!("e"), json!(null)],
+            stream(".c.d, .a, .x", text).unwrap()
+        );
+
+        assert!(matches!(stream(".a[].b", text), Err(QueryError::Index(..))));
+        assert!(matches!(stream(".a.b", text), Err(QueryError::Index(..))));
+        assert!(matches!(stream(".f[]", text), Err(QueryError::Iterate(_))));
+        assert!(matches!(
+            stream(".a | length", text),
+            Err(QueryError::Streaming)
+        ));
+        assert!(matches!(
+            stream(".a", "{\"a\": 1"),
+            Err(QueryError::Json(_))
+        ));
+        assert!(matches!(stream(".a", "{} {}"), Err(QueryError::Json(_))));
+    }
+
+    #[test]
+    fn stream_events() {
+        let text = r#"{"a": [1, {"b": []}], "c": {}} 2 []"#;
+        let expected = vec![
+            json!([["a", 0], 1]),
+            json!([["a", 1, "b"], []]),
+            json!([["a", 1, "b"]]),
+            json!([["a", 1]]),
+            json!([["c"], {}]),
+            json!([["c"]]),
+            json!([[], 2]),
+            json!([[], []]),
+        ];
+        let mut read = Vec::new();
+        read_events(text.as_bytes(), |e| read.push(e)).unwrap();
+        assert_eq!(expected, read);
+
+        let mut output = Vec::new();
+        let mut rebuild = Rebuild::default();
+        for v in serde_json::Deserializer::from_str(text).into_iter::<Value>() {
+            events(&v.unwrap(), &mut Vec::new(), &mut output);
+        }
+        assert_eq!(expected, output);
+
+        let rebuilt: Vec<Value> = expected
+            .iter()
+            .filter_map(|e| rebuild.push(&Env::default(), e).unwrap())
+            .collect();
+        assert_eq!(
+            vec![json!({"a": [1, {"b": []}], "c": {}}), json!(2), json!([])],
+            rebuilt
+        );
+        assert!(rebuild.push(&Env::default(), &json!([1])).is_err());
+
+        let v = json!({"a": [1, {"b": []}], "c": {}});
+        let q: Query = "fromstream(tostream)".parse().unwrap();
+        assert_eq!(vec![v.clone()], q.execute(&v).unwrap());
+        let q: Query = "[tostream]".parse().unwrap();
+        assert_eq!(
+            vec![Value::from(expected[..6].to_vec())],
+            q.execute(&v).unwrap()
+        );
+        let q: Query = "fromstream(.[])".parse().unwrap();
+        assert_eq!(
+            vec![v],
+            q.execute(&Value::from(expected[..6].to_vec())).unwrap()
+        );
+
+        assert!(read_events("[1, 2".as_bytes(), |_| ()).is_err());
+        let mut read = Vec::new();
+        read_events("  ".as_bytes(), |e| read.push(e)).unwrap();
+        assert!(read.is_empty());
+    }
+
+    #[test]
+    fn concatenated_documents() {
+        let text = r#"{"a":1}{"a":2}[3]"x"4 true null"#;
+        let read: Result<Vec<_>, _> = documents(text.as_bytes()).collect();
+        assert_eq!(
+            vec![
+                json!({"a": 1}),
+                json!({"a": 2}),
+                json!([3]),
+                json!("x"),
+                json!(4),
+                json!(true),
+                json!(null)
+            ],
+            read.unwrap()
+        );
+
+        let read: Vec<_> = documents("1 [2 x 3".as_bytes()).collect();
+        assert_eq!(2, read.len());
+        assert!(matches!(read[1], Err(QueryError::Json(_))));
+    }
+}
@@ -0,0 +1,184 @@
+use crate::{
+    builtins,
+    construction::{Construct, Key},
+    format::{Format, Part},
+    parse::ParseError,
+    query::Query,
+    raw::Raw,
+};
+
+/// Checks that a query uses nothing jq doesn't have, so that it runs under
+/// jq just as it does here.
+pub(crate) fn check(query: &Query) -> Result<(), ParseError> {
+    Scope::default().check(query)
+}
+
+/// The functions defined around a query, which may shadow the core
+/// functions jq doesn't have.
+#[derive(Default, Clone)]
+struct Scope<'q> {
+    defined: Vec<(&'q str, usize)>,
+}
+
+impl<'q> Scope<'q> {
+    fn with(&self, name: &'q str, arity: usize) -> Self {
+        let mut scope = self.clone();
+        scope.defined.push((name, arity));
+        scope
+    }
+
+    fn check(&self, query: &'q Query) -> Result<(), ParseError> {
+        match query {
+            Query::Empty
+            | Query::Identity
+            | Query::Index(_)
+            | Query::Iterator
+            | Query::Recurse
+            | Query::Raw(_)
+            | Query::Variable(_) => Ok(()),
+            Query::Split(split) => self.check(&split.0).and(self.check(&split.1)),
+            Query::Chain(chain) => self.check(&chain.0).and(self.check(&chain.1)),
+            Query::Optional(opt) => self.check(&opt.0),
+            Query::Construct(Construct::Array(inner)) => self.check(inner),
+            Query::Construct(Construct::Object(kvs)) => kvs.iter().try_for_each(|(k, v)| {
+                if let Key::Query(k) = k {
+                    self.check(k)?;
+                }
+                self.check(v)
+            }),
+            Query::Op(op) => self.check(&op.left).and(self.check(&op.right)),
+            Query::Update(update) => self.check(&update.path).and(self.check(&update.value)),
+            Query::Call(call) => {
+                let arity = call.args.len();
+                if !self.defined.contains(&(call.name.as_str(), arity)) {
+                    if builtins::EXTENSIONS.contains(&(call.name.as_str(), arity)) {
+                        return Err(extension(format!("{}/{}", call.name, arity)));
+                    }
+                    if let ("getpath" | "setpath", Some(Query::Raw(Raw(path)))) =
+                        (call.name.as_str(), call.args.first().map(unspanned))
+                    {
+                        if path.is_string() {
+                            return Err(extension("A JSON Pointer path"));
+                        }
+                    }
+                }
+                call.args.iter().try_for_each(|a| self.check(a))
+            }
+            Query::Define(define) => {
+                let f = &define.function;
+                let scope = self.with(&f.name, f.params.len());
+                let body = f.params.iter().fold(scope.clone(), |s, p| s.with(p, 0));
+                body.check(&f.body)?;
+                scope.check(&define.rest)
+            }
+            Query::Import(import) => {
+                self.check(&import.module)?;
+                let mut scope = self.clone();
+                if import.alias.is_none() {
+                    let mut module = &import.module;
+                    while let Query::Define(define) = module {
+                        scope = scope.with(&define.function.name, define.function.params.len());
+                        module = &define.rest;
+                    }
+                }
+                scope.check(&import.rest)
+            }
+            Query::Format(f) => format(f),
+            Query::Template(t) => {
+                format(&t.format)?;
+                t.parts.iter().try_for_each(|p| match p {
+                    Part::Literal(_) => Ok(()),
+                    Part::Query(q) => self.check(q),
+                })
+            }
+            Query::Reduce(reduce) => {
+                self.check(&reduce.source)?;
+                self.check(&reduce.init)?;
+                self.check(&reduce.update)
+            }
+            Query::Spanned(s) => self.check(&s.query),
+        }
+    }
+}
+
+fn unspanned(query: &Query) -> &Query {
+    match query {
+        Query::Spanned(s) => unspanned(&s.query),
+        q => q,
+    }
+}
+
+/// Only the formats jq has, where those the context registers are not.
+fn format(format: &Format) -> Result<(), ParseError> {
+    match format {
+        Format::Custom(name) if name != "base32" && name != "base32d" => {
+            Err(extension(format!("@{}", name)))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn extension(what: impl Into<String>) -> ParseError {
+    ParseError::Extension(what.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::ParseOptions;
+
+    use super::*;
+
+    fn parse(s: &str) -> Result<Query, ParseError> {
+        let options = ParseOptions {
+            strict: true,
+            ..Default::default()
+        };
+        Query::parse_with(s, &options)
+    }
+
+    fn rejected(s: &str) -> String {
+        match parse(s) {
+            Err(ParseError::Extension(what)) => what,
+            r => panic!("{}: unexpected {:?}", s, r),
+        }
+    }
+
+    #[test]
+    fn jq_queries() {
+        for s in [
+            ".a[1:] | {b: .c, (.d): [.e[]?]}",
+            "reduce .[] as $x (0; . + $x) | @base64 \"\\(.)\", @base32d",
+            "getpath([\"a\", 0]), setpath([\"a\"]; 1), [limit(2; .[])]",
+            "[paths] | tostream | ltrimstr(\"a\")",
+            // Definitions of the same names are the query's own
+            "def mergepatch(f): . * f; mergepatch({})",
+            "def f(tobytes): tobytes; f(.)",
+            "\"# not a comment\"",
+            "",
+        ] {
+            assert!(parse(s).is_ok(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn extensions() {
+        assert_eq!("mergepatch/1", rejected(".a | mergepatch({b: 1})"));
+        assert_eq!("tobytes/0", rejected("[.[] | tobytes]"));
+        assert_eq!("fromcsv/0", rejected("def f: fromcsv; f"));
+        assert_eq!("tohex/0", rejected("def tohex(f): f; tohex"));
+        assert_eq!("A JSON Pointer path", rejected("getpath(\"/a/0\")"));
+        assert_eq!("A JSON Pointer path", rejected("setpath(\"/a\"; 1)"));
+        assert_eq!("@custom", rejected(".a | @custom"));
+        assert_eq!("@custom", rejected("@custom \"\\(.a)\""));
+        assert_eq!("A comment", rejected(". # comment"));
+
+        // All of which are fine otherwise
+        for s in [
+            ".a | mergepatch({b: 1})",
+            "getpath(\"/a/0\")",
+            ". # comment",
+        ] {
+            assert!(s.parse::<Query>().is_ok(), "{}", s);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use serde_json::ser::{CharEscape, Formatter};
+use std::io::{self, Write};
+
+const NULL: &str = "1;30";
+const FALSE: &str = "0;39";
+const TRUE: &str = "0;39";
+const NUMBER: &str = "0;39";
+const STRING: &str = "0;32";
+const ARRAY: &str = "1;39";
+const OBJECT: &str = "1;39";
+const KEY: &str = "34;1";
+
+/// A formatter which changes how another writes JSON, highlighting it as
+/// `jq` does by wrapping each part in terminal color codes, and escaping
+/// characters which aren't ASCII.
+pub struct Styled<F> {
+    inner: F,
+    color: bool,
+    ascii: bool,
+    /// Whether the string being written is an object key
+    key: bool,
+}
+
+impl<F: Formatter> Styled<F> {
+    pub fn new(inner: F, color: bool, ascii: bool) -> Self {
+        Styled {
+            inner,
+            color,
+            ascii,
+            key: false,
+        }
+    }
+
+    /// Writes what `write` has the inner formatter write, in a color unless
+    /// it writes nothing.
+    fn paint<W, T>(&mut self, writer: &mut W, color: &str, write: T) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+        T: FnOnce(&mut F, &mut Vec<u8>) -> io::Result<()>,
+    {
+        let mut text = Vec::new();
+        write(&mut self.inner, &mut text)?;
+        if !self.color || text.is_empty() {
+            return writer.write_all(&text);
+        }
+        write!(writer, "\x1b[{}m", color)?;
+        writer.write_all(&text)?;
+        writer.write_all(b"\x1b[0m")
+    }
+}
+
+/// Writes text with every character which isn't ASCII escaped as `\uXXXX`,
+/// as a surrogate pair outside the Basic Multilingual Plane.
+pub fn write_ascii<W: ?Sized + Write>(writer: &mut W, text: &str) -> io::Result<()> {
+    let mut rest = text;
+    while let Some(i) = rest.find(|c: char| !c.is_ascii()) {
+        writer.write_all(&rest.as_bytes()[..i])?;
+        let c = rest[i..].chars().next().unwrap_or_default();
+        for unit in c.encode_utf16(&mut [0; 2]) {
+            write!(writer, "\\u{:04x}", unit)?;
+        }
+        rest = &rest[i + c.len_utf8()..];
+    }
+    writer.write_all(rest.as_bytes())
+}
+
+impl<F: Formatter> Formatter for Styled<F> {
+    fn write_null<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.paint(writer, NULL, |f, w| f.write_null(w))
+    }
+
+    fn write_bool<W: ?Sized + Write>(&mut self, writer: &mut W, value: bool) -> io::Result<()> {
+        let color = if value { TRUE } else { FALSE };
+        self.paint(writer, color, |f, w| f.write_bool(w, value))
+    }
+
+    fn write_i64<W: ?Sized + Write>(&mut self, writer: &mut W, value: i64) -> io::Result<()> {
+        self.paint(writer, NUMBER, |f, w| f.write_i64(w, value))
+    }
+
+    fn write_u64<W: ?Sized + Write>(&mut self, writer: &mut W, value: u64) -> io::Result<()> {
+        self.paint(writer, NUMBER, |f, w| f.write_u64(w, value))
+    }
+
+    fn write_f64<W: ?Sized + Write>(&mut self, writer: &mut W, value: f64) -> io::Result<()> {
+        self.paint(writer, NUMBER, |f, w| f.write_f64(w, value))
+    }
+
+    fn write_number_str<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        value: &str,
+    ) -> io::Result<()> {
+        self.paint(writer, NUMBER, |f, w| f.write_number_str(w, value))
+    }
+
+    fn begin_string<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        if self.color {
+            let color = if self.key { KEY } else { STRING };
+            write!(writer, "\x1b[{}m", color)?;
+        }
+        self.inner.begin_string(writer)
+    }
+
+    fn end_string<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_string(writer)?;
+        match self.color {
+            true => writer.write_all(b"\x1b[0m"),
+            false => Ok(()),
+        }
+    }
+
+    fn write_string_fragment<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        fragment: &str,
+    ) -> io::Result<()> {
+        match self.ascii {
+            true => write_ascii(writer, fragment),
+            false => self.inner.write_string_fragment(writer, fragment),
+        }
+    }
+
+    fn write_char_escape<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        char_escape: CharEscape,
+    ) -> io::Result<()> {
+        self.inner.write_char_escape(writer, char_escape)
+    }
+
+    fn begin_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.paint(writer, ARRAY, |f, w| f.begin_array(w))
+    }
+
+    fn end_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.paint(writer, ARRAY, |f, w| f.end_array(w))
+    }
+
+    fn begin_array_value<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        self.paint(writer, ARRAY, |f, w| f.begin_array_value(w, first))
+    }
+
+    fn end_array_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_array_value(writer)
+    }
+
+    fn begin_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.paint(writer, OBJECT, |f, w| f.begin_object(w))
+    }
+
+    fn end_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.paint(writer, OBJECT, |f, w| f.end_object(w))
+    }
+
+    fn begin_object_key<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        self.key = true;
+        self.paint(writer, OBJECT, |f, w| f.begin_object_key(w, first))
+    }
+
+    fn end_object_key<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.key = false;
+        self.inner.end_object_key(writer)
+    }
+
+    fn begin_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.paint(writer, OBJECT, |f, w| f.begin_object_value(w))
+    }
+
+    fn end_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_object_value(writer)
+    }
+
+    fn write_raw_fragment<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        fragment: &str,
+    ) -> io::Result<()> {
+        self.inner.write_raw_fragment(writer, fragment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_core::Serialize;
+    use serde_json::{json, ser::CompactFormatter, Serializer, Value};
+
+    use super::*;
+
+    fn styled(value: Value, color: bool, ascii: bool) -> String {
+        let mut output = Vec::new();
+        let formatter = Styled::new(CompactFormatter, color, ascii);
+        value
+            .serialize(&mut Serializer::with_formatter(&mut output, formatter))
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn colored() {
+        assert_eq!(
+            "\x1b[1;39m{\x1b[0m\x1b[34;1m\"a\"\x1b[0m\x1b[1;39m:\x1b[0m\
+             \x1b[1;39m[\x1b[0m\x1b[1;30mnull\x1b[0m\
+             \x1b[1;39m,\x1b[0m\x1b[0;32m\"b\"\x1b[0m\x1b[1;39m]\x1b[0m\
+             \x1b[1;39m}\x1b[0m",
+            styled(json!({"a": [null, "b"]}), true, false)
+        );
+        assert_eq!(
+            "{\"a\":[null,\"b\"]}",
+            styled(json!({"a": [null, "b"]}), false, false)
+        );
+    }
+
+    #[test]
+    fn ascii() {
+        assert_eq!(
+            "{\"\\u00e9\":\"a\\u00e9b\\ud83d\\ude00\\n\"}",
+            styled(json!({"é": "aéb😀\n"}), false, true)
+        );
+        let mut output = Vec::new();
+        write_ascii(&mut output, "ü-").unwrap();
+        assert_eq!(b"\\u00fc-".to_vec(), output);
+    }
+}
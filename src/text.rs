@@ -0,0 +1,63 @@
+use crate::{
+    parse::ParseOptions,
+    query::{Executable, Query},
+};
+
+/// A parsed query which takes and produces nothing but text, for hosts such
+/// as a JavaScript runtime that only exchange strings with the engine.
+///
+/// Errors are returned as their messages, including which part of the query
+/// failed.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    query: Query,
+}
+
+impl Filter {
+    pub fn new(query: &str) -> Result<Filter, String> {
+        let options = ParseOptions {
+            spans: true,
+            ..Default::default()
+        };
+        let query = Query::parse_with(query, &options).map_err(|e| e.to_string())?;
+        Ok(Filter { query })
+    }
+
+    /// Executes against the JSON text `input`, producing each result as
+    /// compact JSON on its own line.
+    pub fn run(&self, input: &str) -> Result<String, String> {
+        let results = self.query.execute(input).map_err(|e| e.to_string())?;
+        Ok(results.iter().map(|v| format!("{}\n", v)).collect())
+    }
+}
+
+/// Parses `query` and runs it once against `input`, as [`Filter::run`] does.
+pub fn run(query: &str, input: &str) -> Result<String, String> {
+    Filter::new(query)?.run(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_text() {
+        assert_eq!(
+            Ok("1\n[2]\n".to_string()),
+            run(".a, [.b]", r#"{"a": 1, "b": 2}"#)
+        );
+        assert_eq!(Ok(String::new()), run(".[]", "[]"));
+
+        let filter = Filter::new(".a.b").unwrap();
+        assert_eq!(Ok("null\n".to_string()), filter.run("{}"));
+        assert_eq!(
+            Err(r#"Cannot index number (1) with "b" (at .a) at 2..4 of the query"#.to_string()),
+            filter.run(r#"{"a": 1}"#)
+        );
+        assert!(filter
+            .run("{")
+            .unwrap_err()
+            .starts_with("Cannot parse input"));
+        assert!(Filter::new(".a |").is_err());
+    }
+}
@@ -0,0 +1,778 @@
+use nom::{
+    branch::alt,
+    bytes::complete::{is_not, tag, take_while, take_while1, take_while_m_n},
+    character::complete::{char, one_of},
+    combinator::{map, map_opt, map_res, opt, recognize, value, verify},
+    error::{make_error, ErrorKind},
+    multi::{many0, separated_list1},
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    IResult,
+};
+use serde_json::{Map, Number, Value};
+use std::{collections::HashSet, fmt::Write, ops::RangeInclusive};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TomlError {
+    #[error("Invalid TOML at line {0}: {1}")]
+    Syntax(usize, String),
+    #[error("Cannot define {0} more than once")]
+    Duplicate(String),
+    #[error("Cannot write {0} as TOML")]
+    Unsupported(String),
+    #[error("TOML nested more than {} deep", MAX_DEPTH)]
+    Depth,
+}
+
+/// How many tables and arrays deep a value can be, as deep as serde_json
+/// reads JSON.
+const MAX_DEPTH: usize = 128;
+
+/// Reads a TOML document as an object, with dates and times as strings.
+pub fn from_str(text: &str) -> Result<Value, TomlError> {
+    let mut root = Map::new();
+    // Every table defined by a header, which can't be defined again
+    let mut defined = HashSet::new();
+    let mut current: Vec<String> = Vec::new();
+    let mut input = text;
+    loop {
+        input = blank(input);
+        if input.is_empty() {
+            return Ok(Value::Object(root));
+        }
+        let depth = current.len();
+        let (rest, line) =
+            terminated(|i| parse_line(i, depth), end_of_line)(input).map_err(|e| match e {
+                nom::Err::Failure(e) if e.code == ErrorKind::TooLarge => TomlError::Depth,
+                _ => {
+                    // The whole statement which is invalid
+                    let line = text[..text.len() - input.len()].matches('\n').count() + 1;
+                    let found = input.lines().next().unwrap_or_default().to_string();
+                    TomlError::Syntax(line, found)
+                }
+            })?;
+        match line {
+            Line::Table(path) | Line::ArrayTable(path) if path.len() > MAX_DEPTH => {
+                return Err(TomlError::Depth)
+            }
+            Line::Table(path) => {
+                if !defined.insert(path.clone()) {
+                    return Err(TomlError::Duplicate(path.join(".")));
+                }
+                table(&mut root, &path)?;
+                current = path;
+            }
+            Line::ArrayTable(path) => {
+                let parent = table(&mut root, &path[..path.len() - 1])?;
+                let last = &path[path.len() - 1];
+                match parent
+                    .entry(last.clone())
+                    .or_insert_with(|| Value::Array(Vec::new()))
+                {
+                    Value::Array(tables) => tables.push(Value::Object(Map::new())),
+                    _ => return Err(TomlError::Duplicate(path.join("."))),
+                }
+                // Tables within each element are new for every element
+                defined.retain(|d: &Vec<String>| !d.starts_with(&path));
+                current = path;
+            }
+            Line::KeyValue(key, v) => insert(table(&mut root, &current)?, &key, v)?,
+        }
+        input = rest;
+    }
+}
+
+enum Line {
+    Table(Vec<String>),
+    ArrayTable(Vec<String>),
+    KeyValue(Vec<String>, Value),
+}
+
+/// A line within a table `depth` tables deep.
+fn parse_line(input: &str, depth: usize) -> IResult<&str, Line> {
+    alt((
+        map(delimited(tag("[["), key, tag("]]")), Line::ArrayTable),
+        map(delimited(char('['), key, char(']')), Line::Table),
+        map(|i| key_value(i, depth), |(k, v)| Line::KeyValue(k, v)),
+    ))(input)
+}
+
+/// Fails for good once a value is more than `MAX_DEPTH` deep, rather than
+/// recursing on and on.
+fn nest(input: &str, depth: usize) -> IResult<&str, usize> {
+    match depth < MAX_DEPTH {
+        true => Ok((input, depth + 1)),
+        false => Err(nom::Err::Failure(make_error(input, ErrorKind::TooLarge))),
+    }
+}
+
+/// The table at a path, creating any missing along the way, where a path
+/// through an array of tables leads to its last element.
+fn table<'m>(
+    root: &'m mut Map<String, Value>,
+    path: &[String],
+) -> Result<&'m mut Map<String, Value>, TomlError> {
+    path.iter().try_fold(root, |table, key| {
+        let next = table
+            .entry(key.clone())
+            .or_insert_with(|| Value::Object(Map::new()));
+        let next = match next {
+            Value::Array(tables) => tables.last_mut(),
+            next => Some(next),
+        };
+        match next {
+            Some(Value::Object(next)) => Ok(next),
+            _ => Err(TomlError::Duplicate(path.join("."))),
+        }
+    })
+}
+
+fn insert(table: &mut Map<String, Value>, key: &[String], v: Value) -> Result<(), TomlError> {
+    // Keys are never empty, as they are parsed
+    let parent = self::table(table, &key[..key.len() - 1])?;
+    let last = &key[key.len() - 1];
+    match parent.contains_key(last) {
+        true => Err(TomlError::Duplicate(key.join("."))),
+        false => {
+            parent.insert(last.clone(), v);
+            Ok(())
+        }
+    }
+}
+
+fn space(input: &str) -> &str {
+    input.trim_start_matches([' ', '\t'])
+}
+
+fn spaced<'a, O, F>(f: F) -> impl FnMut(&'a str) -> IResult<&'a str, O>
+where
+    F: FnMut(&'a str) -> IResult<&'a str, O>,
+{
+    delimited(
+        take_while(|c| c == ' ' || c == '\t'),
+        f,
+        take_while(|c| c == ' ' || c == '\t'),
+    )
+}
+
+fn comment(input: &str) -> IResult<&str, &str> {
+    recognize(pair(char('#'), opt(is_not("\n"))))(input)
+}
+
+/// Skips whitespace, comments and blank lines.
+fn blank(mut input: &str) -> &str {
+    loop {
+        let rest = space(input);
+        let rest = comment(rest).map_or(rest, |(rest, _)| rest);
+        let rest = rest
+            .strip_prefix("\r\n")
+            .or_else(|| rest.strip_prefix('\n'))
+            .unwrap_or(rest);
+        if rest.len() == input.len() {
+            return rest;
+        }
+        input = rest;
+    }
+}
+
+fn end_of_line(input: &str) -> IResult<&str, ()> {
+    let (input, _) = take_while(|c| c == ' ' || c == '\t')(input)?;
+    let (input, _) = opt(comment)(input)?;
+    if input.is_empty() {
+        return Ok((input, ()));
+    }
+    value((), alt((tag("\r\n"), tag("\n"))))(input)
+}
+
+/// A dotted key, such as `a."b.c".d`.
+fn key(input: &str) -> IResult<&str, Vec<String>> {
+    separated_list1(
+        char('.'),
+        spaced(alt((
+            map(
+                take_while1(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+                String::from,
+            ),
+            basic_string,
+            map(literal_string, String::from),
+        ))),
+    )(input)
+}
+
+/// A key and its value, within a table `depth` deep.
+fn key_value(input: &str, depth: usize) -> IResult<&str, (Vec<String>, Value)> {
+    let (input, k) = key(input)?;
+    // Each part of a dotted key is a table of its own
+    let (input, depth) = nest(input, depth + k.len() - 1)?;
+    let (input, _) = char('=')(input)?;
+    let (input, v) = spaced(|i| parse_value(i, depth))(input)?;
+    Ok((input, (k, v)))
+}
+
+/// A value within `depth` tables and arrays.
+fn parse_value(input: &str, depth: usize) -> IResult<&str, Value> {
+    alt((
+        map(
+            alt((
+                multiline_basic_string,
+                basic_string,
+                multiline_literal_string,
+            )),
+            Value::String,
+        ),
+        map(literal_string, Value::from),
+        value(Value::Bool(true), tag("true")),
+        value(Value::Bool(false), tag("false")),
+        map(date_time, Value::from),
+        number,
+        |i| array(i, depth),
+        |i| inline_table(i, depth),
+    ))(input)
+}
+
+fn escape(input: &str) -> IResult<&str, char> {
+    let hex = |n| {
+        map_opt(
+            take_while_m_n(n, n, |c: char| c.is_ascii_hexdigit()),
+            |h: &str| u32::from_str_radix(h, 16).ok().and_then(char::from_u32),
+        )
+    };
+    preceded(
+        char('\\'),
+        alt((
+            value('\u{8}', char('b')),
+            value('\t', char('t')),
+            value('\n', char('n')),
+            value('\u{c}', char('f')),
+            value('\r', char('r')),
+            value('"', char('"')),
+            value('\\', char('\\')),
+            preceded(char('u'), hex(4)),
+            preceded(char('U'), hex(8)),
+        )),
+    )(input)
+}
+
+fn basic_string(input: &str) -> IResult<&str, String> {
+    let (mut input, _) = char('"')(input)?;
+    let mut output = String::new();
+    loop {
+        let (rest, part) = take_while(|c| c != '"' && c != '\\' && c != '\n')(input)?;
+        output.push_str(part);
+        input = rest;
+        match input.chars().next() {
+            Some('"') => return Ok((&input[1..], output)),
+            Some('\\') => {
+                let (rest, c) = escape(input)?;
+                output.push(c);
+                input = rest;
+            }
+            _ => {
+                return Err(nom::Err::Error(nom::error::make_error(
+                    input,
+                    nom::error::ErrorKind::Char,
+                )))
+            }
+        }
+    }
+}
+
+fn multiline_basic_string(input: &str) -> IResult<&str, String> {
+    let (input, _) = tag("\"\"\"")(input)?;
+    let mut input = input
+        .strip_prefix("\r\n")
+        .or_else(|| input.strip_prefix('\n'))
+        .unwrap_or(input);
+    let mut output = String::new();
+    loop {
+        let (rest, part) = take_while(|c| c != '"' && c != '\\')(input)?;
+        output.push_str(part);
+        input = rest;
+        if let Some(rest) = input.strip_prefix("\"\"\"") {
+            // Up to two quotes may come just before the closing ones
+            let extra = rest.len() - rest.trim_start_matches('"').len();
+            output.push_str(&rest[..extra.min(2)]);
+            return Ok((&rest[extra.min(2)..], output));
+        }
+        if let Some(rest) = input.strip_prefix('"') {
+            output.push('"');
+            input = rest;
+            continue;
+        }
+        let after = input.strip_prefix('\\').unwrap_or(input);
+        let trimmed = after.trim_start_matches([' ', '\t']);
+        if trimmed.starts_with('\n') || trimmed.starts_with("\r\n") {
+            // A backslash ending a line joins it to the next text
+            input = after.trim_start();
+            continue;
+        }
+        let (rest, c) = escape(input)?;
+        output.push(c);
+        input = rest;
+    }
+}
+
+fn literal_string(input: &str) -> IResult<&str, &str> {
+    delimited(
+        char('\''),
+        take_while(|c| c != '\'' && c != '\n'),
+        char('\''),
+    )(input)
+}
+
+fn multiline_literal_string(input: &str) -> IResult<&str, String> {
+    let (input, _) = tag("'''")(input)?;
+    let input = input
+        .strip_prefix("\r\n")
+        .or_else(|| input.strip_prefix('\n'))
+        .unwrap_or(input);
+    match input.find("'''") {
+        Some(end) => {
+            let rest = &input[end + 3..];
+            let extra = (rest.len() - rest.trim_start_matches('\'').len()).min(2);
+            Ok((&rest[extra..], input[..end + extra].to_string()))
+        }
+        None => Err(nom::Err::Error(nom::error::make_error(
+            input,
+            nom::error::ErrorKind::Tag,
+        ))),
+    }
+}
+
+fn digits<'a>(n: usize) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    take_while_m_n(n, n, |c: char| c.is_ascii_digit())
+}
+
+/// `n` digits, as a number within `range`.
+fn field<'a>(n: usize, range: RangeInclusive<u32>) -> impl FnMut(&'a str) -> IResult<&'a str, u32> {
+    verify(map_res(digits(n), str::parse), move |v| range.contains(v))
+}
+
+fn days_in(year: u32, month: u32) -> u32 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// A time of day, where the seconds may be a leap second.
+fn time(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        field(2, 0..=23),
+        char(':'),
+        field(2, 0..=59),
+        char(':'),
+        field(2, 0..=60),
+        opt(pair(char('.'), take_while1(|c: char| c.is_ascii_digit()))),
+    )))(input)
+}
+
+/// A date, time or both, which are kept as the text of them.
+fn date_time(input: &str) -> IResult<&str, &str> {
+    let offset = alt((
+        recognize(one_of("Zz")),
+        recognize(tuple((
+            one_of("+-"),
+            field(2, 0..=23),
+            char(':'),
+            field(2, 0..=59),
+        ))),
+    ));
+    let date = verify(
+        tuple((
+            field(4, 0..=9999),
+            char('-'),
+            field(2, 1..=12),
+            char('-'),
+            field(2, 1..=31),
+        )),
+        |&(year, _, month, _, day)| day <= days_in(year, month),
+    );
+    alt((
+        recognize(tuple((
+            date,
+            opt(tuple((one_of("Tt "), time, opt(offset)))),
+        ))),
+        time,
+    ))(input)
+}
+
+fn number(input: &str) -> IResult<&str, Value> {
+    let (rest, text) =
+        take_while1(|c: char| c.is_ascii_alphanumeric() || "+-._".contains(c))(input)?;
+    let fail = || nom::Err::Error(nom::error::make_error(input, nom::error::ErrorKind::Digit));
+    let clean = text.replace('_', "");
+    let (sign, unsigned) = match clean.strip_prefix('-') {
+        Some(unsigned) => (-1, unsigned),
+        None => (1, clean.strip_prefix('+').unwrap_or(&clean)),
+    };
+    let radix = [("0x", 16), ("0o", 8), ("0b", 2)]
+        .iter()
+        .find_map(|(prefix, radix)| unsigned.strip_prefix(prefix).map(|d| (d, *radix)));
+    let n = match radix {
+        Some((digits, radix)) if sign == 1 => {
+            i64::from_str_radix(digits, radix).map(Value::from).ok()
+        }
+        Some(_) => None,
+        None if unsigned.contains(|c| ".eE".contains(c))
+            || unsigned == "inf"
+            || unsigned == "nan" =>
+        {
+            clean
+                .parse::<f64>()
+                .ok()
+                .and_then(Number::from_f64)
+                .map(Value::Number)
+        }
+        None => clean.parse::<i64>().map(Value::from).ok(),
+    };
+    n.map(|n| (rest, n)).ok_or_else(fail)
+}
+
+/// Skips whitespace, comments and newlines within an array.
+fn gap(input: &str) -> IResult<&str, ()> {
+    Ok((blank(input), ()))
+}
+
+fn array(input: &str, depth: usize) -> IResult<&str, Value> {
+    let (input, _) = pair(char('['), gap)(input)?;
+    let (input, depth) = nest(input, depth)?;
+    let (input, values) = many0(terminated(
+        |i| parse_value(i, depth),
+        tuple((gap, opt(char(',')), gap)),
+    ))(input)?;
+    let (input, _) = char(']')(input)?;
+    Ok((input, Value::Array(values)))
+}
+
+fn inline_table(input: &str, depth: usize) -> IResult<&str, Value> {
+    let (input, _) = pair(char('{'), take_while(|c| c == ' ' || c == '\t'))(input)?;
+    let (input, depth) = nest(input, depth)?;
+    let (input, entries) = terminated(
+        opt(separated_list1(char(','), spaced(|i| key_value(i, depth)))),
+        char('}'),
+    )(input)?;
+    let mut table = Map::new();
+    for (k, v) in entries.unwrap_or_default() {
+        if insert(&mut table, &k, v).is_err() {
+            return Err(nom::Err::Failure(nom::error::make_error(
+                input,
+                nom::error::ErrorKind::Verify,
+            )));
+        }
+    }
+    Ok((input, Value::Object(table)))
+}
+
+/// Writes an object as a TOML document, with each object within it as a
+/// table and each array of objects as an array of tables.
+pub fn to_string(value: &Value) -> Result<String, TomlError> {
+    let table = match value {
+        Value::Object(table) => table,
+        v => return Err(TomlError::Unsupported(crate::describe(v))),
+    };
+    let mut output = String::new();
+    write_table(&mut output, &mut Vec::new(), table)?;
+    Ok(output)
+}
+
+fn is_tables(v: &Value) -> bool {
+    matches!(v, Value::Array(a) if !a.is_empty() && a.iter().all(Value::is_object))
+}
+
+fn write_table(
+    output: &mut String,
+    path: &mut Vec<String>,
+    table: &Map<String, Value>,
+) -> Result<(), TomlError> {
+    for (k, v) in table {
+        if !v.is_object() && !is_tables(v) {
+            write_key(output, k);
+            output.push_str(" = ");
+            write_inline(output, v)?;
+            output.push('\n');
+        }
+    }
+    for (k, v) in table {
+        path.push(k.clone());
+        match v {
+            Value::Object(t) => {
+                write_header(output, path, "[", "]");
+                write_table(output, path, t)?;
+            }
+            Value::Array(tables) if is_tables(v) => {
+                for t in tables.iter().filter_map(Value::as_object) {
+                    write_header(output, path, "[[", "]]");
+                    write_table(output, path, t)?;
+                }
+            }
+            _ => {}
+        }
+        path.pop();
+    }
+    Ok(())
+}
+
+fn write_header(output: &mut String, path: &[String], open: &str, close: &str) {
+    if !output.is_empty() {
+        output.push('\n');
+    }
+    output.push_str(open);
+    for (i, k) in path.iter().enumerate() {
+        if i > 0 {
+            output.push('.');
+        }
+        write_key(output, k);
+    }
+    output.push_str(close);
+    output.push('\n');
+}
+
+fn write_key(output: &mut String, key: &str) {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    match bare {
+        true => output.push_str(key),
+        false => write_string(output, key),
+    }
+}
+
+fn write_string(output: &mut String, s: &str) {
+    output.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\t' => output.push_str("\\t"),
+            '\r' => output.push_str("\\r"),
+            c if c.is_control() => {
+                let _ = write!(output, "\\u{:04X}", c as u32);
+            }
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+fn write_inline(output: &mut String, v: &Value) -> Result<(), TomlError> {
+    match v {
+        Value::Null => return Err(TomlError::Unsupported("null".to_string())),
+        Value::Bool(b) => output.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => output.push_str(&n.to_string()),
+        Value::String(s) => write_string(output, s),
+        Value::Array(a) => {
+            output.push('[');
+            for (i, v) in a.iter().enumerate() {
+                if i > 0 {
+                    output.push_str(", ");
+                }
+                write_inline(output, v)?;
+            }
+            output.push(']');
+        }
+        Value::Object(o) => {
+            output.push('{');
+            for (i, (k, v)) in o.iter().enumerate() {
+                output.push_str(if i > 0 { ", " } else { " " });
+                write_key(output, k);
+                output.push_str(" = ");
+                write_inline(output, v)?;
+            }
+            output.push_str(if o.is_empty() { "}" } else { " }" });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn read_toml() {
+        let text = r#"
+# A package
+title = "rq" # trailing
+"quoted key" = 'C:\path'
+dotted.key = 0x1f
+numbers = [1_000, -2, +3.5, 1e2, 0b101,
+    0o17, # within
+]
+text = """
+first \
+    second\tthird"""
+literal = '''
+raw \n'''
+when = 1979-05-27T07:32:00Z
+day = 1979-05-27
+inline = { a = true, b.c = false }
+
+[package]
+name = "rq"
+
+[package.metadata]
+empty = []
+
+[[bin]]
+name = "a"
+
+[[bin]]
+name = "b"
+
+[bin.extra]
+x = 1
+"#;
+        assert_eq!(
+            json!({
+                "title": "rq",
+                "quoted key": "C:\\path",
+                "dotted": {"key": 31},
+                "numbers": [1000, -2, 3.5, 100.0, 5, 15],
+                "text": "first second\tthird",
+                "literal": "raw \\n",
+                "when": "1979-05-27T07:32:00Z",
+                "day": "1979-05-27",
+                "inline": {"a": true, "b": {"c": false}},
+                "package": {"name": "rq", "metadata": {"empty": []}},
+                "bin": [{"name": "a"}, {"name": "b", "extra": {"x": 1}}],
+            }),
+            from_str(text).unwrap()
+        );
+        assert_eq!(json!({}), from_str("").unwrap());
+
+        assert_eq!(
+            Err(TomlError::Syntax(2, "b = ".to_string())),
+            from_str("a = 1\nb = ")
+        );
+        assert_eq!(
+            Err(TomlError::Syntax(1, "a = 1 b = 2".to_string())),
+            from_str("a = 1 b = 2")
+        );
+        assert_eq!(
+            Err(TomlError::Duplicate("a".to_string())),
+            from_str("a = 1\na = 2")
+        );
+        assert_eq!(
+            Err(TomlError::Duplicate("t".to_string())),
+            from_str("[t]\n[t]")
+        );
+        assert_eq!(
+            Err(TomlError::Duplicate("a.b".to_string())),
+            from_str("a = 1\n[a.b]")
+        );
+    }
+
+    #[test]
+    fn date_time() {
+        let when = |s: &str| from_str(&format!("a = {}", s)).map(|v| v["a"].clone());
+        for valid in [
+            "2020-02-29",
+            "1979-05-27T23:59:60.5-07:30",
+            "1979-12-31 00:00:00z",
+            "07:32:00",
+        ] {
+            assert_eq!(Ok(json!(valid)), when(valid));
+        }
+        for invalid in [
+            "1979-13-01",
+            "1979-00-01",
+            "2021-02-29",
+            "1900-02-29",
+            "1979-04-31",
+            "1979-05-27T24:00:00",
+            "1979-05-27T07:60:00",
+            "07:32:61",
+            "1979-05-27T07:32:00+24:00",
+        ] {
+            assert_eq!(
+                Err(TomlError::Syntax(1, format!("a = {}", invalid))),
+                when(invalid)
+            );
+        }
+    }
+
+    #[test]
+    fn depth() {
+        // The document is itself a table
+        let deepest = (0..MAX_DEPTH - 2).fold(json!([]), |v, _| json!([v]));
+        let nested = |open: &str, close: &str, n: usize| {
+            format!("a = {}{}", open.repeat(n), close.repeat(n))
+        };
+        assert_eq!(
+            Ok(json!({ "a": deepest })),
+            from_str(&nested("[", "]", MAX_DEPTH - 1))
+        );
+        assert_eq!(
+            Err(TomlError::Depth),
+            from_str(&nested("[", "]", MAX_DEPTH))
+        );
+        // Too deep to recurse into at all
+        assert_eq!(Err(TomlError::Depth), from_str(&nested("[", "]", 200_000)));
+        assert_eq!(
+            Err(TomlError::Depth),
+            from_str(&nested("{b=", "}", 200_000))
+        );
+        // Dotted keys and headers nest tables too
+        let dotted = vec!["b"; 200_000].join(".");
+        assert_eq!(Err(TomlError::Depth), from_str(&format!("{} = 1", dotted)));
+        assert_eq!(Err(TomlError::Depth), from_str(&format!("[{}]", dotted)));
+        assert_eq!(
+            Err(TomlError::Depth),
+            from_str(&format!(
+                "[{}]\n{}",
+                &dotted[..199],
+                nested("[", "]", MAX_DEPTH - 1)
+            ))
+        );
+    }
+
+    #[test]
+    fn write_toml() {
+        let v = json!({
+            "name": "rq",
+            "odd key": "a\"b\n",
+            "list": [1, 2.5, {"x": []}],
+            "package": {"version": "0.1.0", "inner": {"deep": true}},
+            "bin": [{"name": "a"}, {"name": "b"}],
+        });
+        let text = to_string(&v).unwrap();
+        assert_eq!(
+            r#"list = [1, 2.5, { x = [] }]
+name = "rq"
+"odd key" = "a\"b\n"
+
+[[bin]]
+name = "a"
+
+[[bin]]
+name = "b"
+
+[package]
+version = "0.1.0"
+
+[package.inner]
+deep = true
+"#,
+            text
+        );
+        assert_eq!(v, from_str(&text).unwrap());
+
+        assert_eq!(
+            Err(TomlError::Unsupported("null".to_string())),
+            to_string(&json!({"a": null}))
+        );
+        assert_eq!(
+            Err(TomlError::Unsupported("array ([1])".to_string())),
+            to_string(&json!([1]))
+        );
+    }
+}
@@ -0,0 +1,476 @@
+use std::{borrow::Cow, convert::TryFrom};
+
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::char,
+    combinator::{not, opt, value},
+    sequence::{pair, terminated},
+    IResult,
+};
+use serde_json::{Map, Value};
+
+use crate::{
+    describe,
+    env::Env,
+    index::{position, Index},
+    operators::parse_compare,
+    parse::{ParseError, Parseable},
+    query::{self, exactly_one, Eval, Query},
+    range::Range,
+    single, space,
+    span::spanned,
+    QueryError, QueryResult,
+};
+
+/// The operator of an [`Update`].
+#[derive(Debug, PartialEq, Clone)]
+pub enum Assign {
+    /// `path = value`, setting every path to each output of `value` on the input
+    Set,
+    /// `path |= f`, replacing the value at every path with the first output of `f` on it
+    Modify,
+}
+
+impl Parseable for Assign {
+    fn parser(input: &str) -> IResult<&str, Self, ParseError> {
+        space::around(alt((
+            value(Assign::Modify, tag("|=")),
+            value(Assign::Set, terminated(char('='), not(char('=')))),
+        )))(input)
+    }
+}
+
+/// An assignment to the paths `path` finds in the input, producing the changed input.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Update {
+    pub path: Query,
+    pub assign: Assign,
+    pub value: Query,
+}
+
+impl Update {
+    /// The paths to assign to, without copying the values found at them
+    /// where the path query allows.
+    fn paths<'a>(&'a self, env: &Env<'a>, value: &Value) -> Result<Vec<Vec<Value>>, QueryError> {
+        match query::paths(&self.path, value) {
+            Some(paths) => Ok(paths),
+            None => Ok(self
+                .path
+                .eval_paths(env, value)?
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect()),
+        }
+    }
+
+    fn modify<'a>(
+        &'a self,
+        env: &Env<'a>,
+        paths: Vec<Vec<Value>>,
+        output: &mut Value,
+    ) -> Result<(), QueryError> {
+        let mut removed = Vec::new();
+        for path in paths {
+            match self
+                .value
+                .eval(env, &*get(output, &path)?)?
+                .into_iter()
+                .next()
+            {
+                Some(new) => set(env, output, &path, new)?,
+                None => removed.push(path),
+            }
+        }
+        // Last first, so removing from an array leaves earlier positions intact
+        for path in removed.iter().rev() {
+            delete(output, path)?;
+        }
+        Ok(())
+    }
+
+    fn apply<'a>(&'a self, env: &Env<'a>, value: &mut Value) -> Result<(), QueryError> {
+        let paths = self.paths(env, value)?;
+        match self.assign {
+            Assign::Set => {
+                let new = exactly_one(self.value.eval(env, value)?)?;
+                for path in &paths {
+                    set(env, value, path, new.clone())?;
+                }
+                Ok(())
+            }
+            Assign::Modify => self.modify(env, paths, value),
+        }
+    }
+}
+
+impl Eval for Update {
+    fn eval<'a>(&'a self, env: &Env<'a>, value: &Value) -> QueryResult {
+        let paths = self.paths(env, value)?;
+        match self.assign {
+            Assign::Set => self
+                .value
+                .eval(env, value)?
+                .into_iter()
+                .map(|new| {
+                    let mut output = value.clone();
+                    for path in &paths {
+                        set(env, &mut output, path, new.clone())?;
+                    }
+                    Ok(output)
+                })
+                .collect(),
+            Assign::Modify => {
+                let mut output = value.clone();
+                self.modify(env, paths, &mut output)?;
+                single(output)
+            }
+        }
+    }
+}
+
+impl Query {
+    /// Replaces `value` with the single output of the query, changing it in
+    /// place rather than copying it for assignments such as `.users[].active = 1`
+    /// and pipes of them.
+    pub fn apply(&self, value: &mut Value) -> Result<(), QueryError> {
+        apply(self, &Env::default(), value)
+    }
+}
+
+fn apply<'a>(query: &'a Query, env: &Env<'a>, value: &mut Value) -> Result<(), QueryError> {
+    match query {
+        Query::Identity => Ok(()),
+        Query::Update(update) => update.apply(env, value),
+        Query::Chain(chain) => {
+            apply(&chain.0, env, value)?;
+            apply(&chain.1, env, value)
+        }
+        Query::Spanned(s) => apply(&s.query, env, value).map_err(|e| e.within(&s.span)),
+        q => {
+            *value = exactly_one(q.eval(env, value)?)?;
+            Ok(())
+        }
+    }
+}
+
+pub(crate) fn parse_update(input: &str) -> IResult<&str, Query, ParseError> {
+    spanned(parse_update_op)(input)
+}
+
+fn parse_update_op(input: &str) -> IResult<&str, Query, ParseError> {
+    let (input, path) = parse_compare(input)?;
+    let (input, opt) = opt(pair(Assign::parser, parse_compare))(input)?;
+
+    if let Some((assign, value)) = opt {
+        Ok((
+            input,
+            Query::Update(Box::new(Update {
+                path,
+                assign,
+                value,
+            })),
+        ))
+    } else {
+        Ok((input, path))
+    }
+}
+
+/// The index of one element of a path into `value`, which is a key, an
+/// array position or a slice given as `{"start": _, "end": _}`.
+fn step(p: &Value, value: &Value) -> Result<Index, QueryError> {
+    let integer = |v: &Value| v.as_i64().and_then(|i| i32::try_from(i).ok());
+    match p {
+        Value::String(s) => Ok(Index::String(s.clone())),
+        Value::Number(_) => match integer(p) {
+            Some(i) => Ok(Index::Integer(i)),
+            None => Err(QueryError::Index(describe(value), describe(p))),
+        },
+        Value::Object(slice) => {
+            let bound = |b: &str| slice.get(b).and_then(integer);
+            Ok(Index::Slice(Range(bound("start"), bound("end"))))
+        }
+        p => Err(QueryError::Index(describe(value), describe(p))),
+    }
+}
+
+/// The value at `path` within `value`, or null if there is none.
+pub(crate) fn get<'v>(value: &'v Value, path: &[Value]) -> Result<Cow<'v, Value>, QueryError> {
+    let mut current = Cow::Borrowed(value);
+    for p in path {
+        let index = step(p, &current)?;
+        current = match current {
+            Cow::Borrowed(v) => index.eval_ref(&Env::default(), v)?.pop(),
+            Cow::Owned(v) => index.eval_owned(&Env::default(), v)?.pop().map(Cow::Owned),
+        }
+        .unwrap_or(Cow::Owned(Value::Null));
+    }
+    Ok(current)
+}
+
+/// The largest position jq assigns to in an array, filling it with nulls
+/// up to there.
+const MAX_INDEX: usize = 536_870_911;
+
+/// Replaces the value at `path` within `value`, creating any objects and
+/// arrays missing along the way from nulls.
+pub(crate) fn set(
+    env: &Env,
+    value: &mut Value,
+    path: &[Value],
+    new: Value,
+) -> Result<(), QueryError> {
+    let (p, rest) = match path.split_first() {
+        Some(split) => split,
+        None => {
+            *value = new;
+            return Ok(());
+        }
+    };
+    let index = step(p, value)?;
+    if value.is_null() {
+        *value = match index {
+            Index::String(_) => Value::Object(Map::new()),
+            _ => Value::Array(Vec::new()),
+        };
+    }
+    if let (Value::Array(arr), Index::Integer(i)) = (&*value, &index) {
+        if *i < 0 && position(arr.len(), *i).is_none() {
+            return Err(QueryError::Index(describe(value), i.to_string()));
+        }
+    }
+    match (value, index) {
+        (Value::Object(map), Index::String(k)) => {
+            set(env, map.entry(k).or_insert(Value::Null), rest, new)
+        }
+        (Value::Array(arr), Index::Integer(i)) => {
+            let index = match position(arr.len(), i) {
+                Some(index) => index,
+                None => {
+                    let index = i as usize;
+                    if index > MAX_INDEX {
+                        return Err(QueryError::IndexTooLarge);
+                    }
+                    let nulls = index + 1 - arr.len();
+                    env.check_memory(nulls)?;
+                    arr.try_reserve_exact(nulls)
+                        .map_err(|_| QueryError::LimitExceeded("memory"))?;
+                    arr.resize(index + 1, Value::Null);
+                    index
+                }
+            };
+            set(env, &mut arr[index], rest, new)
+        }
+        (Value::Array(arr), Index::Slice(r)) => {
+            let range = r.within(arr.len());
+            let mut slice = Value::Array(arr[range.clone()].to_vec());
+            set(env, &mut slice, rest, new)?;
+            match slice {
+                Value::Array(items) => {
+                    arr.splice(range, items);
+                    Ok(())
+                }
+                v => Err(QueryError::Operation(
+                    "spliced",
+                    "a slice".to_string(),
+                    describe(&v),
+                )),
+            }
+        }
+        (v, _) => Err(QueryError::Index(describe(v), describe(p))),
+    }
+}
+
+/// Removes the value at `path` within `value`, if there is one.
+pub(crate) fn delete(value: &mut Value, path: &[Value]) -> Result<(), QueryError> {
+    let (p, rest) = match path.split_first() {
+        Some(split) => split,
+        None => {
+            *value = Value::Null;
+            return Ok(());
+        }
+    };
+    let index = step(p, value)?;
+    match (value, index) {
+        (Value::Null, _) => Ok(()),
+        (Value::Object(map), Index::String(k)) => {
+            if rest.is_empty() {
+                map.remove(&k);
+            } else if let Some(v) = map.get_mut(&k) {
+                delete(v, rest)?;
+            }
+            Ok(())
+        }
+        (Value::Array(arr), Index::Integer(i)) => {
+            if let Some(index) = position(arr.len(), i) {
+                if rest.is_empty() {
+                    arr.remove(index);
+                } else {
+                    delete(&mut arr[index], rest)?;
+                }
+            }
+            Ok(())
+        }
+        (Value::Array(arr), Index::Slice(r)) => {
+            let range = r.within(arr.len());
+            if rest.is_empty() {
+                arr.drain(range);
+            } else {
+                let mut slice = Value::Array(arr[range.clone()].to_vec());
+                delete(&mut slice, rest)?;
+                if let Value::Array(items) = slice {
+                    arr.splice(range, items);
+                }
+            }
+            Ok(())
+        }
+        (v, _) => Err(QueryError::Index(describe(v), describe(p))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::query::Executable;
+
+    use super::*;
+
+    fn update(s: &str, v: Value) -> Result<Vec<Value>, QueryError> {
+        s.parse::<Query>().unwrap().execute(&v)
+    }
+
+    #[test]
+    fn parse_update() {
+        assert!(".a = 1 = 2".parse::<Query>().is_err());
+        assert!(".a = = 1".parse::<Query>().is_err());
+        assert!(matches!(".a == 1".parse::<Query>(), Ok(Query::Op(_))));
+
+        assert_eq!(
+            Query::Update(Box::new(Update {
+                path: Query::Index(Index::String("a".to_string())),
+                assign: Assign::Modify,
+                value: Query::Raw(crate::raw::Raw(json!(1))),
+            })),
+            ".a |= 1".parse().unwrap()
+        );
+        assert!(matches!(
+            ".a = 1 | .b".parse().unwrap(),
+            Query::Chain(c) if matches!(c.0, Query::Update(_))
+        ));
+    }
+
+    #[test]
+    fn set() {
+        let v = json!({"a": [1, 2], "b": {"c": 3}});
+        assert_eq!(
+            vec![json!({"a": [5, 5], "b": {"c": 3}})],
+            update(".a[] = 5", v.clone()).unwrap()
+        );
+        assert_eq!(
+            vec![json!({"a": [1, 2], "b": {"c": 3, "d": [null, null, 4]}})],
+            update(".b.d[2] = 4", v.clone()).unwrap()
+        );
+        assert_eq!(
+            vec![
+                json!({"a": 3, "b": {"c": 3}}),
+                json!({"a": 4, "b": {"c": 3}})
+            ],
+            update(".a = (.b.c, 4)", v.clone()).unwrap()
+        );
+        assert_eq!(
+            vec![json!({"a": [9], "b": {"c": 3}})],
+            update(".a[0:] = [9]", v.clone()).unwrap()
+        );
+
+        assert!(update(".b[0] = 1", v.clone()).is_err());
+        assert!(update(".a[-3] = 1", v.clone()).is_err());
+        assert!(update("1 = 1", v).is_err());
+    }
+
+    #[test]
+    fn set_far_past_the_end() {
+        use crate::context::{Context, Limits};
+
+        assert!(matches!(
+            update(".[2147483647] = 1", Value::Null),
+            Err(QueryError::IndexTooLarge)
+        ));
+        assert!(matches!(
+            update("setpath([\"a\", 536870912]; 1)", Value::Null),
+            Err(QueryError::IndexTooLarge)
+        ));
+
+        // Nulls filling the array are bound by the memory limit before they are built
+        let mut context = Context::new();
+        context.limits(Limits {
+            memory: Some(100 * std::mem::size_of::<Value>()),
+            ..Default::default()
+        });
+        let update = |s: &str| {
+            s.parse::<Query>()
+                .unwrap()
+                .execute_with(&Value::Null, &context)
+        };
+        assert!(update(".[10] = 1").is_ok());
+        assert!(matches!(
+            update(".[100000000] = 1"),
+            Err(QueryError::LimitExceeded("memory"))
+        ));
+        assert!(matches!(
+            update("fromstream([[100000000], 1])"),
+            Err(QueryError::LimitExceeded("memory"))
+        ));
+    }
+
+    #[test]
+    fn modify() {
+        let v = json!({"a": [1, 2, 3], "b": "x"});
+        assert_eq!(
+            vec![json!({"a": [2, 3, 4], "b": "x"})],
+            update(".a[] |= . + 1", v.clone()).unwrap()
+        );
+        assert_eq!(
+            vec![json!({"a": [1, 2, 3]})],
+            update(".b |= (.a, .c)? | .b |= .[]?", v.clone()).unwrap()
+        );
+        assert_eq!(
+            vec![json!({"a": [], "b": "x"})],
+            update(".a[] |= .[]?", v.clone()).unwrap()
+        );
+        assert_eq!(
+            vec![json!({"a": [1, 2, "x", 3], "b": "x"})],
+            update(".a[2:1] = [\"x\"]", v.clone()).unwrap()
+        );
+        assert_eq!(
+            vec![json!({"a": [1, 2, 3], "b": "xy"})],
+            update(".b |= . + \"y\"", v).unwrap()
+        );
+    }
+
+    #[test]
+    fn apply() {
+        let mut v = json!({"users": [{"active": 0}, {"active": 1}], "n": 1});
+        let apply = |s: &str, v: &mut Value| s.parse::<Query>().unwrap().apply(v);
+
+        apply(".users[].active = 1 | .n |= . + 1", &mut v).unwrap();
+        assert_eq!(json!({"users": [{"active": 1}, {"active": 1}], "n": 2}), v);
+
+        apply(".users[1:] |= .[:0] | .users[0] = .n", &mut v).unwrap();
+        assert_eq!(json!({"users": [2], "n": 2}), v);
+
+        apply(".users", &mut v).unwrap();
+        assert_eq!(json!([2]), v);
+
+        assert!(matches!(
+            apply(".[0] = (1, 2)", &mut v),
+            Err(QueryError::Outputs(_, 2))
+        ));
+        assert!(matches!(
+            apply(".[]?", &mut json!(1)),
+            Err(QueryError::Outputs(_, 0))
+        ));
+        assert!(apply(".a = 1", &mut v).is_err());
+        assert_eq!(json!([2]), v);
+    }
+}
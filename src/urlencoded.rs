@@ -0,0 +1,216 @@
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum UrlencodedError {
+    #[error("Cannot use {0} as both a value and a container")]
+    Conflict(String),
+    #[error("Cannot write {0} as a query string")]
+    Unsupported(String),
+    #[error("Query string text is not UTF-8")]
+    Utf8,
+}
+
+/// Reads a form-encoded body or query string such as `a=1&b[]=x&b[]=y` as
+/// an object, with every value a string.
+///
+/// `a[b]=1` sets a key of an object under `a`, and `a[]=1` adds to an array
+/// under `a`. A key given more than once without brackets gathers its values
+/// into an array too.
+pub fn from_str(text: &str) -> Result<Value, UrlencodedError> {
+    let mut root = Value::Object(Map::new());
+    let text = text.trim_end_matches(['\r', '\n']);
+    for pair in text.split('&').filter(|p| !p.is_empty()) {
+        let (key, v) = match pair.split_once('=') {
+            Some((key, v)) => (decode(key)?, decode(v)?),
+            None => (decode(pair)?, String::new()),
+        };
+        insert(&mut root, &path(&key), Value::from(v), &key)?;
+    }
+    Ok(root)
+}
+
+/// Text with `+` as a space and each `%` escape as the byte it stands for,
+/// which together must be UTF-8.
+fn decode(text: &str) -> Result<String, UrlencodedError> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&b, after)) = rest.split_first() {
+        let escaped = match (b, after) {
+            (b'%', [high, low, ..]) => std::str::from_utf8(&[*high, *low])
+                .ok()
+                .and_then(|h| u8::from_str_radix(h, 16).ok()),
+            _ => None,
+        };
+        match (b, escaped) {
+            (_, Some(e)) => {
+                bytes.push(e);
+                rest = &after[2..];
+            }
+            (b'+', None) => {
+                bytes.push(b' ');
+                rest = after;
+            }
+            (b, None) => {
+                bytes.push(b);
+                rest = after;
+            }
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| UrlencodedError::Utf8)
+}
+
+/// The name and then each bracketed part of a key like `a[b][]`, or the key
+/// alone if its brackets aren't closed.
+fn path(key: &str) -> Vec<&str> {
+    let (name, mut rest) = match key.find('[') {
+        Some(i) if i > 0 && key.ends_with(']') => key.split_at(i),
+        _ => return vec![key],
+    };
+    let mut path = vec![name];
+    while let Some(inner) = rest.strip_prefix('[') {
+        match inner.find(']') {
+            Some(end) => {
+                path.push(&inner[..end]);
+                rest = &inner[end + 1..];
+            }
+            None => return vec![key],
+        }
+    }
+    match rest.is_empty() {
+        true => path,
+        false => vec![key],
+    }
+}
+
+fn insert(target: &mut Value, path: &[&str], v: Value, key: &str) -> Result<(), UrlencodedError> {
+    let conflict = || UrlencodedError::Conflict(key.to_string());
+    let (first, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return Err(conflict()),
+    };
+    if target.is_null() {
+        *target = match first.is_empty() {
+            true => Value::Array(Vec::new()),
+            false => Value::Object(Map::new()),
+        };
+    }
+    match target {
+        Value::Array(a) if first.is_empty() => {
+            if rest.is_empty() {
+                a.push(v);
+                return Ok(());
+            }
+            a.push(Value::Null);
+            insert(a.last_mut().ok_or_else(conflict)?, rest, v, key)
+        }
+        Value::Object(o) if !first.is_empty() => {
+            let entry = o.entry(first.to_string()).or_insert(Value::Null);
+            if !rest.is_empty() {
+                return insert(entry, rest, v, key);
+            }
+            match entry {
+                Value::Null => *entry = v,
+                Value::String(_) => *entry = Value::Array(vec![entry.take(), v]),
+                Value::Array(a) if a.iter().all(Value::is_string) => a.push(v),
+                _ => return Err(conflict()),
+            }
+            Ok(())
+        }
+        _ => Err(conflict()),
+    }
+}
+
+/// Writes an object as a query string, the reverse of [`from_str`], with
+/// objects within it as `a[b]=` and arrays as `a[]=`.
+pub fn to_string(value: &Value) -> Result<String, UrlencodedError> {
+    let mut pairs = Vec::new();
+    match value {
+        Value::Object(o) => {
+            for (k, v) in o {
+                write(&mut pairs, encode(k), v)?;
+            }
+        }
+        v => return Err(UrlencodedError::Unsupported(crate::describe(v))),
+    }
+    Ok(pairs.join("&"))
+}
+
+fn write(pairs: &mut Vec<String>, key: String, v: &Value) -> Result<(), UrlencodedError> {
+    match v {
+        Value::Object(o) => {
+            for (k, v) in o {
+                write(pairs, format!("{}[{}]", key, encode(k)), v)?;
+            }
+        }
+        Value::Array(a) => {
+            for v in a {
+                if v.is_array() || v.is_object() {
+                    return Err(UrlencodedError::Unsupported(crate::describe(v)));
+                }
+                write(pairs, format!("{}[]", key), v)?;
+            }
+        }
+        Value::Null => pairs.push(key),
+        Value::String(s) => pairs.push(format!("{}={}", key, encode(s))),
+        v => pairs.push(format!("{}={}", key, v)),
+    }
+    Ok(())
+}
+
+fn encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b' ' => "+".to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn read_urlencoded() {
+        assert_eq!(
+            Ok(json!({"a": "1", "b": ["x", "y"], "c": {"d": "a b&c", "e": ""}, "f": ""})),
+            from_str("a=1&b[]=x&b[]=y&c[d]=a+b%26c&c[e]=&f\n")
+        );
+        assert_eq!(Ok(json!({"a": ["1", "2"]})), from_str("a=1&a=2"));
+        assert_eq!(
+            Ok(json!({"a[": "%zz", "é": "ü"})),
+            from_str("a[=%zz&%C3%A9=%c3%bc")
+        );
+        assert_eq!(Ok(json!({"a": [{"b": "1"}]})), from_str("a[][b]=1"));
+        assert_eq!(Ok(json!({})), from_str(""));
+
+        assert_eq!(
+            Err(UrlencodedError::Conflict("a[b]".to_string())),
+            from_str("a=1&a[b]=2")
+        );
+        assert!(from_str("a[b]=1&a=2").is_err());
+        assert!(from_str("a[]=1&a[b]=2").is_err());
+        assert_eq!(Err(UrlencodedError::Utf8), from_str("a=%ff"));
+        assert_eq!(Err(UrlencodedError::Utf8), from_str("%c3=1"));
+    }
+
+    #[test]
+    fn write_urlencoded() {
+        let v = json!({"a": 1, "b": ["x", "y"], "c": {"d": "a b&c"}, "e": null});
+        let text = to_string(&v).unwrap();
+        assert_eq!("a=1&b[]=x&b[]=y&c[d]=a+b%26c&e", text);
+        assert_eq!(
+            Ok(json!({"a": "1", "b": ["x", "y"], "c": {"d": "a b&c"}, "e": ""})),
+            from_str(&text)
+        );
+
+        assert!(to_string(&json!([1])).is_err());
+        assert!(to_string(&json!({"a": [[1]]})).is_err());
+    }
+}
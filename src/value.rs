@@ -0,0 +1,88 @@
+use serde_json::Value;
+use std::borrow::Cow;
+
+use crate::{
+    query::{Executable, Input},
+    QueryError,
+};
+
+/// A tree representation of JSON which queries can be executed against.
+///
+/// The evaluator itself works on [`serde_json::Value`], so another backend
+/// only has to convert to and from it, borrowing where it already is one.
+pub trait JsonValue: Sized {
+    fn to_json(&self) -> Cow<'_, Value>;
+
+    fn from_json(value: Value) -> Self;
+}
+
+impl JsonValue for Value {
+    fn to_json(&self) -> Cow<'_, Value> {
+        Cow::Borrowed(self)
+    }
+
+    fn from_json(value: Value) -> Self {
+        value
+    }
+}
+
+impl<V: JsonValue> Input for V {
+    fn value(&self) -> Result<Cow<'_, Value>, QueryError> {
+        Ok(self.to_json())
+    }
+}
+
+/// Executes the query against a value of any backend, producing results of
+/// the same backend.
+pub fn execute_as<Q, V>(query: &Q, value: &V) -> Result<Vec<V>, QueryError>
+where
+    Q: Executable,
+    V: JsonValue,
+{
+    let results = match value.to_json() {
+        Cow::Borrowed(v) => query.execute(v)?,
+        Cow::Owned(v) => query.execute_owned(v)?,
+    };
+    Ok(results.into_iter().map(V::from_json).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Query;
+    use serde_json::json;
+
+    /// A backend holding only integers and lists of them.
+    #[derive(Debug, PartialEq)]
+    enum Tree {
+        Leaf(i64),
+        List(Vec<Tree>),
+    }
+
+    impl JsonValue for Tree {
+        fn to_json(&self) -> Cow<'_, Value> {
+            Cow::Owned(match self {
+                Tree::Leaf(i) => json!(i),
+                Tree::List(trees) => trees.iter().map(|t| t.to_json().into_owned()).collect(),
+            })
+        }
+
+        fn from_json(value: Value) -> Self {
+            match value {
+                Value::Array(arr) => Tree::List(arr.into_iter().map(Tree::from_json).collect()),
+                v => Tree::Leaf(v.as_i64().unwrap_or_default()),
+            }
+        }
+    }
+
+    #[test]
+    fn backend() {
+        let tree = Tree::List(vec![Tree::Leaf(1), Tree::List(vec![Tree::Leaf(2)])]);
+        let q: Query = ".[1], .[0] + 1".parse().unwrap();
+        assert_eq!(
+            vec![Tree::List(vec![Tree::Leaf(2)]), Tree::Leaf(2)],
+            execute_as(&q, &tree).unwrap()
+        );
+        assert_eq!(json!([2]), q.execute(&tree).unwrap()[0]);
+    }
+}
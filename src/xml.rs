@@ -0,0 +1,422 @@
+use nom::{
+    branch::alt,
+    bytes::complete::{is_not, tag, take_until, take_while, take_while1},
+    character::complete::{char, multispace0, multispace1},
+    combinator::{map_opt, opt, value},
+    multi::many0,
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    IResult,
+};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum XmlError {
+    #[error("Invalid XML at line {0}: {1}")]
+    Syntax(usize, String),
+    #[error("Expected </{0}> but found </{1}>")]
+    Mismatched(String, String),
+    #[error("Unclosed element <{0}>")]
+    Unclosed(String),
+    #[error("Cannot write {0} as XML")]
+    Unsupported(String),
+    #[error("XML nested more than {} deep", MAX_DEPTH)]
+    Depth,
+}
+
+/// How many elements deep an element can be, as deep as serde_json reads
+/// JSON.
+const MAX_DEPTH: usize = 128;
+
+/// Reads an XML document as an object holding its root element, with each
+/// element as a value as follows:
+///
+/// - An element with neither attributes nor children is its text, or `null`
+///   if it has none.
+/// - Otherwise it is an object, with each attribute under its name prefixed
+///   with `@`, each child under its name, and any text under `#text`.
+/// - Children of the same name are an array of them, in order.
+///
+/// A document is an object with the root element under its name. Text is
+/// trimmed of surrounding whitespace, and comments, processing instructions
+/// and the document type are skipped.
+pub fn from_str(text: &str) -> Result<Value, XmlError> {
+    let reader = Reader { text };
+    let (rest, (name, root)) = reader.element(misc(text), 0)?;
+    let rest = misc(rest);
+    if !rest.is_empty() {
+        return Err(reader.syntax(rest));
+    }
+    let mut document = Map::new();
+    document.insert(name, root);
+    Ok(Value::Object(document))
+}
+
+struct Reader<'t> {
+    text: &'t str,
+}
+
+impl Reader<'_> {
+    /// An error for what remains of the text.
+    fn syntax(&self, rest: &str) -> XmlError {
+        let line = self.text[..self.text.len() - rest.len()]
+            .matches('\n')
+            .count()
+            + 1;
+        let found = rest.lines().next().unwrap_or_default().to_string();
+        XmlError::Syntax(line, found)
+    }
+
+    /// Reads an element within `depth` others.
+    fn element<'a>(
+        &self,
+        input: &'a str,
+        depth: usize,
+    ) -> Result<(&'a str, (String, Value)), XmlError> {
+        if depth >= MAX_DEPTH {
+            return Err(XmlError::Depth);
+        }
+        let (mut input, (name, attributes, closed)) =
+            start_tag(input).map_err(|_| self.syntax(input))?;
+        let mut object = Map::new();
+        for (k, v) in attributes {
+            object.insert(format!("@{}", k), Value::from(v));
+        }
+        let mut text = String::new();
+        if !closed {
+            loop {
+                if let Ok((rest, end)) = end_tag(input) {
+                    if end != name {
+                        return Err(XmlError::Mismatched(name.to_string(), end.to_string()));
+                    }
+                    input = rest;
+                    break;
+                } else if let Ok((rest, _)) = alt((comment, instruction))(input) {
+                    input = rest;
+                } else if let Ok((rest, data)) = cdata(input) {
+                    text.push_str(data);
+                    input = rest;
+                } else if input.starts_with('<') {
+                    let (rest, (child, v)) = self.element(input, depth + 1)?;
+                    add(&mut object, child, v);
+                    input = rest;
+                } else if input.is_empty() {
+                    return Err(XmlError::Unclosed(name.to_string()));
+                } else {
+                    let (rest, data) = char_data(input).map_err(|_| self.syntax(input))?;
+                    text.push_str(&data);
+                    input = rest;
+                }
+            }
+        }
+
+        let text = text.trim();
+        let v = match (object.is_empty(), text.is_empty()) {
+            (true, true) => Value::Null,
+            (true, false) => Value::from(text),
+            (false, _) => {
+                if !text.is_empty() {
+                    object.insert("#text".to_string(), Value::from(text));
+                }
+                Value::Object(object)
+            }
+        };
+        Ok((input, (name.to_string(), v)))
+    }
+}
+
+/// Adds a child, gathering those of the same name into an array.
+fn add(object: &mut Map<String, Value>, name: String, v: Value) {
+    match object.get_mut(&name) {
+        Some(Value::Array(children)) => children.push(v),
+        Some(first) => *first = Value::Array(vec![first.take(), v]),
+        None => {
+            object.insert(name, v);
+        }
+    }
+}
+
+/// Skips whitespace, comments, processing instructions and document types.
+fn misc(mut input: &str) -> &str {
+    while let Ok((rest, _)) = preceded(multispace0, alt((comment, instruction, doctype)))(input) {
+        input = rest;
+    }
+    input.trim_start()
+}
+
+fn comment(input: &str) -> IResult<&str, ()> {
+    value((), tuple((tag("<!--"), take_until("-->"), tag("-->"))))(input)
+}
+
+fn instruction(input: &str) -> IResult<&str, ()> {
+    value((), tuple((tag("<?"), take_until("?>"), tag("?>"))))(input)
+}
+
+fn doctype(input: &str) -> IResult<&str, ()> {
+    let subset = delimited(char('['), take_until("]"), char(']'));
+    value(
+        (),
+        tuple((
+            tag("<!DOCTYPE"),
+            is_not("[>"),
+            opt(subset),
+            multispace0,
+            char('>'),
+        )),
+    )(input)
+}
+
+fn cdata(input: &str) -> IResult<&str, &str> {
+    delimited(tag("<![CDATA["), take_until("]]>"), tag("]]>"))(input)
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':')
+}
+
+fn name(input: &str) -> IResult<&str, &str> {
+    take_while1(is_name_char)(input)
+}
+
+/// The name, attributes and whether the element is closed already, as in
+/// `<a/>`.
+#[allow(clippy::type_complexity)]
+fn start_tag(input: &str) -> IResult<&str, (&str, Vec<(&str, String)>, bool)> {
+    let attribute = pair(
+        preceded(multispace1, name),
+        preceded(
+            delimited(multispace0, char('='), multispace0),
+            alt((quoted('"'), quoted('\''))),
+        ),
+    );
+    tuple((
+        preceded(char('<'), name),
+        many0(attribute),
+        preceded(
+            multispace0,
+            alt((value(true, tag("/>")), value(false, char('>')))),
+        ),
+    ))(input)
+}
+
+fn end_tag(input: &str) -> IResult<&str, &str> {
+    delimited(tag("</"), name, terminated(multispace0, char('>')))(input)
+}
+
+fn quoted(quote: char) -> impl FnMut(&str) -> IResult<&str, String> {
+    move |input| {
+        let (rest, raw) = delimited(
+            char(quote),
+            take_while(|c| c != quote && c != '<'),
+            char(quote),
+        )(input)?;
+        match unescape(raw) {
+            Some(text) => Ok((rest, text)),
+            None => Err(nom::Err::Error(nom::error::Error::new(
+                input,
+                nom::error::ErrorKind::Escaped,
+            ))),
+        }
+    }
+}
+
+fn char_data(input: &str) -> IResult<&str, String> {
+    map_opt(is_not("<"), unescape)(input)
+}
+
+/// Text with each entity replaced by the character it stands for.
+fn unescape(raw: &str) -> Option<String> {
+    let mut text = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(i) = rest.find('&') {
+        text.push_str(&rest[..i]);
+        let end = rest[i..].find(';')? + i;
+        let c = match &rest[i + 1..end] {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            e if e.starts_with("#x") => char::from_u32(u32::from_str_radix(&e[2..], 16).ok()?)?,
+            e if e.starts_with('#') => char::from_u32(e[1..].parse().ok()?)?,
+            _ => return None,
+        };
+        text.push(c);
+        rest = &rest[end + 1..];
+    }
+    text.push_str(rest);
+    Some(text)
+}
+
+/// Writes an object holding a single root element as an XML document, the
+/// reverse of [`from_str`].
+pub fn to_string(value: &Value) -> Result<String, XmlError> {
+    let mut output = String::new();
+    match value {
+        Value::Object(document) if document.len() == 1 => {
+            for (name, root) in document {
+                write_element(&mut output, name, root, 0)?;
+            }
+            Ok(output)
+        }
+        v => Err(XmlError::Unsupported(crate::describe(v))),
+    }
+}
+
+fn write_element(output: &mut String, name: &str, v: &Value, depth: usize) -> Result<(), XmlError> {
+    if name.is_empty() || !name.chars().all(is_name_char) {
+        return Err(XmlError::Unsupported(format!("element name {:?}", name)));
+    }
+    let indent = "  ".repeat(depth);
+    match v {
+        Value::Array(elements) => {
+            for element in elements {
+                if element.is_array() {
+                    return Err(XmlError::Unsupported(crate::describe(element)));
+                }
+                write_element(output, name, element, depth)?;
+            }
+        }
+        Value::Object(o) => {
+            output.push_str(&indent);
+            output.push('<');
+            output.push_str(name);
+            for (k, v) in o.iter().filter(|(k, _)| k.starts_with('@')) {
+                let text = scalar(v)?;
+                output.push_str(&format!(" {}=\"{}\"", &k[1..], escape(&text, true)));
+            }
+            let text = o.get("#text").map(scalar).transpose()?;
+            let children: Vec<_> = o
+                .iter()
+                .filter(|(k, _)| !k.starts_with('@') && *k != "#text")
+                .collect();
+            match (text, children.is_empty()) {
+                (None, true) => output.push_str("/>\n"),
+                (text, true) => {
+                    output.push('>');
+                    output.push_str(&escape(&text.unwrap_or_default(), false));
+                    output.push_str(&format!("</{}>\n", name));
+                }
+                (text, false) => {
+                    output.push('>');
+                    output.push_str(&escape(&text.unwrap_or_default(), false));
+                    output.push('\n');
+                    for (k, v) in children {
+                        write_element(output, k, v, depth + 1)?;
+                    }
+                    output.push_str(&format!("{}</{}>\n", indent, name));
+                }
+            }
+        }
+        Value::Null => output.push_str(&format!("{}<{}/>\n", indent, name)),
+        v => {
+            let text = escape(&scalar(v)?, false);
+            output.push_str(&format!("{}<{}>{}</{}>\n", indent, name, text, name));
+        }
+    }
+    Ok(())
+}
+
+/// The text of a value written as text or as an attribute.
+fn scalar(v: &Value) -> Result<String, XmlError> {
+    match v {
+        Value::String(s) => Ok(s.clone()),
+        Value::Array(_) | Value::Object(_) => Err(XmlError::Unsupported(crate::describe(v))),
+        v => Ok(v.to_string()),
+    }
+}
+
+fn escape(text: &str, attribute: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' if attribute => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn read_xml() {
+        let text = r#"<?xml version="1.0"?>
+<!DOCTYPE rss>
+<!-- a feed -->
+<rss version="2.0">
+  <channel>
+    <title>News &amp; views</title>
+    <item id='1'>First</item>
+    <item id="2"><![CDATA[<b>Second</b>]]></item>
+    <empty/>
+  </channel>
+</rss>
+"#;
+        assert_eq!(
+            Ok(json!({"rss": {
+                "@version": "2.0",
+                "channel": {
+                    "title": "News & views",
+                    "item": [
+                        {"@id": "1", "#text": "First"},
+                        {"@id": "2", "#text": "<b>Second</b>"}
+                    ],
+                    "empty": null
+                }
+            }})),
+            from_str(text)
+        );
+        assert_eq!(Ok(json!({"a": "\u{e9}<"})), from_str("<a>&#233;&#x3c;</a>"));
+
+        assert_eq!(
+            Err(XmlError::Mismatched("b".to_string(), "a".to_string())),
+            from_str("<a><b></a>")
+        );
+        assert_eq!(
+            Err(XmlError::Unclosed("a".to_string())),
+            from_str("<a>text")
+        );
+        assert_eq!(
+            Err(XmlError::Syntax(2, "<b/>".to_string())),
+            from_str("<a/>\n<b/>")
+        );
+        assert!(from_str("<a>&unknown;</a>").is_err());
+    }
+
+    #[test]
+    fn depth() {
+        let nested = |n: usize| "<a>".repeat(n) + &"</a>".repeat(n);
+        let deepest = (0..MAX_DEPTH).fold(Value::Null, |v, _| json!({ "a": v }));
+        assert_eq!(Ok(deepest), from_str(&nested(MAX_DEPTH)));
+        assert_eq!(Err(XmlError::Depth), from_str(&nested(MAX_DEPTH + 1)));
+        // Too deep to recurse into at all
+        assert_eq!(Err(XmlError::Depth), from_str(&"<a>".repeat(200_000)));
+    }
+
+    #[test]
+    fn write_xml() {
+        let v = json!({"rss": {
+            "@version": "2.0",
+            "channel": {"item": [{"@id": "1", "#text": "a < b"}, "c"], "empty": null}
+        }});
+        let text = to_string(&v).unwrap();
+        assert_eq!(
+            "<rss version=\"2.0\">\n  <channel>\n    <empty/>\n    \
+             <item id=\"1\">a &lt; b</item>\n    <item>c</item>\n  </channel>\n</rss>\n",
+            text
+        );
+        assert_eq!(Ok(v), from_str(&text));
+
+        assert!(to_string(&json!({"a": 1, "b": 2})).is_err());
+        assert!(to_string(&json!({"a b": 1})).is_err());
+        assert!(to_string(&json!({"a": {"@b": [1]}})).is_err());
+    }
+}
@@ -0,0 +1,460 @@
+"""Writes the Arrow IPC fixtures read by the tests of src/arrow.rs.
+
+There is no Arrow library to depend on here, so the flatbuffers are built
+from the schema in Arrow's format/*.fbs by hand, back to front and sharing
+equal vtables as the flatbuffers builder does. The expected rows are in the
+tests themselves.
+
+    python3 tests/data/arrow/generate.py
+"""
+
+import os
+import struct
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+
+
+class Builder:
+    """A flatbuffer built from its end, as the flatbuffers library builds one."""
+
+    def __init__(self):
+        self.buf = bytearray()
+        self.vtables = []
+        self.minalign = 1
+
+    def offset(self):
+        return len(self.buf)
+
+    def pad(self, n):
+        self.buf[0:0] = bytes(n)
+
+    def prep(self, size, additional):
+        self.minalign = max(self.minalign, size)
+        self.pad((-(len(self.buf) + additional)) % size)
+
+    def prepend(self, fmt, value):
+        size = struct.calcsize(fmt)
+        self.prep(size, 0)
+        self.buf[0:0] = struct.pack("<" + fmt, value)
+
+    def prepend_offset(self, target):
+        self.prep(4, 0)
+        self.buf[0:0] = struct.pack("<I", self.offset() - target + 4)
+
+    def string(self, s):
+        data = s.encode()
+        self.prep(4, len(data) + 1)
+        self.buf[0:0] = data + b"\0"
+        self.buf[0:0] = struct.pack("<I", len(data))
+        return self.offset()
+
+    def offsets(self, targets):
+        self.prep(4, 4 * len(targets))
+        for target in reversed(targets):
+            self.prepend_offset(target)
+        self.buf[0:0] = struct.pack("<I", len(targets))
+        return self.offset()
+
+    def structs(self, items, size):
+        self.prep(4, size * len(items))
+        self.prep(8, size * len(items))
+        for item in reversed(items):
+            self.buf[0:0] = item
+        self.prep(4, 0)
+        self.buf[0:0] = struct.pack("<I", len(items))
+        return self.offset()
+
+    def table(self, fields):
+        """A table of (slot, format, value) fields, where a format of "o"
+        is an offset to something already built."""
+        start = self.offset()
+        slots = {}
+        for slot, fmt, value in fields:
+            if fmt == "o":
+                self.prepend_offset(value)
+            else:
+                self.prepend(fmt, value)
+            slots[slot] = self.offset()
+        self.prepend("i", 0)
+        table = self.offset()
+        count = max(slots, default=-1) + 1
+        vtable = struct.pack("<HH", 4 + 2 * count, table - start)
+        for slot in range(count):
+            vtable += struct.pack("<H", table - slots[slot] if slot in slots else 0)
+        for existing, data in self.vtables:
+            if data == vtable:
+                soffset = existing - table
+                break
+        else:
+            self.buf[0:0] = vtable
+            soffset = self.offset() - table
+            self.vtables.append((self.offset(), vtable))
+        at = len(self.buf) - table
+        self.buf[at : at + 4] = struct.pack("<i", soffset)
+        return table
+
+    def finish(self, root):
+        self.prep(max(self.minalign, 8), 4)
+        self.prepend_offset(root)
+        return bytes(self.buf)
+
+
+# The ids of the Type union
+NULL, INT, FLOAT, BINARY, UTF8, BOOL, DECIMAL, DATE, TIME, TIMESTAMP = range(1, 11)
+LIST, STRUCT, FIXED_BINARY, FIXED_LIST, MAP, DURATION = 12, 13, 15, 16, 17, 18
+LARGE_BINARY, LARGE_UTF8, LARGE_LIST = 19, 20, 21
+
+
+class Field:
+    def __init__(self, name, kind, options=(), children=(), dictionary=None):
+        self.name = name
+        self.kind = kind
+        # (slot, format, value) fields of the type table
+        self.options = list(options)
+        self.children = list(children)
+        # The id and perhaps index type, as (bits, signed), of a dictionary
+        self.dictionary = dictionary
+
+    def build(self, b):
+        children = b.offsets([c.build(b) for c in self.children])
+        options = []
+        for slot, fmt, value in self.options:
+            if fmt == "s":
+                options.append((slot, "o", b.string(value)))
+            else:
+                options.append((slot, fmt, value))
+        kind = b.table(options)
+        dictionary = None
+        if self.dictionary is not None:
+            id, index = self.dictionary
+            fields = [(0, "q", id)]
+            if index is not None:
+                fields.append((1, "o", b.table([(0, "i", index[0]), (1, "B", index[1])])))
+            dictionary = b.table(fields)
+        name = b.string(self.name)
+        fields = [
+            (0, "o", name),
+            (1, "B", 1),
+            (2, "B", self.kind),
+            (3, "o", kind),
+            (5, "o", children),
+        ]
+        if dictionary is not None:
+            fields.append((4, "o", dictionary))
+        return b.table(fields)
+
+
+def integer(bits, signed=True):
+    return [(0, "i", bits), (1, "B", int(signed))]
+
+
+class Body:
+    """The nodes, buffers and body of a record batch."""
+
+    def __init__(self):
+        self.nodes = []
+        self.buffers = []
+        self.data = bytearray()
+
+    def node(self, values):
+        nulls = sum(v is None for v in values)
+        self.nodes.append(struct.pack("<qq", len(values), nulls))
+
+    def buffer(self, data):
+        self.buffers.append(struct.pack("<qq", len(self.data), len(data)))
+        self.data += data
+        self.data += bytes(-len(self.data) % 8)
+
+    def validity(self, values):
+        bits = bytearray((len(values) + 7) // 8)
+        for i, v in enumerate(values):
+            if v is not None:
+                bits[i // 8] |= 1 << (i % 8)
+        self.buffer(bytes(bits) if None in values else b"")
+
+    def fixed(self, values, fmt):
+        self.node(values)
+        self.validity(values)
+        self.buffer(b"".join(struct.pack("<" + fmt, 0 if v is None else v) for v in values))
+
+    def wide(self, values, size):
+        self.node(values)
+        self.validity(values)
+        self.buffer(b"".join((v or 0).to_bytes(size, "little", signed=True) for v in values))
+
+    def bools(self, values):
+        self.node(values)
+        self.validity(values)
+        bits = bytearray((len(values) + 7) // 8)
+        for i, v in enumerate(values):
+            if v:
+                bits[i // 8] |= 1 << (i % 8)
+        self.buffer(bytes(bits))
+
+    def lengths(self, values, fmt):
+        ends = [0]
+        for v in values:
+            ends.append(ends[-1] + (0 if v is None else len(v)))
+        self.buffer(b"".join(struct.pack("<" + fmt, e) for e in ends))
+
+    def binary(self, values, fmt="i"):
+        self.node(values)
+        self.validity(values)
+        values = [v.encode() if isinstance(v, str) else v for v in values]
+        self.lengths(values, fmt)
+        self.buffer(b"".join(v or b"" for v in values))
+
+    def nulls(self, values):
+        self.node(values)
+
+
+def batch(b, length, body):
+    nodes = b.structs(body.nodes, 16)
+    buffers = b.structs(body.buffers, 16)
+    return b.table([(0, "q", length), (1, "o", nodes), (2, "o", buffers)])
+
+
+def message(kind, header, body=b"", legacy=False):
+    """An encapsulated message, with the continuation marker unless legacy."""
+    b = Builder()
+    root = b.table([(0, "h", 4), (1, "B", kind), (2, "o", header(b)), (3, "q", len(body))])
+    metadata = b.finish(root)
+    prefix = 4 if legacy else 8
+    metadata += bytes(-(len(metadata) + prefix) % 8)
+    marker = b"" if legacy else b"\xff\xff\xff\xff"
+    return marker + struct.pack("<I", len(metadata)) + metadata + bytes(body)
+
+
+def schema(fields):
+    return lambda b: b.table([(1, "o", b.offsets([f.build(b) for f in fields]))])
+
+
+def record(length, body, legacy=False):
+    return message(3, lambda b: batch(b, length, body), body.data, legacy)
+
+
+def dictionary(id, length, body, delta=False, legacy=False):
+    def header(b):
+        data = batch(b, length, body)
+        return b.table([(0, "q", id), (1, "o", data), (2, "B", int(delta))])
+
+    return message(2, header, body.data, legacy)
+
+
+def types():
+    """Every type which can be read, each with a null among its values."""
+    fields = [
+        Field("null", NULL),
+        Field("i8", INT, integer(8)),
+        Field("u16", INT, integer(16, False)),
+        Field("i32", INT, integer(32)),
+        Field("i64", INT, integer(64)),
+        Field("u64", INT, integer(64, False)),
+        Field("half", FLOAT, [(0, "h", 0)]),
+        Field("single", FLOAT, [(0, "h", 1)]),
+        Field("double", FLOAT, [(0, "h", 2)]),
+        Field("flag", BOOL),
+        Field("blob", BINARY),
+        Field("text", UTF8),
+        Field("large_blob", LARGE_BINARY),
+        Field("large_text", LARGE_UTF8),
+        Field("fixed", FIXED_BINARY, [(0, "i", 2)]),
+        Field("decimal", DECIMAL, [(0, "i", 10), (1, "i", 2)]),
+        Field("hundreds", DECIMAL, [(0, "i", 5), (1, "i", -2), (2, "i", 64)]),
+        Field("day", DATE, [(0, "h", 0)]),
+        Field("day_ms", DATE, [(0, "h", 1)]),
+        Field("time_s", TIME, [(0, "h", 0), (1, "i", 32)]),
+        Field("time_ms", TIME, [(0, "h", 1), (1, "i", 32)]),
+        Field("time_us", TIME, [(0, "h", 2), (1, "i", 64)]),
+        Field("time_ns", TIME, [(0, "h", 3), (1, "i", 64)]),
+        Field("at_s", TIMESTAMP, [(0, "h", 0)]),
+        Field("at_ms", TIMESTAMP, [(0, "h", 1), (1, "s", "UTC")]),
+        Field("at_us", TIMESTAMP, [(0, "h", 2), (1, "s", "+01:00")]),
+        Field("at_ns", TIMESTAMP, [(0, "h", 3)]),
+        Field("duration", DURATION, [(0, "h", 1)]),
+        Field("list", LIST, children=[Field("item", INT, integer(32))]),
+        Field("large_list", LARGE_LIST, children=[Field("item", UTF8)]),
+        Field("pair", FIXED_LIST, [(0, "i", 2)], [Field("item", INT, integer(16))]),
+        Field(
+            "point",
+            STRUCT,
+            children=[Field("a", INT, integer(32)), Field("b", UTF8)],
+        ),
+        Field(
+            "map",
+            MAP,
+            children=[
+                Field(
+                    "entries",
+                    STRUCT,
+                    children=[Field("key", UTF8), Field("value", INT, integer(64))],
+                )
+            ],
+        ),
+        Field(
+            "numbered",
+            MAP,
+            children=[
+                Field(
+                    "entries",
+                    STRUCT,
+                    children=[Field("k", INT, integer(32)), Field("v", BOOL)],
+                )
+            ],
+        ),
+    ]
+
+    body = Body()
+    body.nulls([None] * 4)
+    body.fixed([-128, 127, None, 0], "b")
+    body.fixed([65535, 0, 1, None], "H")
+    body.fixed([None, -1, 2**31 - 1, 5], "i")
+    body.fixed([-(2**63), 1, None, 0], "q")
+    body.fixed([2**64 - 1, None, 0, 42], "Q")
+    body.fixed([1.5, -2.0, None, 65504.0], "e")
+    body.fixed([0.25, None, -1.0, 3.5], "f")
+    body.fixed([1e300, -0.5, None, 2.0], "d")
+    body.bools([True, False, None, True])
+    body.binary([b"\x00\x01", b"", None, b"\xff"])
+    body.binary(["héllo", "", None, "\U0001f980"])
+    body.binary([b"ab", None, b"", b"c"], "q")
+    body.binary(["x", "yz", None, ""], "q")
+    body.node([b"ab", b"\0\0", None, b"zz"])
+    body.validity([b"ab", b"\0\0", None, b"zz"])
+    body.buffer(b"ab\0\0\0\0zz")
+    body.wide([12345, -5, None, 0], 16)
+    body.wide([15, 0, -3, None], 8)
+    body.fixed([0, 18262, -1, None], "i")
+    body.fixed([18262 * 86_400_000, -1, None, 0], "q")
+    body.fixed([45296, None, 0, 86399], "i")
+    body.fixed([45296001, 0, None, 86_399_999], "i")
+    body.fixed([45_296_000_001, None, 0, 1], "q")
+    body.fixed([1, 45_296_000_000_000, None, 0], "q")
+    body.fixed([1_577_836_800, -1, None, 0], "q")
+    body.fixed([1_577_836_800_500, 0, -1, None], "q")
+    body.fixed([None, 1_500_000, 0, -1], "q")
+    body.fixed([1, None, -1, 1_577_836_800_000_000_001], "q")
+    body.fixed([1000, -1, None, 0], "q")
+
+    lists = [[1, None], [], None, [3]]
+    body.node(lists)
+    body.validity(lists)
+    body.lengths(lists, "i")
+    body.fixed([1, None, 3], "i")
+
+    lists = [["a"], None, [], ["b", "c"]]
+    body.node(lists)
+    body.validity(lists)
+    body.lengths(lists, "q")
+    body.binary(["a", "b", "c"])
+
+    pairs = [[1, 2], None, [3, None], [0, 0]]
+    body.node(pairs)
+    body.validity(pairs)
+    body.fixed([1, 2, 0, 0, 3, None, 0, 0], "h")
+
+    points = [1, None, 1, 1]
+    body.node(points)
+    body.validity(points)
+    body.fixed([1, 0, None, 4], "i")
+    body.binary(["x", "", "y", None])
+
+    maps = [{"k": 1}, {}, None, {"a": 2, "b": None}]
+    body.node(maps)
+    body.validity(maps)
+    body.lengths(maps, "i")
+    entries = [1, 1, 1]
+    body.node(entries)
+    body.validity(entries)
+    body.binary(["k", "a", "b"])
+    body.fixed([1, 2, None], "q")
+
+    maps = [{1: True, 2: False}, None, {}, {}]
+    body.node(maps)
+    body.validity(maps)
+    body.lengths(maps, "i")
+    entries = [1, 1]
+    body.node(entries)
+    body.validity(entries)
+    body.fixed([1, 2], "i")
+    body.bools([True, False])
+
+    return fields, [message(1, schema(fields)), record(4, body)]
+
+
+def dictionaries():
+    """Dictionary batches both replaced and extended by deltas, in the framing
+    from before version 0.15 without continuation markers."""
+    fields = [
+        Field("color", UTF8, dictionary=(0, (8, True))),
+        # Without an index type, which is then a signed 32 bit integer
+        Field("tags", LIST, children=[Field("item", UTF8, dictionary=(1, None))]),
+    ]
+
+    def values(strings):
+        body = Body()
+        body.binary(strings)
+        return body
+
+    def indices(colors, tags):
+        body = Body()
+        body.fixed(colors, "b")
+        body.node(tags)
+        body.validity(tags)
+        body.lengths(tags, "i")
+        body.fixed([i for t in tags if t for i in t], "i")
+        return body
+
+    return [
+        message(1, schema(fields), legacy=True),
+        dictionary(0, 2, values(["red", "blue"]), legacy=True),
+        dictionary(1, 2, values(["x", "y"]), legacy=True),
+        dictionary(0, 1, values(["green"]), delta=True, legacy=True),
+        record(4, indices([1, 0, None, 2], [[0, 1], [], None, [1]]), legacy=True),
+        dictionary(0, 1, values(["cyan"]), legacy=True),
+        record(2, indices([0, None], [[1, 1], [0]]), legacy=True),
+    ]
+
+
+def stream(messages, legacy=False):
+    end = b"\0\0\0\0" if legacy else b"\xff\xff\xff\xff\0\0\0\0"
+    return b"".join(messages) + end
+
+
+def file(fields, messages):
+    """The stream between the magic numbers, followed by the footer which
+    repeats the schema and lists where each batch is."""
+    data = bytearray(b"ARROW1\0\0")
+    blocks = []
+    for i, m in enumerate(messages):
+        metadata = struct.unpack("<I", m[4:8])[0] + 8
+        if i > 0:
+            blocks.append(struct.pack("<qi4xq", len(data), metadata, len(m) - metadata))
+        data += m
+    data += b"\xff\xff\xff\xff\0\0\0\0"
+
+    b = Builder()
+    batches = b.structs(blocks, 24)
+    dictionaries = b.structs([], 24)
+    footer = b.table([(0, "h", 4), (1, "o", schema(fields)(b)), (2, "o", dictionaries), (3, "o", batches)])
+    footer = b.finish(footer)
+    data += footer
+    data += struct.pack("<I", len(footer))
+    data += b"ARROW1"
+    return bytes(data)
+
+
+def main():
+    fields, messages = types()
+    fixtures = {
+        "types.arrows": stream(messages),
+        "types.arrow": file(fields, messages),
+        "dictionaries.arrows": stream(dictionaries(), legacy=True),
+    }
+    for name, data in fixtures.items():
+        with open(os.path.join(HERE, name), "wb") as f:
+            f.write(data)
+
+
+if __name__ == "__main__":
+    main()